@@ -0,0 +1,10 @@
+// Parses arbitrary input as a statement, see `clyde::check_parse`.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        clyde::check_parse(input);
+    }
+});
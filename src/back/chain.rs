@@ -0,0 +1,360 @@
+//! Several backends used as one. Each question is asked of the backends which
+//! support it, in order, until one answers, so that a semantic backend (e.g.,
+//! one using the compiler's analysis) can fall back to a syntactic one.
+//! Diagnostics are the exception: those from every backend are merged.
+//!
+//! Identifiers and definitions are only meaningful to the backend which found
+//! them, so falling back works best between backends which identify things by
+//! where they are in the source.
+//!
+//! When a question is answered by a backend other than the first in the chain
+//! (e.g., because the build is broken), that is recorded as a `Provenance`, so
+//! that the user can be told the answer may be less complete than usual.
+
+use super::{Backend, Capabilities, Capability, Error, Progress, Provenance, Symbol};
+use crate::file_system::Path;
+use crate::front::data::{Definition, Diagnostic, Identifier, Position, Range};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct ChainBackend {
+    // In the order they are tried.
+    backends: Vec<Rc<dyn Backend>>,
+    // Answers from backends other than the first, since they were last taken.
+    provenance: RefCell<Vec<Provenance>>,
+}
+
+impl ChainBackend {
+    pub fn new(backends: Vec<Rc<dyn Backend>>) -> ChainBackend {
+        ChainBackend {
+            backends,
+            provenance: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Record that `backend` answered for `function`, if it is not the first.
+    fn answered(&self, function: &'static str, backend: &Rc<dyn Backend>) {
+        if Rc::ptr_eq(backend, &self.backends[0]) {
+            return;
+        }
+        let provenance = Provenance {
+            function,
+            backend: backend.name(),
+        };
+        let mut all = self.provenance.borrow_mut();
+        if !all.contains(&provenance) {
+            all.push(provenance);
+        }
+    }
+
+    // The backends which support `capability`, in order.
+    fn supporting(&self, capability: Capability) -> impl Iterator<Item = &Rc<dyn Backend>> {
+        self.backends
+            .iter()
+            .filter(move |b| b.capabilities().contains(capability))
+    }
+
+    // The first answer from a backend which supports `capability`. If they all
+    // fail, the error is the first backend's.
+    fn first<T>(
+        &self,
+        capability: Capability,
+        function: &'static str,
+        f: impl Fn(&dyn Backend) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut error = None;
+        for backend in self.supporting(capability) {
+            match f(&**backend) {
+                Ok(result) => {
+                    self.answered(function, backend);
+                    return Ok(result);
+                }
+                Err(e) => {
+                    log::debug!("backend `{}` failed: {}", backend.name(), e);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or(Error::NotImplemented(function)))
+    }
+}
+
+impl Backend for ChainBackend {
+    fn name(&self) -> &'static str {
+        "chain"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.backends
+            .iter()
+            .fold(Capabilities::NONE, |caps, b| caps.union(b.capabilities()))
+    }
+
+    // Every backend is reloaded, even if one fails.
+    fn reload(&self, build_command: &[String]) -> Result<(), Error> {
+        let mut result = Ok(());
+        for backend in &self.backends {
+            let reloaded = backend.reload(build_command);
+            if result.is_ok() {
+                result = reloaded;
+            }
+        }
+        result
+    }
+
+    fn index_failed(&self) -> bool {
+        self.backends.iter().any(|b| b.index_failed())
+    }
+
+    fn take_provenance(&self) -> Vec<Provenance> {
+        self.provenance.replace(Vec::new())
+    }
+
+    fn idents_at(&self, position: Position) -> Result<Vec<Identifier>, Error> {
+        self.first(Capability::Idents, "idents_at", |b| {
+            b.idents_at(position.clone())
+        })
+    }
+
+    fn idents_in(&self, range: Range) -> Result<Vec<Identifier>, Error> {
+        self.first(Capability::Idents, "idents_in", |b| {
+            b.idents_in(range.clone())
+        })
+    }
+
+    fn definition(&self, id: Identifier) -> Result<Definition, Error> {
+        self.first(Capability::Definitions, "definition", |b| {
+            b.definition(id.clone())
+        })
+    }
+
+    fn idents_in_many(
+        &self,
+        ranges: &[Range],
+        progress: &dyn Progress,
+    ) -> Result<Vec<Vec<Identifier>>, Error> {
+        self.first(Capability::Idents, "idents_in", |b| {
+            b.idents_in_many(ranges, progress)
+        })
+    }
+
+    fn definitions(
+        &self,
+        ids: &[Identifier],
+        progress: &dyn Progress,
+    ) -> Result<Vec<Definition>, Error> {
+        self.first(Capability::Definitions, "definition", |b| {
+            b.definitions(ids, progress)
+        })
+    }
+
+    fn def_ident(&self, def: Definition) -> Result<Identifier, Error> {
+        self.first(Capability::Idents, "idents_in", |b| {
+            b.def_ident(def.clone())
+        })
+    }
+
+    fn symbols_in_file(&self, file: Path) -> Result<Vec<Symbol>, Error> {
+        self.first(Capability::Symbols, "symbols_in_file", |b| {
+            b.symbols_in_file(file)
+        })
+    }
+
+    fn type_of(&self, id: Identifier) -> Result<String, Error> {
+        self.first(Capability::Types, "type_of", |b| b.type_of(id.clone()))
+    }
+
+    // The diagnostics of every backend which can find them, without
+    // duplicates. Backends which fail are left out, unless they all fail.
+    fn diagnostics(&self, range: Range) -> Result<Vec<Diagnostic>, Error> {
+        let mut result: Vec<Diagnostic> = Vec::new();
+        let mut error = None;
+        let mut answered = false;
+        for backend in self.supporting(Capability::Diagnostics) {
+            match backend.diagnostics(range.clone()) {
+                Ok(diagnostics) => {
+                    // Only worth noting if the first backend didn't answer.
+                    if !answered {
+                        self.answered("diagnostics", backend);
+                    }
+                    answered = true;
+                    for d in diagnostics {
+                        if !result.contains(&d) {
+                            result.push(d);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        match error {
+            Some(e) if !answered => Err(e),
+            None if !answered => Err(Error::NotImplemented("diagnostics")),
+            _ => Ok(result),
+        }
+    }
+
+    fn super_traits(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        self.first(Capability::Traits, "super_traits", |b| {
+            b.super_traits(def.clone())
+        })
+    }
+
+    fn sub_traits(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        self.first(Capability::Traits, "sub_traits", |b| {
+            b.sub_traits(def.clone())
+        })
+    }
+
+    fn fields(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        self.first(Capability::Members, "fields", |b| b.fields(def.clone()))
+    }
+
+    fn methods(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        self.first(Capability::Members, "methods", |b| b.methods(def.clone()))
+    }
+
+    fn docs(&self, def: Definition) -> Result<String, Error> {
+        self.first(Capability::Docs, "docs", |b| b.docs(def.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::back::MockBackend;
+    use crate::file_system::{FileSystem, MockFs};
+    use crate::front::data::{DefKind, Severity, Span};
+
+    fn span(line: usize) -> Span {
+        let foo = MockFs.find("foo.rs".to_owned().into()).ok().unwrap()[0];
+        Span::new_zero_indexed(foo, line, 0, line, 4)
+    }
+
+    fn ident(id: u64) -> Identifier {
+        Identifier {
+            id,
+            span: span(id as usize),
+            name: format!("i{}", id),
+            from_macro: false,
+            kind: None,
+            def: None,
+        }
+    }
+
+    fn def(id: u64) -> Definition {
+        Definition {
+            id,
+            span: span(id as usize),
+            name: format!("d{}", id),
+            kind: DefKind::Function,
+            path: None,
+        }
+    }
+
+    fn diagnostic(message: &str) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.to_owned(),
+            span: span(0),
+        }
+    }
+
+    // A semantic backend which can't find identifiers (as if its index had
+    // failed), followed by a syntactic one which knows no definitions.
+    fn chain() -> ChainBackend {
+        let semantic = MockBackend::new()
+            .ident(ident(1), Some(def(10)))
+            .ty(1, "u32")
+            .diagnostic(diagnostic("a"))
+            .only(
+                Capabilities::NONE
+                    .with(Capability::Definitions)
+                    .with(Capability::Types)
+                    .with(Capability::Diagnostics),
+            );
+        let syntactic = MockBackend::new()
+            .ident(ident(1), None)
+            .ident(ident(2), None)
+            .ty(2, "i32")
+            .diagnostic(diagnostic("a"))
+            .diagnostic(diagnostic("b"))
+            .only(
+                Capabilities::NONE
+                    .with(Capability::Idents)
+                    .with(Capability::Types)
+                    .with(Capability::Diagnostics),
+            );
+        ChainBackend::new(vec![Rc::new(semantic), Rc::new(syntactic)])
+    }
+
+    #[test]
+    fn test_first() {
+        let back = chain();
+        assert_eq!(
+            back.capabilities().to_string(),
+            "identifiers, definitions, diagnostics, types"
+        );
+        // Only the second backend finds identifiers.
+        let idents = back.idents_in(Range::File(span(0).file)).ok().unwrap();
+        assert_eq!(idents, vec![ident(1), ident(2)]);
+        // The first backend answers.
+        assert_eq!(back.definition(ident(1)).ok().unwrap(), def(10));
+        assert_eq!(back.type_of(ident(1)).ok().unwrap(), "u32");
+        // The first backend fails, so the second is asked.
+        assert_eq!(back.type_of(ident(2)).ok().unwrap(), "i32");
+        // Both fail, the error is the first's.
+        match back.type_of(ident(3)) {
+            Err(Error::Back(msg)) => assert_eq!(msg, "no type for `i3`"),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_provenance() {
+        let back = chain();
+        let provenance = |function| Provenance {
+            function,
+            backend: "mock",
+        };
+        back.idents_in(Range::File(span(0).file)).ok().unwrap();
+        back.idents_in(Range::File(span(0).file)).ok().unwrap();
+        back.type_of(ident(1)).ok().unwrap();
+        back.type_of(ident(2)).ok().unwrap();
+        assert_eq!(
+            back.take_provenance(),
+            vec![provenance("idents_in"), provenance("type_of")]
+        );
+        assert_eq!(back.take_provenance(), vec![]);
+        assert_eq!(
+            provenance("type_of").to_string(),
+            "`type_of` from the `mock` backend"
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let back = chain();
+        let diagnostics = back.diagnostics(Range::File(span(0).file)).ok().unwrap();
+        assert_eq!(diagnostics, vec![diagnostic("a"), diagnostic("b")]);
+        // The first backend answered.
+        assert_eq!(back.take_provenance(), vec![]);
+    }
+
+    #[test]
+    fn test_not_implemented() {
+        let back = ChainBackend::new(vec![Rc::new(
+            MockBackend::new().only(Capabilities::NONE.with(Capability::Idents)),
+        )]);
+        match back.docs(def(10)) {
+            Err(Error::NotImplemented(f)) => assert_eq!(f, "docs"),
+            _ => panic!(),
+        }
+        match back.diagnostics(Range::File(span(0).file)) {
+            Err(Error::NotImplemented(f)) => assert_eq!(f, "diagnostics"),
+            _ => panic!(),
+        }
+    }
+}
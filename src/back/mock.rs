@@ -0,0 +1,184 @@
+//! A scriptable backend for tests. Identifiers, definitions, symbols, and
+//! diagnostics are provided up front and queries are answered from them.
+
+use super::{Backend, Capabilities, Capability, Error, Symbol};
+use crate::file_system::Path;
+use crate::front::data::{DefKind, Definition, Diagnostic, Identifier, Position, Range};
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct MockBackend {
+    idents: Vec<Identifier>,
+    // Definitions by the id of the identifier which refers to them.
+    defs: HashMap<u64, Definition>,
+    // Types by the id of the identifier.
+    types: HashMap<u64, String>,
+    symbols: Vec<Symbol>,
+    diagnostics: Vec<Diagnostic>,
+    // Pairs of a trait and one of its supertraits.
+    super_traits: Vec<(Definition, Definition)>,
+    // Pairs of a type or trait and one of its fields or methods.
+    members: Vec<(Definition, Definition)>,
+    // Documentation by the id of the definition.
+    docs: HashMap<u64, String>,
+    // If set, the backend claims to support only these.
+    capabilities: Option<Capabilities>,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend::default()
+    }
+
+    /// Add an identifier, and the definition it refers to (if any).
+    pub fn ident(mut self, ident: Identifier, def: Option<Definition>) -> MockBackend {
+        if let Some(def) = def {
+            self.defs.insert(ident.id, def);
+        }
+        self.idents.push(ident);
+        self
+    }
+
+    /// Add an item defined in a file, `parent` is the id of the enclosing item.
+    pub fn symbol(mut self, def: Definition, parent: Option<u64>) -> MockBackend {
+        self.symbols.push(Symbol { def, parent });
+        self
+    }
+
+    /// Set the type of the identifier with id `id`.
+    pub fn ty(mut self, id: u64, ty: &str) -> MockBackend {
+        self.types.insert(id, ty.to_owned());
+        self
+    }
+
+    pub fn diagnostic(mut self, diagnostic: Diagnostic) -> MockBackend {
+        self.diagnostics.push(diagnostic);
+        self
+    }
+
+    /// Make `sup` a supertrait of `sub`.
+    pub fn super_trait(mut self, sub: Definition, sup: Definition) -> MockBackend {
+        self.super_traits.push((sub, sup));
+        self
+    }
+
+    /// Make `member` (a field or method) a member of `def`.
+    pub fn member(mut self, def: Definition, member: Definition) -> MockBackend {
+        self.members.push((def, member));
+        self
+    }
+
+    /// Set the documentation of the definition with id `id`.
+    pub fn docs(mut self, id: u64, docs: &str) -> MockBackend {
+        self.docs.insert(id, docs.to_owned());
+        self
+    }
+
+    /// Support only `capabilities`, rather than everything.
+    pub fn only(mut self, capabilities: Capabilities) -> MockBackend {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    fn members_of(&self, def: &Definition, kind: DefKind) -> Vec<Definition> {
+        self.members
+            .iter()
+            .filter(|(d, m)| d.id == def.id && m.kind == kind)
+            .map(|(_, m)| m.clone())
+            .collect()
+    }
+}
+
+impl Backend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities.unwrap_or_else(|| {
+            Capability::ALL
+                .iter()
+                .fold(Capabilities::NONE, |caps, c| caps.with(*c))
+        })
+    }
+
+    fn idents_at(&self, position: Position) -> Result<Vec<Identifier>, Error> {
+        Ok(self
+            .idents
+            .iter()
+            .filter(|i| i.span.contains(&position))
+            .cloned()
+            .collect())
+    }
+
+    fn idents_in(&self, range: Range) -> Result<Vec<Identifier>, Error> {
+        Ok(self
+            .idents
+            .iter()
+            .filter(|i| i.span.intersects(&range))
+            .cloned()
+            .collect())
+    }
+
+    fn definition(&self, id: Identifier) -> Result<Definition, Error> {
+        self.defs
+            .get(&id.id)
+            .cloned()
+            .ok_or_else(|| Error::Back(format!("no definition for `{}`", id.name)))
+    }
+
+    fn symbols_in_file(&self, file: Path) -> Result<Vec<Symbol>, Error> {
+        Ok(self
+            .symbols
+            .iter()
+            .filter(|s| s.def.span.file == file)
+            .cloned()
+            .collect())
+    }
+
+    fn type_of(&self, id: Identifier) -> Result<String, Error> {
+        self.types
+            .get(&id.id)
+            .cloned()
+            .ok_or_else(|| Error::Back(format!("no type for `{}`", id.name)))
+    }
+
+    fn diagnostics(&self, range: Range) -> Result<Vec<Diagnostic>, Error> {
+        Ok(self
+            .diagnostics
+            .iter()
+            .filter(|d| d.span.intersects(&range))
+            .cloned()
+            .collect())
+    }
+
+    fn super_traits(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        Ok(self
+            .super_traits
+            .iter()
+            .filter(|(sub, _)| sub.id == def.id)
+            .map(|(_, sup)| sup.clone())
+            .collect())
+    }
+
+    fn sub_traits(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        Ok(self
+            .super_traits
+            .iter()
+            .filter(|(_, sup)| sup.id == def.id)
+            .map(|(sub, _)| sub.clone())
+            .collect())
+    }
+
+    fn fields(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        Ok(self.members_of(&def, DefKind::Field))
+    }
+
+    fn methods(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        Ok(self.members_of(&def, DefKind::Method))
+    }
+
+    fn docs(&self, def: Definition) -> Result<String, Error> {
+        Ok(self.docs.get(&def.id).cloned().unwrap_or_default())
+    }
+}
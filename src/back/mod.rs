@@ -1,14 +1,49 @@
+//! Backends answer the questions about a project which need more than its
+//! source text, e.g., where an identifier is defined. Backends are chosen by
+//! name from a `Registry`; several may be chained as one `ChainBackend`.
+
+pub use self::chain::ChainBackend;
+#[cfg(test)]
+pub use self::mock::MockBackend;
 pub use rls::Rls;
 
-use crate::file_system;
-use crate::front::data::{Definition, Identifier, Position, Range};
+use crate::env::Verbosity;
+use crate::file_system::{self, Path, PhysicalFs};
+use crate::front::data::{Definition, Diagnostic, Identifier, Position, Range};
 use std::fmt;
+use std::path::{Path as StdPath, PathBuf};
+use std::rc::Rc;
 
+mod chain;
+#[cfg(test)]
+mod mock;
 mod rls;
 
+/// The version of the `Backend` trait, see `Registry::register`.
+pub const BACKEND_VERSION: u32 = 1;
+
 pub trait Backend {
-    fn ident_at(&self, _position: Position) -> Result<Option<Identifier>, Error> {
-        Err(Error::NotImplemented("ident_at"))
+    /// A short name for the backend, for the user.
+    fn name(&self) -> &'static str;
+    /// Functions not included return `Error::NotImplemented`.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Update the index after source files have changed.
+    fn reload(&self, _build_command: &[String]) -> Result<(), Error> {
+        Ok(())
+    }
+    /// True if the last update of the index failed.
+    fn index_failed(&self) -> bool {
+        false
+    }
+    /// Answers from a fallback backend since the last call, see `ChainBackend`.
+    fn take_provenance(&self) -> Vec<Provenance> {
+        Vec::new()
+    }
+
+    /// There may be more than one, e.g., in a macro expansion.
+    fn idents_at(&self, _position: Position) -> Result<Vec<Identifier>, Error> {
+        Err(Error::NotImplemented("idents_at"))
     }
     fn idents_in(&self, _range: Range) -> Result<Vec<Identifier>, Error> {
         Err(Error::NotImplemented("idents_in"))
@@ -16,10 +51,363 @@ pub trait Backend {
     fn definition(&self, _id: Identifier) -> Result<Definition, Error> {
         Err(Error::NotImplemented("definition"))
     }
+    /// Backends where each call is expensive should override this.
+    fn idents_in_many(
+        &self,
+        ranges: &[Range],
+        progress: &dyn Progress,
+    ) -> Result<Vec<Vec<Identifier>>, Error> {
+        let mut result = Vec::with_capacity(ranges.len());
+        for r in ranges {
+            result.push(self.idents_in(r.clone())?);
+            progress.update(result.len(), ranges.len());
+        }
+        Ok(result)
+    }
+    /// Backends where each call is expensive should override this.
+    fn definitions(
+        &self,
+        ids: &[Identifier],
+        progress: &dyn Progress,
+    ) -> Result<Vec<Definition>, Error> {
+        let mut result = Vec::with_capacity(ids.len());
+        for id in ids {
+            result.push(self.definition(id.clone())?);
+            progress.update(result.len(), ids.len());
+        }
+        Ok(result)
+    }
+    /// The identifier which names `def` where it is declared, e.g., `foo` in
+    /// `fn foo() {}`.
+    fn def_ident(&self, def: Definition) -> Result<Identifier, Error> {
+        for id in self.idents_in(Range::Span(def.span.clone()))? {
+            // Other identifiers in the span may have no definition.
+            let declares = match self.definition(id.clone()) {
+                Ok(d) => d.same_as(&def),
+                Err(_) => false,
+            };
+            if id.name == def.name && declares {
+                return Ok(id);
+            }
+        }
+        Err(Error::Back(format!(
+            "no identifier declares `{}`",
+            def.name
+        )))
+    }
+    /// All items defined in `file`, in no particular order.
+    fn symbols_in_file(&self, _file: Path) -> Result<Vec<Symbol>, Error> {
+        Err(Error::NotImplemented("symbols_in_file"))
+    }
+    /// E.g., `Vec<String>`.
+    fn type_of(&self, _id: Identifier) -> Result<String, Error> {
+        Err(Error::NotImplemented("type_of"))
+    }
+    /// Compiler diagnostics whose primary span intersects `range`.
+    fn diagnostics(&self, _range: Range) -> Result<Vec<Diagnostic>, Error> {
+        Err(Error::NotImplemented("diagnostics"))
+    }
+    /// The direct supertraits of the trait `def`.
+    fn super_traits(&self, _def: Definition) -> Result<Vec<Definition>, Error> {
+        Err(Error::NotImplemented("super_traits"))
+    }
+    /// The traits which have the trait `def` as a direct supertrait.
+    fn sub_traits(&self, _def: Definition) -> Result<Vec<Definition>, Error> {
+        Err(Error::NotImplemented("sub_traits"))
+    }
+    /// The fields of the struct, union, or enum variant `def`.
+    fn fields(&self, _def: Definition) -> Result<Vec<Definition>, Error> {
+        Err(Error::NotImplemented("fields"))
+    }
+    /// Including those in trait impls.
+    fn methods(&self, _def: Definition) -> Result<Vec<Definition>, Error> {
+        Err(Error::NotImplemented("methods"))
+    }
+    /// Without comment markers.
+    fn docs(&self, _def: Definition) -> Result<String, Error> {
+        Err(Error::NotImplemented("docs"))
+    }
+}
+
+/// What a backend needs to start, from the configuration.
+pub struct Options<'a> {
+    pub fs: Rc<PhysicalFs>,
+    pub build_command: &'a [String],
+    /// Where the project is built, relative to the root.
+    pub target_dir: &'a StdPath,
+    /// Other directories where the project may have been built.
+    pub analysis_dirs: &'a [PathBuf],
+    /// Update the index when the backend starts.
+    pub index: bool,
+    pub verbosity: Verbosity,
+}
+
+/// A backend which can be chosen by name.
+pub struct Entry {
+    pub name: &'static str,
+    /// The version of the `Backend` trait which the backend implements.
+    pub version: u32,
+    /// The functionality the backend supports, known without starting it.
+    pub capabilities: Capabilities,
+    /// True if the backend understands more than the code's syntax.
+    pub semantic: bool,
+    pub init: fn(&Options) -> Rc<dyn Backend>,
+}
+
+/// The backends which can be used, by name.
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    /// A registry of the backends built into Clyde.
+    pub fn new() -> Registry {
+        let mut result = Registry {
+            entries: Vec::new(),
+        };
+        result
+            .register(Entry {
+                name: Rls::<PhysicalFs>::NAME,
+                version: BACKEND_VERSION,
+                capabilities: Rls::<PhysicalFs>::CAPABILITIES,
+                semantic: true,
+                init: |options| {
+                    Rc::new(Rls::init(
+                        options.fs.clone(),
+                        options.build_command,
+                        options.target_dir,
+                        options.analysis_dirs,
+                        options.index,
+                        options.verbosity,
+                    ))
+                },
+            })
+            .unwrap();
+        result
+    }
+
+    /// Add a backend, an error if it implements another version of the
+    /// `Backend` trait or its name is taken.
+    pub fn register(&mut self, entry: Entry) -> Result<(), String> {
+        if entry.version != BACKEND_VERSION {
+            return Err(format!(
+                "backend `{}` implements version {} of the backend interface, expected version {}",
+                entry.name, entry.version, BACKEND_VERSION
+            ));
+        }
+        if self.lookup(entry.name).is_some() {
+            return Err(format!(
+                "a backend named `{}` is already registered",
+                entry.name
+            ));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// The names of the registered backends, in the order they were registered.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|e| e.name).collect()
+    }
+
+    /// `choice` is one or more names separated by commas. Semantic backends are
+    /// tried first.
+    pub fn resolve(&self, choice: &str) -> Result<Vec<&Entry>, String> {
+        let mut result: Vec<&Entry> = Vec::new();
+        for name in choice.split(',').map(str::trim) {
+            let entry = self.lookup(name).ok_or_else(|| {
+                format!(
+                    "unknown backend `{}`, expected one of: {}",
+                    name,
+                    self.names().join(", ")
+                )
+            })?;
+            if result.iter().any(|e| e.name == name) {
+                return Err(format!("backend `{}` is given more than once", name));
+            }
+            result.push(entry);
+        }
+        // Stable, so the order given is kept otherwise.
+        result.sort_by_key(|e| !e.semantic);
+        Ok(result)
+    }
+
+    /// The functionality supported by the backends chosen by `choice`.
+    pub fn capabilities(&self, choice: &str) -> Result<Capabilities, String> {
+        Ok(self
+            .resolve(choice)?
+            .iter()
+            .fold(Capabilities::NONE, |caps, e| caps.union(e.capabilities)))
+    }
+
+    /// Start the backends chosen by `choice`, chained if there are several.
+    pub fn init(&self, choice: &str, options: &Options) -> Result<Rc<dyn Backend>, String> {
+        let mut backends: Vec<Rc<dyn Backend>> = self
+            .resolve(choice)?
+            .iter()
+            .map(|e| (e.init)(options))
+            .collect();
+        if backends.len() == 1 {
+            return Ok(backends.pop().unwrap());
+        }
+        Ok(Rc::new(ChainBackend::new(backends)))
+    }
+}
+
+/// A group of related functionality which a backend may support.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Capability {
+    /// `idents_at` and `idents_in`.
+    Idents,
+    /// `definition`.
+    Definitions,
+    /// `symbols_in_file`.
+    Symbols,
+    /// `diagnostics`.
+    Diagnostics,
+    /// `type_of`.
+    Types,
+    /// `super_traits` and `sub_traits`.
+    Traits,
+    /// `fields` and `methods`.
+    Members,
+    /// `docs`.
+    Docs,
+}
+
+impl Capability {
+    pub const ALL: &'static [Capability] = &[
+        Capability::Idents,
+        Capability::Definitions,
+        Capability::Symbols,
+        Capability::Diagnostics,
+        Capability::Types,
+        Capability::Traits,
+        Capability::Members,
+        Capability::Docs,
+    ];
+
+    /// The `Backend` methods which need this capability.
+    pub fn functions(self) -> &'static [&'static str] {
+        match self {
+            Capability::Idents => &["idents_at", "idents_in"],
+            Capability::Definitions => &["definition"],
+            Capability::Symbols => &["symbols_in_file"],
+            Capability::Diagnostics => &["diagnostics"],
+            Capability::Types => &["type_of"],
+            Capability::Traits => &["super_traits", "sub_traits"],
+            Capability::Members => &["fields", "methods"],
+            Capability::Docs => &["docs"],
+        }
+    }
+
+    const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Capability::Idents => write!(f, "identifiers"),
+            Capability::Definitions => write!(f, "definitions"),
+            Capability::Symbols => write!(f, "symbols"),
+            Capability::Diagnostics => write!(f, "diagnostics"),
+            Capability::Types => write!(f, "types"),
+            Capability::Traits => write!(f, "trait hierarchies"),
+            Capability::Members => write!(f, "members"),
+            Capability::Docs => write!(f, "documentation"),
+        }
+    }
+}
+
+/// A set of capabilities.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Capabilities {
+    bits: u32,
+}
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities { bits: 0 };
+
+    pub const fn with(self, c: Capability) -> Capabilities {
+        Capabilities {
+            bits: self.bits | c.bit(),
+        }
+    }
+
+    pub fn contains(self, c: Capability) -> bool {
+        self.bits & c.bit() != 0
+    }
+
+    /// The capabilities in either `self` or `other`.
+    pub const fn union(self, other: Capabilities) -> Capabilities {
+        Capabilities {
+            bits: self.bits | other.bits,
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for c in Capability::ALL.iter().filter(|c| self.contains(**c)) {
+            if first {
+                first = false;
+            } else {
+                write!(f, ", ")?;
+            }
+            c.fmt(f)?;
+        }
+        if first {
+            write!(f, "none")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports how much of a long operation is done, from any thread.
+pub trait Progress: Sync {
+    fn update(&self, done: usize, total: usize);
+}
+
+/// Progress which is not reported.
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn update(&self, _: usize, _: usize) {}
+}
+
+/// An item defined in a file.
+#[derive(Clone)]
+pub struct Symbol {
+    pub def: Definition,
+    /// The id of the definition of the enclosing item, if there is one.
+    pub parent: Option<u64>,
+}
+
+/// The backend which answered a question (named by the `Backend` method), when
+/// it was not the first choice.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Provenance {
+    pub function: &'static str,
+    pub backend: &'static str,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` from the `{}` backend", self.function, self.backend)
+    }
 }
 
 pub enum Error {
     NotImplemented(&'static str),
+    /// There is no previous index to fall back to.
+    IndexFailed(String),
     Back(String),
 }
 
@@ -29,6 +417,7 @@ impl fmt::Display for Error {
             Error::NotImplemented(s) => {
                 write!(f, "Function not implemented by current backend: `{}`", s)
             }
+            Error::IndexFailed(s) => write!(f, "Indexing failed: {}", s),
             Error::Back(s) => s.fmt(f),
         }
     }
@@ -39,3 +428,75 @@ impl From<file_system::Error> for Error {
         Error::Back(format!("file system error: {}", e))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let caps = Capabilities::NONE
+            .with(Capability::Idents)
+            .with(Capability::Diagnostics);
+        assert!(caps.contains(Capability::Idents));
+        assert!(!caps.contains(Capability::Definitions));
+        assert!(!caps.contains(Capability::Symbols));
+        assert!(caps.contains(Capability::Diagnostics));
+        assert_eq!(caps.to_string(), "identifiers, diagnostics");
+        assert_eq!(Capabilities::NONE.to_string(), "none");
+        assert_eq!(
+            caps.union(Capabilities::NONE.with(Capability::Docs))
+                .to_string(),
+            "identifiers, diagnostics, documentation"
+        );
+    }
+
+    fn entry(name: &'static str, semantic: bool) -> Entry {
+        Entry {
+            name,
+            version: BACKEND_VERSION,
+            capabilities: Capabilities::NONE.with(Capability::Idents),
+            semantic,
+            init: |_| Rc::new(MockBackend::new()),
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::new();
+        assert_eq!(registry.names(), vec!["rls"]);
+        registry.register(entry("ctags", false)).unwrap();
+        registry.register(entry("treesitter", false)).unwrap();
+        assert_eq!(
+            registry.register(entry("ctags", false)).unwrap_err(),
+            "a backend named `ctags` is already registered"
+        );
+        let old = Entry {
+            version: BACKEND_VERSION - 1,
+            ..entry("old", true)
+        };
+        assert!(registry
+            .register(old)
+            .unwrap_err()
+            .starts_with("backend `old` implements version 0"));
+
+        let names = |choice| -> Result<Vec<&str>, String> {
+            Ok(registry.resolve(choice)?.iter().map(|e| e.name).collect())
+        };
+        assert_eq!(names("rls").unwrap(), vec!["rls"]);
+        // Semantic backends first, otherwise in the order given.
+        assert_eq!(
+            names("treesitter, ctags,rls").unwrap(),
+            vec!["rls", "treesitter", "ctags"]
+        );
+        assert_eq!(
+            names("foo").unwrap_err(),
+            "unknown backend `foo`, expected one of: rls, ctags, treesitter"
+        );
+        assert!(names("ctags,ctags").is_err());
+        assert_eq!(
+            registry.capabilities("ctags").unwrap(),
+            Capabilities::NONE.with(Capability::Idents)
+        );
+    }
+}
@@ -1,142 +1,1142 @@
-use super::{Backend, Error};
-use crate::file_system::{FileSystem, PhysicalFs};
-use crate::front::data::{Definition, Identifier, Position, Range, Span};
+use super::{Backend, Capabilities, Capability, Error, NoProgress, Progress, Symbol};
+use crate::env::Verbosity;
+use crate::file_system::{FileSystem, Path, PhysicalFs};
+use crate::front::data::{
+    DefKind, DefPath, Definition, Diagnostic, IdentKind, Identifier, Line, Position, Range,
+    Severity, Span,
+};
+use crate::front::syntax::{self, Item};
 
-use rls_analysis::{AnalysisHost, Id, Ident, Span as RlsSpan, Target};
-use rls_span::{Column, Row};
+use rls_analysis::{
+    AResult, AnalysisHost, AnalysisLoader, CargoAnalysisLoader, DefKind as RlsDefKind, Id, Ident,
+    SearchDirectory, Span as RlsSpan, Target,
+};
+use rls_span::{Column as RlsColumn, Row};
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
 use std::mem;
+use std::path::{Path as StdPath, PathBuf};
 use std::process::Command;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
 
-// FIXME use `join` not `/`
+// Relative to the project root, the default in the config.
 const TARGET_DIR: &str = "target/rls";
+// Relative to the target directory.
+const BUILD_LOG: &str = "build.log";
+// The end column of spans which cover whole lines.
+const LINE_END: u32 = u16::MAX as u32;
+// The most threads used to query the analysis data for many files at once.
+const MAX_THREADS: usize = 8;
 
 pub struct Rls<Fs: FileSystem> {
-    analysis_host: AnalysisHost,
+    analysis_host: AnalysisHost<TargetDirLoader>,
     fs: Rc<Fs>,
+    // The root of the project.
+    root: PathBuf,
+    // Where the project is built and the analysis data is written.
+    target_dir: PathBuf,
+    // Where the analysis data is loaded from, shared with the loader.
+    data_dir: Arc<Mutex<PathBuf>>,
+    // Diagnostics emitted by the compiler during the last reindex.
+    diagnostics: RefCell<Vec<CompilerDiagnostic>>,
+    // If a later reload fails, we keep using the previous data.
+    indexed: Cell<bool>,
+    // Why the last reload failed, if it did.
+    index_error: RefCell<Option<String>>,
+    verbosity: Verbosity,
+}
+
+impl<Fs: FileSystem> Rls<Fs> {
+    pub const NAME: &'static str = "rls";
+    pub const CAPABILITIES: Capabilities = Capabilities::NONE
+        .with(Capability::Idents)
+        .with(Capability::Definitions)
+        .with(Capability::Symbols)
+        .with(Capability::Diagnostics)
+        .with(Capability::Types)
+        .with(Capability::Traits)
+        .with(Capability::Members)
+        .with(Capability::Docs);
 }
 
 impl Rls<PhysicalFs> {
-    pub fn init(fs: Rc<PhysicalFs>) -> Rls<PhysicalFs> {
-        let analysis_host = AnalysisHost::new(Target::Debug);
-        println!("building index");
-        Self::reindex();
-        println!("loading analysis...");
+    /// If `target_dir` or one of `other_dirs` has analysis data which is newer
+    /// than the project's sources, that is loaded instead of building.
+    pub fn init(
+        fs: Rc<PhysicalFs>,
+        build_command: &[String],
+        target_dir: &StdPath,
+        other_dirs: &[PathBuf],
+        build: bool,
+        verbosity: Verbosity,
+    ) -> Rls<PhysicalFs> {
+        let target_dir = fs.root.join(target_dir);
+        let data_dir = Arc::new(Mutex::new(target_dir.clone()));
+        let rls = Rls {
+            analysis_host: AnalysisHost::new_with_loader(TargetDirLoader::new(data_dir.clone())),
+            root: fs.root.clone(),
+            fs,
+            target_dir,
+            data_dir,
+            diagnostics: RefCell::new(Vec::new()),
+            indexed: Cell::new(false),
+            index_error: RefCell::new(None),
+            verbosity,
+        };
+        let mut candidates = vec![rls.target_dir.clone()];
+        for dir in other_dirs {
+            let dir = rls.fs.root.join(dir);
+            if !candidates.contains(&dir) {
+                candidates.push(dir);
+            }
+        }
+        let result = match current_analysis(&rls.fs.root, &candidates) {
+            Some(dir) if build => {
+                log::info!("analysis data in {} is up to date", dir.display());
+                if rls.verbosity == Verbosity::Verbose {
+                    eprintln!("using existing analysis data in {}", dir.display());
+                }
+                rls.record_result(rls.load_from(&dir))
+            }
+            None if build => rls.reload(build_command),
+            _ => rls.record_result(rls.load_from(&rls.target_dir)),
+        };
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+        }
+        rls
+    }
+}
+
+impl<Fs: FileSystem> Rls<Fs> {
+    fn record_result(&self, result: Result<(), Error>) -> Result<(), Error> {
+        if let Err(e) = &result {
+            log::warn!("indexing failed: {}", e);
+        }
+        *self.index_error.borrow_mut() = match &result {
+            Err(Error::IndexFailed(msg)) => Some(msg.clone()),
+            Err(e) => Some(e.to_string()),
+            Ok(()) => None,
+        };
+        result
+    }
+
+    fn load_from(&self, target_dir: &StdPath) -> Result<(), Error> {
+        self.progress("loading analysis...");
+        log::info!("loading analysis data from {}", target_dir.display());
+        let moved = {
+            let mut data_dir = self.data_dir.lock().unwrap();
+            let moved = *data_dir != target_dir;
+            *data_dir = target_dir.to_owned();
+            moved
+        };
         // TODO use blacklist
-        analysis_host.reload(&fs.root, &fs.root).unwrap();
-        Rls { analysis_host, fs }
+        let result = if moved {
+            // Don't keep any data from the previous directory.
+            self.analysis_host.hard_reload(&self.root, &self.root)
+        } else {
+            self.analysis_host.reload(&self.root, &self.root)
+        };
+        result.map_err(|e| Error::IndexFailed(format!("could not load analysis data: {}", e)))?;
+        self.indexed.set(true);
+        Ok(())
     }
 
-    fn reindex() {
-        // FIXME redirect stdout to a log file
-        // FIXME set the base directory according to the root of the fs
-        let mut cmd = Command::new("cargo");
-        cmd.arg("check");
+    fn reindex(&self, build_command: &[String]) -> Result<(), Error> {
+        self.progress("building index");
+        log::info!(
+            "running `{}` in {}",
+            build_command.join(" "),
+            self.root.display()
+        );
+        if self.verbosity == Verbosity::Verbose {
+            eprintln!(
+                "running `{}` in {}",
+                build_command.join(" "),
+                self.root.display()
+            );
+        }
+        let mut cmd = Command::new(&build_command[0]);
+        cmd.current_dir(&self.root);
+        cmd.args(&build_command[1..]);
+        // Diagnostics are printed to stdout as JSON, one message per line.
+        cmd.arg("--message-format=json");
         // FIXME configure save-analysis
         cmd.env("RUSTFLAGS", "-Zunstable-options -Zsave-analysis");
-        cmd.env("CARGO_TARGET_DIR", TARGET_DIR);
+        cmd.env("CARGO_TARGET_DIR", &self.target_dir);
+
+        let output = cmd.output().map_err(|e| {
+            Error::IndexFailed(format!("could not run `{}`: {}", build_command[0], e))
+        })?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let messages: Vec<CargoMessage> = stdout
+            .lines()
+            .filter_map(|line| serde_json::from_str::<CargoMessage>(line).ok())
+            .collect();
+        // Cargo reports every crate, including those which were up to date.
+        let mut current: HashMap<String, HashSet<String>> = HashMap::new();
+        for (name, hash) in messages
+            .iter()
+            .flat_map(|m| &m.filenames)
+            .filter_map(|f| crate_hash(f))
+        {
+            current.entry(name).or_default().insert(hash);
+        }
 
-        let status = cmd.status().expect("Running build failed");
-        // FIXME handle an error instead of unwrapping
-        status.code().unwrap();
-        // FIXME cleanup analysis (see cargo src)
+        let mut log = String::from_utf8_lossy(&output.stderr).into_owned();
+        for rendered in messages
+            .iter()
+            .filter_map(|m| m.message.as_ref()?.rendered.as_ref())
+        {
+            log.push_str(rendered);
+        }
+        let log_path = self.target_dir.join(BUILD_LOG);
+        let logged = fs::create_dir_all(&self.target_dir)
+            .and_then(|_| fs::write(&log_path, log))
+            .is_ok();
+
+        *self.diagnostics.borrow_mut() = messages
+            .into_iter()
+            .filter_map(|msg| CompilerDiagnostic::new(msg, &self.root))
+            .collect();
+
+        log::info!(
+            "build finished ({}), {} diagnostics",
+            output.status,
+            self.diagnostics.borrow().len()
+        );
+        if output.status.success() {
+            let dir = analysis_dir(&self.target_dir);
+            if let Err(e) = prune_analysis(&dir, &current) {
+                log::warn!("could not remove stale analysis data: {}", e);
+            }
+            return Ok(());
+        }
+        Err(Error::IndexFailed(build_failure(
+            &build_command.join(" "),
+            output.status.code(),
+            &self.diagnostics.borrow(),
+            if logged { Some(&log_path) } else { None },
+        )))
     }
 }
 
-impl<Fs: FileSystem> Backend for Rls<Fs> {
-    fn ident_at(&self, position: Position) -> Result<Option<Identifier>, Error> {
-        let idents = self.analysis_host.idents(&position.into_with(&*self.fs)?)?;
-        Ok(match idents.into_iter().next() {
-            Some(i) => Some(i.into_with(&*self.fs)?),
-            None => None,
+impl<Fs: FileSystem> Rls<Fs> {
+    fn progress(&self, msg: &str) {
+        if self.verbosity != Verbosity::Quiet {
+            eprintln!("{}", msg);
+        }
+    }
+
+    // Fail rather than return empty results if there is no data.
+    fn check_index(&self) -> Result<(), Error> {
+        if self.indexed.get() {
+            return Ok(());
+        }
+        Err(Error::IndexFailed(
+            self.index_error
+                .borrow()
+                .clone()
+                .unwrap_or_else(|| "no index".to_owned()),
+        ))
+    }
+
+    // The analysis data is indexed by file, so split `range` into files.
+    fn spans(&self, range: Range) -> Result<Vec<RlsSpan>, Error> {
+        match range {
+            Range::MultiFile(paths) => paths
+                .into_iter()
+                .map(|p| Range::File(p).into_with(&*self.fs))
+                .collect(),
+            r => Ok(vec![r.into_with(&*self.fs)?]),
+        }
+    }
+
+    // Call `f` for each of `spans` in parallel, keeping their order.
+    fn query_spans<T: Send>(
+        &self,
+        spans: &[RlsSpan],
+        progress: &dyn Progress,
+        f: impl Fn(&AnalysisHost<TargetDirLoader>, &RlsSpan) -> AResult<Vec<T>> + Sync,
+    ) -> Result<Vec<Vec<T>>, Error> {
+        let done = AtomicUsize::new(0);
+        let f = |host: &AnalysisHost<TargetDirLoader>, span: &RlsSpan| {
+            let result = f(host, span);
+            progress.update(done.fetch_add(1, Ordering::Relaxed) + 1, spans.len());
+            result
+        };
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(MAX_THREADS)
+            .min(spans.len());
+        if threads <= 1 {
+            return Ok(spans
+                .iter()
+                .map(|s| f(&self.analysis_host, s))
+                .collect::<AResult<_>>()?);
+        }
+
+        let host = &self.analysis_host;
+        let f = &f;
+        let chunk_len = spans.len().div_ceil(threads);
+        let results: Vec<AResult<Vec<Vec<T>>>> = thread::scope(|scope| {
+            let handles: Vec<_> = spans
+                .chunks(chunk_len)
+                .map(|chunk| scope.spawn(move || chunk.iter().map(|s| f(host, s)).collect()))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("analysis thread panicked"))
+                .collect()
+        });
+        let mut merged = Vec::with_capacity(spans.len());
+        for r in results {
+            merged.extend(r?);
+        }
+        Ok(merged)
+    }
+
+    fn trait_bounds(&self, def: &Definition) -> Result<Vec<Span>, Error> {
+        let (first_line, lines) = self.fs.signature(&def.span)?;
+        Ok(bound_spans(
+            def.span.file,
+            &lines,
+            first_line.zero_indexed(),
+            (
+                def.span.end_line.zero_indexed(),
+                def.span.end_column.zero_indexed(),
+            ),
+        )
+        .into_iter()
+        .map(|s| s.with_offsets(&*self.fs))
+        .collect())
+    }
+
+    fn child_defs(&self, def: &Definition, kind: DefKind) -> Result<Vec<Definition>, Error> {
+        self.check_index()?;
+        let children = self
+            .analysis_host
+            .for_each_child_def(Id::new(def.id), |id, d| (id, d.clone()))?;
+        let mut result = Vec::new();
+        for (id, d) in children {
+            if def_kind(d.kind) != kind {
+                continue;
+            }
+            result.push(Definition {
+                id: unsafe { mem::transmute::<Id, u64>(id) },
+                path: self.def_path(id, &d.qualname),
+                name: d.name,
+                span: d.span.into_with(&*self.fs)?,
+                kind,
+            });
+        }
+        Ok(result)
+    }
+
+    // The crate is the one whose root module is the def's outermost parent.
+    fn def_path(&self, id: Id, qualname: &str) -> Option<DefPath> {
+        if qualname.is_empty() {
+            return None;
+        }
+        let root = match self.analysis_host.def_parents(id).ok()?.first() {
+            Some((root, _)) => *root,
+            None => id,
+        };
+        let (_, krate) = self
+            .analysis_host
+            .def_roots()
+            .ok()?
+            .into_iter()
+            .find(|(r, _)| *r == root)?;
+        Some(DefPath {
+            krate,
+            path: qualname.trim_start_matches("::").to_owned(),
         })
     }
 
+    // A method's parent is an impl, but the data doesn't say which, so the
+    // methods are matched to impls by the impl blocks in the source.
+    fn impl_methods(&self, def: &Definition) -> Result<Vec<Definition>, Error> {
+        self.check_index()?;
+        let impls: Vec<Span> = self
+            .analysis_host
+            .find_impls(Id::new(def.id))?
+            .into_iter()
+            .filter_map(|s| s.into_with(&*self.fs).ok())
+            .collect();
+        let mut files: Vec<Path> = Vec::new();
+        for i in &impls {
+            if !files.contains(&i.file) {
+                files.push(i.file);
+            }
+        }
+
+        let mut result: Vec<Definition> = Vec::new();
+        for file in files {
+            let symbols = self.symbols_in_file(file)?;
+            // Methods whose parent is not an item, i.e., is an impl, by parent.
+            let mut groups: Vec<(u64, Vec<Definition>)> = Vec::new();
+            for s in &symbols {
+                let parent = match s.parent {
+                    Some(p) if s.def.kind == DefKind::Method => p,
+                    _ => continue,
+                };
+                if symbols.iter().any(|s| s.def.id == parent) {
+                    continue;
+                }
+                match groups.iter_mut().find(|(p, _)| *p == parent) {
+                    Some((_, methods)) => methods.push(s.def.clone()),
+                    None => groups.push((parent, vec![s.def.clone()])),
+                }
+            }
+            let mut groups: Vec<Vec<Definition>> = groups.into_iter().map(|(_, g)| g).collect();
+            for methods in &mut groups {
+                methods.sort_by_key(|d| (d.span.start_line, d.span.start_column));
+            }
+            let blocks = self
+                .fs
+                .with_file(file, |f| Ok(syntax::find_in_file(f, Item::Impl)))?;
+
+            for i in impls.iter().filter(|i| i.file == file) {
+                for m in methods_of_impl(i, &groups, &blocks) {
+                    if !result.iter().any(|d| d.id == m.id) {
+                        result.push(m.clone());
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn identifier(&self, ident: Ident) -> Result<Identifier, Error> {
+        let def = ident.id;
+        let mut result: Identifier = ident.into_with(&*self.fs)?;
+        result.kind = self
+            .analysis_host
+            .get_def(def)
+            .ok()
+            .and_then(|d| IdentKind::of(def_kind(d.kind)));
+        Ok(result)
+    }
+
+    fn idents_in_ranges(
+        &self,
+        ranges: &[Range],
+        progress: &dyn Progress,
+    ) -> Result<Vec<Vec<Identifier>>, Error> {
+        self.check_index()?;
+        // The number of spans for each range.
+        let mut counts = Vec::with_capacity(ranges.len());
+        let mut spans = Vec::new();
+        for r in ranges {
+            let s = self.spans(r.clone())?;
+            counts.push(s.len());
+            spans.extend(s);
+        }
+
+        let mut idents = self
+            .query_spans(&spans, progress, |host, span| host.idents(span))?
+            .into_iter();
+        counts
+            .into_iter()
+            .map(|count| {
+                idents
+                    .by_ref()
+                    .take(count)
+                    .flatten()
+                    .map(|i| self.identifier(i))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+// The groups of methods (each with the same parent) in the impl block whose
+// header is at `header`, `blocks` are the impl blocks of the file.
+fn methods_of_impl<'a>(
+    header: &Span,
+    groups: &'a [Vec<Definition>],
+    blocks: &'a [Span],
+) -> impl Iterator<Item = &'a Definition> {
+    let block = innermost_block(blocks, header);
+    groups
+        .iter()
+        .filter(move |methods| {
+            block.is_some()
+                && methods
+                    .iter()
+                    .all(|m| innermost_block(blocks, &m.span) == block)
+        })
+        .flatten()
+}
+
+fn innermost_block<'a>(blocks: &'a [Span], span: &Span) -> Option<&'a Span> {
+    let start = (span.start_line, span.start_column);
+    blocks
+        .iter()
+        .filter(|b| {
+            b.file == span.file
+                && (b.start_line, b.start_column) <= start
+                && start < (b.end_line, b.end_column)
+        })
+        .min_by_key(|b| (b.end_line, b.end_column))
+}
+
+// E.g., `Bar + Baz` in `trait Foo<T: Qux>: Bar + Baz<T> where T: Copy {`.
+fn bound_spans(
+    file: Path,
+    lines: &[String],
+    first_line: usize,
+    name_end: (usize, usize),
+) -> Vec<Span> {
+    let mut result = Vec::new();
+    let mut in_bounds = false;
+    // The nesting of brackets (including generics).
+    let mut depth = 0usize;
+    for (i, text) in lines.iter().enumerate() {
+        let line = first_line + i;
+        if line < name_end.0 {
+            continue;
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let at_where = |col: usize| {
+            let word: String = chars[col..].iter().take(6).collect();
+            word.starts_with("where")
+                && word.chars().nth(5).is_none_or(|c| !c.is_alphanumeric())
+                && (col == 0 || chars[col - 1].is_whitespace())
+        };
+        // The start column of the current part of the bounds.
+        let mut part_start = None;
+        let end_part = |result: &mut Vec<Span>, part_start: &mut Option<usize>, col| {
+            if let Some(start) = part_start.take() {
+                result.push(Span::new_zero_indexed(file, line, start, line, col));
+            }
+        };
+        let first_col = if line == name_end.0 { name_end.1 } else { 0 };
+        for col in first_col..chars.len() {
+            let prev = if col > 0 { chars[col - 1] } else { ' ' };
+            let next = chars.get(col + 1).cloned().unwrap_or(' ');
+            match chars[col] {
+                '<' | '(' | '[' => {
+                    end_part(&mut result, &mut part_start, col);
+                    depth += 1;
+                }
+                // `->` does not close a bracket.
+                '>' if prev == '-' => {}
+                '>' | ')' | ']' => depth = depth.saturating_sub(1),
+                '{' | ';' if depth == 0 => {
+                    end_part(&mut result, &mut part_start, col);
+                    return result;
+                }
+                'w' if depth == 0 && at_where(col) => {
+                    end_part(&mut result, &mut part_start, col);
+                    return result;
+                }
+                ':' if depth == 0 && !in_bounds && prev != ':' && next != ':' => in_bounds = true,
+                c if depth == 0 && in_bounds && part_start.is_none() && !c.is_whitespace() => {
+                    part_start = Some(col)
+                }
+                _ => {}
+            }
+        }
+        end_part(&mut result, &mut part_start, chars.len());
+    }
+    result
+}
+
+// Loads analysis data from `Rls::data_dir` rather than `target/rls`.
+struct TargetDirLoader {
+    cargo: CargoAnalysisLoader,
+    // Shared with `Rls::data_dir`.
+    target_dir: Arc<Mutex<PathBuf>>,
+    path_prefix: Option<PathBuf>,
+}
+
+impl TargetDirLoader {
+    fn new(target_dir: Arc<Mutex<PathBuf>>) -> TargetDirLoader {
+        TargetDirLoader {
+            cargo: CargoAnalysisLoader::new(Target::Debug),
+            target_dir,
+            path_prefix: None,
+        }
+    }
+}
+
+// `CargoAnalysisLoader` is not `Clone`.
+impl Clone for TargetDirLoader {
+    fn clone(&self) -> TargetDirLoader {
+        TargetDirLoader {
+            cargo: CargoAnalysisLoader {
+                path_prefix: self.cargo.path_prefix.clone(),
+                target: self.cargo.target,
+            },
+            target_dir: self.target_dir.clone(),
+            path_prefix: self.path_prefix.clone(),
+        }
+    }
+}
+
+impl AnalysisLoader for TargetDirLoader {
+    fn needs_hard_reload(&self, path_prefix: &StdPath) -> bool {
+        self.cargo.needs_hard_reload(path_prefix)
+    }
+
+    fn fresh_host(&self) -> AnalysisHost<Self> {
+        AnalysisHost::new_with_loader(self.clone())
+    }
+
+    fn set_path_prefix(&mut self, path_prefix: &StdPath) {
+        self.cargo.set_path_prefix(path_prefix);
+        self.path_prefix = Some(path_prefix.to_owned());
+    }
+
+    fn abs_path_prefix(&self) -> Option<PathBuf> {
+        self.cargo.abs_path_prefix()
+    }
+
+    fn search_directories(&self) -> Vec<SearchDirectory> {
+        let default = self
+            .path_prefix
+            .as_ref()
+            .map(|p| analysis_dir(&p.join(TARGET_DIR)));
+        let mut result: Vec<_> = self
+            .cargo
+            .search_directories()
+            .into_iter()
+            .filter(|d| Some(&d.path) != default.as_ref())
+            .collect();
+        result.push(SearchDirectory {
+            path: analysis_dir(&self.target_dir.lock().unwrap()),
+            prefix_rewrite: None,
+        });
+        result
+    }
+}
+
+fn analysis_dir(target_dir: &StdPath) -> PathBuf {
+    target_dir.join("debug").join("deps").join("save-analysis")
+}
+
+// The first of `target_dirs` with analysis data at least as new as the sources.
+fn current_analysis(root: &StdPath, target_dirs: &[PathBuf]) -> Option<PathBuf> {
+    let sources = match newest_file(root, &mut |path| {
+        path.extension().is_some_and(|e| e == "rs")
+            || path.ends_with("Cargo.toml")
+            || path.ends_with("Cargo.lock")
+    }) {
+        Ok(Some(time)) => time,
+        // Without any sources, there is nothing for the data to be current with.
+        Ok(None) => return None,
+        Err(e) => {
+            log::warn!("could not check the age of source files: {}", e);
+            return None;
+        }
+    };
+    target_dirs
+        .iter()
+        .find(|dir| {
+            let data = newest_file(&analysis_dir(dir), &mut |path| {
+                path.extension().is_some_and(|e| e == "json")
+            });
+            match data {
+                Ok(Some(time)) => time >= sources,
+                _ => false,
+            }
+        })
+        .cloned()
+}
+
+// Hidden and target directories are skipped.
+fn newest_file(
+    dir: &StdPath,
+    include: &mut dyn FnMut(&StdPath) -> bool,
+) -> io::Result<Option<SystemTime>> {
+    let mut result = None;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        let time = if file_type.is_dir() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') || name == "target" {
+                continue;
+            }
+            newest_file(&path, include)?
+        } else if file_type.is_file() && include(&path) {
+            Some(entry.metadata()?.modified()?)
+        } else {
+            None
+        };
+        result = result.max(time);
+    }
+    Ok(result)
+}
+
+// E.g., `foo` and `1a2b3c` for `target/rls/debug/deps/libfoo-1a2b3c.rmeta`.
+fn crate_hash(path: &StdPath) -> Option<(String, String)> {
+    if path.parent()?.file_name()? != "deps" {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let (name, hash) = stem.rsplit_once('-')?;
+    let name = name.strip_prefix("lib").unwrap_or(name);
+    Some((name.to_owned(), hash.to_owned()))
+}
+
+// The compiler leaves old analysis data behind when a crate's hash changes.
+fn prune_analysis(dir: &StdPath, current: &HashMap<String, HashSet<String>>) -> io::Result<()> {
+    // Without the hashes, we can't tell which data is stale.
+    if current.is_empty() {
+        return Ok(());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        files.push(entry?.path());
+    }
+    for path in stale_analysis(files, current) {
+        log::info!("removing stale analysis data {}", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// Files for crates we know nothing about are kept.
+fn stale_analysis(files: Vec<PathBuf>, current: &HashMap<String, HashSet<String>>) -> Vec<PathBuf> {
+    files
+        .into_iter()
+        .filter(|f| f.extension().is_some_and(|e| e == "json"))
+        .filter(|f| {
+            let stem = match f.file_stem().and_then(|s| s.to_str()) {
+                Some(stem) => stem,
+                None => return false,
+            };
+            let (name, hash) = match stem.rsplit_once('-') {
+                Some(parts) => parts,
+                None => return false,
+            };
+            let name = name.strip_prefix("lib").unwrap_or(name);
+            matches!(current.get(name), Some(hashes) if !hashes.contains(hash))
+        })
+        .collect()
+}
+
+fn build_failure(
+    command: &str,
+    exit_code: Option<i32>,
+    diagnostics: &[CompilerDiagnostic],
+    log_path: Option<&PathBuf>,
+) -> String {
+    let mut result = match exit_code {
+        Some(code) => format!("`{}` failed (exit code {})", command, code),
+        None => format!("`{}` was terminated", command),
+    };
+    let mut errors = diagnostics.iter().filter(|d| d.severity == Severity::Error);
+    if let Some(first) = errors.next() {
+        let count = errors.count() + 1;
+        result.push_str(&format!(
+            " with {} error{}, first: {} ({}:{})",
+            count,
+            if count == 1 { "" } else { "s" },
+            first.message,
+            first.file.display(),
+            first.span.line_start
+        ));
+    }
+    if let Some(path) = log_path {
+        result.push_str(&format!("; see {} for the full output", path.display()));
+    }
+    result
+}
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<RustcDiagnostic>,
+    // Only for `compiler-artifact` messages.
+    #[serde(default)]
+    filenames: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct RustcDiagnostic {
+    message: String,
+    level: String,
+    spans: Vec<RustcSpan>,
+    // The diagnostic as the compiler would print it.
+    rendered: Option<String>,
+}
+
+// Lines and columns are one-indexed.
+#[derive(Deserialize)]
+struct RustcSpan {
+    file_name: PathBuf,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    column_end: usize,
+    is_primary: bool,
+}
+
+// A diagnostic which we have not yet mapped into the file system.
+struct CompilerDiagnostic {
+    severity: Severity,
+    message: String,
+    file: PathBuf,
+    span: RustcSpan,
+}
+
+impl CompilerDiagnostic {
+    fn new(msg: CargoMessage, root: &StdPath) -> Option<CompilerDiagnostic> {
+        if msg.reason != "compiler-message" {
+            return None;
+        }
+        let diagnostic = msg.message?;
+        let severity = match &*diagnostic.level {
+            "warning" => Severity::Warning,
+            "note" => Severity::Note,
+            "help" => Severity::Help,
+            l if l.starts_with("error") => Severity::Error,
+            _ => return None,
+        };
+        let span = diagnostic.spans.into_iter().find(|s| s.is_primary)?;
+        Some(CompilerDiagnostic {
+            severity,
+            message: diagnostic.message,
+            file: root.join(&span.file_name),
+            span,
+        })
+    }
+}
+
+impl<Fs: FileSystem> Backend for Rls<Fs> {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Self::CAPABILITIES
+    }
+
+    fn reload(&self, build_command: &[String]) -> Result<(), Error> {
+        let result = self
+            .reindex(build_command)
+            .and_then(|_| self.load_from(&self.target_dir));
+        self.record_result(result)
+    }
+
+    fn index_failed(&self) -> bool {
+        self.index_error.borrow().is_some()
+    }
+
+    fn idents_at(&self, position: Position) -> Result<Vec<Identifier>, Error> {
+        // The index only finds identifiers which intersect a span, so look up
+        // the whole line and keep the identifiers which cover the position.
+        let idents = self.idents_in(Range::Line(position.file, position.line))?;
+        Ok(idents
+            .into_iter()
+            .filter(|i| i.span.contains(&position))
+            .collect())
+    }
+
     fn idents_in(&self, range: Range) -> Result<Vec<Identifier>, Error> {
-        let idents = self.analysis_host.idents(&range.into_with(&*self.fs)?)?;
-        idents.into_iter().map(|i| i.into_with(&*self.fs)).collect()
+        Ok(self.idents_in_ranges(&[range], &NoProgress)?.pop().unwrap())
+    }
+
+    fn idents_in_many(
+        &self,
+        ranges: &[Range],
+        progress: &dyn Progress,
+    ) -> Result<Vec<Vec<Identifier>>, Error> {
+        self.idents_in_ranges(ranges, progress)
     }
 
     fn definition(&self, id: Identifier) -> Result<Definition, Error> {
+        self.check_index()?;
         let def = self.analysis_host.get_def(Id::new(id.id))?;
         Ok(Definition {
             id: id.id,
+            path: self.def_path(Id::new(id.id), &def.qualname),
             name: def.name,
             span: def.span.into_with(&*self.fs)?,
+            kind: def_kind(def.kind),
         })
     }
-}
 
-trait IntoWithFs<T, Fs: FileSystem> {
-    fn into_with(self, fs: &Fs) -> Result<T, Error>;
+    fn symbols_in_file(&self, file: Path) -> Result<Vec<Symbol>, Error> {
+        self.check_index()?;
+        let symbols = self.analysis_host.symbols(&self.fs.physical_path(&file)?)?;
+        symbols
+            .into_iter()
+            .map(|s| {
+                Ok(Symbol {
+                    def: Definition {
+                        id: unsafe { mem::transmute::<Id, u64>(s.id) },
+                        name: s.name,
+                        span: s.span.into_with(&*self.fs)?,
+                        kind: def_kind(s.kind),
+                        // Symbols don't have qualified names, and looking up
+                        // each one would be slow for a large file.
+                        path: None,
+                    },
+                    parent: s.parent.map(|p| unsafe { mem::transmute::<Id, u64>(p) }),
+                })
+            })
+            .collect()
+    }
+
+    fn type_of(&self, id: Identifier) -> Result<String, Error> {
+        self.check_index()?;
+        Ok(self
+            .analysis_host
+            .show_type(&id.span.into_with(&*self.fs)?)?)
+    }
+
+    fn diagnostics(&self, range: Range) -> Result<Vec<Diagnostic>, Error> {
+        Ok(self
+            .diagnostics
+            .borrow()
+            .iter()
+            .filter_map(|d| {
+                // Skip diagnostics in files which no longer exist.
+                let file = self.fs.resolve_path(&d.file).ok()?;
+                // The compiler's lines and columns are one-indexed.
+                let start = (
+                    d.span.line_start.checked_sub(1)?,
+                    d.span.column_start.checked_sub(1)?,
+                );
+                let end = (
+                    d.span.line_end.checked_sub(1)?,
+                    d.span.column_end.checked_sub(1)?,
+                );
+                Some(Diagnostic {
+                    severity: d.severity,
+                    message: d.message.clone(),
+                    span: byte_span(&*self.fs, file, start, end),
+                })
+            })
+            .filter(|d| d.span.intersects(&range))
+            .collect())
+    }
+
+    fn super_traits(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        self.check_index()?;
+        let bounds: Vec<Range> = self
+            .trait_bounds(&def)?
+            .into_iter()
+            .map(Range::Span)
+            .collect();
+        let mut result: Vec<Definition> = Vec::new();
+        for id in self
+            .idents_in_ranges(&bounds, &NoProgress)?
+            .into_iter()
+            .flatten()
+        {
+            // Traits from other crates may not have definitions in the index.
+            if let Ok(sup) = self.definition(id) {
+                if sup.kind == DefKind::Trait && !result.iter().any(|d| d.id == sup.id) {
+                    result.push(sup);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn sub_traits(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        self.check_index()?;
+        // References to the trait which are in the bounds of another trait.
+        let refs: Vec<Span> = self
+            .analysis_host
+            .find_all_refs_by_id(Id::new(def.id))?
+            .into_iter()
+            .filter_map(|s| s.into_with(&*self.fs).ok())
+            .collect();
+        let mut files: Vec<Path> = Vec::new();
+        for r in &refs {
+            if !files.contains(&r.file) {
+                files.push(r.file);
+            }
+        }
+
+        let mut result: Vec<Definition> = Vec::new();
+        for file in files {
+            for symbol in self.symbols_in_file(file)? {
+                let sub = symbol.def;
+                if sub.kind != DefKind::Trait || sub.id == def.id {
+                    continue;
+                }
+                let bounds = self.trait_bounds(&sub)?;
+                let is_sub = refs
+                    .iter()
+                    .any(|r| bounds.iter().any(|b| r.intersects(&Range::Span(b.clone()))));
+                if is_sub && !result.iter().any(|d| d.id == sub.id) {
+                    result.push(sub);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn fields(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        self.child_defs(&def, DefKind::Field)
+    }
+
+    fn methods(&self, def: Definition) -> Result<Vec<Definition>, Error> {
+        // A trait's methods are its children, a type's are in its impls.
+        if def.kind == DefKind::Trait {
+            self.child_defs(&def, DefKind::Method)
+        } else {
+            self.impl_methods(&def)
+        }
+    }
+
+    fn docs(&self, def: Definition) -> Result<String, Error> {
+        self.check_index()?;
+        Ok(self.analysis_host.get_def(Id::new(def.id))?.docs)
+    }
 }
 
-impl<Fs: FileSystem> IntoWithFs<RlsSpan, Fs> for Position {
-    fn into_with(self, fs: &Fs) -> Result<RlsSpan, Error> {
-        let row = Row::new_zero_indexed(self.line as u32);
-        let column = Column::new_zero_indexed(self.column as u32);
-        Ok(RlsSpan::new(
-            row,
-            row,
-            column,
-            column,
-            fs.physical_path(&self.file)?,
-        ))
+fn def_kind(kind: RlsDefKind) -> DefKind {
+    match kind {
+        RlsDefKind::Function | RlsDefKind::ForeignFunction => DefKind::Function,
+        RlsDefKind::Method => DefKind::Method,
+        RlsDefKind::Struct | RlsDefKind::Tuple => DefKind::Struct,
+        RlsDefKind::Enum => DefKind::Enum,
+        RlsDefKind::TupleVariant | RlsDefKind::StructVariant => DefKind::Variant,
+        RlsDefKind::Union => DefKind::Union,
+        RlsDefKind::Trait => DefKind::Trait,
+        RlsDefKind::Type | RlsDefKind::ExternType => DefKind::Type,
+        RlsDefKind::Mod => DefKind::Mod,
+        RlsDefKind::Macro => DefKind::Macro,
+        RlsDefKind::Const => DefKind::Const,
+        RlsDefKind::Static | RlsDefKind::ForeignStatic => DefKind::Static,
+        RlsDefKind::Field => DefKind::Field,
+        RlsDefKind::Local => DefKind::Local,
     }
 }
 
+trait IntoWithFs<T, Fs: FileSystem> {
+    fn into_with(self, fs: &Fs) -> Result<T, Error>;
+}
+
 impl<Fs: FileSystem> IntoWithFs<RlsSpan, Fs> for Range {
     fn into_with(self, fs: &Fs) -> Result<RlsSpan, Error> {
         match self {
-            Range::Line(p, line) => {
-                let row = Row::new_zero_indexed(line as u32);
-                let column_start = Column::new_zero_indexed(0);
-                let column_end = Column::new_zero_indexed(255);
+            Range::Line(p, line) => Ok(RlsSpan::new(
+                Row::new_zero_indexed(line.zero_indexed() as u32),
+                Row::new_zero_indexed(line.zero_indexed() as u32),
+                RlsColumn::new_zero_indexed(0),
+                RlsColumn::new_zero_indexed(LINE_END),
+                fs.physical_path(&p)?,
+            )),
+            Range::File(p) => {
+                let len = fs.with_file(p, |f| Ok(f.lines.len()))?;
                 Ok(RlsSpan::new(
-                    row,
-                    row,
-                    column_start,
-                    column_end,
+                    Row::new_zero_indexed(0),
+                    Row::new_zero_indexed(len.saturating_sub(1) as u32),
+                    RlsColumn::new_zero_indexed(0),
+                    RlsColumn::new_zero_indexed(LINE_END),
                     fs.physical_path(&p)?,
                 ))
             }
             Range::Span(sp) => sp.into_with(fs),
-            r => Err(Error::Back(format!("Unimplemented range: {:?}", r))),
+            // Must be split into files first, see `Rls::spans`.
+            r @ Range::MultiFile(_) => Err(Error::Back(format!("Unimplemented range: {:?}", r))),
         }
     }
 }
 
 impl<Fs: FileSystem> IntoWithFs<RlsSpan, Fs> for Span {
     fn into_with(self, fs: &Fs) -> Result<RlsSpan, Error> {
+        let (start_column, end_column) = char_columns(fs, &self);
         Ok(RlsSpan::new(
-            Row::new_zero_indexed(self.start_line as u32),
-            Row::new_zero_indexed(self.end_line as u32),
-            Column::new_zero_indexed(self.start_column as u32),
-            Column::new_zero_indexed(self.end_column as u32),
+            Row::new_zero_indexed(self.start_line.zero_indexed() as u32),
+            Row::new_zero_indexed(self.end_line.zero_indexed() as u32),
+            RlsColumn::new_zero_indexed(start_column as u32),
+            RlsColumn::new_zero_indexed(end_column as u32),
             fs.physical_path(&self.file)?,
         ))
     }
 }
 
+// Rls columns count chars, but a `Column` is a byte offset.
+fn char_columns(fs: &impl FileSystem, span: &Span) -> (usize, usize) {
+    fs.with_file(span.file, |f| {
+        Ok((
+            f.char_column(span.start_line, span.start_column),
+            f.char_column(span.end_line, span.end_column),
+        ))
+    })
+    .unwrap_or((
+        span.start_column.zero_indexed(),
+        span.end_column.zero_indexed(),
+    ))
+}
+
+fn byte_span(fs: &impl FileSystem, file: Path, start: (usize, usize), end: (usize, usize)) -> Span {
+    let (start_line, end_line) = (
+        Line::new_zero_indexed(start.0),
+        Line::new_zero_indexed(end.0),
+    );
+    fs.with_file(file, |f| {
+        Ok(Span::new(
+            file,
+            start_line,
+            f.byte_column(start_line, start.1),
+            end_line,
+            f.byte_column(end_line, end.1),
+        ))
+    })
+    .unwrap_or_else(|_| Span::new_zero_indexed(file, start.0, start.1, end.0, end.1))
+    .with_offsets(fs)
+}
+
 impl<Fs: FileSystem> IntoWithFs<Identifier, Fs> for Ident {
     fn into_with(self, fs: &Fs) -> Result<Identifier, Error> {
         let span = self.span.into_with(fs)?;
+        let name = fs.snippet(&Range::Span(span.clone()))?;
+        // The id of an identifier is the id of the definition it refers to.
+        let id = unsafe { mem::transmute::<Id, u64>(self.id) };
         Ok(Identifier {
-            id: unsafe { mem::transmute::<Id, u64>(self.id) },
-            name: fs.snippet(&Range::Span(span.clone()))?,
+            id,
+            from_macro: !looks_like_ident(&name),
+            name,
             span,
+            kind: None,
+            def: Some(id),
         })
     }
 }
 
+// Identifiers from macro expansions often have the span of the invocation.
+fn looks_like_ident(s: &str) -> bool {
+    let s = s
+        .strip_prefix("r#")
+        .or_else(|| s.strip_prefix('\''))
+        .unwrap_or(s);
+    !s.is_empty()
+        && !s.starts_with(|c: char| c.is_numeric())
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
 impl<Fs: FileSystem> IntoWithFs<Span, Fs> for RlsSpan {
     fn into_with(self, fs: &Fs) -> Result<Span, Error> {
-        Ok(Span::new(
+        Ok(byte_span(
+            fs,
             fs.resolve_path(&self.file)?,
-            self.range.row_start.0 as usize,
-            self.range.row_end.0 as usize,
-            self.range.col_start.0 as usize,
-            self.range.col_end.0 as usize,
+            (
+                self.range.row_start.0 as usize,
+                self.range.col_start.0 as usize,
+            ),
+            (self.range.row_end.0 as usize, self.range.col_end.0 as usize),
         ))
     }
 }
@@ -146,3 +1146,292 @@ impl From<rls_analysis::AError> for Error {
         Error::Back(format!("Error in RLS backend: {}", e))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_system::{File, MockFs};
+
+    fn diagnostic(level: &str, message: &str) -> CompilerDiagnostic {
+        let json = format!(
+            r#"{{"reason":"compiler-message","message":{{"message":"{}","level":"{}","rendered":null,"spans":[{{"file_name":"src/lib.rs","line_start":3,"line_end":3,"column_start":5,"column_end":6,"is_primary":true}}]}}}}"#,
+            message, level
+        );
+        let msg: CargoMessage = serde_json::from_str(&json).unwrap();
+        CompilerDiagnostic::new(msg, StdPath::new("/root")).unwrap()
+    }
+
+    #[test]
+    fn test_build_failure() {
+        let diagnostics = vec![
+            diagnostic("warning", "unused variable"),
+            diagnostic("error", "cannot find value `x`"),
+            diagnostic("error", "mismatched types"),
+        ];
+        let log = PathBuf::from("/root/target/rls/build.log");
+        assert_eq!(
+            build_failure("cargo check", Some(101), &diagnostics, Some(&log)),
+            "`cargo check` failed (exit code 101) with 2 errors, first: cannot find value `x` \
+             (/root/src/lib.rs:3); see /root/target/rls/build.log for the full output"
+        );
+        assert_eq!(
+            build_failure("cargo check", None, &diagnostics[..1], None),
+            "`cargo check` was terminated"
+        );
+    }
+
+    #[test]
+    fn test_current_analysis() {
+        let root = PathBuf::from(format!("./target/test-analysis-{}", std::process::id()));
+        let touch = |path: &StdPath, secs: u64| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let file = fs::File::create(path).unwrap();
+            let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            file.set_modified(time).unwrap();
+        };
+        let (editor, clyde) = (root.join("target/debug"), root.join("target/clyde"));
+        touch(&root.join("src/lib.rs"), 2000);
+        touch(&root.join("Cargo.toml"), 1000);
+        // Not a source file, or in a target directory.
+        touch(&root.join("README.md"), 5000);
+        touch(&root.join("target/debug/build/out.rs"), 5000);
+        touch(&analysis_dir(&clyde).join("libfoo-1a2b.json"), 1500);
+        touch(&analysis_dir(&editor).join("libfoo-3c4d.json"), 1000);
+        touch(&analysis_dir(&editor).join("libbar-5e6f.json"), 3000);
+
+        let dirs = vec![clyde.clone(), editor.clone()];
+        assert_eq!(current_analysis(&root, &dirs), Some(editor.clone()));
+        touch(&root.join("src/lib.rs"), 4000);
+        assert_eq!(current_analysis(&root, &dirs), None);
+        touch(&analysis_dir(&clyde).join("libfoo-1a2b.json"), 4000);
+        assert_eq!(current_analysis(&root, &dirs), Some(clyde));
+        assert_eq!(current_analysis(&root, &[root.join("target/none")]), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_stale_analysis() {
+        let artifact = |filenames: &str| {
+            let json = format!(
+                r#"{{"reason":"compiler-artifact","filenames":[{}]}}"#,
+                filenames
+            );
+            serde_json::from_str::<CargoMessage>(&json).unwrap()
+        };
+        let messages = [
+            artifact(r#""/root/target/rls/debug/deps/libfoo-1a2b.rmeta""#),
+            artifact(r#""/root/target/rls/debug/deps/tool-9a8b.rmeta""#),
+            artifact(r#""/root/target/rls/debug/my-tool""#),
+            artifact(r#""/root/target/rls/debug/app-server.exe""#),
+        ];
+        let mut current: HashMap<String, HashSet<String>> = HashMap::new();
+        for (name, hash) in messages
+            .iter()
+            .flat_map(|m| &m.filenames)
+            .filter_map(|f| crate_hash(f))
+        {
+            current.entry(name).or_default().insert(hash);
+        }
+        assert_eq!(current.len(), 2);
+        assert!(current["foo"].contains("1a2b"));
+        assert!(current["tool"].contains("9a8b"));
+
+        let dir = analysis_dir(StdPath::new("/root/target/rls"));
+        assert_eq!(
+            dir,
+            PathBuf::from("/root/target/rls/debug/deps/save-analysis")
+        );
+        let files = vec![
+            dir.join("libfoo-1a2b.json"),
+            dir.join("libfoo-3c4d.json"),
+            dir.join("libbar-5e6f.json"),
+            dir.join("tool-9a8b.json"),
+            dir.join("tool-7c6d.json"),
+            dir.join("my_tool-1f2e.json"),
+            dir.join("notes.txt"),
+        ];
+        assert_eq!(
+            stale_analysis(files, &current),
+            vec![dir.join("libfoo-3c4d.json"), dir.join("tool-7c6d.json")]
+        );
+    }
+
+    // Records the most steps done.
+    struct MaxProgress(AtomicUsize);
+
+    impl Progress for MaxProgress {
+        fn update(&self, done: usize, total: usize) {
+            assert!(done <= total);
+            self.0.fetch_max(done, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_query_spans() {
+        let data_dir = Arc::new(Mutex::new(PathBuf::from(TARGET_DIR)));
+        let rls = Rls {
+            analysis_host: AnalysisHost::new_with_loader(TargetDirLoader::new(data_dir.clone())),
+            fs: Rc::new(MockFs),
+            root: PathBuf::new(),
+            target_dir: PathBuf::from(TARGET_DIR),
+            data_dir,
+            diagnostics: RefCell::new(Vec::new()),
+            indexed: Cell::new(true),
+            index_error: RefCell::new(None),
+            verbosity: Verbosity::Normal,
+        };
+        let span = |i: u32| {
+            RlsSpan::new(
+                Row::new_zero_indexed(i),
+                Row::new_zero_indexed(i),
+                RlsColumn::new_zero_indexed(0),
+                RlsColumn::new_zero_indexed(LINE_END),
+                PathBuf::from(format!("{}.rs", i)),
+            )
+        };
+
+        // Results are kept in order, however the spans are split between threads.
+        let spans: Vec<_> = (0..50).map(span).collect();
+        let progress = MaxProgress(AtomicUsize::new(0));
+        let rows = rls
+            .query_spans(&spans, &progress, |_, s| Ok(vec![s.range.row_start.0; 2]))
+            .ok()
+            .unwrap();
+        assert_eq!(rows.len(), 50);
+        assert!(rows.iter().enumerate().all(|(i, r)| r == &[i as u32; 2]));
+        // Every span is counted.
+        assert_eq!(progress.0.load(Ordering::Relaxed), 50);
+
+        assert!(rls
+            .query_spans(&spans, &NoProgress, |_, s| if s.range.row_start.0 == 42 {
+                Err(rls_analysis::AError::Unclassified)
+            } else {
+                Ok(vec![()])
+            })
+            .is_err());
+        assert!(rls
+            .query_spans(&[], &NoProgress, |_, _| Ok(vec![()]))
+            .ok()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_char_columns() {
+        let root = PathBuf::from(format!("./target/test-columns-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            root.join("lib.rs"),
+            "let größe = \"日本\"; // 🦀\nlet x = größe;\n",
+        )
+        .unwrap();
+        let physical = PhysicalFs::new(&root);
+        let file = physical.find("lib.rs".to_owned().into()).unwrap()[0];
+        let path = physical.physical_path(&file).unwrap();
+
+        // `🦀` is after 21 chars, but 27 bytes.
+        let span = Span::new_zero_indexed(file, 0, 27, 0, 31);
+        let rls: RlsSpan = span.clone().into_with(&physical).ok().unwrap();
+        assert_eq!((rls.range.col_start.0, rls.range.col_end.0), (21, 22));
+        let back: Span = rls.into_with(&physical).ok().unwrap();
+        assert_eq!(back, span);
+        assert_eq!(physical.snippet(&Range::Span(back)).unwrap(), "🦀");
+
+        // `größe` on the second line, as the analysis data has it.
+        let rls = RlsSpan::new(
+            Row::new_zero_indexed(1),
+            Row::new_zero_indexed(1),
+            RlsColumn::new_zero_indexed(8),
+            RlsColumn::new_zero_indexed(13),
+            path,
+        );
+        let span: Span = rls.into_with(&physical).ok().unwrap();
+        assert_eq!(
+            (
+                span.start_column.zero_indexed(),
+                span.end_column.zero_indexed()
+            ),
+            (8, 15)
+        );
+        assert_eq!(physical.snippet(&Range::Span(span)).unwrap(), "größe");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_looks_like_ident() {
+        for s in &["foo", "_", "Foo_1", "r#type", "'a", "größe"] {
+            assert!(looks_like_ident(s), "{}", s);
+        }
+        for s in &["", "1st", "vec![a, b]", "foo.bar", "r#", "a b"] {
+            assert!(!looks_like_ident(s), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_methods_of_impl() {
+        let file = MockFs.find("foo.rs".to_owned().into()).ok().unwrap()[0];
+        let text = "struct Foo;\nimpl Marker for Foo {}\n\nimpl Bar {\n    fn bar() {}\n    \
+                    fn baz() {\n        impl Qux {\n            fn qux() {}\n        }\n    }\n}\n";
+        let blocks = syntax::find_in_file(&File::new(file, text), Item::Impl);
+        let span = |line, start, end| Span::new_zero_indexed(file, line, start, line, end);
+        let method = |id, line, start, name: &str| Definition {
+            id,
+            span: span(line, start, start + name.len()),
+            name: name.to_owned(),
+            kind: DefKind::Method,
+            path: None,
+        };
+        let groups = vec![
+            vec![method(1, 4, 7, "bar"), method(2, 5, 7, "baz")],
+            vec![method(3, 7, 15, "qux")],
+        ];
+        let methods = |header| -> Vec<u64> {
+            methods_of_impl(&header, &groups, &blocks)
+                .map(|m| m.id)
+                .collect()
+        };
+        // The empty impl doesn't get the methods of the next one.
+        assert!(methods(span(1, 16, 19)).is_empty());
+        assert_eq!(methods(span(3, 5, 8)), [1, 2]);
+        assert_eq!(methods(span(6, 13, 16)), [3]);
+        // Not in an impl block.
+        assert!(methods(span(0, 7, 10)).is_empty());
+    }
+
+    #[test]
+    fn test_bound_spans() {
+        let file = MockFs.find("foo.rs".to_owned().into()).ok().unwrap()[0];
+        let spans = |lines: &[&str], name_end| {
+            let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+            bound_spans(file, &lines, 3, name_end)
+                .into_iter()
+                .map(|s| {
+                    (
+                        s.start_line.zero_indexed(),
+                        s.start_column.zero_indexed(),
+                        s.end_column.zero_indexed(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(spans(&["pub trait Foo {"], (3, 13)), vec![]);
+        assert_eq!(
+            spans(&["trait Foo<T: Qux>: Bar + Baz<T> where T: Copy {"], (3, 9)),
+            vec![(3, 19, 28)]
+        );
+        assert_eq!(
+            spans(&["trait Foo: fmt::Debug + Fn(u8) -> u8 + Bar;"], (3, 9)),
+            vec![(3, 11, 26), (3, 31, 42)]
+        );
+        // Bounds over several lines, the signature starts before the name.
+        assert_eq!(
+            spans(
+                &["#[foo]", "trait Foo:", "    Bar", "    + Baz", "{"],
+                (4, 9)
+            ),
+            vec![(5, 4, 7), (6, 4, 9)]
+        );
+        assert_eq!(spans(&["trait Foo where Self: Bar {"], (3, 9)), vec![]);
+    }
+}
@@ -1,7 +1,219 @@
-use clyde::{Repl, ReplConfig};
+use clyde::{ColorChoice, ConfigOverrides, Format, Repl, ReplConfig, Session, Verbosity};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "usage: clyde [OPTIONS] [SCRIPT]
+
+Starts the REPL, or evaluates the statements in SCRIPT.
+
+Options:
+  -e, --query STATEMENT  evaluate a single statement and exit
+      --daemon           serve queries over a socket, keeping the index warm
+      --client           send the statement given with `-e` to a daemon
+      --lsp              run as a language server on stdin and stdout
+      --format FORMAT    output format for `-e` or a script: `text` or `json`
+  -r, --root DIR         the root directory of the project to query
+  -b, --backend NAME     the backend to use (`rls`), or several separated by
+                         commas
+      --no-index         don't build the project when the backend starts, use
+                         the existing index (if any)
+      --color WHEN       color output: `auto` (the default), `always`, or
+                         `never`; `auto` colors output to a terminal unless
+                         `NO_COLOR` is set or `TERM` is `dumb`
+  -v, --verbose          report more about what Clyde is doing
+  -q, --quiet            only report errors
+  -h, --help             show this message";
+
+// Command line arguments.
+#[derive(Debug)]
+struct Args {
+    // A statement to evaluate instead of starting the REPL.
+    statement: Option<String>,
+    // A file of statements to evaluate instead of starting the REPL.
+    script: Option<PathBuf>,
+    format: Format,
+    // Serve queries rather than starting the REPL.
+    daemon: bool,
+    // Send the statement to a daemon rather than evaluating it.
+    client: bool,
+    // Run as a language server rather than starting the REPL.
+    lsp: bool,
+    // Applied to the configuration.
+    overrides: ConfigOverrides,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut result = Args {
+        statement: None,
+        script: None,
+        format: Format::Text,
+        daemon: false,
+        client: false,
+        lsp: false,
+        overrides: ConfigOverrides::default(),
+    };
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "-e" | "--query" => result.statement = Some(value(&arg, "a statement", &mut args)?),
+            "--format" => {
+                result.format = Format::parse(&value(&arg, "`text` or `json`", &mut args)?)?
+            }
+            "-r" | "--root" => {
+                result.overrides.root = Some(value(&arg, "a directory", &mut args)?.into())
+            }
+            "-b" | "--backend" => {
+                result.overrides.backend = Some(value(&arg, "a backend name", &mut args)?)
+            }
+            "--color" => {
+                result.overrides.color = Some(ColorChoice::parse(&value(
+                    &arg,
+                    "`auto`, `always`, or `never`",
+                    &mut args,
+                )?)?)
+            }
+            s if s.starts_with("--color=") => {
+                result.overrides.color = Some(ColorChoice::parse(&s["--color=".len()..])?)
+            }
+            "--daemon" => result.daemon = true,
+            "--client" => result.client = true,
+            "--lsp" => result.lsp = true,
+            "--no-index" => result.overrides.no_index = true,
+            "-v" | "--verbose" => result.overrides.verbosity = Some(Verbosity::Verbose),
+            "-q" | "--quiet" => result.overrides.verbosity = Some(Verbosity::Quiet),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            s if s.starts_with('-') && s.len() > 1 => {
+                return Err(format!("unexpected argument `{}`", arg))
+            }
+            _ if result.script.is_some() => {
+                return Err(format!(
+                    "unexpected argument `{}`, only one script may be given",
+                    arg
+                ))
+            }
+            _ => result.script = Some(arg.into()),
+        }
+    }
+    if result.statement.is_some() && result.script.is_some() {
+        return Err("`-e` cannot be used with a script".to_owned());
+    }
+    if result.daemon && (result.client || result.statement.is_some() || result.script.is_some()) {
+        return Err("`--daemon` cannot be used with `--client`, `-e`, or a script".to_owned());
+    }
+    if result.lsp
+        && (result.daemon || result.client || result.statement.is_some() || result.script.is_some())
+    {
+        return Err(
+            "`--lsp` cannot be used with `--daemon`, `--client`, `-e`, or a script".to_owned(),
+        );
+    }
+    if result.client && result.statement.is_none() {
+        return Err("`--client` requires `-e`".to_owned());
+    }
+    if result.statement.is_none() && result.script.is_none() && result.format != Format::Text {
+        return Err("`--format` can only be used with `-e` or a script".to_owned());
+    }
+    Ok(result)
+}
+
+// The value of an option which requires one, `what` describes the value.
+fn value(arg: &str, what: &str, args: &mut impl Iterator<Item = String>) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("`{}` requires {}", arg, what))
+}
 
 fn main() {
-    let config = ReplConfig::default();
-    let repl = Repl::new(config);
-    repl.run();
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let mut config = match ReplConfig::load_with(args.overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    if args.statement.is_some() || args.script.is_some() {
+        // Nothing can change while a single statement or script is evaluated
+        // (at least nothing we should act on).
+        config.watch = false;
+    }
+    if args.daemon {
+        process::exit(clyde::run_daemon(config));
+    }
+    if args.lsp {
+        process::exit(clyde::run_lsp_server(config));
+    }
+    let code = match (args.statement, args.script) {
+        (Some(statement), _) if args.client => {
+            clyde::run_client(&config.root, &statement, args.format)
+        }
+        (Some(statement), _) => {
+            let mut session = Session::new(config).format(args.format);
+            clyde::eval_once(&mut session, &statement)
+        }
+        (None, Some(script)) => {
+            let mut session = Session::new(config).format(args.format);
+            clyde::run_script(&mut session, &script)
+        }
+        (None, None) => Repl::new(config).run(),
+    };
+    process::exit(code);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["-r", "../foo", "--no-index", "-q", "-e", "show $"]).unwrap();
+        assert_eq!(args.statement, Some("show $".to_owned()));
+        assert_eq!(args.overrides.root, Some(PathBuf::from("../foo")));
+        assert!(args.overrides.no_index);
+        assert_eq!(args.overrides.verbosity, Some(Verbosity::Quiet));
+
+        let args = parse(&["--format", "json", "-b", "rls", "script.cly"]).unwrap();
+        assert_eq!(args.script, Some(PathBuf::from("script.cly")));
+        assert_eq!(args.format, Format::Json);
+        assert_eq!(args.overrides.backend, Some("rls".to_owned()));
+
+        assert_eq!(parse(&["-e"]).unwrap_err(), "`-e` requires a statement");
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["a.cly", "b.cly"]).is_err());
+        assert!(parse(&["-e", "show $", "a.cly"]).is_err());
+        assert!(parse(&["--format", "json"]).is_err());
+
+        let args = parse(&["--client", "-e", "show $", "--format", "json"]).unwrap();
+        assert!(args.client);
+        assert!(parse(&["--daemon"]).unwrap().daemon);
+        assert_eq!(
+            parse(&["--client"]).unwrap_err(),
+            "`--client` requires `-e`"
+        );
+        assert!(parse(&["--daemon", "-e", "show $"]).is_err());
+        assert!(parse(&["--lsp"]).unwrap().lsp);
+        assert!(parse(&["--lsp", "--daemon"]).is_err());
+
+        assert_eq!(
+            parse(&["--color=never"]).unwrap().overrides.color,
+            Some(ColorChoice::Never)
+        );
+        assert_eq!(
+            parse(&["--color", "always"]).unwrap().overrides.color,
+            Some(ColorChoice::Always)
+        );
+        assert!(parse(&["--color=rainbow"]).is_err());
+    }
 }
@@ -0,0 +1,588 @@
+//! Configuration for Clyde. Configuration comes from defaults, which are
+//! overridden by the project's `Clyde.toml` (if there is one), which are
+//! overridden by environment variables, which are overridden by command line
+//! arguments (see `Overrides`).
+//!
+//! An example `Clyde.toml`:
+//!
+//! ```toml
+//! root = "."
+//! backend = "rls"
+//! build = ["cargo", "check", "--all"]
+//! target-dir = "target/rls"
+//! analysis-dirs = ["target/rls", "target"]
+//! ignore = ["benches/**", "src/generated/*.rs"]
+//! editor = "vim"
+//! file-cache = 256
+//! watch = true
+//! auto-reload = false
+//! disambiguate = true
+//! verbosity = "verbose"
+//! color = "auto"
+//!
+//! [output]
+//! display = "full"
+//! max-set-len = 10
+//!
+//! [aliases]
+//! defs = "idents->def"
+//! ```
+//!
+//! Aliases defined with `^alias` can be added to the file with `^alias save`
+//! (see `save_aliases`).
+
+use super::Settings;
+use crate::back;
+use crate::file_system::{PhysicalFs, DEFAULT_CACHE_SIZE};
+use crate::parse::{Alias, Aliases};
+use glob::Pattern;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = "Clyde.toml";
+
+pub struct Config {
+    /// The directory Clyde was started in, paths given to meta-commands are
+    /// relative to this directory.
+    pub current_dir: PathBuf,
+    /// The root of the project (or workspace) to query.
+    pub root: PathBuf,
+    /// Watch source files for changes.
+    pub watch: bool,
+    /// Rebuild the index when source files change (requires `watch`).
+    pub auto_reload: bool,
+    /// The name of the backend, or several separated by commas to chain them
+    /// (see `back::Registry::resolve`).
+    pub backend: String,
+    /// The program and arguments used to build the project when indexing.
+    pub build_command: Vec<String>,
+    /// Where the project is built when indexing, relative to the root.
+    pub target_dir: PathBuf,
+    /// Other target directories with analysis data, e.g., from an editor.
+    pub analysis_dirs: Vec<PathBuf>,
+    /// Changes to files matching any of these patterns (relative to the root)
+    /// are ignored.
+    pub ignore: Vec<Pattern>,
+    /// The command used to open a file in an editor.
+    pub editor: Option<String>,
+    /// The most files whose contents are kept in memory.
+    pub file_cache: usize,
+    /// When several identifiers are at a position, ask the user to choose
+    /// between them (only in interactive sessions).
+    pub disambiguate: bool,
+    /// Build the project to update the index when the backend starts. If
+    /// false, the existing index (if any) is used until `^reload`.
+    pub index: bool,
+    /// How much is reported to the user and logged (see `env::logging`).
+    pub verbosity: Verbosity,
+    /// When output is colored (see `front::DisplayPolicy`).
+    pub color: ColorChoice,
+    /// The initial settings.
+    pub settings: Settings,
+    /// Aliases defined for the project.
+    pub aliases: Aliases,
+    // Kept so that the same overrides are applied when the config is reloaded.
+    overrides: Overrides,
+}
+
+/// How much Clyde reports about what it is doing (e.g., building the index).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Verbosity {
+    /// Only errors.
+    Quiet,
+    Normal,
+    /// Progress and details.
+    Verbose,
+}
+
+/// When output is colored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorChoice {
+    /// Unless the user has set `NO_COLOR`.
+    Auto,
+    Always,
+    Never,
+}
+
+/// Configuration given on the command line, this overrides all other sources
+/// of configuration.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    /// Relative to the current directory.
+    pub root: Option<PathBuf>,
+    pub backend: Option<String>,
+    pub no_index: bool,
+    pub verbosity: Option<Verbosity>,
+    pub color: Option<ColorChoice>,
+}
+
+impl Verbosity {
+    pub fn parse(s: &str) -> Result<Verbosity, String> {
+        match s {
+            "quiet" => Ok(Verbosity::Quiet),
+            "normal" => Ok(Verbosity::Normal),
+            "verbose" => Ok(Verbosity::Verbose),
+            _ => Err(format!(
+                "unknown verbosity `{}`, expected `quiet`, `normal`, or `verbose`",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorChoice {
+    pub fn parse(s: &str) -> Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown color choice `{}`, expected `auto`, `always`, or `never`",
+                s
+            )),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration for a session started in the current directory.
+    pub fn load() -> Result<Config, Error> {
+        Config::load_with(Overrides::default())
+    }
+
+    /// Load the configuration for a session started in the current directory,
+    /// with command line arguments applied.
+    pub fn load_with(overrides: Overrides) -> Result<Config, Error> {
+        let mut config = Config::default();
+        let path = config.current_dir.join(FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(text) => config
+                .apply_file(&text, &config.current_dir.clone())
+                .map_err(|msg| Error::new(&path, msg))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::new(&path, e.to_string())),
+        }
+        config
+            .apply_env(|name| env::var(name).ok())
+            .map_err(|msg| Error::new(Path::new("environment"), msg))?;
+        config
+            .apply_overrides(overrides)
+            .map_err(|msg| Error::new(Path::new("command line"), msg))?;
+        Ok(config)
+    }
+
+    /// Reload the configuration, e.g., after the config file has changed.
+    /// The same command line arguments are applied.
+    pub fn reload(&self) -> Result<Config, Error> {
+        Config::load_with(self.overrides.clone())
+    }
+
+    // Override `self` with the contents of a config file in `dir`.
+    fn apply_file(&mut self, text: &str, dir: &Path) -> Result<(), String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        if let Some(root) = file.root {
+            self.root = canonical_dir(&dir.join(root)).map_err(|e| format!("`root`: {}", e))?;
+        }
+        if let Some(backend) = file.backend {
+            self.backend = check_backend(backend).map_err(|e| format!("`backend`: {}", e))?;
+        }
+        if let Some(build) = file.build {
+            if build.is_empty() {
+                return Err("`build`: the build command must not be empty".to_owned());
+            }
+            self.build_command = build;
+        }
+        if let Some(target_dir) = file.target_dir {
+            if target_dir.as_os_str().is_empty() {
+                return Err("`target-dir`: the target directory must not be empty".to_owned());
+            }
+            self.target_dir = target_dir;
+        }
+        if let Some(analysis_dirs) = file.analysis_dirs {
+            if analysis_dirs.iter().any(|d| d.as_os_str().is_empty()) {
+                return Err("`analysis-dirs`: directories must not be empty".to_owned());
+            }
+            self.analysis_dirs = analysis_dirs;
+        }
+        for pat in file.ignore {
+            let pattern = Pattern::new(&pat)
+                .map_err(|e| format!("`ignore`: invalid pattern `{}`: {}", pat, e))?;
+            self.ignore.push(pattern);
+        }
+        if let Some(editor) = file.editor {
+            self.editor = Some(editor);
+        }
+        if let Some(file_cache) = file.file_cache {
+            self.file_cache = file_cache;
+        }
+        if let Some(watch) = file.watch {
+            self.watch = watch;
+        }
+        if let Some(auto_reload) = file.auto_reload {
+            self.auto_reload = auto_reload;
+        }
+        if let Some(disambiguate) = file.disambiguate {
+            self.disambiguate = disambiguate;
+        }
+        if let Some(verbosity) = file.verbosity {
+            self.verbosity =
+                Verbosity::parse(&verbosity).map_err(|e| format!("`verbosity`: {}", e))?;
+        }
+        if let Some(color) = file.color {
+            self.color = ColorChoice::parse(&color).map_err(|e| format!("`color`: {}", e))?;
+        }
+        for (name, value) in file.output {
+            let value = match value {
+                toml::Value::String(s) => s,
+                v => v.to_string(),
+            };
+            self.settings
+                .set(&name, &value)
+                .map_err(|e| format!("`output.{}`: {}", name, e))?;
+        }
+        for (name, query) in file.aliases {
+            if !is_identifier(&name) {
+                return Err(format!("`aliases`: invalid alias name `{}`", name));
+            }
+            let alias = Alias::new(&query).map_err(|e| format!("`aliases.{}`: {}", name, e))?;
+            self.aliases.insert(name, alias);
+        }
+
+        Ok(())
+    }
+
+    // Override `self` with values from environment variables, `var` looks up a
+    // variable by name.
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        if let Some(root) = var("CLYDE_ROOT") {
+            self.root = canonical_dir(&self.current_dir.join(root))
+                .map_err(|e| format!("`CLYDE_ROOT`: {}", e))?;
+        }
+        if let Some(backend) = var("CLYDE_BACKEND") {
+            self.backend = check_backend(backend).map_err(|e| format!("`CLYDE_BACKEND`: {}", e))?;
+        }
+        if let Some(editor) = var("CLYDE_EDITOR") {
+            self.editor = Some(editor);
+        }
+        Ok(())
+    }
+
+    // Override `self` with values from the command line.
+    fn apply_overrides(&mut self, overrides: Overrides) -> Result<(), String> {
+        if let Some(root) = &overrides.root {
+            self.root = canonical_dir(&self.current_dir.join(root))
+                .map_err(|e| format!("`--root`: {}", e))?;
+        }
+        if let Some(backend) = &overrides.backend {
+            self.backend =
+                check_backend(backend.clone()).map_err(|e| format!("`--backend`: {}", e))?;
+        }
+        if overrides.no_index {
+            self.index = false;
+        }
+        if let Some(verbosity) = overrides.verbosity {
+            self.verbosity = verbosity;
+        }
+        if let Some(color) = overrides.color {
+            self.color = color;
+        }
+        self.overrides = overrides;
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let current_dir = env::current_dir().expect("Could not access current directory");
+        Config {
+            root: current_dir.clone(),
+            current_dir,
+            watch: true,
+            auto_reload: false,
+            backend: back::Rls::<PhysicalFs>::NAME.to_owned(),
+            build_command: vec!["cargo".to_owned(), "check".to_owned(), "--all".to_owned()],
+            target_dir: PathBuf::from("target/rls"),
+            analysis_dirs: vec![PathBuf::from("target/rls"), PathBuf::from("target")],
+            ignore: Vec::new(),
+            editor: env::var("VISUAL").or_else(|_| env::var("EDITOR")).ok(),
+            file_cache: DEFAULT_CACHE_SIZE,
+            disambiguate: true,
+            index: true,
+            verbosity: Verbosity::Normal,
+            color: ColorChoice::Auto,
+            settings: Settings::default(),
+            aliases: Aliases::new(),
+            overrides: Overrides::default(),
+        }
+    }
+}
+
+// The contents of `Clyde.toml`, all fields are optional.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    root: Option<PathBuf>,
+    backend: Option<String>,
+    build: Option<Vec<String>>,
+    target_dir: Option<PathBuf>,
+    analysis_dirs: Option<Vec<PathBuf>>,
+    #[serde(default)]
+    ignore: Vec<String>,
+    editor: Option<String>,
+    file_cache: Option<usize>,
+    watch: Option<bool>,
+    auto_reload: Option<bool>,
+    disambiguate: Option<bool>,
+    verbosity: Option<String>,
+    color: Option<String>,
+    #[serde(default)]
+    output: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+}
+
+/// Replace the `[aliases]` table of the config file in `dir`. Comments and
+/// formatting are not kept.
+pub fn save_aliases(dir: &Path, aliases: &Aliases) -> Result<PathBuf, Error> {
+    let path = dir.join(FILE_NAME);
+    let err = |msg: String| Error::new(&path, msg);
+    let mut file = match fs::read_to_string(&path) {
+        Ok(text) => text
+            .parse::<toml::Value>()
+            .map_err(|e| err(e.to_string()))?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            toml::Value::Table(toml::value::Table::new())
+        }
+        Err(e) => return Err(err(e.to_string())),
+    };
+
+    let table = aliases
+        .iter()
+        .map(|(name, alias)| (name.clone(), toml::Value::String(alias.source.clone())))
+        .collect();
+    match file.as_table_mut() {
+        Some(file) => {
+            file.insert("aliases".to_owned(), toml::Value::Table(table));
+        }
+        None => return Err(err("expected a table".to_owned())),
+    }
+
+    let text = toml::to_string(&file).map_err(|e| err(e.to_string()))?;
+    fs::write(&path, text).map_err(|e| err(e.to_string()))?;
+    Ok(path)
+}
+
+fn canonical_dir(path: &Path) -> Result<PathBuf, String> {
+    match path.canonicalize() {
+        Ok(p) if p.is_dir() => Ok(p),
+        _ => Err(format!("directory not found: {}", path.display())),
+    }
+}
+
+// A backend, or several separated by commas, see `back::Registry::resolve`.
+fn check_backend(backend: String) -> Result<String, String> {
+    back::Registry::new().resolve(&backend)?;
+    Ok(backend)
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    // The file (or other source) of the configuration.
+    source: PathBuf,
+    msg: String,
+}
+
+impl Error {
+    fn new(source: &Path, msg: String) -> Error {
+        Error {
+            source: source.to_owned(),
+            msg,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error in configuration ({}): {}",
+            self.source.display(),
+            self.msg
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::DisplayMode;
+    use std::time::SystemTime;
+
+    fn apply(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        config.apply_file(text, Path::new("."))?;
+        Ok(config)
+    }
+
+    #[test]
+    fn test_file() {
+        let config = apply(
+            r#"
+            build = ["cargo", "build"]
+            target-dir = "target/clyde"
+            analysis-dirs = ["target"]
+            ignore = ["target/**", "*.bk"]
+            editor = "vim"
+            file-cache = 16
+            auto-reload = true
+            disambiguate = false
+            verbosity = "quiet"
+            color = "never"
+
+            [output]
+            display = "full"
+            max-set-len = 10
+
+            [aliases]
+            defs = "idents->def"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.build_command, vec!["cargo", "build"]);
+        assert_eq!(config.target_dir, PathBuf::from("target/clyde"));
+        assert_eq!(config.analysis_dirs, vec![PathBuf::from("target")]);
+        assert_eq!(config.ignore.len(), 2);
+        assert!(config.ignore[1].matches("foo.bk"));
+        assert_eq!(config.editor, Some("vim".to_owned()));
+        assert_eq!(config.file_cache, 16);
+        assert!(config.watch);
+        assert!(config.auto_reload);
+        assert!(!config.disambiguate);
+        assert_eq!(config.verbosity, Verbosity::Quiet);
+        assert_eq!(config.color, ColorChoice::Never);
+        assert_eq!(config.settings.display, DisplayMode::Full);
+        assert_eq!(config.settings.max_set_len, 10);
+        assert_eq!(config.aliases["defs"].source, "idents->def");
+
+        // Everything is optional.
+        let config = apply("").unwrap();
+        assert_eq!(config.backend, "rls");
+        assert_eq!(config.root, Config::default().root);
+    }
+
+    #[test]
+    fn test_file_errors() {
+        let err = |text| apply(text).err().unwrap();
+        assert!(err("foo = 1").contains("unknown field `foo`"));
+        assert!(err("build = \"cargo\"").contains("invalid type"));
+        assert!(err("build = []").starts_with("`build`"));
+        assert!(err("target-dir = \"\"").starts_with("`target-dir`"));
+        assert!(err("analysis-dirs = [\"\"]").starts_with("`analysis-dirs`"));
+        assert!(err("backend = \"foo\"").starts_with("`backend`: unknown backend `foo`"));
+        assert!(err("backend = \"rls, rls\"").starts_with("`backend`: backend `rls` is given more"));
+        assert!(err("root = \"does/not/exist\"").starts_with("`root`: directory not found"));
+        assert!(err("ignore = [\"a**b\"]").starts_with("`ignore`: invalid pattern `a**b`"));
+        assert!(err("verbosity = \"loud\"").starts_with("`verbosity`: unknown verbosity"));
+        assert!(err("color = \"rainbow\"").starts_with("`color`: unknown color choice"));
+        assert!(err("[output]\ndisplay = \"fancy\"").starts_with("`output.display`"));
+        assert!(err("[output]\nfoo = \"bar\"").starts_with("`output.foo`"));
+        assert!(err("[aliases]\n\"a b\" = \"idents\"").starts_with("`aliases`"));
+        assert!(err("[aliases]\ndefs = \"idents->\"").starts_with("`aliases.defs`"));
+    }
+
+    #[test]
+    fn test_save_aliases() {
+        let dir = PathBuf::from(format!(
+            "./target/test-config-{}",
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(FILE_NAME),
+            "editor = \"vim\"\n[aliases]\nold = \"idents\"\n",
+        )
+        .unwrap();
+        let mut aliases = Aliases::new();
+        aliases.insert("defs".to_owned(), Alias::new("idents->def").unwrap());
+        save_aliases(&dir, &aliases).unwrap();
+
+        let text = fs::read_to_string(dir.join(FILE_NAME)).unwrap();
+        let config = apply(&text).unwrap();
+        assert_eq!(config.editor, Some("vim".to_owned()));
+        assert_eq!(config.aliases.len(), 1);
+        assert_eq!(config.aliases["defs"].source, "idents->def");
+    }
+
+    #[test]
+    fn test_env() {
+        let mut config = Config::default();
+        config
+            .apply_file("editor = \"vim\"", Path::new("."))
+            .unwrap();
+        config
+            .apply_env(|name| match name {
+                "CLYDE_EDITOR" => Some("emacs".to_owned()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(config.editor, Some("emacs".to_owned()));
+
+        assert!(config
+            .apply_env(|name| match name {
+                "CLYDE_BACKEND" => Some("foo".to_owned()),
+                _ => None,
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut config = Config::default();
+        config
+            .apply_file("backend = \"rls\"", Path::new("."))
+            .unwrap();
+        config
+            .apply_overrides(Overrides {
+                root: Some(PathBuf::from(".")),
+                no_index: true,
+                verbosity: Some(Verbosity::Quiet),
+                color: Some(ColorChoice::Always),
+                ..Overrides::default()
+            })
+            .unwrap();
+        assert_eq!(config.root, Config::default().root.canonicalize().unwrap());
+        assert!(!config.index);
+        assert_eq!(config.verbosity, Verbosity::Quiet);
+        assert_eq!(config.overrides.verbosity, Some(Verbosity::Quiet));
+        assert_eq!(config.color, ColorChoice::Always);
+
+        let err = |overrides| Config::default().apply_overrides(overrides).err().unwrap();
+        assert!(err(Overrides {
+            root: Some(PathBuf::from("does/not/exist")),
+            ..Overrides::default()
+        })
+        .starts_with("`--root`: directory not found"));
+        assert!(err(Overrides {
+            backend: Some("foo".to_owned()),
+            ..Overrides::default()
+        })
+        .starts_with("`--backend`: unknown backend `foo`"));
+    }
+}
@@ -0,0 +1,374 @@
+//! Serving queries from a long-running Clyde (`clyde --daemon`), so that the
+//! backend's index is kept warm between queries from editors and scripts.
+//!
+//! The daemon listens on a unix socket at `SOCKET_PATH` (relative to the
+//! project root). Each request is a line of JSON, e.g.,
+//! `{"statement": "show (:src/lib.rs:10)->def", "format": "text"}` (`format`
+//! is optional and defaults to text), and is answered with a line of JSON,
+//! `{"output": "..."}` if the statement succeeded, or
+//! `{"output": "...", "error": "..."}` if it failed. A connection may be used
+//! for any number of requests, and is closed after `IDLE_TIMEOUT` without one.
+//! Requests are evaluated one at a time, in the same session, so results and
+//! variables are shared between clients.
+//!
+//! Meta-commands are not accepted, they would affect every client.
+
+use super::config::Config;
+use super::repl;
+use super::session::{Format, Session};
+use super::Environment;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const SOCKET_PATH: &str = "target/clyde/daemon.sock";
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub statement: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The socket of the daemon for the project at `root`.
+pub fn socket_path(root: &Path) -> PathBuf {
+    root.join(SOCKET_PATH)
+}
+
+/// Run a daemon until the process is killed, returns the exit code.
+pub fn run_daemon(mut config: Config) -> i32 {
+    // Nobody is around to run `^reload`.
+    config.watch = true;
+    config.auto_reload = true;
+    let path = socket_path(&config.root);
+    let mut session = Session::new(config);
+    // Build the index before accepting queries, rather than on the first one.
+    session.backend();
+    match serve(&mut session, &path) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Evaluate `statement` with the daemon for `root`, returns the exit code.
+pub fn run_client(root: &Path, statement: &str, format: Format) -> i32 {
+    let request = Request {
+        statement: statement.to_owned(),
+        format: Some(format_name(format).to_owned()),
+    };
+    match send(&socket_path(root), &request) {
+        Ok(response) => {
+            print!("{}", response.output);
+            match response.error {
+                Some(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+                None => 0,
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+// Answer a single request.
+fn respond(session: &mut Session, request: &Request) -> Response {
+    let format = match request.format.as_deref().map(Format::parse) {
+        None => Format::Text,
+        Some(Ok(format)) => format,
+        Some(Err(e)) => return Response::error(e),
+    };
+    if request.statement.trim_start().starts_with('^') {
+        return Response::error("Meta-commands can't be sent to the daemon".to_owned());
+    }
+    let (result, output) = session.eval_captured(&request.statement, format);
+    Response {
+        output,
+        error: result
+            .err()
+            .map(|e| repl::error_message(e, &request.statement)),
+    }
+}
+
+impl Response {
+    fn error(message: String) -> Response {
+        Response {
+            output: String::new(),
+            error: Some(message),
+        }
+    }
+}
+
+fn format_name(format: Format) -> &'static str {
+    match format {
+        Format::Text => "text",
+        Format::Json => "json",
+    }
+}
+
+// Answer a request, a line of JSON.
+fn handle(session: &mut Session, line: &str) -> Response {
+    match serde_json::from_str::<Request>(line) {
+        Ok(request) => respond(session, &request),
+        Err(e) => Response::error(format!("Invalid request: {}", e)),
+    }
+}
+
+fn reply(mut output: impl Write, response: &Response) -> io::Result<()> {
+    serde_json::to_writer(&mut output, response)?;
+    writeln!(output)?;
+    output.flush()
+}
+
+#[cfg(unix)]
+fn serve(session: &mut Session, path: &Path) -> Result<(), Error> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::mpsc;
+    use std::thread;
+
+    if UnixStream::connect(path).is_ok() {
+        return Err(Error::Running(path.to_owned()));
+    }
+    // Left behind by a daemon which didn't exit cleanly.
+    let _ = std::fs::remove_file(path);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    log::info!("daemon listening on {}", path.display());
+    eprintln!("listening on {}", path.display());
+    // The session can't be shared between threads, so each connection is
+    // read on its own thread and the requests are answered on this one.
+    let (requests, received) = mpsc::channel::<(String, UnixStream)>();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("could not accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let requests = requests.clone();
+            thread::spawn(move || {
+                if let Err(e) = read_requests(stream, requests) {
+                    log::warn!("connection failed: {}", e);
+                }
+            });
+        }
+    });
+    for (line, stream) in received {
+        let response = handle(session, &line);
+        if let Err(e) = reply(&stream, &response) {
+            log::warn!("connection failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Send each request on `stream` to be answered, with where to reply, until the
+// client closes the connection or is idle for too long.
+#[cfg(unix)]
+fn read_requests(
+    stream: std::os::unix::net::UnixStream,
+    requests: std::sync::mpsc::Sender<(String, std::os::unix::net::UnixStream)>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    for line in BufReader::new(stream.try_clone()?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if requests.send((line, stream.try_clone()?)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve(_: &mut Session, _: &Path) -> Result<(), Error> {
+    Err(Error::Unsupported)
+}
+
+#[cfg(unix)]
+fn send(path: &Path, request: &Request) -> Result<Response, Error> {
+    use std::os::unix::net::UnixStream;
+
+    let mut stream =
+        UnixStream::connect(path).map_err(|e| Error::NotRunning(path.to_owned(), e))?;
+    serde_json::to_writer(&mut stream, request)?;
+    writeln!(stream)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(not(unix))]
+fn send(_: &Path, _: &Request) -> Result<Response, Error> {
+    Err(Error::Unsupported)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Running(PathBuf),
+    NotRunning(PathBuf, io::Error),
+    #[allow(dead_code)]
+    Unsupported,
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Running(path) => {
+                write!(f, "a daemon is already listening on {}", path.display())
+            }
+            Error::NotRunning(path, e) => write!(
+                f,
+                "could not connect to a daemon on {} ({}), start one with `clyde --daemon`",
+                path.display(),
+                e
+            ),
+            Error::Unsupported => write!(f, "the daemon is only supported on unix"),
+            Error::IoError(e) => write!(f, "daemon error: {}", e),
+            Error::JsonError(e) => write!(f, "invalid response from the daemon: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::JsonError(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session() -> Session {
+        let mut config = Config::default();
+        config.watch = false;
+        Session::new(config)
+    }
+
+    fn requests(session: &mut Session, input: &str) -> Vec<Response> {
+        input
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| handle(session, l))
+            .collect()
+    }
+
+    #[test]
+    fn test_handle() {
+        let mut session = session();
+        let input = "{\"statement\": \"show [1, 2]\"}\n\
+                     \n\
+                     {\"statement\": \"show $0\", \"format\": \"yaml\"}\n\
+                     {\"statement\": \"show $->\"}\n\
+                     {\"statement\": \"^set max-set-len 1\"}\n\
+                     not json\n";
+        let responses = requests(&mut session, input);
+        assert_eq!(responses.len(), 5);
+        assert_eq!(
+            responses[0],
+            Response {
+                output: "[1, 2]\n".to_owned(),
+                error: None,
+            }
+        );
+        assert_eq!(
+            responses[1].error.as_deref(),
+            Some("Unknown format: `yaml`, expected `text` or `json`")
+        );
+        assert!(responses[2].error.as_ref().unwrap().starts_with("show $->"));
+        assert_eq!(
+            responses[3].error.as_deref(),
+            Some("Meta-commands can't be sent to the daemon")
+        );
+        assert!(responses[4]
+            .error
+            .as_ref()
+            .unwrap()
+            .starts_with("Invalid request"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve() {
+        use std::os::unix::net::UnixStream;
+        use std::thread;
+
+        let dir = PathBuf::from(format!("target/test-daemon-{}", std::process::id()));
+        let path = dir.join("daemon.sock");
+        let serving = path.clone();
+        thread::spawn(move || serve(&mut session(), &serving));
+        while UnixStream::connect(&path).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // A client which hasn't sent a request doesn't hold up the others.
+        let mut idle = UnixStream::connect(&path).unwrap();
+        let clients: Vec<_> = (0..2)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let request = Request {
+                        statement: format!("show [{}]", i),
+                        format: None,
+                    };
+                    send(&path, &request).unwrap()
+                })
+            })
+            .collect();
+        for (i, client) in clients.into_iter().enumerate() {
+            assert_eq!(client.join().unwrap().output, format!("[{}]\n", i));
+        }
+        writeln!(idle, "{{\"statement\": \"show [3]\"}}").unwrap();
+        let mut line = String::new();
+        BufReader::new(idle).read_line(&mut line).unwrap();
+        let response: Response = serde_json::from_str(&line).unwrap();
+        assert_eq!(response.output, "[3]\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_request() {
+        let request = Request {
+            statement: "show $".to_owned(),
+            format: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            "{\"statement\":\"show $\"}"
+        );
+        let response: Response = serde_json::from_str("{\"output\": \"x\"}").unwrap();
+        assert_eq!(response.error, None);
+    }
+}
@@ -0,0 +1,130 @@
+//! Logging what Clyde does to a file. Each session logs to its own file in
+//! `LOG_DIR` (relative to the project root), the end of the log can be shown
+//! with `^log tail`.
+//!
+//! The amount logged depends on the verbosity: warnings and errors are always
+//! logged, informational messages unless Clyde is quiet, and debugging
+//! messages when Clyde is verbose.
+
+use super::Verbosity;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime};
+
+pub const LOG_DIR: &str = "target/clyde/logs";
+
+// There can only be one logger per process.
+static LOGGER: OnceLock<FileLogger> = OnceLock::new();
+
+struct FileLogger {
+    path: PathBuf,
+    file: Mutex<File>,
+    // Entries are timestamped relative to when logging started.
+    start: Instant,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let elapsed = self.start.elapsed();
+        let mut file = self.file.lock().unwrap();
+        // There is nowhere to report a failure to log.
+        let _ = writeln!(
+            file,
+            "[{}.{:03}s] {:<5} {}: {}",
+            elapsed.as_secs(),
+            elapsed.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = self.file.lock().unwrap().flush();
+    }
+}
+
+/// Start logging to a new file, returns its path. If logging has already
+/// started, only the verbosity is changed.
+pub fn init(root: &Path, verbosity: Verbosity) -> io::Result<PathBuf> {
+    set_verbosity(verbosity);
+    if let Some(logger) = LOGGER.get() {
+        return Ok(logger.path.clone());
+    }
+
+    let dir = root.join(LOG_DIR);
+    fs::create_dir_all(&dir)?;
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = dir.join(format!("clyde-{}-{}.log", secs, process::id()));
+    let file = File::create(&path)?;
+    let logger = LOGGER.get_or_init(|| FileLogger {
+        path,
+        file: Mutex::new(file),
+        start: Instant::now(),
+    });
+    // Fails only if another logger has been installed, in which case log
+    // messages go to that logger instead.
+    let _ = log::set_logger(logger);
+    Ok(logger.path.clone())
+}
+
+/// Change how much is logged.
+pub fn set_verbosity(verbosity: Verbosity) {
+    log::set_max_level(level(verbosity));
+}
+
+fn level(verbosity: Verbosity) -> LevelFilter {
+    match verbosity {
+        Verbosity::Quiet => LevelFilter::Warn,
+        Verbosity::Normal => LevelFilter::Info,
+        Verbosity::Verbose => LevelFilter::Debug,
+    }
+}
+
+/// The last `n` lines of the log file at `path`.
+pub fn tail(path: &Path, n: usize) -> io::Result<Vec<String>> {
+    let mut lines = BufReader::new(File::open(path)?)
+        .lines()
+        .collect::<io::Result<Vec<_>>>()?;
+    let start = lines.len().saturating_sub(n);
+    Ok(lines.split_off(start))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tail() {
+        let dir = PathBuf::from("./target");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("test-tail-{}.log", process::id()));
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+        assert_eq!(tail(&path, 2).unwrap(), vec!["two", "three"]);
+        assert_eq!(tail(&path, 5).unwrap(), vec!["one", "two", "three"]);
+        assert!(tail(&path, 0).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+        assert!(tail(&path, 1).is_err());
+    }
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(Verbosity::Quiet), LevelFilter::Warn);
+        assert_eq!(level(Verbosity::Verbose), LevelFilter::Debug);
+    }
+}
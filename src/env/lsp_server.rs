@@ -0,0 +1,276 @@
+//! Clyde as a language server (`clyde --lsp`), so that queries can be made
+//! from an editor and their results jumped to.
+//!
+//! Statements are sent with the `workspace/executeCommand` request, using the
+//! `clyde.query` command with the statement as its only argument. The result
+//! has the output of the statement as Clyde would show it (`output`, which an
+//! editor can show as a virtual document), and the locations in its result
+//! (`locations`, each an LSP `Location` with the `text` Clyde shows for it in
+//! quickfix output). Columns are counted in characters, not UTF-16 code units.
+//!
+//! Only the parts of the protocol needed for this are implemented. Messages
+//! are read from stdin and written to stdout, as with any language server.
+//! Like the daemon, the server keeps the index warm and does not accept
+//! meta-commands.
+
+use super::config::Config;
+use super::session::{Format, Session};
+use super::Environment;
+use crate::file_system::FileSystem;
+use crate::front;
+use serde_json::{json, Value as Json};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+pub const QUERY_COMMAND: &str = "clyde.query";
+
+// JSON-RPC and LSP error codes.
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// Serve requests on stdin until the client exits, returns the exit code.
+pub fn run_lsp_server(mut config: Config) -> i32 {
+    // As for the daemon, nobody can run `^reload`.
+    config.watch = true;
+    config.auto_reload = true;
+    let mut session = Session::new(config);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match serve(&mut session, stdin.lock(), stdout.lock()) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("language server error: {}", e);
+            1
+        }
+    }
+}
+
+// Handle messages until the `exit` notification or the end of the input.
+// Returns true if the client shut the server down properly.
+fn serve(
+    session: &mut Session,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<bool> {
+    let mut shut_down = false;
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            // Notifications (e.g., `initialized` or `textDocument/didOpen`) need
+            // no response, and only `exit` needs any action.
+            None => {
+                if method == "exit" {
+                    return Ok(shut_down);
+                }
+                continue;
+            }
+        };
+        log::debug!("language server request: {}", method);
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "executeCommandProvider": { "commands": [QUERY_COMMAND] },
+                },
+                "serverInfo": { "name": "clyde" },
+            })),
+            "shutdown" => {
+                shut_down = true;
+                Ok(Json::Null)
+            }
+            "workspace/executeCommand" => execute_command(session, &message["params"]),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: `{}`", method))),
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        write_message(&mut output, &response)?;
+    }
+    Ok(false)
+}
+
+fn execute_command(session: &mut Session, params: &Json) -> Result<Json, (i64, String)> {
+    let command = params["command"].as_str().unwrap_or_default();
+    if command != QUERY_COMMAND {
+        return Err((INVALID_PARAMS, format!("Unknown command: `{}`", command)));
+    }
+    let statement = match params["arguments"].get(0).and_then(Json::as_str) {
+        Some(statement) => statement,
+        None => {
+            return Err((
+                INVALID_PARAMS,
+                format!("`{}` requires a statement", QUERY_COMMAND),
+            ))
+        }
+    };
+    if statement.trim_start().starts_with('^') {
+        return Err((
+            REQUEST_FAILED,
+            "Meta-commands can't be sent to the language server".to_owned(),
+        ));
+    }
+    let (result, output) = session.eval_captured(statement, Format::Text);
+    let value = result.map_err(|e| (REQUEST_FAILED, super::repl::error_message(e, statement)))?;
+    let locations = locations(session, value).map_err(|e| (REQUEST_FAILED, e.to_string()))?;
+    Ok(json!({ "output": output, "locations": locations }))
+}
+
+// The locations in a result, evaluating it if it is a query.
+fn locations(session: &Session, value: front::Value) -> Result<Vec<Json>, front::Error> {
+    let value = if value.ty.is_query() {
+        front::eval_query(&value.expect_query(), session)?
+    } else {
+        value
+    };
+    let fs = session.file_system();
+    value
+        .locations(session)?
+        .into_iter()
+        .map(|(file, line, column, text)| {
+            let path = fs.physical_path(&file)?;
+            let position = json!({
+                "line": line.zero_indexed(),
+                "character": column.zero_indexed(),
+            });
+            Ok(json!({
+                "uri": file_uri(&path),
+                "range": { "start": position, "end": position },
+                "text": text,
+            }))
+        })
+        .collect()
+}
+
+// A `file://` URI for an absolute path.
+fn file_uri(path: &Path) -> String {
+    let mut uri = "file://".to_owned();
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(b as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+// Read a message (headers, a blank line, then `Content-Length` bytes of JSON).
+// Returns `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = value.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| invalid_data("missing `Content-Length` header"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid_data(&e.to_string()))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session() -> Session {
+        let mut config = Config::default();
+        config.watch = false;
+        Session::new(config)
+    }
+
+    fn messages(messages: &[Json]) -> Vec<u8> {
+        let mut input = Vec::new();
+        for m in messages {
+            write_message(&mut input, m).unwrap();
+        }
+        input
+    }
+
+    #[test]
+    fn test_serve() {
+        let input = messages(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "workspace/executeCommand",
+                "params": { "command": QUERY_COMMAND, "arguments": ["show [1, 2]"] },
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "workspace/executeCommand",
+                "params": { "command": QUERY_COMMAND, "arguments": ["^reload"] },
+            }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "textDocument/hover", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+        let mut session = session();
+        let mut output = Vec::new();
+        assert!(serve(&mut session, &input[..], &mut output).unwrap());
+
+        let mut output = &output[..];
+        let mut responses = Vec::new();
+        while let Some(response) = read_message(&mut output).unwrap() {
+            responses.push(response);
+        }
+        assert_eq!(responses.len(), 5);
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["executeCommandProvider"]["commands"],
+            json!([QUERY_COMMAND])
+        );
+        assert_eq!(
+            responses[1]["result"],
+            json!({ "output": "[1, 2]\n", "locations": [] })
+        );
+        assert_eq!(responses[2]["error"]["code"], json!(REQUEST_FAILED));
+        assert_eq!(responses[3]["error"]["code"], json!(METHOD_NOT_FOUND));
+        assert_eq!(
+            responses[4],
+            json!({ "jsonrpc": "2.0", "id": 5, "result": null })
+        );
+
+        // Exiting without a shutdown request is an error.
+        let input = messages(&[json!({ "jsonrpc": "2.0", "method": "exit" })]);
+        assert!(!serve(&mut session, &input[..], Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn test_file_uri() {
+        assert_eq!(
+            file_uri(Path::new("/home/me/my project/src/lib.rs")),
+            "file:///home/me/my%20project/src/lib.rs"
+        );
+    }
+}
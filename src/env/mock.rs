@@ -0,0 +1,1549 @@
+//! Environments for tests.
+//!
+//! `MockEnv` has no backend and no state. `TestEnv` combines `MockFs` with a
+//! `MockBackend` and keeps the results of previous statements and variables, so that
+//! statements can be run end to end and their output checked.
+
+use super::*;
+use crate::back::{Capability, MockBackend};
+use crate::file_system::MockFs;
+use crate::front::Show;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub struct MockEnv;
+
+impl Environment for MockEnv {
+    type ParseContext = ();
+    type Fs = MockFs;
+
+    fn show(&self, value: &front::Value) -> Result<(), front::Error> {
+        Err(front::Error::Other(value.show_str(self)))
+    }
+
+    fn lookup_var(&self, _: &front::MetaVar) -> Result<front::Value, front::Error> {
+        Err(front::Error::Other(
+            "MockEnv does not support var lookup".to_owned(),
+        ))
+    }
+
+    fn lookup_numeric_var(&self, _: isize) -> Result<front::Value, front::Error> {
+        Err(front::Error::Other(
+            "MockEnv does not support numeric var lookup".to_owned(),
+        ))
+    }
+
+    fn file_system(&self) -> &Self::Fs {
+        &MockFs
+    }
+
+    fn backend(&self) -> Rc<dyn Backend> {
+        unimplemented!()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capability::ALL
+            .iter()
+            .fold(Capabilities::NONE, |caps, c| caps.with(*c))
+    }
+
+    fn settings(&self) -> Settings {
+        Settings::default()
+    }
+}
+
+impl parse::EnvContext for () {
+    fn clone(&self) -> Box<dyn parse::EnvContext> {
+        Box::new(())
+    }
+}
+
+// The name of a meta command, used as an error message by environments which
+// do not support it.
+fn meta_name(mk: ast::MetaKind) -> String {
+    match mk {
+        ast::MetaKind::Help => "help".to_owned(),
+        ast::MetaKind::Exit => "exit".to_owned(),
+        ast::MetaKind::Save(..) => "save".to_owned(),
+        ast::MetaKind::Load(..) => "load".to_owned(),
+        ast::MetaKind::Reload => "reload".to_owned(),
+        ast::MetaKind::Set(..) => "set".to_owned(),
+        ast::MetaKind::Backend => "backend".to_owned(),
+        ast::MetaKind::ReloadConfig => "config reload".to_owned(),
+        ast::MetaKind::Alias(..) => "alias".to_owned(),
+        ast::MetaKind::SaveAliases => "alias save".to_owned(),
+        ast::MetaKind::Record(..) => "record".to_owned(),
+        ast::MetaKind::StopRecording => "stop".to_owned(),
+        ast::MetaKind::Log => "log".to_owned(),
+        ast::MetaKind::LogTail(_) => "log tail".to_owned(),
+        ast::MetaKind::Focus(_) => "focus".to_owned(),
+        ast::MetaKind::Explain(_) => "explain".to_owned(),
+    }
+}
+
+/// An environment for end-to-end tests, see `TestEnv::run`.
+pub struct TestEnv {
+    backend: Rc<MockBackend>,
+    settings: Settings,
+    prev_results: Vec<Option<front::Value>>,
+    variables: HashMap<front::MetaVar, front::Value>,
+    // What has been shown by the current statement.
+    output: RefCell<Vec<String>>,
+    // The index to choose when there are several identifiers at a position.
+    choice: Option<usize>,
+    // The user's answers to prompts, `None` if the user can't be asked.
+    answers: Option<RefCell<Vec<String>>>,
+    focus: Option<Path>,
+    // The output of `git diff`, see `vcs::parse_diff`.
+    diff: String,
+    budget: Option<front::Budget>,
+}
+
+impl TestEnv {
+    pub fn new(backend: MockBackend) -> TestEnv {
+        TestEnv {
+            backend: Rc::new(backend),
+            settings: Settings::default(),
+            prev_results: Vec::new(),
+            variables: HashMap::new(),
+            output: RefCell::new(Vec::new()),
+            choice: None,
+            answers: None,
+            focus: None,
+            diff: String::new(),
+            budget: None,
+        }
+    }
+
+    /// Limit how long every statement may take to `timeout` from now.
+    pub fn timeout(mut self, timeout: Duration) -> TestEnv {
+        self.budget = Some(front::Budget::start(timeout));
+        self
+    }
+
+    /// Use `diff` (in the format of `git diff --no-prefix --unified=0`) for
+    /// the changes since any revision.
+    pub fn diff(mut self, diff: &str) -> TestEnv {
+        self.diff = diff.to_owned();
+        self
+    }
+
+    /// Choose the `choice`th identifier when there are several at a position,
+    /// as if the user had been asked.
+    pub fn choose(mut self, choice: usize) -> TestEnv {
+        self.choice = Some(choice);
+        self
+    }
+
+    /// Answer prompts (see `Environment::prompt_user`) with `answers`, in
+    /// order. Prompts are shown as output.
+    pub fn answer(mut self, answers: &[&str]) -> TestEnv {
+        self.answers = Some(RefCell::new(
+            answers.iter().rev().map(|s| s.to_string()).collect(),
+        ));
+        self
+    }
+
+    /// Parse and run a statement as the REPL would, returning what is shown to
+    /// the user (including error messages).
+    pub fn run(&mut self, input: &str) -> String {
+        match parse::parse_stmt(input, None, &parse::Aliases::new()) {
+            Ok(stmt) => {
+                if let Err(e) = front::eval_stmt(self, stmt) {
+                    self.output.get_mut().push(format!("Error: {}", e));
+                }
+            }
+            Err(e) => {
+                self.output
+                    .get_mut()
+                    .push(format!("Error parsing input: {}", e));
+                self.prev_results.push(None);
+            }
+        }
+        self.output.get_mut().split_off(0).join("\n")
+    }
+}
+
+impl SessionState for TestEnv {
+    fn exec_meta(&mut self, mk: ast::MetaKind) -> Result<(), front::Error> {
+        match mk {
+            ast::MetaKind::Set(Some((name, value))) => self
+                .settings
+                .set(&name, &value)
+                .map_err(front::Error::Other),
+            ast::MetaKind::Explain(text) => {
+                let stmt = parse::parse_stmt(&text, None, &parse::Aliases::new())
+                    .map_err(|e| front::Error::Other(format!("Error parsing input: {}", e)))?;
+                let plan = front::explain(self, stmt)?;
+                self.output.get_mut().push(plan.to_string());
+                Ok(())
+            }
+            mk => Err(front::Error::Other(meta_name(mk))),
+        }
+    }
+
+    fn set_var(&mut self, var: front::MetaVar, value: front::Value) {
+        self.variables.insert(var, value);
+    }
+
+    fn push_result(&mut self, result: Option<front::Value>) {
+        self.prev_results.push(result);
+    }
+
+    fn set_focus(&mut self, file: Path) {
+        self.focus = Some(file);
+    }
+}
+
+impl Environment for TestEnv {
+    type ParseContext = ();
+    type Fs = MockFs;
+
+    fn show(&self, value: &front::Value) -> Result<(), front::Error> {
+        let text = if self.settings.output == OutputMode::Text {
+            value.show_str(self)
+        } else {
+            let value = match &value.kind {
+                front::data::ValueKind::Query(q) => front::eval_query(q, self)?,
+                _ => value.clone(),
+            };
+            let mut buf = Vec::new();
+            match self.settings.output.separator() {
+                Some(sep) => value.show_table(&mut buf, self, &self.show_ctx(), sep)?,
+                None => value.show_quickfix(&mut buf, self, &self.show_ctx())?,
+            }
+            String::from_utf8(buf).unwrap()
+        };
+        self.output.borrow_mut().push(text);
+        Ok(())
+    }
+
+    fn write_text(&self, path: Option<&str>, text: &str) -> Result<(), front::Error> {
+        let text = match path {
+            Some(path) => format!("{}:\n{}", path, text),
+            None => text.to_owned(),
+        };
+        self.output.borrow_mut().push(text);
+        Ok(())
+    }
+
+    fn show_empty(&self, value: &front::Value) -> Result<(), front::Error> {
+        match self.settings.output {
+            OutputMode::Text => self.output.borrow_mut().push("no results".to_owned()),
+            OutputMode::Quickfix => {}
+            OutputMode::Csv | OutputMode::Tsv => return self.show(value),
+        }
+        Ok(())
+    }
+
+    fn lookup_var(&self, var: &front::MetaVar) -> Result<front::Value, front::Error> {
+        match self.variables.get(var) {
+            Some(value) => Ok(value.clone()),
+            None => Err(front::Error::VarNotFound(var.clone())),
+        }
+    }
+
+    fn lookup_numeric_var(&self, requested: isize) -> Result<front::Value, front::Error> {
+        let prev_results = &self.prev_results;
+        let id = if requested < 0 {
+            prev_results.len() as isize + requested
+        } else {
+            requested
+        };
+        match prev_results.get(id as usize) {
+            Some(Some(value)) if id >= 0 => Ok(value.clone()),
+            Some(None) if id >= 0 => Err(front::Error::FailedResult(id as usize)),
+            _ => Err(front::Error::NumericVarNotFound(
+                requested,
+                prev_results.len(),
+            )),
+        }
+    }
+
+    fn all_results(&self) -> Vec<front::Value> {
+        self.prev_results.iter().flatten().cloned().collect()
+    }
+
+    fn file_system(&self) -> &Self::Fs {
+        &MockFs
+    }
+
+    fn backend(&self) -> Rc<dyn Backend> {
+        self.backend.clone()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.backend.capabilities()
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings.clone()
+    }
+
+    fn chooses_idents(&self) -> bool {
+        self.choice.is_some()
+    }
+
+    fn can_prompt(&self) -> bool {
+        self.answers.is_some()
+    }
+
+    fn prompt_user(&self, message: &str) -> Result<Option<String>, front::Error> {
+        self.output.borrow_mut().push(message.to_owned());
+        Ok(self.answers.as_ref().and_then(|a| a.borrow_mut().pop()))
+    }
+
+    fn focus(&self) -> Option<Path> {
+        self.focus
+    }
+
+    fn budget(&self) -> Option<&front::Budget> {
+        self.budget.as_ref()
+    }
+
+    fn changed(&self, _: &str) -> Result<Vec<(Path, Line, Line)>, front::Error> {
+        super::vcs::parse_diff(&self.diff)
+            .into_iter()
+            .map(|h| {
+                let file = MockFs.find(h.file.display().to_string().into())?[0];
+                Ok((file, h.start, h.end))
+            })
+            .collect()
+    }
+
+    fn choose_idents(
+        &self,
+        _: &Position,
+        idents: Vec<Identifier>,
+    ) -> Result<Vec<Identifier>, front::Error> {
+        Ok(vec![idents[self.choice.unwrap()].clone()])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::front::data::{
+        DefKind, DefPath, Definition, Diagnostic, IdentKind, Identifier, Severity, Span,
+    };
+
+    // `foo.rs` refers to `line` (defined in `bar.rs`) on lines 3 and 5, and
+    // has an error on line 5.
+    fn env() -> TestEnv {
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let bar = MockFs.find("bar.rs".to_owned().into()).unwrap()[0];
+        let def = Definition {
+            id: 100,
+            span: Span::new_zero_indexed(bar, 0, 8, 0, 12),
+            name: "line".to_owned(),
+            kind: DefKind::Struct,
+            path: None,
+        };
+        let ident = |id, file, line| Identifier {
+            id,
+            span: Span::new_zero_indexed(file, line, 8, line, 12),
+            name: "line".to_owned(),
+            from_macro: false,
+            kind: None,
+            def: None,
+        };
+        TestEnv::new(
+            MockBackend::new()
+                .ident(ident(1, foo, 2), Some(def.clone()))
+                .ident(ident(2, foo, 4), Some(def.clone()))
+                // Where `line` is declared.
+                .ident(ident(3, bar, 0), Some(def.clone()))
+                .ty(1, "Vec<String>")
+                .docs(100, " A line of a file.\n\n More details.")
+                .symbol(def, None)
+                .diagnostic(Diagnostic {
+                    severity: Severity::Error,
+                    message: "oops".to_owned(),
+                    span: Span::new_zero_indexed(foo, 4, 0, 4, 4),
+                }),
+        )
+    }
+
+    #[test]
+    fn test_idents() {
+        let mut env = env();
+        assert_eq!(env.run("select (:foo.rs:3)->idents"), "[`line`]");
+        assert_eq!(
+            env.run("show $->def"),
+            "[struct `line` at  --> bar.rs:1:9->13\n\
+             1 | This is line 0 of a file with number 2.\n\
+             \x20           ^^^^]"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs:5:10)->idents->def->kind \"struct\"")
+                .lines()
+                .count(),
+            3
+        );
+        assert_eq!(env.run("show (:foo.rs)->idents->def->kind \"fn\""), "[]");
+        assert_eq!(env.run("show (:baz.rs)->idents"), "[]");
+        // A position anywhere in an identifier, but not just after it.
+        assert_eq!(env.run("select (:foo.rs:5:12)->idents"), "[`line`]");
+        assert_eq!(env.run("select (:foo.rs:5:13)->idents"), "no results");
+        // The same position, from a variable.
+        env.run("5");
+        assert_eq!(env.run("select (:foo.rs)->at($, 12)->idents"), "[`line`]");
+    }
+
+    #[test]
+    fn test_at() {
+        let mut env = env();
+        assert!(env.run("(:foo.rs)->at(2)").starts_with(" --> foo.rs:2:1\n"));
+        // The end of the line.
+        assert!(env
+            .run("(:foo.rs)->at(2, 40)")
+            .starts_with(" --> foo.rs:2:40\n"));
+        assert_eq!(
+            env.run("(:foo.rs)->at(2, 41)"),
+            "Error: Invalid location: column 41 of line 2 of foo.rs does not exist, the line has 39 bytes"
+        );
+        assert!(env
+            .run("(:uni.rs)->at(1, 9)")
+            .starts_with(" --> uni.rs:1:9\n"));
+        assert_eq!(
+            env.run("(:uni.rs)->at(1, 8)"),
+            "Error: Invalid location: column 8 of line 1 of uni.rs is inside a char"
+        );
+        assert_eq!(
+            env.run("(:foo.rs)->at(0)"),
+            "Error: Lines and columns start at 1, found 0:1"
+        );
+    }
+
+    #[test]
+    fn test_declarations() {
+        let mut env = env();
+        env.run("^set output quickfix");
+        assert_eq!(
+            env.run("select (:foo.rs:3)->idents[0]->decl"),
+            "bar.rs:1:9: `line`"
+        );
+        // Both identifiers are declared by the same identifier.
+        assert_eq!(
+            env.run("select (:foo.rs)->idents->decl"),
+            "bar.rs:1:9: `line`"
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:5)->idents[0]->def->ident"),
+            "bar.rs:1:9: `line`"
+        );
+        // And back again.
+        assert_eq!(
+            env.run("select (:foo.rs)->idents->def->ident->def"),
+            "bar.rs:1:9: struct `line`"
+        );
+        assert_eq!(
+            env.run("(:foo.rs)->decl"),
+            "Error: Expected identifier, found Location"
+        );
+        assert_eq!(
+            env.run("(:foo.rs)->idents->ident"),
+            "Error: Expected def, found Query(Set(Identifier))"
+        );
+    }
+
+    #[test]
+    fn test_sets() {
+        let mut env = env();
+        assert_eq!(env.run("show [1, 2,]"), "[1, 2]");
+        assert_eq!(
+            env.run("show [(:foo.rs:3), (:foo.rs:5:10)]->idents"),
+            "[`line`, `line`]"
+        );
+        assert_eq!(
+            env.run("show [(:foo.rs:3)->idents->pick, (:foo.rs:5)->idents->pick]"),
+            "[`line`, `line`]"
+        );
+        assert_eq!(
+            env.run("show [1, \"a\"]"),
+            "Error: Set elements must have the same type, found Number and String"
+        );
+        assert_eq!(env.run("show []"), "[]");
+    }
+
+    #[test]
+    fn test_index() {
+        let mut env = env();
+        env.run("[10, 11, 12, 13, 14]");
+        assert_eq!(env.run("show $[1]"), "11");
+        assert_eq!(env.run("show $0[-1]"), "14");
+        assert_eq!(env.run("show $0[1..3]"), "[11, 12]");
+        assert_eq!(env.run("show $0[-2..]"), "[13, 14]");
+        assert_eq!(env.run("show $0[3..1]"), "[]");
+        assert_eq!(env.run("show $0[..4]"), "[10, 11, 12, 13]");
+        assert_eq!(
+            env.run("show $0[5]"),
+            "Error: Index out of range: 5 (the set has 5 elements)"
+        );
+        assert_eq!(env.run("show 42[0]"), "Error: Expected set, found Number");
+        // Queries are indexed when they are evaluated.
+        assert_eq!(env.run("show (:foo.rs)->idents[-1].name"), "\"line\"");
+        assert_eq!(
+            env.run("show (:foo.rs)->idents[2]"),
+            "Error: Index out of range: 2 (the set has 2 elements)"
+        );
+    }
+
+    #[test]
+    fn test_focus() {
+        let mut env = env();
+        assert_eq!(
+            env.run("select (:3)->idents"),
+            "Error: Invalid location: no file given and there is no focus file (see `^focus`)"
+        );
+        // The focus is the file of the last result.
+        env.run("(:foo.rs)");
+        assert_eq!(env.run("select (:3)->idents"), "[`line`]");
+        env.run("(:bar.rs:1)");
+        assert_eq!(
+            env.run("show (:2)"),
+            " --> bar.rs:2\n2 | This is line 1 of a file with number 2."
+        );
+        // Results in several files (or none), and unevaluated queries, leave
+        // the focus alone.
+        env.run("[(:foo.rs), (:bar.rs)]");
+        env.run("42");
+        env.run("(:foo.rs)->idents");
+        assert_eq!(
+            env.run("show (:3)"),
+            " --> bar.rs:3\n3 | This is line 2 of a file with number 2."
+        );
+        env.run("(:foo.rs)");
+        assert_eq!(env.run("select (:5)->idents"), "[`line`]");
+    }
+
+    #[test]
+    fn test_traits() {
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let def = |id, line, name: &str| Definition {
+            id,
+            span: Span::new_zero_indexed(foo, line, 6, line, 6 + name.len()),
+            name: name.to_owned(),
+            kind: DefKind::Trait,
+            path: None,
+        };
+        let (eq, ord, hash) = (def(10, 0, "Eq"), def(11, 1, "Ord"), def(12, 2, "Hash"));
+        let ident = |id, line, name: &str| Identifier {
+            id,
+            span: Span::new_zero_indexed(foo, line, 6, line, 6 + name.len()),
+            name: name.to_owned(),
+            from_macro: false,
+            kind: None,
+            def: None,
+        };
+        let not_trait = Definition {
+            kind: DefKind::Struct,
+            ..def(13, 3, "Foo")
+        };
+        let mut env = TestEnv::new(
+            MockBackend::new()
+                .ident(ident(1, 0, "Eq"), Some(eq.clone()))
+                .ident(ident(2, 3, "Foo"), Some(not_trait))
+                .super_trait(ord.clone(), eq.clone())
+                .super_trait(hash.clone(), eq.clone())
+                .super_trait(ord.clone(), hash),
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:1:7)->idents->subtraits.name"),
+            "[\"Ord\", \"Hash\"]"
+        );
+        env.run("(:foo.rs:1:7)->idents->subtraits");
+        assert_eq!(env.run("select $->supertraits.name"), "[\"Eq\", \"Hash\"]");
+        // Elements of a set which are not traits are ignored.
+        assert_eq!(
+            env.run("select (:foo.rs:4:7)->idents->supertraits"),
+            "no results"
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:4:7)->idents[0]->supertraits"),
+            "Error: `Foo` is not a trait"
+        );
+        assert_eq!(
+            env.run("select (:foo.rs)->outline->supertraits"),
+            "Error: Expected identifier or def, found Query(Tree)"
+        );
+    }
+
+    #[test]
+    fn test_members() {
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let def = |id, line, name: &str, kind| Definition {
+            id,
+            span: Span::new_zero_indexed(foo, line, 4, line, 4 + name.len()),
+            name: name.to_owned(),
+            kind,
+            path: None,
+        };
+        let point = def(1, 0, "Point", DefKind::Struct);
+        let shape = def(2, 5, "Shape", DefKind::Trait);
+        let ident = |d: &Definition| Identifier {
+            id: d.id,
+            span: d.span.clone(),
+            name: d.name.clone(),
+            from_macro: false,
+            kind: None,
+            def: None,
+        };
+        let mut env = TestEnv::new(
+            MockBackend::new()
+                .ident(ident(&point), Some(point.clone()))
+                .ident(ident(&shape), Some(shape.clone()))
+                .member(point.clone(), def(3, 1, "x", DefKind::Field))
+                .member(point.clone(), def(4, 2, "y", DefKind::Field))
+                .member(point.clone(), def(5, 3, "len", DefKind::Method))
+                .member(shape.clone(), def(6, 6, "area", DefKind::Method)),
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:1:5)->idents->fields.name"),
+            "[\"x\", \"y\"]"
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:1:5)->idents->methods.name"),
+            "[\"len\"]"
+        );
+        let both = "[(:foo.rs:1:5)->idents, (:foo.rs:6:5)->idents]";
+        assert_eq!(
+            env.run(&format!("select {}->methods.name", both)),
+            "[\"len\", \"area\"]"
+        );
+        // Traits have methods but not fields, they are ignored in a set.
+        assert_eq!(
+            env.run(&format!("select {}->fields.name", both)),
+            "[\"x\", \"y\"]"
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:6:5)->idents[0]->fields"),
+            "Error: `Shape` is not a struct, union, or variant"
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:1:5)->fields"),
+            "Error: Expected identifier or def, found Location"
+        );
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut env = env();
+        env.run("^set limit 1");
+        assert_eq!(env.run("select (:foo.rs)->idents"), "[`line`]+");
+        assert_eq!(env.run("select $->def"), "[struct `line` at  --> bar.rs:1:9->13\n1 | This is line 0 of a file with number 2.\n            ^^^^]");
+        assert_eq!(env.run("select (:foo.rs)->idents->all"), "[`line`, `line`]");
+        assert_eq!(
+            env.run("select (:foo.rs)->all"),
+            "Error: Expected query, found Location"
+        );
+        env.run("^set limit 0");
+        assert_eq!(env.run("select (:foo.rs)->idents"), "[`line`, `line`]");
+    }
+
+    #[test]
+    fn test_eager_limit() {
+        // Out of time before the first file is searched.
+        let mut timed = env().timeout(Duration::from_secs(0));
+        assert_eq!(timed.run("show (:foo.rs)->grep \"line\""), "[]+");
+
+        let mut env = env();
+        env.run("^set limit 1");
+        let shown = env.run("show (:foo.rs)->grep \"line\"");
+        assert_eq!(shown.matches("-->").count(), 1);
+        assert!(shown.ends_with("]+"));
+    }
+
+    #[test]
+    fn test_changed() {
+        let mut env = env().diff(
+            "diff --git foo.rs foo.rs\n\
+             --- foo.rs\n\
+             +++ foo.rs\n\
+             @@ -3 +3 @@\n\
+             -old\n\
+             +new\n\
+             @@ -6,0 +7,2 @@\n\
+             +new\n\
+             +new\n",
+        );
+        assert_eq!(
+            env.run("show \"HEAD~1\"->changed"),
+            "[ --> foo.rs:3\n3 | This is line 2 of a file with number 1.,  --> foo.rs:7:1->8:40\n]"
+        );
+        assert_eq!(env.run("show \"HEAD~1\"->changed->idents"), "[`line`]");
+        assert_eq!(
+            env.run("show 42 ->changed"),
+            "Error: Expected string (a git revision), found Number"
+        );
+    }
+
+    #[test]
+    fn test_quickfix() {
+        let mut env = env();
+        env.run("^set output quickfix");
+        assert_eq!(
+            env.run("(:foo.rs)->idents"),
+            "foo.rs:3:9: `line`\nfoo.rs:5:9: `line`"
+        );
+        assert_eq!(env.run("select $->def"), "bar.rs:1:9: struct `line`");
+        assert_eq!(env.run("(:foo.rs)->diagnostics"), "foo.rs:5:1: error: oops");
+        assert_eq!(
+            env.run("(:bar.rs:2)"),
+            "bar.rs:2:1: This is line 1 of a file with number 2."
+        );
+        // Values without locations are shown as usual.
+        assert_eq!(env.run("[1, 2]"), "[1, 2]");
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut env = env();
+        env.run("^set display verbose");
+        assert_eq!(
+            env.run("show (:foo.rs:3)->idents->def").lines().next(),
+            Some("[def of `line`: struct `line` at bar.rs:1:9")
+        );
+        // Labels are only shown in verbose mode.
+        env.run("^set display compact");
+        assert_eq!(env.run("show (:foo.rs:3)->idents[0]->def"), "struct `line` at  --> bar.rs:1:9->13\n1 | This is line 0 of a file with number 2.\n            ^^^^");
+    }
+
+    #[test]
+    fn test_def_paths() {
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let bar = MockFs.find("bar.rs".to_owned().into()).unwrap()[0];
+        // Two instantiations of a generic function, with different ids.
+        let def = |id| Definition {
+            id,
+            span: Span::new_zero_indexed(bar, 0, 3, 0, 7),
+            name: "line".to_owned(),
+            kind: DefKind::Function,
+            path: Some(DefPath {
+                krate: "lines".to_owned(),
+                path: "util::line".to_owned(),
+            }),
+        };
+        let ident = |id, line| Identifier {
+            id,
+            span: Span::new_zero_indexed(foo, line, 8, line, 12),
+            name: "line".to_owned(),
+            from_macro: false,
+            kind: None,
+            def: None,
+        };
+        let mut env = TestEnv::new(
+            MockBackend::new()
+                .ident(ident(1, 2), Some(def(100)))
+                .ident(ident(2, 4), Some(def(101))),
+        );
+        assert_eq!(env.run("select (:foo.rs)->idents->def.name"), "[\"line\"]");
+        env.run("^set display verbose");
+        assert_eq!(
+            env.run("show (:foo.rs:3)->idents[0]->def").lines().next(),
+            Some("def of `line`: fn `line` (lines::util::line) at bar.rs:1:4")
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let ident = |id, name: &str, from_macro| Identifier {
+            id,
+            span: Span::new_zero_indexed(foo, 2, 0, 2, 20),
+            name: name.to_owned(),
+            from_macro,
+            kind: None,
+            def: None,
+        };
+        let mut env = TestEnv::new(
+            MockBackend::new()
+                .ident(ident(1, "line", false), None)
+                .ident(ident(2, "vec![line]", true), None),
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:3)->idents"),
+            "[`line`, `vec![line]` [macro]]"
+        );
+        assert_eq!(env.run("show $->nomacro"), "[`line`]");
+        assert_eq!(env.run("show (:foo.rs:3)->idents[1]->nomacro"), "[]");
+        assert_eq!(
+            env.run("show 42->nomacro"),
+            "Error: Expected identifiers, found Number"
+        );
+        env.run("^set macros hide");
+        assert_eq!(env.run("select (:foo.rs:3)->idents"), "[`line`]");
+        assert_eq!(env.run("select (:foo.rs:3:5)->idents"), "[`line`]");
+    }
+
+    #[test]
+    fn test_ident_kinds() {
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let ident = |id, column, name: &str, kind| Identifier {
+            id,
+            span: Span::new_zero_indexed(foo, 2, column, 2, column + 4),
+            name: name.to_owned(),
+            from_macro: false,
+            kind,
+            def: Some(id),
+        };
+        let mut env = TestEnv::new(
+            MockBackend::new()
+                .ident(ident(1, 0, "x", Some(IdentKind::Variable)), None)
+                .ident(ident(2, 5, "f", Some(IdentKind::Function)), None)
+                .ident(ident(3, 10, "Foo", Some(IdentKind::Type)), None)
+                .ident(ident(4, 15, "y", None), None),
+        );
+        env.run("^set max-set-len 10");
+        assert_eq!(
+            env.run("select (:foo.rs:3)->idents"),
+            "[variable `x`, function `f`, type `Foo`, `y`]"
+        );
+        assert_eq!(env.run("show $->kind \"function\""), "[function `f`]");
+        assert_eq!(
+            env.run("select (:foo.rs:3)->idents->kind \"type\""),
+            "[type `Foo`]"
+        );
+        assert_eq!(env.run("show $1->kind \"field\""), "[]");
+        assert_eq!(
+            env.run("show $1->kind \"fn\""),
+            "Error: Unknown identifier kind: `fn`, expected one of: variable, function, type, field, module, other"
+        );
+        assert_eq!(
+            env.run("show 42->kind \"fn\""),
+            "Error: Expected definitions or identifiers, found Number"
+        );
+    }
+
+    #[test]
+    fn test_all_results() {
+        let mut env = env();
+        assert_eq!(env.run("show $all"), "[]");
+        env.run("select (:foo.rs:3)->idents");
+        env.run("show $->nope");
+        env.run("^set max-set-len 10");
+        env.run("select (:foo.rs:5)->idents");
+        assert_eq!(env.run("show $all"), "[[`line`], [`line`]]");
+        assert_eq!(env.run("show $all->flatten->def.name"), "[\"line\"]");
+        // Earlier results of other types are left out.
+        env.run("42");
+        assert_eq!(env.run("show $all"), "[42]");
+        assert!(env.run("show $ all").starts_with("Error parsing input"));
+    }
+
+    // The environment's state is changed after each statement is evaluated.
+    #[test]
+    fn test_state() {
+        let mut env = env();
+        assert_eq!(env.run("x = 42"), "");
+        assert_eq!(env.run("^help"), "Error: help");
+        assert_eq!(env.run("y = $->nope"), "Error: Unknown function: `nope`");
+        assert_eq!(env.run("^set limit 1"), "");
+        // Assignments, meta-commands, and failed statements all have results.
+        assert_eq!(env.run("show $0"), "()");
+        assert_eq!(
+            env.run("show $1"),
+            "Error: $1 has no value, statement 1 failed"
+        );
+        assert_eq!(
+            env.run("show $2"),
+            "Error: $2 has no value, statement 2 failed"
+        );
+        assert_eq!(env.run("show x"), "42");
+        assert_eq!(env.run("show y"), "Error: Variable not found: `y`");
+        assert_eq!(env.settings().limit, 1);
+        assert_eq!(env.focus(), None);
+        env.run("(:foo.rs:3)");
+        assert!(env.focus().is_some());
+    }
+
+    #[test]
+    fn test_lambda() {
+        let mut env = env();
+        assert_eq!(env.run("f = |x| x->idents->def.name"), "");
+        assert_eq!(env.run("f"), "|x| x->idents->def.name");
+        assert_eq!(env.run("show (:foo.rs)->f"), "[\"line\"]");
+        // Lambdas can apply other lambdas, and the parameter is only bound in
+        // the lambda's body.
+        env.run("x = 42");
+        env.run("g = |x| [x->f, (:foo.rs:5)->f]");
+        assert_eq!(env.run("show (:foo.rs:3)->g"), "[[\"line\"], [\"line\"]]");
+        assert_eq!(env.run("show x"), "42");
+        assert_eq!(
+            env.run("show x->g"),
+            "Error: Expected location, found Number"
+        );
+        assert_eq!(env.run("show $->h"), "Error: Unknown function: `h`");
+        assert_eq!(
+            env.run("show $->x"),
+            "Error: `x` is not a function, found Number"
+        );
+        assert_eq!(
+            env.run("show $->f 1"),
+            "Error: `f` takes no arguments, found 1"
+        );
+        assert_eq!(
+            env.run("idents = |x| x"),
+            "Error: Can't assign to `idents`, it is the name of a function"
+        );
+        env.run("r = |x| x->r");
+        assert_eq!(
+            env.run("show $->r"),
+            "Error: Functions are nested too deeply (is one recursive?)"
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        let mut env = env();
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->map _.name"),
+            "[\"line\", \"line\"]"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->map [_.name, \"x\"]"),
+            "[[\"line\", \"x\"], [\"line\", \"x\"]]"
+        );
+        env.run("f = |x| x->def.name");
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->map f"),
+            "[\"line\", \"line\"]"
+        );
+        assert_eq!(
+            env.run("show [(:foo.rs:3), (:foo.rs:5)]->map |l| l->idents"),
+            "[[`line`], [`line`]]"
+        );
+        assert_eq!(env.run("show []->map _"), "[]");
+        assert_eq!(
+            env.run("show 42->map _"),
+            "Error: Expected set, found Number"
+        );
+        assert_eq!(env.run("show _"), "Error: Variable not found: `_`");
+    }
+
+    #[test]
+    fn test_graph() {
+        let mut env = env();
+        let dot = "digraph {\n    n0 [label=\"line (bar.rs:1)\"];\n}\n";
+        assert_eq!(env.run("(:foo.rs:3)->idents->def->graph"), dot);
+        assert_eq!(
+            env.run("(:foo.rs)->idents->def->graph(2, \"line.dot\")"),
+            format!("line.dot:\n{}", dot)
+        );
+        assert_eq!(env.run("[]->graph"), "digraph {\n}\n");
+        assert_eq!(
+            env.run("(:foo.rs)->idents->graph"),
+            "Error: Expected def, found query<set<identifier>>"
+        );
+        assert_eq!(
+            env.run("[]->graph \"2\""),
+            "Error: Expected number, found string"
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let mut env = env();
+        assert_eq!(
+            env.run("(:foo.rs)->idents->export \"out.csv\""),
+            "out.csv:\nfile,line,col,name,kind\n\
+             foo.rs,3,9,line,identifier\n\
+             foo.rs,5,9,line,identifier\n"
+        );
+        assert_eq!(
+            env.run("[]->export \"out.tsv\""),
+            "out.tsv:\nfile\tline\tcol\tname\tkind\n"
+        );
+        assert_eq!(
+            env.run("1->export \"out.csv\""),
+            "Error: Expected location, identifier, def, or diagnostic, found number"
+        );
+
+        env.run("^set output csv");
+        assert_eq!(
+            env.run("(:foo.rs)->diagnostics"),
+            "file,line,col,name,kind\nfoo.rs,5,1,oops,error"
+        );
+        assert_eq!(
+            env.run("show (:bar.rs:1:9)->idents->def"),
+            "file,line,col,name,kind\nbar.rs,1,9,line,struct"
+        );
+        assert_eq!(env.run("show 42"), "42");
+        assert_eq!(env.run("show []"), "file,line,col,name,kind");
+    }
+
+    #[test]
+    fn test_grep() {
+        let mut env = env();
+        env.run("^set output quickfix");
+        assert_eq!(
+            env.run("(:foo.rs)->grep \"line 1[^0-9]\""),
+            "foo.rs:2:9: This is line 1 of a file with number 1."
+        );
+        assert_eq!(
+            env.run("(:foo.rs:3)->grep(\"THIS\", \"-i\")"),
+            "foo.rs:3:1: This is line 2 of a file with number 1."
+        );
+        // Each match once, even if locations overlap.
+        assert_eq!(
+            env.run("[(:foo.rs:3), (:foo.rs:3:1)]->grep(\".\", \"F\")"),
+            "foo.rs:3:39: This is line 2 of a file with number 1."
+        );
+        assert_eq!(
+            env.run("(:foo.rs:3)->idents->def->grep(\"line\", \"-w\")"),
+            "bar.rs:1:9: This is line 0 of a file with number 2."
+        );
+        env.run("^set output text");
+        env.run("^set display verbose");
+        assert_eq!(
+            env.run("(:foo.rs:2)->grep(\"is\", \"-wi\")").lines().next(),
+            Some("[match of \"is\" -iw:  --> foo.rs:2:6->8")
+        );
+        assert_eq!(env.run("show (:foo.rs)->grep \"zzz\""), "[]");
+        assert_eq!(
+            env.run("(:foo.rs)->grep(\"x\", \"-v\")"),
+            "Error: Unknown flag for `grep`: `v`, expected `i` (ignore case), `w` (whole words), \
+             or `F` (fixed string)"
+        );
+        assert_eq!(
+            env.run("(:foo.rs)->grep \"(\""),
+            "Error: Invalid search: invalid regular expression `(`: unclosed group"
+        );
+        assert_eq!(
+            env.run("5->grep \"x\""),
+            "Error: Expected location, found Number"
+        );
+    }
+
+    #[test]
+    fn test_show_non_ascii() {
+        let mut env = env();
+        // Columns are bytes, but carets are under the chars (`名` is two
+        // columns wide).
+        assert_eq!(
+            env.run("show (:uni.rs)->grep \"ße\""),
+            "[ --> uni.rs:1:9->12\n\
+             1 | let größe = \"名\"; // ße\n\
+             \x20          ^^, \
+             \x20--> uni.rs:1:25->28\n\
+             1 | let größe = \"名\"; // ße\n\
+             \x20                        ^^]"
+        );
+        assert_eq!(
+            env.run("show (:uni.rs:1:21)"),
+            " --> uni.rs:1:21\n1 | let größe = \"名\"; // ße\n                     ^"
+        );
+    }
+
+    #[test]
+    fn test_dups() {
+        let mut env = env();
+        // Every line of a mock file is different.
+        assert_eq!(env.run("show [(:foo.rs), (:bar.rs:2)]->dups 1"), "[]");
+        assert_eq!(
+            env.run("(:foo.rs)->dups 0"),
+            "Error: `dups` compares at least 1 line, found 0"
+        );
+        assert_eq!(
+            env.run("(:foo.rs)->dups \"x\""),
+            "Error: Expected number, found string"
+        );
+        assert_eq!(env.run("5->dups"), "Error: Expected location, found Number");
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut env = env();
+        assert_eq!(env.run("select (:baz.rs)->idents"), "no results");
+        // Functions of an empty set give an empty set.
+        assert_eq!(env.run("show (:baz.rs)->idents->def"), "[]");
+        // Except for those which need an element.
+        assert_eq!(env.run("show (:baz.rs)->idents->pick"), "Error: `pick` requires at least one element, but the set is empty (there are no results)");
+        env.run("^set output quickfix");
+        assert_eq!(env.run("select (:baz.rs)->idents"), "");
+    }
+
+    #[test]
+    fn test_ambiguous_file() {
+        // Without a line, the location is every file.
+        assert_eq!(env().run("select (:dup.rs)->idents"), "no results");
+        assert_eq!(env().run("show (:dup.rs:3)"), "Error: Invalid location: `dup.rs` matches 2 files, give more of the path to choose one of: a/dup.rs, b/dup.rs");
+        let mut env = env().answer(&["1"]);
+        assert_eq!(env.run("show (:dup.rs:3:2)"), "`dup.rs` matches 2 files:\n0: a/dup.rs\n1: b/dup.rs\nchoose one (0-1), or press enter for the first: \n --> b/dup.rs:3:2\n3 | This is line 2 of a file with number 5.\n     ^");
+    }
+
+    #[test]
+    fn test_crate_and_mod() {
+        let mut env = env();
+        assert_eq!(env.run("crate \"b\""), "b/dup.rs");
+        assert_eq!(
+            env.run("show (crate \"mock\")->idents"),
+            "[`line`, `line`, `line`]"
+        );
+        assert_eq!(env.run("show \"a\"->mod"), "[a/dup.rs, a/lib.rs]");
+        assert_eq!(env.run("mod \"foo\""), "foo.rs");
+        assert_eq!(
+            env.run("crate \"c\""),
+            "Error: Invalid location: no crate named `c`"
+        );
+        assert_eq!(
+            env.run("mod \"qux\""),
+            "Error: Invalid location: no module `qux`"
+        );
+        assert_eq!(env.run("crate 1"), "Error: Expected string, found number");
+    }
+
+    #[test]
+    fn test_context() {
+        let mut env = env();
+        env.run("^set context 1");
+        assert_eq!(env.run("show (:foo.rs:9:6)"), " --> foo.rs:9:6\n 8 | This is line 7 of a file with number 1.\n 9 | This is line 8 of a file with number 1.\n          ^\n10 | This is line 9 of a file with number 1.");
+        assert_eq!(env.run("show (:foo.rs:1)"), " --> foo.rs:1\n1 | This is line 0 of a file with number 1.\n2 | This is line 1 of a file with number 1.");
+        assert_eq!(env.run("show (:bar.rs:1:9)->idents->def"), "[struct `line` at  --> bar.rs:1:9->13\n1 | This is line 0 of a file with number 2.\n            ^^^^\n2 | This is line 1 of a file with number 2.]");
+        env.run("^set context 0");
+        assert_eq!(
+            env.run("show (:foo.rs:9:6)"),
+            " --> foo.rs:9:6\n9 | This is line 8 of a file with number 1.\n         ^"
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut env = env();
+        assert_eq!(env.run("^explain show (:foo.rs)->idents->def"), "show: void\n  def: query<set<def>>, calls definition\n    idents: query<set<identifier>>, calls idents_at, idents_in\n      (:foo.rs): location");
+        env.run("[1, 2, 3]");
+        assert_eq!(env.run("^explain x = [$, $[1..], [4]][..2]"), "x = [..2]: set<set<number>>, at most 2 elements\n  [...]: set<set<number>>\n    $: set<number>, 3 elements\n    [1..]: set<number>, 2 elements\n      $: set<number>, 3 elements\n    [...]: set<number>, at most 1 element\n      4: number");
+        assert_eq!(
+            env.run("^explain (:bar.rs)->idents->map _->def->kind(\"struct\")"),
+            "map _->def->kind \"struct\": set<set<def>>\n  idents: query<set<identifier>>, calls idents_at, idents_in\n    (:bar.rs): location"
+        );
+        assert_eq!(
+            env.run("^explain 3->idents"),
+            "Error: Expected location, found Number"
+        );
+        // Nothing was evaluated.
+        assert_eq!(env.run("x"), "Error: Variable not found: `x`");
+    }
+
+    #[test]
+    fn test_pick() {
+        let mut env = env().answer(&["7", "1", "", "0"]);
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->pick->start"),
+            "2 elements:\n\
+             0: foo.rs:3:9: `line`\n\
+             1: foo.rs:5:9: `line`\n\
+             choose one (0-1), or press enter for the first: \n\
+             expected a number from 0 to 1\n\
+             choose one (0-1), or press enter for the first: \n \
+             --> foo.rs:5:9\n\
+             5 | This is line 4 of a file with number 1.\n\
+             \x20           ^"
+        );
+        assert_eq!(
+            env.run("show [3, 4]->pick"),
+            "2 elements:\n0: 3\n1: 4\nchoose one (0-1), or press enter for the first: \n3"
+        );
+        // A single element is picked without asking.
+        assert_eq!(env.run("show [5]->pick"), "5");
+        assert_eq!(
+            env.run("show [(:foo.rs:3), (:bar.rs:2)]->pick")
+                .lines()
+                .nth(1),
+            Some("0: foo.rs:3:1: This is line 2 of a file with number 1.")
+        );
+        // The input has ended.
+        assert_eq!(
+            env.run("show [7, 8]->pick"),
+            "2 elements:\n0: 7\n1: 8\nchoose one (0-1), or press enter for the first: \n7"
+        );
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut env = env();
+        assert_eq!(env.run("show [[1, 2], [3]]->flatten"), "[1, 2, 3]");
+        assert_eq!(
+            env.run("show [(:foo.rs:3)->idents, (:foo.rs:5)->idents]->flatten"),
+            "[`line`, `line`]"
+        );
+        assert_eq!(
+            env.run("show [1, 2]->flatten"),
+            "Error: Expected set of sets, found Set(Number)"
+        );
+        // Functions which take a set flatten nested sets.
+        assert_eq!(
+            env.run("show [(:foo.rs:3)->idents, (:foo.rs:5)->idents]->name"),
+            "[\"line\", \"line\"]"
+        );
+        assert_eq!(
+            env.run("show [[(:foo.rs:3)->idents]]->def->kind \"struct\"")
+                .lines()
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_within() {
+        let mut env = env();
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->within (:foo.rs:3)"),
+            "[`line`]"
+        );
+        assert_eq!(env.run("show (:foo.rs)->idents->within (:foo.rs:2)"), "[]");
+        env.run("refs = [(:foo.rs:3), (:foo.rs:5)]->idents");
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->within refs"),
+            "[`line`, `line`]"
+        );
+        assert_eq!(env.run("show (:foo.rs:3:10)->within (:foo.rs:3)"), "true");
+        assert_eq!(env.run("show (:foo.rs:3)->within (:foo.rs:3:10)"), "false");
+        assert!(env
+            .run("show (:foo.rs:3)->idents->def->within (:bar.rs)")
+            .starts_with("[struct `line` at  --> bar.rs:1:9->13"));
+        assert_eq!(
+            env.run("show (:foo.rs:3)->idents->def->within (:foo.rs)"),
+            "[]"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->overlaps (:foo.rs:5:10)"),
+            "[`line`]"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->overlaps (:foo.rs:5:13)"),
+            "[]"
+        );
+        assert!(env
+            .run("show (:foo.rs)->diagnostics->overlaps (:foo.rs:5)")
+            .starts_with("[error: oops"));
+        assert_eq!(
+            env.run("show (:foo.rs)->diagnostics->overlaps (:foo.rs:5:10)"),
+            "[]"
+        );
+        assert_eq!(
+            env.run("show 1->within (:foo.rs)"),
+            "Error: Expected location, identifier, def, or diagnostic, found Number"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs)->within 1"),
+            "Error: Expected location, found Number"
+        );
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut env = env();
+        assert_eq!(env.run("show [1, 2, 3]->changes [2, 3, 4]"), "- 1\n+ 4");
+        env.run("select (:foo.rs:3)->idents");
+        env.run("select (:foo.rs)->idents");
+        assert_eq!(env.run("show $1->changes $2"), "+ `line`");
+        assert_eq!(env.run("show $2->changes $1"), "- `line`");
+        assert_eq!(
+            env.run("show $1->changes (:foo.rs:5)->idents"),
+            "- `line`\n+ `line`"
+        );
+        assert_eq!(env.run("show $1->changes $1"), "no changes");
+        assert_eq!(
+            env.run("show $1->changes [1]"),
+            "Error: Can't compare sets of different types, found set<identifier> and set<number>"
+        );
+        env.run("^set output quickfix");
+        assert_eq!(env.run("show $1->changes $2"), "foo.rs:5:9: + `line`");
+    }
+
+    #[test]
+    fn test_syntax() {
+        let mut env = env();
+        // The mock files are not Rust, see `front::syntax` for the scanning.
+        assert_eq!(env.run("show (:foo.rs)->unsafes"), "[]");
+        assert_eq!(env.run("show [(:foo.rs:3), (:bar.rs)]->derives"), "[]");
+        assert_eq!(env.run("show (:foo.rs)->idents->def->tests"), "[]");
+        assert_eq!(
+            env.run("show 1->unsafes"),
+            "Error: Expected location, found Number"
+        );
+    }
+
+    #[test]
+    fn test_group() {
+        let mut env = env();
+        // Largest first, nested sets are flattened.
+        assert_eq!(
+            env.run("show [(:bar.rs)->idents, (:foo.rs)->idents]->group \"file\""),
+            "foo.rs: 2\nbar.rs: 1"
+        );
+        assert_eq!(env.run("show (:foo.rs)->idents->group \"name\""), "line: 2");
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->def->group \"kind\""),
+            "struct: 1"
+        );
+        assert_eq!(env.run("show []->group \"name\""), "{}");
+        assert_eq!(
+            env.run("show [1, 2]->group \"kind\""),
+            "Error: Can't group number by kind"
+        );
+        assert_eq!(
+            env.run("show [1, 2]->group \"file\""),
+            "Error: Can't group by file, number is not in a single file"
+        );
+        assert_eq!(
+            env.run("show []->group \"size\""),
+            "Error: Unknown key for `group`: `size`, expected `file`, `kind`, or `name`"
+        );
+        assert_eq!(
+            env.run("show 42->group \"name\""),
+            "Error: Expected set, found Number"
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut env = env();
+        assert_eq!(env.run("show (:foo.rs:3)->idents->name"), "[\"line\"]");
+        assert_eq!(env.run("show (:foo.rs:3)->idents->pick.name"), "\"line\"");
+        assert_eq!(env.run("show \"test_foo\"->matches \"^test_\""), "true");
+        assert_eq!(env.run("show \"foo\"->starts \"o\""), "false");
+        // On sets, tests are filters.
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->contains \"in\""),
+            "[`line`, `line`]"
+        );
+        assert_eq!(env.run("show (:foo.rs)->idents->def->starts \"x\""), "[]");
+        assert_eq!(
+            env.run("show (:foo.rs)->idents.name->matches \"e$\""),
+            "[\"line\", \"line\"]"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs)->idents->matches \"(\""),
+            "Error: Invalid regular expression `(`: unclosed group"
+        );
+        assert_eq!(
+            env.run("show [1, 2]->contains \"4\""),
+            "Error: Expected string, identifier, or def, found Set(Number)"
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_idents() {
+        // `line` on line 3 is also part of a (macro-expanded) `expanded`.
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let backend = || {
+            MockBackend::new()
+                .ident(
+                    Identifier {
+                        id: 1,
+                        span: Span::new_zero_indexed(foo, 2, 8, 2, 12),
+                        name: "line".to_owned(),
+                        from_macro: false,
+                        kind: None,
+                        def: None,
+                    },
+                    None,
+                )
+                .ident(
+                    Identifier {
+                        id: 2,
+                        span: Span::new_zero_indexed(foo, 2, 0, 2, 20),
+                        name: "expanded".to_owned(),
+                        from_macro: false,
+                        kind: None,
+                        def: None,
+                    },
+                    None,
+                )
+                .ty(1, "Line")
+                .ty(2, "Expanded")
+        };
+
+        let mut env = TestEnv::new(backend());
+        assert_eq!(
+            env.run("show (:foo.rs:3:10)->idents"),
+            "[`line`, `expanded`]"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs:3:10)->typeof"),
+            "Error: 2 identifiers at position (`line`, `expanded`), use `idents` to choose one"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs:3:2)->typeof"),
+            "\"expanded: Expanded\""
+        );
+
+        let mut env = TestEnv::new(backend()).choose(1);
+        assert_eq!(env.run("show (:foo.rs:3:10)->idents"), "[`expanded`]");
+        assert_eq!(
+            env.run("show (:foo.rs:3:10)->typeof"),
+            "\"expanded: Expanded\""
+        );
+        // Only positions are ambiguous.
+        assert_eq!(env.run("show (:foo.rs:3)->idents"), "[`line`, `expanded`]");
+    }
+
+    #[test]
+    fn test_info() {
+        let mut env = env();
+        assert_eq!(
+            env.run("show (:foo.rs:3:10)->info"),
+            "`line`: Vec<String>\n  struct `line` at bar.rs:1:9\n  /// A line of a file."
+        );
+        // No type for the second identifier.
+        assert_eq!(
+            env.run("show (:foo.rs:5:10)->info"),
+            "`line`\n  struct `line` at bar.rs:1:9\n  /// A line of a file."
+        );
+        assert_eq!(env.run("show (:foo.rs)->idents->info").lines().count(), 5);
+        assert_eq!(
+            env.run("show (:foo.rs:1:1)->info"),
+            "Error: No identifier at position"
+        );
+        env.run("^set output quickfix");
+        assert_eq!(
+            env.run("show (:foo.rs:3:10)->info"),
+            "bar.rs:1:9: `line`: Vec<String>"
+        );
+    }
+
+    #[test]
+    fn test_typeof() {
+        let mut env = env();
+        assert_eq!(
+            env.run("show (:foo.rs:3:10)->typeof"),
+            "\"line: Vec<String>\""
+        );
+        assert_eq!(
+            env.run("show (:foo.rs:3)->idents->typeof"),
+            "[\"line: Vec<String>\"]"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs:5)->idents->typeof"),
+            "Error: no type for `line`"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs:1:1)->typeof"),
+            "Error: No identifier at position"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs)->typeof"),
+            "Error: Unexpected runtime type, expected: identifier or position, found: Range"
+        );
+    }
+
+    #[test]
+    fn test_outline_and_diagnostics() {
+        let mut env = env();
+        assert_eq!(env.run("show (:bar.rs)->outline"), "struct line (bar.rs:1)");
+        assert_eq!(
+            env.run("show (:foo.rs:5)->diagnostics"),
+            "[error: oops\n\
+             \x20--> foo.rs:5:1->5\n\
+             5 | This is line 4 of a file with number 1.\n\
+             \x20   ^^^^]"
+        );
+        assert_eq!(env.run("show (:foo.rs:4)->diagnostics"), "[]");
+    }
+
+    #[test]
+    fn test_enclosing() {
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let def = |id, line, name: &str, kind| Definition {
+            id,
+            span: Span::new_zero_indexed(foo, line, 4, line, 4 + name.len()),
+            name: name.to_owned(),
+            kind,
+            path: None,
+        };
+        // mod m {
+        //     fn a() {
+        //         let x = ...
+        //     }
+        //
+        //     struct S;
+        // }
+        // fn b() { a() }
+        let a = def(2, 1, "a", DefKind::Function);
+        let mut env = TestEnv::new(
+            MockBackend::new()
+                .symbol(def(1, 0, "m", DefKind::Mod), None)
+                .symbol(a.clone(), Some(1))
+                .symbol(def(3, 2, "x", DefKind::Local), Some(2))
+                .symbol(def(4, 5, "S", DefKind::Struct), Some(1))
+                .symbol(def(5, 7, "b", DefKind::Function), None)
+                .ident(
+                    Identifier {
+                        id: 6,
+                        span: Span::new_zero_indexed(foo, 7, 12, 7, 13),
+                        name: "a".to_owned(),
+                        from_macro: false,
+                        kind: None,
+                        def: None,
+                    },
+                    Some(a),
+                ),
+        );
+        assert_eq!(env.run("select (:foo.rs:3:1)->enclosing.name"), "[\"a\"]");
+        assert_eq!(env.run("select (:foo.rs:3:5)->enclosing.name"), "[\"x\"]");
+        assert_eq!(
+            env.run("select (:foo.rs:3:10)->enclosing(\"mod\").name"),
+            "[\"m\"]"
+        );
+        // The item after `a` which is not nested in it ends it.
+        assert_eq!(
+            env.run("select [(:foo.rs:4), (:foo.rs:7)]->enclosing.name"),
+            "[\"a\", \"S\"]"
+        );
+        assert_eq!(
+            env.run("select (:foo.rs:8:13)->idents->enclosing(\"fn\").name"),
+            "[\"b\"]"
+        );
+        // Of a definition, its parent.
+        assert_eq!(
+            env.run("select (:foo.rs:8:13)->idents->def->enclosing.name"),
+            "[\"m\"]"
+        );
+        assert_eq!(env.run("select (:foo.rs)->enclosing"), "no results");
+        assert_eq!(
+            env.run("(:foo.rs:3)->enclosing \"impl\""),
+            "Error: Unknown kind: `impl`, expected one of: fn, method, struct, enum, variant, \
+             union, trait, type, mod, macro, const, static, field, local"
+        );
+        assert_eq!(
+            env.run("[1]->enclosing"),
+            "Error: Expected location, found set<number>"
+        );
+        assert_eq!(
+            env.run("(:foo.rs:3)->enclosing 1"),
+            "Error: Expected string, found number"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut env = env();
+        assert_eq!(env.run("foo $"), "Error: Unknown function: `foo`");
+        assert_eq!(
+            env.run("show $3"),
+            "Error: $3 requested but only 1 result exists"
+        );
+        assert_eq!(
+            env.run("show $0"),
+            "Error: $0 has no value, statement 0 failed"
+        );
+        env.run("5");
+        assert_eq!(
+            env.run("show $-9"),
+            "Error: $-9 requested but only 4 results exist"
+        );
+        assert_eq!(
+            env.run("show $-1"),
+            "Error: $4 has no value, statement 4 failed"
+        );
+        assert_eq!(
+            env.run("show (:foo.rs"),
+            "Error parsing input: Unexpected end of input (unclosed delimiters), \
+             expected `)` (at offset 12)"
+        );
+        assert_eq!(env.run("^set foo bar"), "Error: Unknown setting: `foo`");
+    }
+}
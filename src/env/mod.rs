@@ -1,69 +1,108 @@
-use crate::back::Backend;
-use crate::file_system::FileSystem;
-use crate::front::{self, Show};
+use crate::back::{Backend, Capabilities, NoProgress, Progress};
+use crate::file_system::{FileSystem, Path};
+use crate::front::data::{Identifier, Line, Position};
+use crate::front::{self, DisplayPolicy, ShowCtx};
 use crate::parse::{self, ast};
 use std::rc::Rc;
 
+pub use self::config::{ColorChoice, Verbosity};
+pub use self::settings::{
+    DisplayDuration, DisplayMode, MacroMode, OutputMode, PromptVars, Settings,
+};
+
+pub(crate) mod config;
+pub(crate) mod daemon;
+mod logging;
+pub(crate) mod lsp_server;
+#[cfg(test)]
+pub mod mock;
+mod persist;
+mod progress;
+mod record;
 pub(crate) mod repl;
+pub(crate) mod session;
+mod settings;
+mod vcs;
+mod watch;
 
+/// What the interpreter needs while a statement is evaluated. State which
+/// changes between statements belongs in `SessionState`.
 pub trait Environment {
     type ParseContext: parse::EnvContext;
     type Fs: FileSystem;
 
-    fn exec_meta(&self, mk: ast::MetaKind) -> Result<(), front::Error>;
-    fn show(&self, s: &impl Show) -> Result<(), front::Error>;
+    fn show(&self, value: &front::Value) -> Result<(), front::Error>;
+    /// Show a result which is an empty set.
+    fn show_empty(&self, value: &front::Value) -> Result<(), front::Error> {
+        self.show(value)
+    }
     fn lookup_var(&self, var: &front::MetaVar) -> Result<front::Value, front::Error>;
     fn lookup_numeric_var(&self, id: isize) -> Result<front::Value, front::Error>;
+    /// The results of previous statements, oldest first.
+    fn all_results(&self) -> Vec<front::Value> {
+        Vec::new()
+    }
     fn file_system(&self) -> &Self::Fs;
     fn backend(&self) -> Rc<dyn Backend>;
-}
-
-#[cfg(test)]
-pub mod mock {
-    use super::*;
-    use crate::file_system::MockFs;
-
-    pub struct MockEnv;
-
-    impl Environment for MockEnv {
-        type ParseContext = ();
-        type Fs = MockFs;
-
-        fn exec_meta(&self, mk: ast::MetaKind) -> Result<(), front::Error> {
-            Err(front::Error::Other(match mk {
-                ast::MetaKind::Help => "help".to_owned(),
-                ast::MetaKind::Exit => "exit".to_owned(),
-            }))
-        }
-
-        fn show(&self, s: &impl Show) -> Result<(), front::Error> {
-            Err(front::Error::Other(s.show_str(self)))
-        }
-
-        fn lookup_var(&self, _: &front::MetaVar) -> Result<front::Value, front::Error> {
-            Err(front::Error::Other(
-                "MockEnv does not support var lookup".to_owned(),
-            ))
-        }
-
-        fn lookup_numeric_var(&self, _: isize) -> Result<front::Value, front::Error> {
-            Err(front::Error::Other(
-                "MockEnv does not support numeric var lookup".to_owned(),
-            ))
-        }
-
-        fn file_system(&self) -> &Self::Fs {
-            &MockFs
-        }
-
-        fn backend(&self) -> Rc<dyn Backend> {
-            unimplemented!()
-        }
+    /// Should not require starting the backend.
+    fn capabilities(&self) -> Capabilities;
+    fn settings(&self) -> Settings;
+    fn chooses_idents(&self) -> bool {
+        false
     }
-
-    impl parse::EnvContext for () {
-        fn clone(&self) -> Box<dyn parse::EnvContext> {
-            Box::new(())
-        }
+    /// By default, all are used.
+    fn choose_idents(
+        &self,
+        _position: &Position,
+        idents: Vec<Identifier>,
+    ) -> Result<Vec<Identifier>, front::Error> {
+        Ok(idents)
+    }
+    fn can_prompt(&self) -> bool {
+        false
+    }
+    /// `None` if there is no answer, e.g., the input has ended.
+    fn prompt_user(&self, _message: &str) -> Result<Option<String>, front::Error> {
+        Ok(None)
+    }
+    /// Dropped when evaluation finishes.
+    fn progress(&self) -> Box<dyn Progress> {
+        Box::new(NoProgress)
+    }
+    /// See `Settings::timeout`.
+    fn budget(&self) -> Option<&front::Budget> {
+        None
+    }
+    fn display_policy(&self) -> DisplayPolicy {
+        DisplayPolicy::default()
     }
+    fn show_ctx(&self) -> ShowCtx {
+        self.display_policy().show_ctx(&self.settings())
+    }
+    /// Write `text` to the file `path`, or to the output.
+    fn write_text(&self, _path: Option<&str>, _text: &str) -> Result<(), front::Error> {
+        Err(front::Error::Other(
+            "writing text is not supported".to_owned(),
+        ))
+    }
+    /// See `^focus`.
+    fn focus(&self) -> Option<Path> {
+        None
+    }
+    /// The first and last lines of each change since `revision`.
+    fn changed(&self, _revision: &str) -> Result<Vec<(Path, Line, Line)>, front::Error> {
+        Err(front::Error::Other(
+            "version control is not supported".to_owned(),
+        ))
+    }
+}
+
+/// The state of an environment which changes between statements, see
+/// `front::eval_stmt`.
+pub trait SessionState: Environment {
+    fn exec_meta(&mut self, mk: ast::MetaKind) -> Result<(), front::Error>;
+    fn set_var(&mut self, var: front::MetaVar, value: front::Value);
+    /// `None` if the statement failed.
+    fn push_result(&mut self, result: Option<front::Value>);
+    fn set_focus(&mut self, file: Path);
 }
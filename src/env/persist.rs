@@ -0,0 +1,48 @@
+//! Saving values to, and loading values from, files.
+
+use crate::file_system::{FileSystem, Path};
+use crate::front::{self, Value};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+// The keys of `Path`s are specific to a session, so we save the physical path
+// for every path used in the value and remap them when the value is loaded.
+#[derive(Serialize, Deserialize)]
+struct SavedValue {
+    paths: Vec<(Path, PathBuf)>,
+    value: Value,
+}
+
+pub fn save(value: &Value, fs: &impl FileSystem, w: impl Write) -> Result<(), front::Error> {
+    let mut paths = Vec::new();
+    let mut value = value.clone();
+    value.try_map_paths(&mut |p| -> Result<Path, front::Error> {
+        if !paths.iter().any(|(k, _)| *k == p) {
+            paths.push((p, fs.physical_path(&p)?));
+        }
+        Ok(p)
+    })?;
+
+    serde_json::to_writer_pretty(w, &SavedValue { paths, value })
+        .map_err(|e| front::Error::Other(format!("Error saving value: {}", e)))
+}
+
+pub fn load(fs: &impl FileSystem, r: impl Read) -> Result<Value, front::Error> {
+    let saved: SavedValue = serde_json::from_reader(r)
+        .map_err(|e| front::Error::Other(format!("Error loading value: {}", e)))?;
+
+    let mut paths = Vec::new();
+    for (key, physical) in saved.paths {
+        paths.push((key, fs.resolve_path(&physical)?));
+    }
+
+    let mut value = saved.value;
+    value.try_map_paths(&mut |p| match paths.iter().find(|(k, _)| *k == p) {
+        Some((_, p)) => Ok(*p),
+        None => Err(front::Error::Other(
+            "Error loading value: unknown path".to_owned(),
+        )),
+    })?;
+    Ok(value)
+}
@@ -0,0 +1,83 @@
+//! Showing the progress of long operations (e.g., finding the identifiers in
+//! every file of a project) as a bar on stderr.
+
+use crate::back::Progress;
+use std::io::{stderr, Write};
+use std::sync::Mutex;
+
+// The width of the bar, not including the brackets or the count.
+const WIDTH: usize = 30;
+
+/// A progress bar drawn on a single line of stderr, which is erased when the
+/// bar is dropped. Only use this when stderr is a terminal.
+pub struct ProgressBar {
+    // The line last drawn, if any.
+    drawn: Mutex<Option<String>>,
+}
+
+impl ProgressBar {
+    pub fn new() -> ProgressBar {
+        ProgressBar {
+            drawn: Mutex::new(None),
+        }
+    }
+}
+
+impl Progress for ProgressBar {
+    fn update(&self, done: usize, total: usize) {
+        // A single step is over too soon for a bar to be any use.
+        if total <= 1 {
+            return;
+        }
+        let line = render(done, total);
+        let mut drawn = self.drawn.lock().unwrap();
+        if drawn.as_ref() == Some(&line) {
+            return;
+        }
+        let mut err = stderr();
+        // There is nowhere to report a failure to draw the bar.
+        let _ = write!(err, "\r{}", line);
+        let _ = err.flush();
+        *drawn = Some(line);
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        if let Some(line) = &*self.drawn.lock().unwrap() {
+            let mut err = stderr();
+            let _ = write!(err, "\r{:width$}\r", "", width = line.len());
+            let _ = err.flush();
+        }
+    }
+}
+
+// E.g., `[=========>          ] 12/40`.
+fn render(done: usize, total: usize) -> String {
+    let done = done.min(total);
+    let filled = done * WIDTH / total;
+    let bar = if filled == WIDTH {
+        "=".repeat(WIDTH)
+    } else if filled == 0 {
+        " ".repeat(WIDTH)
+    } else {
+        "=".repeat(filled - 1) + ">" + &" ".repeat(WIDTH - filled)
+    };
+    format!("[{}] {}/{}", bar, done, total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render(0, 3), format!("[{}] 0/3", " ".repeat(WIDTH)));
+        assert_eq!(
+            render(1, 3),
+            format!("[{}>{}] 1/3", "=".repeat(9), " ".repeat(20))
+        );
+        assert_eq!(render(3, 3), format!("[{}] 3/3", "=".repeat(WIDTH)));
+        assert_eq!(render(5, 3), render(3, 3));
+    }
+}
@@ -0,0 +1,88 @@
+//! Recording a session to a transcript file (`^record path`). A transcript is
+//! a Clyde script: each statement is written on its own line and, optionally,
+//! output is written as comments. A transcript can be replayed by piping it
+//! to Clyde.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub struct Recorder {
+    path: PathBuf,
+    file: File,
+    // Record output as well as statements.
+    output: bool,
+}
+
+impl Recorder {
+    /// Start recording to `path`, appending if the file already exists.
+    pub fn new(path: &Path, output: bool) -> io::Result<Recorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            path: path.to_owned(),
+            file,
+            output,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn statement(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.file, "{}", text.trim())
+    }
+
+    /// True if output as well as statements is being recorded.
+    pub fn records_output(&self) -> bool {
+        self.output
+    }
+
+    /// Record output, if we are recording output.
+    pub fn output(&mut self, text: &str) -> io::Result<()> {
+        if !self.output {
+            return Ok(());
+        }
+        for line in text.lines() {
+            if line.is_empty() {
+                writeln!(self.file, "#")?;
+            } else {
+                writeln!(self.file, "# {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_record() {
+        let dir = PathBuf::from("./target");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!(
+            "test-record-{}.cly",
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+
+        let mut recorder = Recorder::new(&path, false).unwrap();
+        recorder.statement("show $;\n").unwrap();
+        recorder.output("42").unwrap();
+        let mut recorder = Recorder::new(&path, true).unwrap();
+        recorder.statement("  select $->idents").unwrap();
+        recorder.output("[`foo`]\n\n --> foo.rs").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "show $;\nselect $->idents\n# [`foo`]\n#\n#  --> foo.rs\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
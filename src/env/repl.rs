@@ -1,172 +1,424 @@
-use super::Environment;
-use crate::back;
-use crate::file_system::PhysicalFs;
-use crate::front::{self, data, MetaVar, Show};
-use crate::parse::{self, ast};
-use std::cell::RefCell;
-use std::env;
-use std::io::{stdin, stdout, Write};
-use std::path::PathBuf;
-use std::process;
-use std::rc::Rc;
+//! The interactive interface to Clyde, and evaluating single statements or
+//! scripts from the command line.
+
+use super::config::Config;
+use super::session::{EvalError, Session};
+use crate::parse;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, stdin, stdout, BufRead, BufReader, Write};
+use std::mem;
+use std::path::Path;
 
 pub struct Repl {
-    config: Config,
-    file_system: Rc<PhysicalFs>,
-    rls: RefCell<Option<Rc<back::Rls<PhysicalFs>>>>,
-    prev_results: RefCell<Vec<Option<data::Value>>>,
+    session: Session,
 }
 
 impl Repl {
     pub fn new(config: Config) -> Repl {
         Repl {
-            file_system: Rc::new(PhysicalFs::new(&config.current_dir)),
-            config,
-            rls: RefCell::new(None),
-            prev_results: RefCell::new(Vec::new()),
+            session: Session::new(config).interactive(atty::is(atty::Stream::Stdin)),
         }
     }
 
-    pub fn run(&self) {
+    /// Run the REPL until the user exits or the input ends, returns the exit code.
+    pub fn run(&mut self) -> i32 {
+        let interactive = atty::is(atty::Stream::Stdin);
         let stdin = stdin();
-        let mut buf = String::new();
-        loop {
-            let prompt = self.prompt();
+        let statements = Statements::new(stdin.lock(), false);
+        let failed = run_lines(&mut self.session, statements, interactive);
+        if failed && !interactive {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// Evaluate each statement in the file at `path`, returns the exit code.
+pub fn run_script(session: &mut Session, path: &Path) -> i32 {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    if run_lines(session, Statements::new(BufReader::new(file), true), false) {
+        1
+    } else {
+        0
+    }
+}
+
+// Evaluate each statement of `input` until it ends. If `interactive`, the
+// prompt is shown before each statement. Returns true if any statement failed.
+fn run_lines(
+    session: &mut Session,
+    mut input: Statements<impl BufRead>,
+    interactive: bool,
+) -> bool {
+    let mut buf = String::new();
+    let mut failed = false;
+    loop {
+        session.check_for_changes();
+        let prompt = if interactive {
+            session.prompt()
+        } else {
+            String::new()
+        };
+        if !input.pasting() {
             print!("{}", prompt);
             stdout().flush().expect("Couldn't flush stdout");
+        }
 
-            buf.truncate(0);
-            stdin.read_line(&mut buf).expect("Error reading from stdin");
-            match parse::parse_stmt(&buf, None) {
-                Ok(node) => {
-                    let result = self.interpret(node);
-                }
-                Err(e) => match e {
-                    parse::Error::EmptyInput => {}
-                    parse::Error::Lexing(msg, offset) => {
-                        let offset = offset + prompt.len();
-                        println!("{}^", " ".repeat(offset));
-                        println!("{}", msg);
-                        self.prev_results.borrow_mut().push(None);
-                    }
-                    parse::Error::Parsing(msg) => {
-                        println!("{}", msg);
-                        self.prev_results.borrow_mut().push(None);
-                    }
-                    parse::Error::Other(msg) => println!("Error parsing input: {}", msg),
-                },
+        let more = input.read(&mut buf).expect("Error reading input");
+        if !more {
+            // End of input.
+            if interactive {
+                println!();
             }
+            break;
         }
-    }
-
-    fn interpret(&self, stmt: ast::Statement) -> Result<front::Value, front::Error> {
-        let mut interpreter = front::Interpreter::new(self);
-        let result = interpreter.interpret_stmt(stmt.clone());
-        match &result {
-            Ok(v) => self.prev_results.borrow_mut().push(Some(v.clone())),
+        // The pasted text is on the terminal all at once, so show each
+        // statement after a prompt with its number as it is evaluated.
+        let pasted = input.pasted();
+        let prompt = if pasted {
+            let prompt = session.prompt();
+            print!("{}{}", prompt, buf);
+            if !buf.ends_with('\n') {
+                println!();
+            }
+            prompt
+        } else {
+            prompt
+        };
+        match session.eval_str(&buf) {
+            // Return rather than exiting the process, so that the session
+            // is shut down properly.
+            Ok(_) if session.exiting() => break,
+            Ok(_) => {}
+            Err(EvalError::Parse(parse::Error::EmptyInput)) => {}
+            Err(EvalError::Parse(e @ parse::Error::Lexing(..)))
+            | Err(EvalError::Parse(e @ parse::Error::Parsing(..)))
+            | Err(EvalError::Parse(e @ parse::Error::Multiple(_))) => {
+                // The prompt may contain wide chars, so measure it in columns.
+                let prompt_len = parse::column(&prompt, prompt.len());
+                // Echo the input so the error carets make sense, unless it is
+                // already on the terminal (on a single line after the prompt).
+                let echo = !(interactive || pasted) || buf.trim_end().contains('\n');
+                write_parse_errors(&mut stdout(), e, &buf, prompt_len, echo)
+                    .expect("Couldn't write to stdout");
+                failed = true;
+            }
             Err(e) => {
-                println!("Error: {}", e);
-                self.prev_results.borrow_mut().push(None);
+                println!("{}", e);
+                failed = true;
             }
         }
-        result
     }
+    failed
+}
 
-    fn prompt(&self) -> String {
-        format!("{} > ", self.prev_results.borrow().len())
+/// Evaluate a single statement, returns the exit code.
+pub fn eval_once(session: &mut Session, input: &str) -> i32 {
+    match session.eval_str(input) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", error_message(e, input));
+            1
+        }
     }
 }
 
-impl Environment for Repl {
-    type ParseContext = ReplParseContext;
-    type Fs = PhysicalFs;
-
-    fn exec_meta(&self, mk: ast::MetaKind) -> Result<(), front::Error> {
-        match mk {
-            ast::MetaKind::Exit => process::exit(0),
-            ast::MetaKind::Help => {
-                println!("Clyde 0.1");
-                println!("");
-                println!("Meta-commands:");
-                println!("  ^help     display this message");
-                println!("  ^exit     exit Clyde");
-                println!("");
-                println!("Some common statements:");
-                println!("  select    query the program");
-                println!("  x =       variable assignment");
-                println!("  show      print a value");
-            }
+// Parse errors are shown under the echoed input.
+pub(super) fn error_message(e: EvalError, input: &str) -> String {
+    match e {
+        EvalError::Parse(parse::Error::EmptyInput) => {
+            "Error parsing input: no statement to evaluate".to_owned()
+        }
+        EvalError::Parse(parse::Error::Other(msg)) => format!("Error parsing input: {}", msg),
+        EvalError::Parse(e) => {
+            let mut out = Vec::new();
+            write_parse_errors(&mut out, e, input, 0, true).expect("Couldn't write to a buffer");
+            String::from_utf8_lossy(&out).trim_end().to_owned()
         }
+        e => e.to_string(),
+    }
+}
 
-        Ok(())
+// Reads statements from lines. Only `multi_line` statements (for scripts) and
+// `^paste` blocks may span several lines.
+struct Statements<R> {
+    input: R,
+    multi_line: bool,
+    // Lines which have been read, but are not part of the previous statement.
+    pending: String,
+    // Statements from a `^paste` block which have not been read.
+    pasted: VecDeque<String>,
+    // True if the previous statement was from a `^paste` block.
+    in_paste: bool,
+}
+
+impl<R: BufRead> Statements<R> {
+    fn new(input: R, multi_line: bool) -> Statements<R> {
+        Statements {
+            input,
+            multi_line,
+            pending: String::new(),
+            pasted: VecDeque::new(),
+            in_paste: false,
+        }
     }
 
-    fn show(&self, s: &impl Show) -> Result<(), front::Error> {
-        println!("{}", s.show_str(self));
-        Ok(())
+    // True if the statement most recently read was from a `^paste` block.
+    fn pasted(&self) -> bool {
+        self.in_paste
     }
 
-    fn lookup_var(&self, var: &front::MetaVar) -> Result<front::Value, front::Error> {
-        // TODO lookup variable by name
-        Err(front::Error::VarNotFound(var.clone()))
+    // True if the next statement is from a `^paste` block.
+    fn pasting(&self) -> bool {
+        !self.pasted.is_empty()
     }
 
-    fn lookup_numeric_var(&self, mut id: isize) -> Result<front::Value, front::Error> {
-        let prev_result = {
-            let prev_results = self.prev_results.borrow();
-            if id < 0 {
-                id = prev_results.len() as isize + id;
-            }
-            if id < 0 || id as usize >= prev_results.len() {
-                return Err(front::Error::NumericVarNotFound(
-                    id as usize,
-                    prev_results.len().saturating_sub(1),
-                ));
+    // Read the next statement into `buf`. Returns false at the end of the input.
+    fn read(&mut self, buf: &mut String) -> io::Result<bool> {
+        buf.clear();
+        self.in_paste = false;
+        if let Some(stmt) = self.pasted.pop_front() {
+            *buf = stmt;
+            self.in_paste = true;
+            return Ok(true);
+        }
+        if self.pending.is_empty() {
+            if self.input.read_line(buf)? == 0 {
+                return Ok(false);
             }
-            prev_results[id as usize].clone()
-        };
-        if let Some(result) = prev_result {
-            Ok(result)
         } else {
-            Err(front::Error::VarNotFound(MetaVar::new(&id.to_string())))
+            *buf = mem::take(&mut self.pending);
+        }
+        if !self.multi_line {
+            if buf.trim() == PASTE {
+                self.read_paste()?;
+                return self.read(buf);
+            }
+            return Ok(true);
         }
-    }
 
-    fn file_system(&self) -> &PhysicalFs {
-        &self.file_system
+        loop {
+            let incomplete = parse::is_incomplete(buf);
+            // Read the next line, and any blank lines or comments before it.
+            let mut next = String::new();
+            loop {
+                let start = next.len();
+                if self.input.read_line(&mut next)? == 0 {
+                    break;
+                }
+                let line = next[start..].trim_start();
+                if incomplete || !(line.is_empty() || line.starts_with('#')) {
+                    break;
+                }
+            }
+            if next.is_empty() {
+                return Ok(true);
+            }
+            if incomplete || parse::is_continuation(&next) {
+                buf.push_str(&next);
+            } else {
+                self.pending = next;
+                return Ok(true);
+            }
+        }
     }
 
-    fn backend(&self) -> Rc<dyn back::Backend> {
-        let mut rls = self.rls.borrow_mut();
-        match &*rls {
-            Some(rls) => rls.clone(),
-            None => {
-                *rls = Some(Rc::new(back::Rls::init(self.file_system.clone())));
-                rls.as_ref().unwrap().clone()
+    // Up to `^end` or the end of the input.
+    fn read_paste(&mut self) -> io::Result<()> {
+        let mut text = String::new();
+        loop {
+            let start = text.len();
+            if self.input.read_line(&mut text)? == 0 {
+                break;
+            }
+            if text[start..].trim() == PASTE_END {
+                text.truncate(start);
+                break;
+            }
+        }
+        let mut statements = Statements::new(text.as_bytes(), true);
+        let mut stmt = String::new();
+        while statements.read(&mut stmt)? {
+            let blank = stmt.lines().all(|l| {
+                let l = l.trim_start();
+                l.is_empty() || l.starts_with('#')
+            });
+            if !blank {
+                let stmt = stmt.trim_start_matches(&['\r', '\n'][..]);
+                self.pasted.push_back(stmt.to_owned());
             }
         }
+        Ok(())
     }
 }
 
-pub struct Config {
-    pub current_dir: PathBuf,
-}
+const PASTE: &str = "^paste";
+const PASTE_END: &str = "^end";
 
-impl Default for Config {
-    fn default() -> Config {
-        Config {
-            current_dir: env::current_dir().expect("Could not access current directory"),
+// Without `echo`, the input is a single line after the prompt.
+fn write_parse_errors(
+    w: &mut impl Write,
+    e: parse::Error,
+    input: &str,
+    prompt_len: usize,
+    echo: bool,
+) -> io::Result<()> {
+    let mut errors: Vec<(usize, String)> = match e {
+        parse::Error::Multiple(errs) => errs,
+        e => vec![e],
+    }
+    .into_iter()
+    .filter_map(|e| match e {
+        parse::Error::Lexing(msg, offset) | parse::Error::Parsing(msg, offset) => {
+            Some((offset, msg))
+        }
+        _ => None,
+    })
+    .collect();
+    errors.sort_by_key(|(offset, _)| *offset);
+
+    let lines: Vec<&str> = input.trim_end_matches('\n').split('\n').collect();
+    let mut line_start = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let last = i == lines.len() - 1;
+        let line_end = line_start + line.len() + 1;
+        // Errors after the end of the input point past the end of the last line.
+        let offsets: Vec<usize> = errors
+            .iter()
+            .map(|(offset, _)| *offset)
+            .filter(|offset| *offset >= line_start && (last || *offset < line_end))
+            .collect();
+        if echo {
+            writeln!(w, "{}", line.trim_end_matches('\r'))?;
         }
+        if last || !offsets.is_empty() {
+            let mut carets = String::new();
+            for offset in offsets {
+                let offset = parse::column(line, offset - line_start) + prompt_len;
+                if offset >= carets.len() {
+                    carets.push_str(&" ".repeat(offset - carets.len()));
+                    carets.push('^');
+                }
+            }
+            writeln!(w, "{}", carets)?;
+        }
+        line_start = line_end;
+    }
+    for (_, msg) in errors {
+        writeln!(w, "{}", msg)?;
     }
+    Ok(())
 }
 
-#[derive(Clone)]
-pub struct ReplParseContext {
-    line_number: usize,
-}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn statements(input: &str, multi_line: bool) -> Vec<String> {
+        let mut statements = Statements::new(input.as_bytes(), multi_line);
+        let mut result = Vec::new();
+        let mut buf = String::new();
+        while statements.read(&mut buf).unwrap() {
+            result.push(buf.clone());
+        }
+        result
+    }
+
+    #[test]
+    fn test_statements() {
+        let script = "# A script.\n\
+                      select (:foo.rs)\n\
+                      \x20   ->idents # all of them\n\
+                      \n\
+                      \x20   # just the defs\n\
+                      \x20   ->def\n\
+                      show [\n\
+                      \x20   $1,\n\
+                      ]\n\
+                      ^set display full\n";
+        assert_eq!(
+            statements(script, true),
+            vec![
+                "# A script.\n",
+                "select (:foo.rs)\n    ->idents # all of them\n\n    # just the defs\n    ->def\n",
+                "show [\n    $1,\n]\n",
+                "^set display full\n",
+            ]
+        );
+        assert_eq!(statements(script, false).len(), 10);
+        assert_eq!(statements("show $->\n", true), vec!["show $->\n"]);
+    }
+
+    #[test]
+    fn test_paste() {
+        let input = "show 1\n\
+                     ^paste\n\
+                     # Pasted.\n\
+                     select (:foo.rs)\n\
+                     \x20   ->idents\n\
+                     \n\
+                     show $\n\
+                     ^end\n\
+                     show 2\n";
+        let mut reader = Statements::new(input.as_bytes(), false);
+        let mut result = Vec::new();
+        let mut buf = String::new();
+        while reader.read(&mut buf).unwrap() {
+            result.push((buf.clone(), reader.pasted()));
+        }
+        assert_eq!(
+            result,
+            vec![
+                ("show 1\n".to_owned(), false),
+                ("select (:foo.rs)\n    ->idents\n".to_owned(), true),
+                ("show $\n".to_owned(), true),
+                ("show 2\n".to_owned(), false),
+            ]
+        );
+
+        // A block continues to the end of the input if there is no `^end`.
+        assert_eq!(
+            statements("^paste\nshow [\n  1,\n]\n", false),
+            vec!["show [\n  1,\n]\n"]
+        );
+        // Scripts can't contain blocks.
+        assert_eq!(statements("^paste\nshow 1\n^end\n", true).len(), 3);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut config = Config::default();
+        config.watch = false;
+        let mut session = Session::new(config);
+        let input = "show 1\n^exit\nshow $->\n";
+        // The last statement would fail, but isn't evaluated.
+        assert!(!run_lines(
+            &mut session,
+            Statements::new(input.as_bytes(), false),
+            false
+        ));
+        assert!(session.exiting());
+    }
 
-impl parse::EnvContext for ReplParseContext {
-    fn clone(&self) -> Box<dyn parse::EnvContext> {
-        Box::new(Clone::clone(self))
+    #[test]
+    fn test_parse_errors() {
+        let input = "show (\n  $ %\n)\n";
+        let e = parse::parse_stmt(input, None, &parse::Aliases::new())
+            .err()
+            .unwrap();
+        let mut out = Vec::new();
+        write_parse_errors(&mut out, e, input, 0, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "show (\n  $ %\n    ^\n)\n\nUnexpected token\n"
+        );
     }
 }
@@ -0,0 +1,1048 @@
+//! A Clyde session: the state shared by the statements a user evaluates (the
+//! backend, previous results, variables, settings, etc.). A session is
+//! driven either interactively by the `Repl`, or by evaluating a single
+//! statement (`clyde -e`).
+//!
+//! Statements are evaluated with `&mut self`, and the session's state is changed
+//! after the interpreter has finished with it (see `SessionState`). Only the
+//! destinations of output (which is written during evaluation) and the backend
+//! (which is started the first time it is needed) are shared while a statement
+//! is evaluated.
+
+use super::config::{self, Config};
+use super::logging;
+use super::progress::ProgressBar;
+use super::record::Recorder;
+use super::vcs;
+use super::watch::{Change, FileWatcher};
+use super::{
+    persist, settings, DisplayDuration, Environment, OutputMode, PromptVars, SessionState,
+    Settings, Verbosity,
+};
+use crate::back;
+use crate::file_system::{self, FileSystem, PhysicalFs};
+use crate::front::{self, data, DisplayPolicy, MetaVar, Show};
+use crate::parse::{self, ast};
+use std::cell::{OnceCell, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, stdout, BufReader, BufWriter, StdoutLock, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// The number of lines shown by `^log tail` if no number is given.
+const DEFAULT_LOG_TAIL: usize = 20;
+
+pub struct Session {
+    config: Config,
+    file_system: Rc<PhysicalFs>,
+    // Started when it is first needed.
+    backend: OnceCell<Rc<dyn back::Backend>>,
+    prev_results: Vec<Option<data::Value>>,
+    variables: HashMap<MetaVar, data::Value>,
+    watcher: Option<FileWatcher>,
+    // True if source files have changed since the backend was last indexed.
+    index_stale: bool,
+    settings: Settings,
+    aliases: parse::Aliases,
+    // The transcript of the session, if we are recording (`^record`). Shown
+    // output is recorded as it is written.
+    recorder: RefCell<Option<Recorder>>,
+    format: Format,
+    // Shown output is collected here rather than written to stdout while a
+    // statement is evaluated with `eval_captured`.
+    captured: RefCell<Option<Vec<u8>>>,
+    // True if the user is at a terminal (and so can be asked questions).
+    interactive: bool,
+    // The file this session logs to, `None` if it could not be created.
+    log_path: Option<PathBuf>,
+    // Locations without a file name are in this file (see `^focus`).
+    focus: Option<file_system::Path>,
+    // True once `^exit` has been evaluated.
+    exiting: bool,
+    // How long the current statement may take (see `Settings::timeout`).
+    budget: Option<front::Budget>,
+}
+
+/// How values are written to stdout.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    /// As used by `^save`.
+    Json,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "Unknown format: `{}`, expected `text` or `json`",
+                s
+            )),
+        }
+    }
+}
+
+impl Session {
+    pub fn new(config: Config) -> Session {
+        if config.verbosity == Verbosity::Verbose {
+            eprintln!("root: {}", config.root.display());
+            eprintln!("backend: {}", config.backend);
+        }
+        let log_path = match logging::init(&config.root, config.verbosity) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                eprintln!("warning: could not create log file: {}", e);
+                None
+            }
+        };
+        log::info!(
+            "session started in {}, backend: {}",
+            config.root.display(),
+            config.backend
+        );
+        let file_system = Rc::new(PhysicalFs::new(&config.root));
+        file_system.set_cache_size(config.file_cache);
+        let watcher = if config.watch {
+            match FileWatcher::new(&file_system.roots(), config.ignore.clone()) {
+                Ok(w) => Some(w),
+                Err(e) => {
+                    eprintln!("warning: could not watch files for changes: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Session {
+            file_system,
+            settings: config.settings.clone(),
+            aliases: config.aliases.clone(),
+            recorder: RefCell::new(None),
+            config,
+            backend: OnceCell::new(),
+            prev_results: Vec::new(),
+            variables: HashMap::new(),
+            watcher,
+            index_stale: false,
+            format: Format::Text,
+            captured: RefCell::new(None),
+            interactive: false,
+            log_path,
+            focus: None,
+            exiting: false,
+            budget: None,
+        }
+    }
+
+    /// Set whether the user is at a terminal, i.e., whether they can be asked
+    /// to choose between ambiguous results.
+    pub fn interactive(mut self, interactive: bool) -> Session {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Set how values are written to stdout.
+    pub fn format(mut self, format: Format) -> Session {
+        self.format = format;
+        self
+    }
+
+    /// True once the user has asked to exit, with `^exit`.
+    pub fn exiting(&self) -> bool {
+        self.exiting
+    }
+
+    // Run when the session ends, however it ends (other than the process
+    // being killed).
+    fn shutdown(&self) {
+        if let Some(recorder) = self.recorder.replace(None) {
+            log::info!("stopped recording to {}", recorder.path().display());
+        }
+        log::info!("session ended");
+        log::logger().flush();
+        let _ = stdout().flush();
+    }
+
+    /// Parse and evaluate a single statement, showing its result. Errors are
+    /// returned rather than printed, so that the caller can report them.
+    pub fn eval_str(&mut self, input: &str) -> Result<front::Value, EvalError> {
+        let stmt = match parse::parse_stmt(input, None, &self.aliases) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                match e {
+                    parse::Error::EmptyInput | parse::Error::Other(_) => {}
+                    // Keep the numbering of results in line with the prompt.
+                    _ => self.prev_results.push(None),
+                }
+                return Err(EvalError::Parse(e));
+            }
+        };
+        log::debug!("statement: {}", input.trim());
+        self.record_statement(&stmt, input);
+        self.interpret(stmt).map_err(EvalError::Interpret)
+    }
+
+    /// Like `eval_str`, but shown output is returned rather than written.
+    pub(crate) fn eval_captured(
+        &mut self,
+        input: &str,
+        format: Format,
+    ) -> (Result<front::Value, EvalError>, String) {
+        let old_format = std::mem::replace(&mut self.format, format);
+        *self.captured.get_mut() = Some(Vec::new());
+        self.check_for_changes();
+        let result = self.eval_str(input);
+        let output = self.captured.get_mut().take().unwrap_or_default();
+        self.format = old_format;
+        (result, String::from_utf8_lossy(&output).into_owned())
+    }
+
+    // Where shown output is written.
+    fn output(&self) -> Output<'_> {
+        let captured = self.captured.borrow_mut();
+        if captured.is_some() {
+            Output::Captured(RefMut::map(captured, |c| c.as_mut().unwrap()))
+        } else {
+            Output::Stdout(stdout().lock())
+        }
+    }
+
+    fn interpret(&mut self, stmt: ast::Statement) -> Result<front::Value, front::Error> {
+        self.budget = self.settings.timeout.map(front::Budget::start);
+        let result = front::eval_stmt(self, stmt);
+        if let Err(e) = &result {
+            log::info!("statement failed: {}", e);
+            self.record_output(&format!("Error: {}", e));
+        }
+        self.report_provenance();
+        if result.is_ok() {
+            self.report_timeout();
+        }
+        result
+    }
+
+    // Tell the user if the statement ran out of time, so its result is only
+    // what was found before then.
+    fn report_timeout(&self) {
+        let timeout = match &self.budget {
+            Some(budget) if budget.was_exceeded() => budget.timeout,
+            _ => return,
+        };
+        log::info!("query aborted after {}", DisplayDuration(timeout));
+        // Keep output for editors and scripts parseable.
+        if self.format == Format::Text && self.settings().output == OutputMode::Text {
+            let _ = writeln!(
+                self.output(),
+                "note: query aborted after {}, the result is incomplete (see `^set timeout`)",
+                DisplayDuration(timeout)
+            );
+        }
+    }
+
+    // Tell the user if any answers came from a fallback backend, since they
+    // may be less complete than usual.
+    fn report_provenance(&self) {
+        let provenance = match self.backend.get() {
+            Some(backend) => backend.take_provenance(),
+            None => return,
+        };
+        if provenance.is_empty() {
+            return;
+        }
+        let answers: Vec<_> = provenance.iter().map(ToString::to_string).collect();
+        log::info!("fallback answers: {}", answers.join(", "));
+        // Keep output for editors and scripts parseable.
+        if self.format == Format::Text && self.settings().output == OutputMode::Text {
+            let _ = writeln!(
+                self.output(),
+                "note: some results may be incomplete, they include {}",
+                answers.join(", ")
+            );
+        }
+    }
+
+    // Invalidate any cached data for files which have changed since we last checked.
+    pub(crate) fn check_for_changes(&mut self) {
+        let changes = match &self.watcher {
+            Some(w) => w.changes(),
+            None => return,
+        };
+        if changes.is_empty() {
+            return;
+        }
+
+        for change in changes {
+            match change {
+                Change::File(path) => {
+                    log::debug!("changed: {}", path.display());
+                    self.file_system.invalidate(&path)
+                }
+                Change::All => {
+                    log::debug!("changed: all files");
+                    self.file_system.invalidate_all()
+                }
+            }
+        }
+
+        // If the backend has not been initialised, then it will be up to date
+        // when it is.
+        if self.backend.get().is_none() {
+            return;
+        }
+        if self.config.auto_reload {
+            self.reload();
+        } else if !self.index_stale {
+            let _ = writeln!(self.output(), "index out of date, run `^reload` to update");
+            self.index_stale = true;
+        }
+    }
+
+    fn reload(&mut self) {
+        if let Some(backend) = self.backend.get() {
+            if let Err(e) = backend.reload(&self.config.build_command) {
+                let _ = writeln!(self.output(), "Error: {}", e);
+            }
+        }
+        self.index_stale = false;
+        self.file_system.clear_changed();
+    }
+
+    fn focus_on(&mut self, name: &str) -> Result<(), front::Error> {
+        let paths = self.file_system.find(name.to_owned().into())?;
+        match &*paths {
+            [path] => {
+                self.focus = Some(*path);
+                Ok(())
+            }
+            [] => Err(front::Error::Other(format!("No files match `{}`", name))),
+            _ => Err(front::Error::Other(format!(
+                "`{}` matches {} files, the focus must be a single file",
+                name,
+                paths.len()
+            ))),
+        }
+    }
+
+    fn log_path(&self) -> Result<&Path, front::Error> {
+        self.log_path
+            .as_deref()
+            .ok_or_else(|| front::Error::Other("No log file for this session".to_owned()))
+    }
+
+    // The capabilities of the backend (or backends), without starting it.
+    fn backend_capabilities(&self) -> back::Capabilities {
+        match self.backend.get() {
+            Some(backend) => backend.capabilities(),
+            None => back::Registry::new()
+                .capabilities(&self.config.backend)
+                .expect("the backend is checked when the config is read"),
+        }
+    }
+
+    // Re-read the configuration file. Settings and aliases are reset to the
+    // values in the file.
+    fn reload_config(&mut self) -> Result<(), front::Error> {
+        let new = self
+            .config
+            .reload()
+            .map_err(|e| front::Error::Other(e.to_string()))?;
+        let config = &mut self.config;
+        if new.root != config.root
+            || new.backend != config.backend
+            || new.watch != config.watch
+            || new.target_dir != config.target_dir
+            || new.analysis_dirs != config.analysis_dirs
+        {
+            println!(
+                "note: changes to `root`, `backend`, `watch`, `target-dir`, or `analysis-dirs` \
+                 take effect when Clyde is restarted"
+            );
+        }
+        if let Some(watcher) = &self.watcher {
+            watcher.set_ignore(new.ignore.clone());
+        }
+        self.settings = new.settings.clone();
+        self.aliases = new.aliases.clone();
+
+        config.auto_reload = new.auto_reload;
+        config.build_command = new.build_command;
+        config.ignore = new.ignore;
+        config.editor = new.editor;
+        if new.file_cache != config.file_cache {
+            self.file_system.set_cache_size(new.file_cache);
+            config.file_cache = new.file_cache;
+        }
+        config.disambiguate = new.disambiguate;
+        config.settings = new.settings;
+        config.aliases = new.aliases;
+        if new.verbosity != config.verbosity {
+            logging::set_verbosity(new.verbosity);
+            config.verbosity = new.verbosity;
+        }
+        log::info!("reloaded {}", config::FILE_NAME);
+        Ok(())
+    }
+
+    fn record_statement(&self, stmt: &ast::Statement, text: &str) {
+        match stmt.kind {
+            ast::StatementKind::Meta(ast::MetaKind::Record(..))
+            | ast::StatementKind::Meta(ast::MetaKind::StopRecording)
+            | ast::StatementKind::Meta(ast::MetaKind::Exit) => return,
+            _ => {}
+        }
+        if let Some(recorder) = &mut *self.recorder.borrow_mut() {
+            if let Err(e) = recorder.statement(text) {
+                println!("warning: could not record statement: {}", e);
+            }
+        }
+    }
+
+    fn record_output(&self, text: &str) {
+        if let Some(recorder) = &mut *self.recorder.borrow_mut() {
+            if let Err(e) = recorder.output(text) {
+                println!("warning: could not record output: {}", e);
+            }
+        }
+    }
+
+    fn start_recording(&self, path: &str, output: bool) -> Result<(), front::Error> {
+        let path = self.config.current_dir.join(path);
+        let recorder = Recorder::new(&path, output)?;
+        if let Some(old) = self.recorder.replace(Some(recorder)) {
+            println!("stopped recording to {}", old.path().display());
+        }
+        println!("recording to {}", path.display());
+        Ok(())
+    }
+
+    fn define_alias(&mut self, name: &str, source: &str) -> Result<(), front::Error> {
+        if front::Registry::<Self>::new().lookup(name).is_some() {
+            return Err(front::Error::Other(format!(
+                "`{}` is a function and cannot be an alias",
+                name
+            )));
+        }
+        let alias = parse::Alias::new(source)
+            .map_err(|e| front::Error::Other(format!("Invalid alias `{}`: {}", name, e)))?;
+        self.aliases.insert(name.to_owned(), alias);
+        Ok(())
+    }
+
+    pub(crate) fn prompt(&self) -> String {
+        let prev_results = &self.prev_results;
+        let workspace = self
+            .config
+            .root
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let backend = match self.backend.get() {
+            None => "idle",
+            Some(backend) if backend.index_failed() => "failed",
+            Some(_) if self.index_stale => "stale",
+            Some(_) => "ready",
+        };
+        let status = match prev_results.last() {
+            Some(None) => "error",
+            _ => "ok",
+        };
+        let vars = PromptVars {
+            n: prev_results.len(),
+            workspace: &workspace,
+            backend,
+            status,
+        };
+        settings::render_prompt(&self.settings.prompt, &vars)
+    }
+
+    fn lookup_meta_var(&self, var: &ast::MetaVarKind) -> Result<front::Value, front::Error> {
+        match var {
+            ast::MetaVarKind::Dollar => self.lookup_numeric_var(-1),
+            ast::MetaVarKind::Numeric(n) => self.lookup_numeric_var(*n),
+            ast::MetaVarKind::Named(id) => self.lookup_var(&MetaVar::new(&id.name)),
+            ast::MetaVarKind::All => front::all_results(self),
+        }
+    }
+
+    fn save(&self, var: &ast::MetaVarKind, path: &str) -> Result<(), front::Error> {
+        let value = front::eval_queries(self.lookup_meta_var(var)?, self)?;
+        if has_lambda(&value) {
+            return Err(front::Error::Other(format!(
+                "Can't save {}, it contains a lambda",
+                var_name(var)
+            )));
+        }
+        let file = File::create(self.config.current_dir.join(path))?;
+        persist::save(&value, &*self.file_system, BufWriter::new(file))
+    }
+
+    fn load(&mut self, name: &ast::Identifier, path: &str) -> Result<(), front::Error> {
+        let file = File::open(self.config.current_dir.join(path))?;
+        let value = persist::load(&*self.file_system, BufReader::new(file))?;
+        self.variables.insert(MetaVar::new(&name.name), value);
+        Ok(())
+    }
+}
+
+fn has_lambda(value: &data::Value) -> bool {
+    match &value.kind {
+        data::ValueKind::Fn(_) => true,
+        data::ValueKind::Set(vs) => vs.iter().any(has_lambda),
+        data::ValueKind::Partial(v) => has_lambda(v),
+        data::ValueKind::Map(entries) => {
+            entries.iter().any(|(k, v)| has_lambda(k) || has_lambda(v))
+        }
+        _ => false,
+    }
+}
+
+// `var` as the user wrote it.
+fn var_name(var: &ast::MetaVarKind) -> String {
+    match var {
+        ast::MetaVarKind::Dollar => "`$`".to_owned(),
+        ast::MetaVarKind::Numeric(n) => format!("`${}`", n),
+        ast::MetaVarKind::Named(id) => format!("`{}`", id.name),
+        ast::MetaVarKind::All => "`$all`".to_owned(),
+    }
+}
+
+impl Environment for Session {
+    type ParseContext = SessionParseContext;
+    type Fs = PhysicalFs;
+
+    // Output is written directly to stdout as it is rendered, rather than
+    // being rendered to a string first.
+    fn show(&self, value: &front::Value) -> Result<(), front::Error> {
+        if self.format == Format::Json {
+            let value = front::eval_queries(value.clone(), self)?;
+            let mut out = self.output();
+            persist::save(&value, &*self.file_system, &mut out)?;
+            return writeln!(out).map_err(Into::into);
+        }
+
+        let mut w = ShowWriter {
+            out: self.output(),
+            session: self,
+            line: Vec::new(),
+        };
+        let ctx = self.show_ctx();
+        let output = self.settings().output;
+        if output == OutputMode::Text {
+            value.show(&mut w, self, &ctx)?;
+        } else {
+            // Queries must be evaluated to find their locations.
+            let evaluated;
+            let value = match &value.kind {
+                data::ValueKind::Query(q) => {
+                    evaluated = front::eval_query(q, self)?;
+                    &evaluated
+                }
+                _ => value,
+            };
+            match output.separator() {
+                Some(sep) => value.show_table(&mut w, self, &ctx, sep)?,
+                None => value.show_quickfix(&mut w, self, &ctx)?,
+            }
+        }
+        writeln!(w)?;
+        w.flush().map_err(Into::into)
+    }
+
+    fn show_empty(&self, value: &front::Value) -> Result<(), front::Error> {
+        match (self.format, self.settings().output) {
+            (Format::Json, _) => self.show(value),
+            // Nothing for an editor to jump to.
+            (_, OutputMode::Quickfix) => Ok(()),
+            // Just the header.
+            (_, OutputMode::Csv) | (_, OutputMode::Tsv) => self.show(value),
+            (_, OutputMode::Text) => writeln!(self.output(), "no results").map_err(Into::into),
+        }
+    }
+
+    fn lookup_var(&self, var: &front::MetaVar) -> Result<front::Value, front::Error> {
+        match self.variables.get(var) {
+            Some(value) => Ok(value.clone()),
+            None => Err(front::Error::VarNotFound(var.clone())),
+        }
+    }
+
+    fn write_text(&self, path: Option<&str>, text: &str) -> Result<(), front::Error> {
+        match path {
+            Some(path) => {
+                std::fs::write(self.config.current_dir.join(path), text)?;
+                writeln!(self.output(), "wrote {}", path).map_err(Into::into)
+            }
+            None => {
+                let mut w = ShowWriter {
+                    out: self.output(),
+                    session: self,
+                    line: Vec::new(),
+                };
+                w.write_all(text.as_bytes()).map_err(Into::into)
+            }
+        }
+    }
+
+    fn lookup_numeric_var(&self, requested: isize) -> Result<front::Value, front::Error> {
+        let prev_results = &self.prev_results;
+        let id = if requested < 0 {
+            prev_results.len() as isize + requested
+        } else {
+            requested
+        };
+        if id < 0 || id as usize >= prev_results.len() {
+            return Err(front::Error::NumericVarNotFound(
+                requested,
+                prev_results.len(),
+            ));
+        }
+        prev_results[id as usize]
+            .clone()
+            .ok_or(front::Error::FailedResult(id as usize))
+    }
+
+    fn all_results(&self) -> Vec<front::Value> {
+        self.prev_results.iter().flatten().cloned().collect()
+    }
+
+    fn file_system(&self) -> &PhysicalFs {
+        &self.file_system
+    }
+
+    fn capabilities(&self) -> back::Capabilities {
+        self.backend_capabilities()
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings.clone()
+    }
+
+    fn chooses_idents(&self) -> bool {
+        self.interactive && self.config.disambiguate
+    }
+
+    fn choose_idents(
+        &self,
+        position: &data::Position,
+        idents: Vec<data::Identifier>,
+    ) -> Result<Vec<data::Identifier>, front::Error> {
+        let ctx = self.show_ctx();
+        println!("{} identifiers at this position:", idents.len());
+        position.show(&mut stdout(), self, &ctx)?;
+        println!();
+        for (i, id) in idents.iter().enumerate() {
+            println!("{}: `{}`", i, id.name);
+            id.span.show(&mut stdout(), self, &ctx)?;
+            println!();
+        }
+
+        let question = format!(
+            "choose one (0-{}), or press enter for all: ",
+            idents.len() - 1
+        );
+        loop {
+            let answer = match self.prompt_user(&question)? {
+                Some(answer) => answer,
+                None => return Ok(idents),
+            };
+            match answer.trim() {
+                "" => return Ok(idents),
+                s => match s.parse::<usize>() {
+                    Ok(i) if i < idents.len() => return Ok(vec![idents[i].clone()]),
+                    _ => println!("expected a number from 0 to {}", idents.len() - 1),
+                },
+            }
+        }
+    }
+
+    fn can_prompt(&self) -> bool {
+        self.interactive
+    }
+
+    fn prompt_user(&self, message: &str) -> Result<Option<String>, front::Error> {
+        print!("{}", message);
+        stdout().flush()?;
+        let mut buf = String::new();
+        if io::stdin().read_line(&mut buf)? == 0 {
+            // End of input.
+            println!();
+            return Ok(None);
+        }
+        Ok(Some(buf.trim_end_matches(&['\r', '\n'][..]).to_owned()))
+    }
+
+    fn focus(&self) -> Option<file_system::Path> {
+        self.focus
+    }
+
+    fn changed(
+        &self,
+        revision: &str,
+    ) -> Result<Vec<(file_system::Path, data::Line, data::Line)>, front::Error> {
+        let root = self.config.root.clone();
+        let hunks =
+            vcs::changed(&root, revision).map_err(|e| front::Error::Other(e.to_string()))?;
+        hunks
+            .into_iter()
+            .map(|h| {
+                let file = self.file_system.resolve_path(&root.join(&h.file))?;
+                Ok((file, h.start, h.end))
+            })
+            .collect()
+    }
+
+    fn budget(&self) -> Option<&front::Budget> {
+        self.budget.as_ref()
+    }
+
+    fn progress(&self) -> Box<dyn back::Progress> {
+        // Progress is only useful to someone watching, and would be mixed up
+        // with JSON output.
+        if self.interactive && self.format != Format::Json && atty::is(atty::Stream::Stderr) {
+            Box::new(ProgressBar::new())
+        } else {
+            Box::new(back::NoProgress)
+        }
+    }
+
+    fn display_policy(&self) -> DisplayPolicy {
+        // Captured output is not written to the terminal, even if stdout is one.
+        let tty = atty::is(atty::Stream::Stdout) && self.captured.borrow().is_none();
+        let mut policy =
+            DisplayPolicy::new(self.config.color, tty, |name| std::env::var(name).ok());
+        // The width is checked each time since the terminal might be resized.
+        if tty {
+            policy.width = term_size::dimensions_stdout().map(|(w, _)| w);
+        }
+        policy.transcript = match &*self.recorder.borrow() {
+            Some(recorder) => recorder.records_output(),
+            None => false,
+        };
+        policy
+    }
+
+    fn backend(&self) -> Rc<dyn back::Backend> {
+        let config = &self.config;
+        self.backend
+            .get_or_init(|| {
+                let options = back::Options {
+                    fs: self.file_system.clone(),
+                    build_command: &config.build_command,
+                    target_dir: &config.target_dir,
+                    analysis_dirs: &config.analysis_dirs,
+                    index: config.index,
+                    verbosity: config.verbosity,
+                };
+                back::Registry::new()
+                    .init(&config.backend, &options)
+                    .expect("the backend is checked when the config is read")
+            })
+            .clone()
+    }
+}
+
+impl SessionState for Session {
+    fn exec_meta(&mut self, mk: ast::MetaKind) -> Result<(), front::Error> {
+        match mk {
+            ast::MetaKind::Exit => self.exiting = true,
+            ast::MetaKind::Help => {
+                println!("Clyde 0.1");
+                println!("");
+                println!("Meta-commands:");
+                println!("  ^help     display this message");
+                println!("  ^exit     exit Clyde");
+                println!("  ^save     save a variable to a file, e.g., `^save $3 foo.json`");
+                println!("  ^load     load a variable from a file, e.g., `^load x foo.json`");
+                println!("  ^reload   rebuild the index of the program");
+                println!("  ^config reload  re-read {}", config::FILE_NAME);
+                println!("  ^backend  show the backend and the functionality it supports");
+                println!("  ^alias    define an alias, e.g., `^alias defs = idents->def`, or list aliases");
+                println!("  ^alias save  save aliases to {}", config::FILE_NAME);
+                println!(
+                    "  ^record   record statements (and with `--output`, their output) to a file"
+                );
+                println!("  ^stop     stop recording");
+                println!(
+                    "  ^set      change a setting, e.g., `^set display full`, or list settings"
+                );
+                println!("  ^explain  show what a statement would do without evaluating it, e.g., `^explain show $->idents`");
+                println!("  ^focus    set the file for locations without one, e.g., `^focus src/lib.rs` then `(:42)`");
+                println!("  ^log      show the path of the log file");
+                println!("  ^log tail  show the end of the log, e.g., `^log tail 50`");
+                println!("  ^paste    evaluate the statements pasted on the following lines, up to a line `^end`");
+                println!("");
+                println!("Functions:");
+                for f in front::Registry::<Self>::new().functions() {
+                    println!("  {:<10}{}", f.name, f.help);
+                }
+            }
+            ast::MetaKind::Save(var, path) => self.save(&var, &path)?,
+            ast::MetaKind::Load(name, path) => self.load(&name, &path)?,
+            ast::MetaKind::Reload => self.reload(),
+            ast::MetaKind::ReloadConfig => self.reload_config()?,
+            ast::MetaKind::Backend => {
+                println!("backend: {}", self.config.backend);
+                println!("supports: {}", self.backend_capabilities());
+            }
+            ast::MetaKind::Alias(None) => {
+                for (name, alias) in &self.aliases {
+                    println!("{} = {}", name, alias.source);
+                }
+            }
+            ast::MetaKind::Alias(Some((name, source))) => self.define_alias(&name, &source)?,
+            ast::MetaKind::SaveAliases => {
+                let path = config::save_aliases(&self.config.current_dir, &self.aliases)
+                    .map_err(|e| front::Error::Other(e.to_string()))?;
+                println!("saved aliases to {}", path.display());
+            }
+            ast::MetaKind::Record(path, output) => self.start_recording(&path, output)?,
+            ast::MetaKind::StopRecording => match self.recorder.replace(None) {
+                Some(recorder) => println!("stopped recording to {}", recorder.path().display()),
+                None => return Err(front::Error::Other("Not recording".to_owned())),
+            },
+            ast::MetaKind::Set(None) => println!("{}", self.settings),
+            ast::MetaKind::Set(Some((name, value))) => self
+                .settings
+                .set(&name, &value)
+                .map_err(front::Error::Other)?,
+            ast::MetaKind::Focus(None) => match self.focus {
+                Some(file) => {
+                    self.file_system.show_path(file, &mut stdout())?;
+                    println!();
+                }
+                None => println!("no focus file"),
+            },
+            ast::MetaKind::Focus(Some(name)) => self.focus_on(&name)?,
+            ast::MetaKind::Explain(text) => {
+                let stmt = parse::parse_stmt(&text, None, &self.aliases)
+                    .map_err(|e| front::Error::Other(format!("Error parsing input: {}", e)))?;
+                println!("{}", front::explain(self, stmt)?);
+            }
+            ast::MetaKind::Log => println!("{}", self.log_path()?.display()),
+            ast::MetaKind::LogTail(n) => {
+                let path = self.log_path()?;
+                let lines = logging::tail(path, n.unwrap_or(DEFAULT_LOG_TAIL))
+                    .map_err(|e| front::Error::Other(format!("Error reading log: {}", e)))?;
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_var(&mut self, var: front::MetaVar, value: front::Value) {
+        self.variables.insert(var, value);
+    }
+
+    fn push_result(&mut self, result: Option<front::Value>) {
+        self.prev_results.push(result);
+    }
+
+    fn set_focus(&mut self, file: file_system::Path) {
+        self.focus = Some(file);
+    }
+}
+
+// Shown output goes to stdout, unless it is being captured.
+enum Output<'a> {
+    Stdout(StdoutLock<'static>),
+    Captured(RefMut<'a, Vec<u8>>),
+}
+
+impl<'a> Write for Output<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(out) => out.write(buf),
+            Output::Captured(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(out) => out.flush(),
+            Output::Captured(_) => Ok(()),
+        }
+    }
+}
+
+// Writes shown output, and records it (one line at a time) if we are
+// recording output.
+struct ShowWriter<'a> {
+    out: Output<'a>,
+    session: &'a Session,
+    // Output which has not been recorded yet because it is not a complete line.
+    line: Vec<u8>,
+}
+
+impl<'a> Write for ShowWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.out.write(buf)?;
+        let recording = match &*self.session.recorder.borrow() {
+            Some(recorder) => recorder.records_output(),
+            None => false,
+        };
+        if recording {
+            self.line.extend_from_slice(&buf[..len]);
+            while let Some(i) = self.line.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.line.drain(..=i).collect();
+                self.session
+                    .record_output(&String::from_utf8_lossy(&line[..line.len() - 1]));
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// An error evaluating a statement with `Session::eval_str`.
+#[derive(Debug)]
+pub enum EvalError {
+    Parse(parse::Error),
+    Interpret(front::Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Parse(e) => write!(f, "Error parsing input: {}", e),
+            EvalError::Interpret(e) => write!(f, "Error: {}", e),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionParseContext {
+    line_number: usize,
+}
+
+impl parse::EnvContext for SessionParseContext {
+    fn clone(&self) -> Box<dyn parse::EnvContext> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session() -> Session {
+        let mut config = Config::default();
+        config.watch = false;
+        Session::new(config)
+    }
+
+    #[test]
+    fn test_eval_str() {
+        let mut session = session();
+        match session.eval_str("[1, 2]").unwrap().kind {
+            data::ValueKind::Set(s) => assert_eq!(s.len(), 2),
+            _ => panic!(),
+        }
+        match session.eval_str("^set max-set-len 10") {
+            Ok(v) => assert!(v.kind.is_void()),
+            Err(e) => panic!("{}", e),
+        }
+        assert_eq!(session.settings().max_set_len, 10);
+
+        match session.eval_str("  ") {
+            Err(EvalError::Parse(parse::Error::EmptyInput)) => {}
+            _ => panic!(),
+        }
+        match session.eval_str("show ]") {
+            Err(EvalError::Parse(parse::Error::Lexing(..))) => {}
+            _ => panic!(),
+        }
+        match session.eval_str("foo 1") {
+            Err(e @ EvalError::Interpret(_)) => {
+                assert_eq!(e.to_string(), "Error: Unknown function: `foo`")
+            }
+            _ => panic!(),
+        }
+        // Failed statements still count as results.
+        assert_eq!(session.prompt(), "4 > ");
+        match session.eval_str("$0").unwrap().kind {
+            data::ValueKind::Set(s) => assert_eq!(s.len(), 2),
+            _ => panic!(),
+        }
+
+        session
+            .eval_str("^set prompt \"[{backend}, {status}] {n}> \"")
+            .unwrap();
+        assert_eq!(session.prompt(), "[idle, ok] 6> ");
+        assert!(session.eval_str("$42").is_err());
+        assert_eq!(session.prompt(), "[idle, error] 7> ");
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut session = session();
+        assert!(!session.exiting());
+        assert!(session.eval_str("^exit").unwrap().kind.is_void());
+        assert!(session.exiting());
+    }
+
+    #[test]
+    fn test_eval_captured() {
+        let mut session = session();
+        let (result, output) = session.eval_captured("show [1, 2]", Format::Text);
+        assert!(result.is_ok());
+        assert_eq!(output, "[1, 2]\n");
+        let (result, output) = session.eval_captured("show []", Format::Text);
+        assert!(result.is_ok());
+        assert_eq!(output, "[]\n");
+        let (result, output) = session.eval_captured("show $->", Format::Text);
+        assert!(result.is_err());
+        assert_eq!(output, "");
+        // The session's own format is unchanged.
+        assert_eq!(session.format, Format::Text);
+    }
+
+    #[test]
+    fn test_save() {
+        let mut session = session();
+        let path = format!("target/test-save-{}.json", std::process::id());
+        session.eval_str("f = |x| x").unwrap();
+        session.eval_str("g = [f]").unwrap();
+        for var in ["f", "g"] {
+            assert_eq!(
+                session
+                    .eval_str(&format!("^save {} {}", var, path))
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                format!("Error: Can't save `{}`, it contains a lambda", var)
+            );
+        }
+        session.eval_str("n = [[1], [2]]").unwrap();
+        session.eval_str(&format!("^save n {}", path)).unwrap();
+        session.eval_str(&format!("^load m {}", path)).unwrap();
+        assert_eq!(
+            session.eval_captured("show m", Format::Text).1,
+            "[[1], [2]]\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Format::parse("json"), Ok(Format::Json));
+        assert!(Format::parse("yaml")
+            .unwrap_err()
+            .starts_with("Unknown format"));
+    }
+}
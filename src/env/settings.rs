@@ -0,0 +1,400 @@
+//! User-configurable settings, changed with `^set name value`.
+
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub display: DisplayMode,
+    /// Sets with more elements than this are shown as a count, rather than
+    /// element by element.
+    pub max_set_len: usize,
+    /// The REPL's prompt, see `render_prompt` for the placeholders.
+    pub prompt: String,
+    /// The maximum number of elements in a set produced by a query, larger
+    /// sets are truncated. Zero for no limit.
+    pub limit: usize,
+    pub output: OutputMode,
+    pub macros: MacroMode,
+    /// The number of lines of source text shown before and after the line of a
+    /// position or span.
+    pub context: usize,
+    /// How long a statement's queries may be evaluated for before they are
+    /// aborted. `None` for no timeout.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            display: DisplayMode::default(),
+            max_set_len: 4,
+            prompt: "{n} > ".to_owned(),
+            limit: 10000,
+            output: OutputMode::default(),
+            macros: MacroMode::default(),
+            context: 0,
+            timeout: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Set the setting called `name` from a string given by the user.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "display" => self.display = DisplayMode::parse(value)?,
+            "max-set-len" => {
+                self.max_set_len = value.parse().map_err(|_| {
+                    format!("Expected a number for `max-set-len`, found `{}`", value)
+                })?
+            }
+            "prompt" => {
+                check_prompt(value)?;
+                self.prompt = value.to_owned();
+            }
+            "limit" => {
+                self.limit = value
+                    .parse()
+                    .map_err(|_| format!("Expected a number for `limit`, found `{}`", value))?
+            }
+            "output" => self.output = OutputMode::parse(value)?,
+            "macros" => self.macros = MacroMode::parse(value)?,
+            "context" => {
+                self.context = value
+                    .parse()
+                    .map_err(|_| format!("Expected a number for `context`, found `{}`", value))?
+            }
+            "timeout" => self.timeout = parse_timeout(value)?,
+            _ => return Err(format!("Unknown setting: `{}`", name)),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "display = {}", self.display)?;
+        writeln!(f, "max-set-len = {}", self.max_set_len)?;
+        writeln!(f, "prompt = \"{}\"", self.prompt)?;
+        writeln!(f, "limit = {}", self.limit)?;
+        writeln!(f, "output = {}", self.output)?;
+        writeln!(f, "macros = {}", self.macros)?;
+        writeln!(f, "context = {}", self.context)?;
+        match self.timeout {
+            Some(timeout) => write!(f, "timeout = {}", DisplayDuration(timeout)),
+            None => write!(f, "timeout = 0"),
+        }
+    }
+}
+
+// A duration such as `30s`, `500ms`, or `2m` (a number alone is in seconds).
+// Zero is no timeout.
+fn parse_timeout(s: &str) -> Result<Option<Duration>, String> {
+    let err = || {
+        format!(
+            "Expected a duration for `timeout`, e.g., `30s`, `500ms`, or `2m`, found `{}`",
+            s
+        )
+    };
+    let (n, millis) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 1)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1000)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60 * 1000)
+    } else {
+        (s, 1000)
+    };
+    let n: u64 = n.trim().parse().map_err(|_| err())?;
+    let millis = n.checked_mul(millis).ok_or_else(err)?;
+    if millis == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(Duration::from_millis(millis)))
+    }
+}
+
+/// Shows a duration in the largest unit which is exact, as it is given to
+/// `^set timeout`, e.g., `30s`.
+pub struct DisplayDuration(pub Duration);
+
+impl fmt::Display for DisplayDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis = self.0.as_millis();
+        if millis / (60 * 1000) * (60 * 1000) == millis {
+            write!(f, "{}m", millis / (60 * 1000))
+        } else if millis / 1000 * 1000 == millis {
+            write!(f, "{}s", millis / 1000)
+        } else {
+            write!(f, "{}ms", millis)
+        }
+    }
+}
+
+/// Values for the placeholders in a prompt template.
+pub struct PromptVars<'a> {
+    /// `{n}`, the number of the next statement (i.e., of its result).
+    pub n: usize,
+    /// `{workspace}`, the name of the project or workspace.
+    pub workspace: &'a str,
+    /// `{backend}`, the state of the backend: `idle` (not started), `ready`,
+    /// `stale` (source files have changed since the last index), or `failed`.
+    pub backend: &'a str,
+    /// `{status}`, `ok` or `error` for the previous statement.
+    pub status: &'a str,
+}
+
+impl<'a> PromptVars<'a> {
+    fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "n" => Some(self.n.to_string()),
+            "workspace" => Some(self.workspace.to_owned()),
+            "backend" => Some(self.backend.to_owned()),
+            "status" => Some(self.status.to_owned()),
+            _ => None,
+        }
+    }
+}
+
+/// Replace each `{name}` placeholder in `template` with its value.
+pub fn render_prompt(template: &str, vars: &PromptVars) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match rest
+            .find('}')
+            .and_then(|end| Some((end, vars.get(&rest[1..end])?)))
+        {
+            Some((end, value)) => {
+                result.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Check that every placeholder in a prompt template is known.
+fn check_prompt(template: &str) -> Result<(), String> {
+    let vars = PromptVars {
+        n: 0,
+        workspace: "",
+        backend: "",
+        status: "",
+    };
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        let end = rest
+            .find('}')
+            .ok_or_else(|| "Unclosed `{` in prompt".to_owned())?;
+        if vars.get(&rest[..end]).is_none() {
+            return Err(format!(
+                "Unknown placeholder in prompt: `{{{}}}`, expected one of \
+                 `{{n}}`, `{{workspace}}`, `{{backend}}`, or `{{status}}`",
+                &rest[..end]
+            ));
+        }
+        rest = &rest[end + 1..];
+    }
+    Ok(())
+}
+
+/// How much detail to show when displaying a definition.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DisplayMode {
+    /// The name and location of the definition.
+    #[default]
+    Compact,
+    /// The name, location, and the lines of the item's signature.
+    Full,
+    /// As `Full`, and where each result came from.
+    Verbose,
+}
+
+impl DisplayMode {
+    fn parse(s: &str) -> Result<DisplayMode, String> {
+        match s {
+            "compact" => Ok(DisplayMode::Compact),
+            "full" => Ok(DisplayMode::Full),
+            "verbose" => Ok(DisplayMode::Verbose),
+            _ => Err(format!(
+                "Unknown display mode: `{}`, expected `compact`, `full`, or `verbose`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisplayMode::Compact => write!(f, "compact"),
+            DisplayMode::Full => write!(f, "full"),
+            DisplayMode::Verbose => write!(f, "verbose"),
+        }
+    }
+}
+
+/// How results are written.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OutputMode {
+    /// For people.
+    #[default]
+    Text,
+    /// `path:line:col: text`, like the output of grep.
+    Quickfix,
+    Csv,
+    Tsv,
+}
+
+impl OutputMode {
+    fn parse(s: &str) -> Result<OutputMode, String> {
+        match s {
+            "text" => Ok(OutputMode::Text),
+            "quickfix" => Ok(OutputMode::Quickfix),
+            "csv" => Ok(OutputMode::Csv),
+            "tsv" => Ok(OutputMode::Tsv),
+            _ => Err(format!(
+                "Unknown output mode: `{}`, expected `text`, `quickfix`, `csv`, or `tsv`",
+                s
+            )),
+        }
+    }
+
+    /// The separator between columns, if results are written as a table.
+    pub fn separator(self) -> Option<char> {
+        match self {
+            OutputMode::Csv => Some(','),
+            OutputMode::Tsv => Some('\t'),
+            OutputMode::Text | OutputMode::Quickfix => None,
+        }
+    }
+}
+
+impl fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputMode::Text => write!(f, "text"),
+            OutputMode::Quickfix => write!(f, "quickfix"),
+            OutputMode::Csv => write!(f, "csv"),
+            OutputMode::Tsv => write!(f, "tsv"),
+        }
+    }
+}
+
+/// What to do with identifiers from macro expansions.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MacroMode {
+    #[default]
+    Show,
+    Hide,
+}
+
+impl MacroMode {
+    fn parse(s: &str) -> Result<MacroMode, String> {
+        match s {
+            "show" => Ok(MacroMode::Show),
+            "hide" => Ok(MacroMode::Hide),
+            _ => Err(format!(
+                "Unknown macro mode: `{}`, expected `show` or `hide`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MacroMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacroMode::Show => write!(f, "show"),
+            MacroMode::Hide => write!(f, "hide"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.display, DisplayMode::Compact);
+        settings.set("display", "full").unwrap();
+        assert_eq!(settings.display, DisplayMode::Full);
+        assert!(settings.set("display", "fancy").is_err());
+        assert_eq!(settings.display, DisplayMode::Full);
+        assert!(settings.set("foo", "full").is_err());
+
+        assert_eq!(settings.max_set_len, 4);
+        settings.set("max-set-len", "10").unwrap();
+        assert_eq!(settings.max_set_len, 10);
+        assert!(settings.set("max-set-len", "-1").is_err());
+
+        settings.set("prompt", "[{workspace}] {n}> ").unwrap();
+        assert_eq!(settings.prompt, "[{workspace}] {n}> ");
+        assert!(settings
+            .set("prompt", "{foo} > ")
+            .unwrap_err()
+            .starts_with("Unknown placeholder in prompt: `{foo}`"));
+        assert!(settings.set("prompt", "{n > ").is_err());
+
+        assert_eq!(settings.output, OutputMode::Text);
+        settings.set("output", "quickfix").unwrap();
+        assert_eq!(settings.output, OutputMode::Quickfix);
+        assert_eq!(settings.output.separator(), None);
+        settings.set("output", "tsv").unwrap();
+        assert_eq!(settings.output.separator(), Some('\t'));
+        assert!(settings.set("output", "json").is_err());
+
+        assert_eq!(settings.macros, MacroMode::Show);
+        settings.set("macros", "hide").unwrap();
+        assert_eq!(settings.macros, MacroMode::Hide);
+        assert!(settings.set("macros", "expand").is_err());
+
+        assert_eq!(settings.context, 0);
+        settings.set("context", "2").unwrap();
+        assert_eq!(settings.context, 2);
+        assert!(settings.set("context", "all").is_err());
+
+        assert_eq!(settings.timeout, None);
+        settings.set("timeout", "30s").unwrap();
+        assert_eq!(settings.timeout, Some(Duration::from_secs(30)));
+        settings.set("timeout", "1500ms").unwrap();
+        assert_eq!(settings.timeout, Some(Duration::from_millis(1500)));
+        settings.set("timeout", "2m").unwrap();
+        assert_eq!(settings.timeout, Some(Duration::from_secs(120)));
+        assert!(settings.to_string().ends_with("timeout = 2m"));
+        settings.set("timeout", "10").unwrap();
+        assert_eq!(settings.timeout, Some(Duration::from_secs(10)));
+        settings.set("timeout", "0").unwrap();
+        assert_eq!(settings.timeout, None);
+        assert!(settings.set("timeout", "soon").is_err());
+        assert!(settings.set("timeout", "-1s").is_err());
+    }
+
+    #[test]
+    fn test_render_prompt() {
+        let vars = PromptVars {
+            n: 3,
+            workspace: "clyde",
+            backend: "ready",
+            status: "error",
+        };
+        assert_eq!(render_prompt("{n} > ", &vars), "3 > ");
+        assert_eq!(
+            render_prompt("{workspace} ({backend}, {status}) {n}> ", &vars),
+            "clyde (ready, error) 3> "
+        );
+        assert_eq!(render_prompt("{x}{{n}} {", &vars), "{x}{3} {");
+    }
+}
@@ -0,0 +1,151 @@
+//! Finding which lines of a project have changed, using version control. Only
+//! git is supported, we run `git diff` rather than reading the repository.
+
+use crate::front::data::Line;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A range of lines which were added or changed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hunk {
+    /// Relative to the directory `git diff` was run in.
+    pub file: PathBuf,
+    pub start: Line,
+    /// Inclusive.
+    pub end: Line,
+}
+
+/// The hunks of Rust source files which have changed since `revision`, not
+/// including removed lines.
+pub fn changed(root: &Path, revision: &str) -> Result<Vec<Hunk>, Error> {
+    // Don't let a revision be taken as an option.
+    if revision.is_empty() || revision.starts_with('-') {
+        return Err(Error::Git(format!("invalid revision `{}`", revision)));
+    }
+    let output = Command::new("git")
+        .current_dir(root)
+        .args([
+            "diff",
+            "--no-color",
+            "--no-ext-diff",
+            "--no-prefix",
+            "--unified=0",
+            "--relative",
+        ])
+        .arg(revision)
+        .args(["--", "*.rs"])
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(parse_diff(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The hunks in the output of `git diff --no-prefix --unified=0`, by line in
+/// the new version of each file.
+pub fn parse_diff(diff: &str) -> Vec<Hunk> {
+    let mut result = Vec::new();
+    let mut file = None;
+    // True between the start of a file's diff and its first hunk, lines of the
+    // hunks themselves might look like headers.
+    let mut in_header = false;
+    for line in diff.lines() {
+        if line.starts_with("diff ") {
+            in_header = true;
+            file = None;
+        } else if in_header && line.starts_with("+++ ") {
+            file = match line[4..].trim_matches('"') {
+                "/dev/null" => None,
+                path => Some(PathBuf::from(path)),
+            };
+        } else if line.starts_with("@@ ") {
+            in_header = false;
+            let (file, (start, len)) = match (&file, new_lines(line)) {
+                (Some(file), Some(lines)) => (file, lines),
+                _ => continue,
+            };
+            // If lines were only removed, there is nothing to point at.
+            if let (Some(start), true) = (Line::new_one_indexed(start), len > 0) {
+                result.push(Hunk {
+                    file: file.clone(),
+                    start,
+                    end: start + (len - 1),
+                });
+            }
+        }
+    }
+    result
+}
+
+// E.g., `12` and `3` in `@@ -10,2 +12,3 @@ fn foo() {`.
+fn new_lines(header: &str) -> Option<(usize, usize)> {
+    let range = header.split(' ').find(|s| s.starts_with('+'))?;
+    let mut parts = range[1..].split(',');
+    let start: usize = parts.next()?.parse().ok()?;
+    let len = match parts.next() {
+        Some(len) => len.parse().ok()?,
+        None => 1,
+    };
+    Some((start, len))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Git(String),
+    IoError(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Git(s) => write!(f, "git error: {}", s),
+            Error::IoError(e) => write!(f, "could not run git: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_diff() {
+        let diff = "diff --git src/foo.rs src/foo.rs\n\
+                    index 1234..5678 100644\n\
+                    --- src/foo.rs\n\
+                    +++ src/foo.rs\n\
+                    @@ -3 +3 @@ fn main() {\n\
+                    -    old();\n\
+                    +    new();\n\
+                    @@ -10,0 +11,3 @@\n\
+                    +++ a line which looks like a header\n\
+                    +\n\
+                    +}\n\
+                    @@ -20,2 +23,0 @@\n\
+                    -gone\n\
+                    -gone\n\
+                    diff --git src/old.rs src/old.rs\n\
+                    deleted file mode 100644\n\
+                    --- src/old.rs\n\
+                    +++ /dev/null\n\
+                    @@ -1 +0,0 @@\n\
+                    -fn old() {}\n";
+        let hunk = |start, end| Hunk {
+            file: PathBuf::from("src/foo.rs"),
+            start: Line::new_zero_indexed(start),
+            end: Line::new_zero_indexed(end),
+        };
+        assert_eq!(parse_diff(diff), vec![hunk(2, 2), hunk(10, 12)]);
+        assert!(parse_diff("").is_empty());
+    }
+}
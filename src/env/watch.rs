@@ -0,0 +1,96 @@
+//! Watches the source files of a project for changes.
+
+use glob::Pattern;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+pub struct FileWatcher {
+    // Never read, but must be kept alive for events to be sent.
+    _watcher: RecommendedWatcher,
+    events: Receiver<DebouncedEvent>,
+    // Changes in these directories are ignored (this is where build output goes).
+    target_dirs: Vec<PathBuf>,
+    // Changes to files matching these patterns (relative to `root`) are ignored.
+    root: PathBuf,
+    ignore: RefCell<Vec<Pattern>>,
+}
+
+pub enum Change {
+    File(PathBuf),
+    // We don't know what has changed, everything must be invalidated.
+    All,
+}
+
+impl FileWatcher {
+    /// Watch `roots` and all their subdirectories. Paths matching any pattern
+    /// in `ignore` (relative to the first root) are ignored.
+    pub fn new(roots: &[&Path], ignore: Vec<Pattern>) -> Result<FileWatcher, notify::Error> {
+        let (tx, events) = channel();
+        let mut watcher = notify::watcher(tx, Duration::from_millis(500))?;
+        let mut target_dirs = Vec::new();
+        for root in roots {
+            // A member inside another root is already watched.
+            if roots.iter().any(|r| r != root && root.starts_with(r)) {
+                continue;
+            }
+            watcher.watch(root, RecursiveMode::Recursive)?;
+            target_dirs.push(root.join("target"));
+        }
+        Ok(FileWatcher {
+            _watcher: watcher,
+            events,
+            target_dirs,
+            root: roots[0].to_owned(),
+            ignore: RefCell::new(ignore),
+        })
+    }
+
+    /// Changes to source files since the last call to `changes`. Does not block.
+    pub fn changes(&self) -> Vec<Change> {
+        let mut result = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                DebouncedEvent::Write(p)
+                | DebouncedEvent::Create(p)
+                | DebouncedEvent::Remove(p) => self.push_change(&mut result, p),
+                DebouncedEvent::Rename(from, to) => {
+                    self.push_change(&mut result, from);
+                    self.push_change(&mut result, to);
+                }
+                DebouncedEvent::Rescan | DebouncedEvent::Error(..) => result.push(Change::All),
+                DebouncedEvent::NoticeWrite(_)
+                | DebouncedEvent::NoticeRemove(_)
+                | DebouncedEvent::Chmod(_) => {}
+            }
+        }
+        result
+    }
+
+    pub fn set_ignore(&self, ignore: Vec<Pattern>) {
+        *self.ignore.borrow_mut() = ignore;
+    }
+
+    fn push_change(&self, changes: &mut Vec<Change>, path: PathBuf) {
+        if self.target_dirs.iter().any(|t| path.starts_with(t)) {
+            return;
+        }
+        if let Ok(relative) = path.strip_prefix(&self.root) {
+            if self
+                .ignore
+                .borrow()
+                .iter()
+                .any(|p| p.matches_path(relative))
+            {
+                return;
+            }
+        }
+        let is_source = path.extension().map(|e| e == "rs").unwrap_or(false)
+            || path.file_name().map(|n| n == "Cargo.toml").unwrap_or(false);
+        if is_source {
+            changes.push(Change::File(path));
+        }
+    }
+}
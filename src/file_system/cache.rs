@@ -0,0 +1,127 @@
+//! A cache of the contents of files with a bounded size. When the cache is
+//! full, the least recently used file is evicted to make room for another.
+
+use crate::file_system::File;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The number of files kept in a cache by default.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+pub struct FileCache {
+    capacity: usize,
+    // Each file with the time it was last used.
+    files: HashMap<u64, (Rc<File>, u64)>,
+    // Incremented each time a file is used.
+    clock: u64,
+}
+
+impl FileCache {
+    /// A cache which holds at most `capacity` files. If `capacity` is zero,
+    /// nothing is cached.
+    pub fn new(capacity: usize) -> FileCache {
+        FileCache {
+            capacity,
+            files: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// The file with `key`, if it is cached, which becomes the most recently
+    /// used file.
+    pub fn get(&mut self, key: u64) -> Option<Rc<File>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.files.get_mut(&key).map(|(file, used)| {
+            *used = clock;
+            file.clone()
+        })
+    }
+
+    /// Add `file` to the cache with `key`, evicting the least recently used
+    /// files if the cache is full.
+    pub fn insert(&mut self, key: u64, file: File) -> Rc<File> {
+        let file = Rc::new(file);
+        if self.capacity == 0 {
+            return file;
+        }
+        self.files.remove(&key);
+        self.shrink_to(self.capacity - 1);
+        self.clock += 1;
+        self.files.insert(key, (file.clone(), self.clock));
+        file
+    }
+
+    pub fn remove(&mut self, key: u64) {
+        self.files.remove(&key);
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    /// Change the most files the cache holds, evicting files if there are
+    /// now too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.shrink_to(capacity);
+    }
+
+    // Evict the least recently used files until there are at most `len`.
+    fn shrink_to(&mut self, len: usize) {
+        while self.files.len() > len {
+            let oldest = self
+                .files
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| *key)
+                .unwrap();
+            self.files.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_system::Path;
+
+    fn file(key: u64) -> File {
+        File::new(Path { key }, &format!("file {}\n", key))
+    }
+
+    fn keys(cache: &FileCache) -> Vec<u64> {
+        let mut keys: Vec<_> = cache.files.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut cache = FileCache::new(2);
+        cache.insert(1, file(1));
+        cache.insert(2, file(2));
+        assert_eq!(cache.get(1).unwrap().lines, vec!["file 1"]);
+        // 2 is the least recently used.
+        cache.insert(3, file(3));
+        assert_eq!(keys(&cache), vec![1, 3]);
+        assert!(cache.get(2).is_none());
+
+        // Replacing a file doesn't evict another.
+        cache.insert(1, file(1));
+        assert_eq!(keys(&cache), vec![1, 3]);
+
+        cache.set_capacity(1);
+        assert_eq!(keys(&cache), vec![1]);
+        cache.remove(1);
+        assert!(cache.files.is_empty());
+    }
+
+    #[test]
+    fn test_no_capacity() {
+        let mut cache = FileCache::new(0);
+        assert_eq!(cache.insert(1, file(1)).lines, vec!["file 1"]);
+        assert!(cache.get(1).is_none());
+        assert!(cache.files.is_empty());
+    }
+}
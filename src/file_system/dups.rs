@@ -0,0 +1,342 @@
+//! Finding code which is repeated (e.g., copied and pasted) in a set of files,
+//! see `FileSystem::duplicates`.
+//!
+//! Lines are compared once they are normalised: leading and trailing
+//! whitespace is removed, runs of whitespace inside a line count as a single
+//! space, and lines without any letters or digits (blank lines, or lines such as
+//! `}` or `});`) are skipped. The lines of each file are hashed on several
+//! threads, then windows of consecutive lines with the same hashes are grouped.
+
+use super::{Error, FileSystem, Path};
+use crate::front::data::{Column, Line, Range, Span};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::thread;
+
+// Files are read by the calling thread (a file system can't be shared between
+// threads), in batches of this many, so that only a batch is kept in memory.
+const BATCH_LEN: usize = 256;
+const MAX_THREADS: usize = 8;
+
+/// See `FileSystem::duplicates`.
+pub fn duplicates<Fs: FileSystem + ?Sized>(
+    fs: &Fs,
+    ranges: &[Range],
+    n: usize,
+    next: &mut dyn FnMut(usize, usize) -> bool,
+) -> Result<Vec<Vec<Span>>, Error> {
+    let segments = segments(ranges);
+    let mut scanned = Vec::with_capacity(segments.len());
+    let mut stopped = false;
+    for batch in segments.chunks(BATCH_LEN) {
+        let mut texts = Vec::with_capacity(batch.len());
+        for s in batch {
+            if !next(scanned.len() + texts.len(), segments.len()) {
+                stopped = true;
+                break;
+            }
+            texts.push(fs.with_file(s.path, |f| {
+                Ok(f.lines
+                    .iter()
+                    .take(s.last.saturating_add(1))
+                    .skip(s.first)
+                    .cloned()
+                    .collect::<Vec<_>>())
+            })?);
+        }
+        scanned.extend(scan(&batch[..texts.len()], &texts));
+        if stopped {
+            break;
+        }
+    }
+    Ok(group(&segments[..scanned.len()], &scanned, n.max(1)))
+}
+
+// Lines `first` to `last` (inclusive, `usize::MAX` for the end of the file) of
+// a file. Windows of lines don't cross from one segment to another.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Segment {
+    path: Path,
+    first: usize,
+    last: usize,
+}
+
+// The parts of files in `ranges`, with overlapping parts of a file merged, so
+// that no line is compared with itself.
+fn segments(ranges: &[Range]) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    for range in ranges {
+        let mut add =
+            |path: Path, first: usize, last: usize| segments.push(Segment { path, first, last });
+        match range {
+            Range::File(path) => add(*path, 0, usize::MAX),
+            Range::MultiFile(paths) => {
+                for path in paths {
+                    add(*path, 0, usize::MAX);
+                }
+            }
+            Range::Line(path, line) => add(*path, line.zero_indexed(), line.zero_indexed()),
+            Range::Span(span) => add(
+                span.file,
+                span.start_line.zero_indexed(),
+                span.end_line.zero_indexed(),
+            ),
+        }
+    }
+    segments.sort_by_key(|s| (s.path.key, s.first));
+    let mut result: Vec<Segment> = Vec::new();
+    for s in segments {
+        match result.last_mut() {
+            Some(prev) if prev.path == s.path && s.first <= prev.last.saturating_add(1) => {
+                prev.last = prev.last.max(s.last);
+            }
+            _ => result.push(s),
+        }
+    }
+    result
+}
+
+// A line which is compared with others.
+struct Hashed {
+    line: Line,
+    // The length of the line in bytes, where a span of it ends.
+    len: usize,
+    hash: u64,
+}
+
+// The lines worth comparing of each segment, `texts` are the lines of each.
+// Segments are divided between threads.
+fn scan(segments: &[Segment], texts: &[Vec<String>]) -> Vec<Vec<Hashed>> {
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(MAX_THREADS)
+        .min(texts.len());
+    if threads <= 1 {
+        return segments
+            .iter()
+            .zip(texts)
+            .map(|(s, t)| hash_lines(s, t))
+            .collect();
+    }
+
+    let chunk_len = texts.len().div_ceil(threads);
+    thread::scope(|scope| {
+        let handles: Vec<_> = segments
+            .chunks(chunk_len)
+            .zip(texts.chunks(chunk_len))
+            .map(|(segments, texts)| {
+                scope.spawn(move || {
+                    segments
+                        .iter()
+                        .zip(texts)
+                        .map(|(s, t)| hash_lines(s, t))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("scanning thread panicked"))
+            .collect()
+    })
+}
+
+fn hash_lines(segment: &Segment, lines: &[String]) -> Vec<Hashed> {
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, text)| text.chars().any(char::is_alphanumeric))
+        .map(|(i, text)| {
+            let mut hasher = DefaultHasher::new();
+            for word in text.split_whitespace() {
+                word.hash(&mut hasher);
+            }
+            Hashed {
+                line: Line::new_zero_indexed(segment.first + i),
+                len: text.len(),
+                hash: hasher.finish(),
+            }
+        })
+        .collect()
+}
+
+// Consecutive groups of windows are merged into one longer repeat.
+fn group(segments: &[Segment], scanned: &[Vec<Hashed>], n: usize) -> Vec<Vec<Span>> {
+    // Each window as the index of its segment and of its first line.
+    let mut windows: HashMap<u64, Vec<(usize, usize)>> = HashMap::new();
+    for (s, lines) in scanned.iter().enumerate() {
+        for (i, window) in lines.windows(n).enumerate() {
+            let mut hasher = DefaultHasher::new();
+            for line in window {
+                line.hash.hash(&mut hasher);
+            }
+            windows.entry(hasher.finish()).or_default().push((s, i));
+        }
+    }
+    let repeated: HashSet<Vec<(usize, usize)>> =
+        windows.into_values().filter(|w| w.len() > 1).collect();
+    let shifted = |group: &[(usize, usize)], by: isize| -> Option<Vec<(usize, usize)>> {
+        group
+            .iter()
+            .map(|&(s, i)| Some((s, i.checked_add_signed(by)?)))
+            .collect()
+    };
+
+    let mut result: Vec<(usize, Vec<(usize, usize)>)> = repeated
+        .iter()
+        // Only the first group of a longer repeat.
+        .filter(|g| !matches!(shifted(g, -1), Some(prev) if repeated.contains(&prev)))
+        .map(|g| {
+            let mut len = n;
+            while matches!(shifted(g, (len - n + 1) as isize), Some(next) if repeated.contains(&next))
+            {
+                len += 1;
+            }
+            (len, g.clone())
+        })
+        .collect();
+    result.sort_by(|(a_len, a), (b_len, b)| b_len.cmp(a_len).then_with(|| a.cmp(b)));
+    result
+        .into_iter()
+        .map(|(len, group)| {
+            group
+                .into_iter()
+                .map(|(s, i)| {
+                    let (first, last) = (&scanned[s][i], &scanned[s][i + len - 1]);
+                    Span::new(
+                        segments[s].path,
+                        first.line,
+                        Column::new_zero_indexed(0),
+                        last.line,
+                        Column::new_zero_indexed(last.len),
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_system::test::MockFs;
+
+    fn path(key: u64) -> Path {
+        Path { key }
+    }
+
+    // The groups of repeated lines in `files` (with keys from 1), as the key
+    // and one-indexed first and last lines of each location.
+    fn dups(files: &[&str], n: usize) -> Vec<Vec<(u64, usize, usize)>> {
+        let segments: Vec<_> = (1..=files.len() as u64)
+            .map(|key| Segment {
+                path: path(key),
+                first: 0,
+                last: usize::MAX,
+            })
+            .collect();
+        let texts: Vec<Vec<String>> = files
+            .iter()
+            .map(|f| f.lines().map(ToOwned::to_owned).collect())
+            .collect();
+        group(&segments, &scan(&segments, &texts), n)
+            .into_iter()
+            .map(|g| {
+                g.into_iter()
+                    .map(|s| {
+                        (
+                            s.file.key,
+                            s.start_line.one_indexed(),
+                            s.end_line.one_indexed(),
+                        )
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let a = "fn a() {\n    let x = foo(1,  2);\n    bar(x);\n}\n\nfn b() {}\n";
+        // The same body, indented differently, with a blank line.
+        let b = "impl B {\n    fn a() {\n        let x = foo(1, 2);\n\n        bar(x);\n    }\n}\n";
+        assert_eq!(dups(&[a, b], 2), vec![vec![(1, 1, 3), (2, 2, 5)]]);
+        // Repeated single lines are merged into the longer repeat.
+        assert_eq!(dups(&[a, b], 1), vec![vec![(1, 1, 3), (2, 2, 5)]]);
+        assert!(dups(&[a, b], 4).is_empty());
+        // Lines without letters or digits are not compared.
+        assert!(dups(&["}\n});\n", "}\n});\n"], 1).is_empty());
+
+        // Repeats within a file, and which overlap.
+        let c = "x();\ny();\nz();\nx();\ny();\n";
+        assert_eq!(
+            dups(&[c, "y();\nz();\n"], 2),
+            vec![vec![(1, 1, 2), (1, 4, 5)], vec![(1, 2, 3), (2, 1, 2)],]
+        );
+        assert_eq!(dups(&[c, c], 3), vec![vec![(1, 1, 5), (2, 1, 5)]]);
+        assert!(dups(&[c], 3).is_empty());
+    }
+
+    #[test]
+    fn test_next() {
+        let files = [Range::MultiFile(vec![path(1), path(2), path(3)])];
+        let mut calls = Vec::new();
+        let mut next = |done, total| {
+            calls.push((done, total));
+            done < 2
+        };
+        duplicates(&MockFs, &files, 3, &mut next).unwrap();
+        // The third file is not read.
+        assert_eq!(calls, [(0, 3), (1, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn test_segments() {
+        let (a, b) = (path(1), path(2));
+        let line = |path, l| Range::Line(path, Line::new_zero_indexed(l));
+        let span = |path, first, last| Range::Span(Span::new_zero_indexed(path, first, 0, last, 0));
+        assert_eq!(
+            segments(&[
+                line(b, 3),
+                span(a, 2, 5),
+                line(a, 6),
+                span(a, 4, 5),
+                line(a, 9)
+            ]),
+            vec![
+                Segment {
+                    path: a,
+                    first: 2,
+                    last: 6
+                },
+                Segment {
+                    path: a,
+                    first: 9,
+                    last: 9
+                },
+                Segment {
+                    path: b,
+                    first: 3,
+                    last: 3
+                },
+            ]
+        );
+        assert_eq!(
+            segments(&[line(a, 3), Range::MultiFile(vec![b, a])]),
+            vec![
+                Segment {
+                    path: a,
+                    first: 0,
+                    last: usize::MAX
+                },
+                Segment {
+                    path: b,
+                    first: 0,
+                    last: usize::MAX
+                },
+            ]
+        );
+    }
+}
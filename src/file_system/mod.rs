@@ -1,33 +1,173 @@
 use crate::ast;
 use crate::front;
-use crate::front::data::{Position, Range};
+use crate::front::data::{Column, Line, Position, Range, Span};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::io::{self, Write};
 use std::path::{Path as StdPath, PathBuf};
 
+pub use self::cache::DEFAULT_CAPACITY as DEFAULT_CACHE_SIZE;
 pub use self::physical::PhysicalFs;
 #[cfg(test)]
 pub use self::test::MockFs;
 
+mod cache;
+mod dups;
 mod physical;
+mod workspace;
 
 pub trait FileSystem {
+    /// Call `f` with the contents of the file at `path`.
     fn with_file<F, T>(&self, path: Path, f: F) -> Result<T, Error>
     where
-        F: FnOnce(&File) -> T;
+        F: FnOnce(&File) -> Result<T, Error>;
+
+    /// Call `f` with each line until it returns false, without keeping the whole
+    /// file in memory.
+    fn for_each_line<F>(&self, path: Path, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(Line, &str) -> Result<bool, Error>,
+    {
+        self.with_file(path, |file| {
+            for (i, text) in file.lines.iter().enumerate() {
+                if !f(Line::new_zero_indexed(i), text)? {
+                    break;
+                }
+            }
+            Ok(())
+        })
+    }
 
     fn find(&self, pat: SearchPattern) -> Result<Vec<Path>, Error>;
-    fn resolve_location(&self, loc: ast::Location) -> Result<front::Locator, Error>;
+    /// A location without a file name (e.g., `(:42)`) is in the `focus` file.
+    fn resolve_location(
+        &self,
+        loc: ast::Location,
+        focus: Option<Path>,
+    ) -> Result<front::Locator, Error>;
     fn show_path(&self, path: Path, w: &mut dyn Write) -> Result<(), Error>;
     fn snippet(&self, range: &Range) -> Result<String, Error>;
+    /// The source files of the crate `name`, a member of the workspace.
+    fn crate_files(&self, name: &str) -> Result<Vec<Path>, Error>;
+    /// The source files of the module `name`, see `in_module`.
+    fn module_files(&self, name: &str) -> Result<Vec<Path>, Error>;
 
-    fn get_line(&self, path: Path, line: usize) -> Result<String, Error> {
-        self.with_file(path, |file| {
-            // FIXME could panic
-            file.lines[line].clone()
+    fn get_line(&self, path: Path, line: Line) -> Result<String, Error> {
+        self.with_file(path, |file| match file.line(line) {
+            Some(text) => Ok(text.to_owned()),
+            None => Err(self.line_out_of_range(path, line, file.lines.len())),
+        })
+    }
+
+    fn line_out_of_range(&self, path: Path, line: Line, len: usize) -> Error {
+        Error::BadLocation(format!(
+            "line {} of {} does not exist, the file has {} line{}",
+            line,
+            self.path_name(path),
+            len,
+            if len == 1 { "" } else { "s" }
+        ))
+    }
+
+    /// `path` as it is shown to the user (see `show_path`).
+    fn path_name(&self, path: Path) -> String {
+        let mut name = Vec::new();
+        match self.show_path(path, &mut name) {
+            Ok(()) => String::from_utf8_lossy(&name).into_owned(),
+            Err(_) => "<unknown file>".to_owned(),
+        }
+    }
+
+    /// The first line and the lines of the signature of the item at `span`, up to
+    /// its opening brace or semicolon.
+    fn signature(&self, span: &Span) -> Result<(Line, Vec<String>), Error> {
+        self.with_file(span.file, |file| {
+            let mut start = span.start_line;
+            let mut lines: Vec<String> = Vec::new();
+            for line in file
+                .lines
+                .iter()
+                .skip(start.zero_indexed())
+                .take(MAX_SIGNATURE_LINES)
+            {
+                lines.push(line.to_owned());
+                let line = line.trim_end();
+                if line.contains('{') || line.ends_with(';') {
+                    break;
+                }
+            }
+            while lines.last().map(|l| l.trim().is_empty()).unwrap_or(false) {
+                lines.pop();
+            }
+            while lines.first().map(|l| l.trim().is_empty()).unwrap_or(false) {
+                lines.remove(0);
+                start = start + 1;
+            }
+            Ok((start, lines))
         })
     }
 
+    /// Matches never span more than one line.
+    fn search_text(&self, range: &Range, search: &TextSearch) -> Result<Vec<Span>, Error> {
+        let regex = search.regex()?;
+        let mut result = Vec::new();
+        let mut search = |path: Path, within: Option<&Span>| {
+            self.for_each_line(path, |line, text| {
+                if let Some(span) = within {
+                    if line < span.start_line {
+                        return Ok(true);
+                    }
+                    if line > span.end_line {
+                        return Ok(false);
+                    }
+                }
+                search_line(
+                    path,
+                    line,
+                    text,
+                    &regex,
+                    search.whole_words,
+                    within,
+                    &mut result,
+                );
+                Ok(true)
+            })
+        };
+        match range {
+            Range::File(path) => search(*path, None)?,
+            Range::MultiFile(paths) => {
+                for path in paths {
+                    search(*path, None)?;
+                }
+            }
+            Range::Line(path, line) => {
+                let end = self.get_line(*path, *line)?.len();
+                let line = Span::new(
+                    *path,
+                    *line,
+                    Column::new_zero_indexed(0),
+                    *line,
+                    Column::new_zero_indexed(end),
+                );
+                search(*path, Some(&line))?;
+            }
+            Range::Span(span) => search(span.file, Some(span))?,
+        }
+        Ok(result)
+    }
+
+    /// The groups of locations where `n` or more lines are repeated, see `dups`.
+    /// Only files read before `next(done, total)` returns false are compared.
+    fn duplicates(
+        &self,
+        ranges: &[Range],
+        n: usize,
+        next: &mut dyn FnMut(usize, usize) -> bool,
+    ) -> Result<Vec<Vec<Span>>, Error> {
+        dups::duplicates(self, ranges, n, next)
+    }
+
     fn resolve_path(&self, path: &StdPath) -> Result<Path, Error> {
         let pat: SearchPattern = path.canonicalize()?.display().to_string().into();
         let paths = self.find(pat)?;
@@ -45,15 +185,209 @@ pub trait FileSystem {
     }
 
     fn physical_path(&self, path: &Path) -> Result<PathBuf, Error>;
+
+    /// True if the file has changed since it was first read.
+    fn has_changed(&self, _path: Path) -> bool {
+        false
+    }
+}
+
+// Don't show more than this many lines of a signature.
+const MAX_SIGNATURE_LINES: usize = 10;
+
+/// True if the file shown as `name` is part of `module`, e.g., `front::data`.
+/// Only the part of the name after a `src` directory is compared.
+pub fn in_module(name: &str, module: &str) -> bool {
+    let module = module.replace("::", "/");
+    let module = module.trim_matches('/');
+    if module.is_empty() {
+        return false;
+    }
+    let name = format!("/{}", name);
+    let name = match name.find("/src/") {
+        Some(i) => &name[i + 4..],
+        None => &name,
+    };
+    name.ends_with(&format!("/{}.rs", module)) || name.contains(&format!("/{}/", module))
+}
+
+// `whole_words` is as for `grep -w`.
+fn search_line(
+    path: Path,
+    line: Line,
+    text: &str,
+    regex: &Regex,
+    whole_words: bool,
+    within: Option<&Span>,
+    result: &mut Vec<Span>,
+) {
+    let is_word = |c: Option<char>| matches!(c, Some(c) if c.is_alphanumeric() || c == '_');
+    for m in regex.find_iter(text) {
+        if whole_words
+            && (is_word(text[..m.start()].chars().next_back())
+                || is_word(text[m.end()..].chars().next()))
+        {
+            continue;
+        }
+        let start = Column::new_zero_indexed(m.start());
+        let end = Column::new_zero_indexed(m.end());
+        let inside = match within {
+            Some(span) => {
+                (span.start_line, span.start_column) <= (line, start)
+                    && (line, end) <= (span.end_line, span.end_column)
+            }
+            None => true,
+        };
+        if inside {
+            result.push(Span::new(path, line, start, line, end));
+        }
+    }
+}
+
+/// What to search for with `FileSystem::search_text`, with options like those
+/// of grep.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TextSearch {
+    pub pattern: String,
+    /// Match regardless of case (`-i`).
+    pub ignore_case: bool,
+    /// Only match whole words (`-w`).
+    pub whole_words: bool,
+    /// The pattern is literal text, not a regular expression (`-F`).
+    pub fixed: bool,
+}
+
+impl TextSearch {
+    pub fn new(pattern: String) -> TextSearch {
+        TextSearch {
+            pattern,
+            ..TextSearch::default()
+        }
+    }
+
+    /// The options as flags, e.g., `-iw`, empty if there are none.
+    pub fn flags(&self) -> String {
+        let mut result = String::new();
+        for (set, flag) in &[
+            (self.ignore_case, 'i'),
+            (self.whole_words, 'w'),
+            (self.fixed, 'F'),
+        ] {
+            if *set {
+                result.push(*flag);
+            }
+        }
+        if !result.is_empty() {
+            result.insert(0, '-');
+        }
+        result
+    }
+
+    /// The regex to search with, an error if the pattern is not a valid regular
+    /// expression.
+    pub fn regex(&self) -> Result<Regex, Error> {
+        if self.pattern.is_empty() {
+            return Err(Error::BadPattern("the pattern is empty".to_owned()));
+        }
+        let pattern = if self.fixed {
+            regex::escape(&self.pattern)
+        } else {
+            self.pattern.clone()
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(self.ignore_case)
+            .build()
+            .map_err(|e| {
+                // Syntax errors are shown over several lines, with the cause last.
+                let msg = e.to_string();
+                let cause = msg.lines().last().unwrap_or("");
+                Error::BadPattern(format!(
+                    "invalid regular expression `{}`: {}",
+                    self.pattern,
+                    cause.trim_start_matches("error: ")
+                ))
+            })
+    }
+}
+
+// E.g., `"foo" -iw`.
+impl fmt::Display for TextSearch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self.pattern)?;
+        let flags = self.flags();
+        if !flags.is_empty() {
+            write!(f, " {}", flags)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
 pub struct File {
     pub path: Path,
+    /// Without line endings.
     pub lines: Vec<String>,
+    // The byte offset in the file of the start of each line.
+    line_starts: Vec<usize>,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+impl File {
+    /// Split `text` into lines, which may end with `\n` or `\r\n`. The line
+    /// endings are not part of the lines, so columns are the same for either.
+    pub fn new(path: Path, text: &str) -> File {
+        let mut lines = Vec::new();
+        let mut line_starts = Vec::new();
+        let mut start = 0;
+        for line in text.split_inclusive('\n') {
+            line_starts.push(start);
+            start += line.len();
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            lines.push(line.strip_suffix('\r').unwrap_or(line).to_owned());
+        }
+        File {
+            path,
+            lines,
+            line_starts,
+        }
+    }
+
+    /// `None` if `column` is past the end of the line.
+    pub fn offset(&self, line: Line, column: Column) -> Option<usize> {
+        let text = self.line(line)?;
+        if column.zero_indexed() > text.len() {
+            return None;
+        }
+        Some(self.line_starts[line.zero_indexed()] + column.zero_indexed())
+    }
+
+    /// The text of `line`, `None` if the file does not have that line.
+    pub fn line(&self, line: Line) -> Option<&str> {
+        self.lines.get(line.zero_indexed()).map(|s| &**s)
+    }
+
+    /// The column as the compiler counts it. Past the end of the line, each byte
+    /// counts as a char.
+    pub fn char_column(&self, line: Line, column: Column) -> usize {
+        let text = self.line(line).unwrap_or("");
+        let column = column.zero_indexed();
+        let chars = text.char_indices().take_while(|(i, _)| *i < column).count();
+        chars + column.saturating_sub(text.len())
+    }
+
+    /// The column of `line` after `chars` chars, the inverse of `char_column`.
+    pub fn byte_column(&self, line: Line, chars: usize) -> Column {
+        let text = self.line(line).unwrap_or("");
+        let column = match text.char_indices().nth(chars) {
+            Some((i, _)) => i,
+            None => text.len() + (chars - text.chars().count()),
+        };
+        Column::new_zero_indexed(column)
+    }
+}
+
+// Note that the key is only meaningful within a single session, use
+// `FileSystem::physical_path` and `FileSystem::resolve_path` to persist a path.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Path {
     key: u64,
 }
@@ -72,8 +406,12 @@ impl From<String> for SearchPattern {
 #[derive(Debug)]
 pub enum Error {
     BadLocation(String),
+    /// A file name which matches several files, with the name of each.
+    Ambiguous(String, Vec<(Path, String)>),
     InternalError(String),
     IoError(io::Error),
+    /// A pattern to search for which can't be used.
+    BadPattern(String),
     Other(String),
 }
 
@@ -81,8 +419,15 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::BadLocation(s) => write!(f, "Invalid location: {}", s),
+            Error::Ambiguous(name, files) => write!(
+                f,
+                "Invalid location: `{}` matches {} files",
+                name,
+                files.len()
+            ),
             Error::InternalError(s) => write!(f, "Internal error: {}", s),
             Error::IoError(e) => e.fmt(f),
+            Error::BadPattern(s) => write!(f, "Invalid search: {}", s),
             Error::Other(s) => write!(f, "File error: {}", s),
         }
     }
@@ -95,35 +440,48 @@ impl From<io::Error> for Error {
 }
 
 // Helper function which should only be used by file systems
-fn resolve_location<Fs: FileSystem>(loc: ast::Location, fs: &Fs) -> Result<front::Locator, Error> {
-    match loc.file {
-        Some(f) => {
+fn resolve_location<Fs: FileSystem>(
+    loc: ast::Location,
+    focus: Option<Path>,
+    fs: &Fs,
+) -> Result<front::Locator, Error> {
+    let path = match (loc.file, focus) {
+        (Some(f), _) => {
             let mut paths = fs.find(f.clone().into())?;
             if paths.is_empty() {
                 return Err(Error::BadLocation(format!("no files match `{}`", f)));
             }
             if paths.len() > 1 {
-                if loc.line.is_some() || loc.column.is_some() {
-                    return Err(Error::BadLocation(format!(
-                        "line or column specified for multiple a multi-file range"
-                    )));
+                if loc.line.is_none() && loc.column.is_none() {
+                    return Ok(front::Locator::Range(Range::MultiFile(paths)));
                 }
-                return Ok(front::Locator::Range(Range::MultiFile(paths)));
-            }
-            let path = paths.pop().unwrap();
-            match loc.line {
-                Some(l) if l > 0 => match loc.column {
-                    Some(c) if c > 0 => Ok(front::Locator::Position(Position {
-                        file: path,
-                        line: l - 1,
-                        column: c - 1,
-                    })),
-                    _ => Ok(front::Locator::Range(Range::Line(path, l - 1))),
-                },
-                _ => Ok(front::Locator::Range(Range::File(path))),
+                // A line in several files makes no sense, unless one of them
+                // is shown with exactly the name given.
+                let mut files: Vec<_> = paths.into_iter().map(|p| (p, fs.path_name(p))).collect();
+                match files.iter().position(|(_, name)| *name == f) {
+                    Some(i) => files.swap_remove(i).0,
+                    None => return Err(Error::Ambiguous(f, files)),
+                }
+            } else {
+                paths.pop().unwrap()
             }
         }
-        None => Err(Error::BadLocation("unspecified location".to_owned())),
+        (None, Some(focus)) => focus,
+        (None, None) => {
+            return Err(Error::BadLocation(
+                "no file given and there is no focus file (see `^focus`)".to_owned(),
+            ))
+        }
+    };
+    // Line or column 0 means the whole file or line.
+    let line = loc.line.and_then(Line::new_one_indexed);
+    let column = loc.column.and_then(Column::new_one_indexed);
+    match (line, column) {
+        (Some(line), Some(column)) => {
+            Ok(front::Locator::Position(Position::new(path, line, column)))
+        }
+        (Some(line), None) => Ok(front::Locator::Range(Range::Line(path, line))),
+        (None, _) => Ok(front::Locator::Range(Range::File(path))),
     }
 }
 
@@ -137,19 +495,15 @@ mod test {
     impl FileSystem for MockFs {
         fn with_file<F, T>(&self, path: Path, f: F) -> Result<T, Error>
         where
-            F: FnOnce(&File) -> T,
+            F: FnOnce(&File) -> Result<T, Error>,
         {
-            let mut file = File {
-                path,
-                lines: Vec::new(),
-            };
-            for i in 0..20 {
-                file.lines.push(format!(
-                    "This is line {} of a file with number {}.",
-                    i, path.key
-                ));
+            if path.key == 8 {
+                return f(&File::new(path, "let größe = \"名\"; // ße\n"));
             }
-            Ok(f(&file))
+            let text: String = (0..20)
+                .map(|i| format!("This is line {} of a file with number {}.\n", i, path.key))
+                .collect();
+            f(&File::new(path, &text))
         }
 
         fn find(&self, pat: SearchPattern) -> Result<Vec<Path>, Error> {
@@ -157,12 +511,25 @@ mod test {
                 SearchPattern::Name(s) if s == "foo.rs" => Ok(vec![Path { key: 1 }]),
                 SearchPattern::Name(s) if s == "bar.rs" => Ok(vec![Path { key: 2 }]),
                 SearchPattern::Name(s) if s == "baz.rs" => Ok(vec![Path { key: 3 }]),
+                // With non-ASCII text.
+                SearchPattern::Name(s) if s == "uni.rs" => Ok(vec![Path { key: 8 }]),
+                // In several workspace members.
+                SearchPattern::Name(s) if s == "dup.rs" => {
+                    Ok(vec![Path { key: 4 }, Path { key: 5 }])
+                }
+                SearchPattern::Name(s) if s == "lib.rs" => {
+                    Ok(vec![Path { key: 6 }, Path { key: 7 }])
+                }
                 p => Err(Error::Other(format!("{:?}", p))),
             }
         }
 
-        fn resolve_location(&self, loc: ast::Location) -> Result<front::Locator, Error> {
-            resolve_location(loc, self)
+        fn resolve_location(
+            &self,
+            loc: ast::Location,
+            focus: Option<Path>,
+        ) -> Result<front::Locator, Error> {
+            resolve_location(loc, focus, self)
         }
 
         fn show_path(&self, path: Path, w: &mut dyn Write) -> Result<(), Error> {
@@ -170,6 +537,11 @@ mod test {
                 1 => write!(w, "foo.rs"),
                 2 => write!(w, "bar.rs"),
                 3 => write!(w, "baz.rs"),
+                4 => write!(w, "a/dup.rs"),
+                5 => write!(w, "b/dup.rs"),
+                6 => write!(w, "a/lib.rs"),
+                7 => write!(w, "lib.rs"),
+                8 => write!(w, "uni.rs"),
                 _ => panic!(),
             }?;
             Ok(())
@@ -179,6 +551,29 @@ mod test {
             Ok(format!("snippet at {:?}", range))
         }
 
+        // The crates are `a` and `b`, for the `a/` and `b/` files, and `mock`
+        // for the rest.
+        fn crate_files(&self, name: &str) -> Result<Vec<Path>, Error> {
+            let keys = match name {
+                "a" => vec![4, 6],
+                "b" => vec![5],
+                "mock" => vec![1, 2, 3, 7],
+                _ => return Err(Error::BadLocation(format!("no crate named `{}`", name))),
+            };
+            Ok(keys.into_iter().map(|key| Path { key }).collect())
+        }
+
+        fn module_files(&self, name: &str) -> Result<Vec<Path>, Error> {
+            let paths: Vec<_> = (1..=7)
+                .map(|key| Path { key })
+                .filter(|p| in_module(&self.path_name(*p), name))
+                .collect();
+            if paths.is_empty() {
+                return Err(Error::BadLocation(format!("no module `{}`", name)));
+            }
+            Ok(paths)
+        }
+
         fn physical_path(&self, path: &Path) -> Result<PathBuf, Error> {
             Err(Error::Other(format!("Path: {:?}", path)))
         }
@@ -189,23 +584,171 @@ mod test {
     }
 
     fn line_range(key: u64, line: usize) -> front::Locator {
-        front::Locator::Range(Range::Line(Path { key }, line))
+        front::Locator::Range(Range::Line(Path { key }, Line::new_zero_indexed(line)))
     }
 
     fn position(key: u64, line: usize, column: usize) -> front::Locator {
-        front::Locator::Position(Position {
-            file: Path { key },
-            line,
-            column,
-        })
+        front::Locator::Position(Position::new_zero_indexed(Path { key }, line, column))
+    }
+
+    #[test]
+    fn test_search_text() {
+        let foo = Path { key: 1 };
+        let search = |range: Range, search: TextSearch| {
+            MockFs
+                .search_text(&range, &search)
+                .unwrap()
+                .into_iter()
+                .map(|s| {
+                    (
+                        s.start_line.zero_indexed(),
+                        s.start_column.zero_indexed(),
+                        s.end_column.zero_indexed(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // `line 1` and `line 10` to `line 19`.
+        let line_1 = TextSearch::new("line 1".to_owned());
+        assert_eq!(search(Range::File(foo), line_1.clone()).len(), 11);
+        let whole_words = TextSearch {
+            whole_words: true,
+            ..line_1
+        };
+        assert_eq!(search(Range::File(foo), whole_words), vec![(1, 8, 14)]);
+
+        let this = TextSearch::new("this".to_owned());
+        assert_eq!(search(Range::File(foo), this.clone()), vec![]);
+        let ignore_case = TextSearch {
+            ignore_case: true,
+            ..this
+        };
+        assert_eq!(
+            search(Range::MultiFile(vec![foo, Path { key: 2 }]), ignore_case).len(),
+            40
+        );
+
+        // Only the full stop, not every character.
+        let dot = TextSearch {
+            fixed: true,
+            ..TextSearch::new(".".to_owned())
+        };
+        assert_eq!(
+            search(Range::Line(foo, Line::new_zero_indexed(2)), dot),
+            vec![(2, 38, 39)]
+        );
+
+        // Only matches inside a span.
+        let span = Range::Span(Span::new_zero_indexed(foo, 2, 3, 3, 7));
+        let is = TextSearch::new("is".to_owned());
+        assert_eq!(
+            search(span.clone(), is.clone()),
+            vec![(2, 5, 7), (3, 2, 4), (3, 5, 7)]
+        );
+        let is = TextSearch {
+            whole_words: true,
+            ..is
+        };
+        assert_eq!(search(span, is), vec![(2, 5, 7), (3, 5, 7)]);
+
+        assert_eq!(
+            MockFs
+                .search_text(&Range::File(foo), &TextSearch::new("(".to_owned()))
+                .unwrap_err()
+                .to_string(),
+            "Invalid search: invalid regular expression `(`: unclosed group"
+        );
+        assert!(MockFs
+            .search_text(&Range::File(foo), &TextSearch::new(String::new()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_text_search_flags() {
+        let mut search = TextSearch::new("foo".to_owned());
+        assert_eq!(search.flags(), "");
+        assert_eq!(search.to_string(), "\"foo\"");
+        search.fixed = true;
+        search.ignore_case = true;
+        assert_eq!(search.flags(), "-iF");
+        assert_eq!(search.to_string(), "\"foo\" -iF");
+    }
+
+    #[test]
+    fn test_file_offset() {
+        let file = File::new(Path { key: 1 }, "fn main() {\r\n    é();\n}");
+        assert_eq!(file.lines, vec!["fn main() {", "    é();", "}"]);
+        let offset = |line, column| {
+            file.offset(
+                Line::new_zero_indexed(line),
+                Column::new_zero_indexed(column),
+            )
+        };
+        assert_eq!(offset(0, 3), Some(3));
+        assert_eq!(offset(1, 0), Some(13));
+        // `é` is two bytes.
+        assert_eq!(offset(1, 7), Some(20));
+        assert_eq!(offset(1, 9), Some(22));
+        assert_eq!(offset(1, 10), None);
+        assert_eq!(offset(2, 1), Some(24));
+        assert_eq!(offset(3, 0), None);
+        assert!(File::new(Path { key: 1 }, "").lines.is_empty());
+    }
+
+    #[test]
+    fn test_char_columns() {
+        let file = File::new(Path { key: 1 }, "let größe = \"日本\"; // 🦀\nlet x = 1;");
+        let line = Line::new_zero_indexed(0);
+        // (byte, char) columns of `größe`, `=`, `"`, `;`, `🦀`, and the end.
+        let columns = [(4, 4), (12, 10), (14, 12), (22, 16), (27, 21), (31, 22)];
+        for &(bytes, chars) in &columns {
+            assert_eq!(
+                file.char_column(line, Column::new_zero_indexed(bytes)),
+                chars
+            );
+            assert_eq!(
+                file.byte_column(line, chars),
+                Column::new_zero_indexed(bytes)
+            );
+        }
+        // In the middle of `ö`.
+        assert_eq!(file.char_column(line, Column::new_zero_indexed(7)), 7);
+        // Past the end of the line, and of the file.
+        assert_eq!(file.char_column(line, Column::new_zero_indexed(40)), 31);
+        assert_eq!(file.byte_column(line, 31), Column::new_zero_indexed(40));
+        let past = Line::new_zero_indexed(5);
+        assert_eq!(file.char_column(past, Column::new_zero_indexed(3)), 3);
+        assert_eq!(file.byte_column(past, 3), Column::new_zero_indexed(3));
+        // ASCII columns are the same either way.
+        let line = Line::new_zero_indexed(1);
+        assert_eq!(file.char_column(line, Column::new_zero_indexed(6)), 6);
+        assert_eq!(file.byte_column(line, 6), Column::new_zero_indexed(6));
+    }
+
+    #[test]
+    fn test_in_module() {
+        assert!(in_module("src/front.rs", "front"));
+        assert!(in_module("src/front/mod.rs", "front"));
+        assert!(in_module("src/front/data.rs", "front"));
+        assert!(in_module("front/data.rs", "front"));
+        assert!(in_module("src/front/data.rs", "front::data"));
+        assert!(in_module("src/front/data/show.rs", "front/data"));
+        assert!(!in_module("src/frontend.rs", "front"));
+        assert!(!in_module("src/back/front.txt", "front"));
+        assert!(!in_module("src/front.rs", "data"));
+        assert!(!in_module("src/front.rs", "::"));
+        assert!(!in_module("crates/front/src/lib.rs", "front"));
+        assert!(in_module("crates/a/src/front/mod.rs", "front"));
     }
 
     #[test]
     fn test_resolve_loc() {
-        assert!(resolve_location(builder::location(None, None, None), &MockFs).is_err());
+        assert!(resolve_location(builder::location(None, None, None), None, &MockFs).is_err());
         assert_eq!(
             resolve_location(
                 builder::location(Some("bar.rs".to_owned()), None, None),
+                None,
                 &MockFs
             )
             .unwrap(),
@@ -214,6 +757,7 @@ mod test {
         assert_eq!(
             resolve_location(
                 builder::location(Some("baz.rs".to_owned()), Some(4), None),
+                None,
                 &MockFs
             )
             .unwrap(),
@@ -222,10 +766,70 @@ mod test {
         assert_eq!(
             resolve_location(
                 builder::location(Some("foo.rs".to_owned()), Some(4), Some(42)),
+                None,
                 &MockFs
             )
             .unwrap(),
             position(1, 3, 41)
         );
+
+        // Locations without a file are in the focus file.
+        let focus = Some(Path { key: 2 });
+        assert_eq!(
+            resolve_location(builder::location(None, Some(7), None), focus, &MockFs).unwrap(),
+            line_range(2, 6)
+        );
+        assert_eq!(
+            resolve_location(builder::location(None, Some(7), Some(3)), focus, &MockFs).unwrap(),
+            position(2, 6, 2)
+        );
+        assert!(resolve_location(builder::location(None, Some(7), None), None, &MockFs).is_err());
+        // A line in several files is ambiguous, unless one is named exactly.
+        assert_eq!(
+            resolve_location(
+                builder::location(Some("dup.rs".to_owned()), None, None),
+                None,
+                &MockFs
+            )
+            .unwrap(),
+            front::Locator::Range(Range::MultiFile(vec![Path { key: 4 }, Path { key: 5 }]))
+        );
+        match resolve_location(
+            builder::location(Some("dup.rs".to_owned()), Some(3), None),
+            None,
+            &MockFs,
+        ) {
+            Err(Error::Ambiguous(name, files)) => {
+                assert_eq!(name, "dup.rs");
+                assert_eq!(
+                    files,
+                    vec![
+                        (Path { key: 4 }, "a/dup.rs".to_owned()),
+                        (Path { key: 5 }, "b/dup.rs".to_owned())
+                    ]
+                );
+            }
+            _ => panic!(),
+        }
+        assert_eq!(
+            resolve_location(
+                builder::location(Some("lib.rs".to_owned()), Some(3), None),
+                None,
+                &MockFs
+            )
+            .unwrap(),
+            line_range(7, 2)
+        );
+
+        // A file name overrides the focus.
+        assert_eq!(
+            resolve_location(
+                builder::location(Some("foo.rs".to_owned()), None, None),
+                focus,
+                &MockFs
+            )
+            .unwrap(),
+            file_range(1)
+        );
     }
 }
@@ -1,27 +1,140 @@
 use crate::ast;
-use crate::file_system::{self, File, FileSystem, Path, SearchPattern};
+use crate::file_system::cache::{self, FileCache};
+use crate::file_system::{self, workspace, File, FileSystem, Path, SearchPattern};
 use crate::front;
-use crate::front::data::Range;
+use crate::front::data::{Column, Line, Range};
 use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
-use std::fs::File as StdFile;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::hash::{Hash, Hasher};
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path as StdPath, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+// The most files offered for a name which only matches fuzzily.
+const MAX_FUZZY_MATCHES: usize = 10;
 
 pub struct PhysicalFs {
     pub root: PathBuf,
+    // If `root` is a workspace, the root directories of its members.
+    members: Vec<PathBuf>,
     path_map: RefCell<HashMap<u64, PathBuf>>,
-    file_cache: RefCell<HashMap<u64, File>>,
+    file_cache: RefCell<FileCache>,
+    // The stamp of each file when it was last read.
+    stamps: RefCell<HashMap<u64, Option<Stamp>>>,
+    // Files whose contents have changed since `clear_changed` was last called.
+    changed: RefCell<HashSet<u64>>,
+}
+
+// The modification time and length of a file, to notice when it changes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(path: &StdPath) -> Option<Stamp> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Stamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
 }
 
 impl PhysicalFs {
     pub fn new(root: &StdPath) -> PhysicalFs {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_owned());
         PhysicalFs {
-            root: root.to_owned(),
+            members: workspace::members(&root),
+            root,
             path_map: RefCell::new(HashMap::new()),
-            file_cache: RefCell::new(HashMap::new()),
+            file_cache: RefCell::new(FileCache::new(cache::DEFAULT_CAPACITY)),
+            stamps: RefCell::new(HashMap::new()),
+            changed: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// Set the most files whose contents are kept in memory. The least
+    /// recently used files are dropped when there are more.
+    pub fn set_cache_size(&self, size: usize) {
+        self.file_cache.borrow_mut().set_capacity(size);
+    }
+
+    /// The workspace root followed by the roots of any workspace members.
+    pub fn roots(&self) -> Vec<&StdPath> {
+        let mut result = vec![&*self.root];
+        result.extend(self.members.iter().map(|m| &**m));
+        result
+    }
+
+    // The name and root directory of each crate.
+    fn crates(&self) -> Vec<(String, &StdPath)> {
+        let mut result = Vec::new();
+        if let Some(name) = workspace::package_name(&self.root) {
+            result.push((name, &*self.root));
+        }
+        for member in &self.members {
+            let name = workspace::package_name(member)
+                .or_else(|| Some(member.file_name()?.to_string_lossy().into_owned()));
+            if let Some(name) = name {
+                result.push((name, &**member));
+            }
+        }
+        result
+    }
+
+    // Relative to the workspace root, otherwise to any member.
+    fn lookup(&self, path: PathBuf) -> Result<Vec<Path>, file_system::Error> {
+        if path.is_absolute() || self.root.join(&path).exists() {
+            return Ok(vec![self.insert_path(path)?]);
+        }
+
+        let mut result = Vec::new();
+        for member in &self.members {
+            let abs_path = member.join(&path);
+            if abs_path.exists() {
+                let p = self.insert_path(abs_path)?;
+                if !result.contains(&p) {
+                    result.push(p);
+                }
+            }
+        }
+        if result.is_empty() {
+            return self.find_similar(&path);
+        }
+        Ok(result)
+    }
+
+    // Several fuzzy matches are ambiguous, even without a line.
+    fn find_similar(&self, path: &StdPath) -> Result<Vec<Path>, file_system::Error> {
+        let files = workspace::source_files(&self.roots());
+        let names: Vec<String> = files
+            .iter()
+            .map(|f| slashed(&self.display_path(f)))
+            .collect();
+        let name = slashed(path);
+        match rank(&name, &names) {
+            Some(Matches::Suffix(indices)) => indices
+                .into_iter()
+                .map(|i| self.insert_path(files[i].clone()))
+                .collect(),
+            Some(Matches::Fuzzy(indices)) if indices.len() == 1 => {
+                Ok(vec![self.insert_path(files[indices[0]].clone())?])
+            }
+            Some(Matches::Fuzzy(indices)) => {
+                let files = indices
+                    .into_iter()
+                    .map(|i| Ok((self.insert_path(files[i].clone())?, names[i].clone())))
+                    .collect::<Result<_, file_system::Error>>()?;
+                Err(file_system::Error::Ambiguous(name, files))
+            }
+            // Gives a 'file not found' error.
+            None => Ok(vec![self.insert_path(path.to_owned())?]),
         }
     }
 
@@ -36,103 +149,411 @@ impl PhysicalFs {
 
         let abs_path = abs_path.canonicalize()?;
 
-        let mut hasher = DefaultHasher::new();
-        abs_path.hash(&mut hasher);
-        let key = hasher.finish();
+        let key = key(&abs_path);
         let mut path_map = self.path_map.borrow_mut();
-        path_map.insert(key, abs_path);
+        // Keep the first spelling of a path we see, so that it is shown
+        // consistently if the file system ignores case.
+        path_map.entry(key).or_insert(abs_path);
         Ok(Path { key })
     }
 
-    fn ensure_path(&self, path: Path) -> Result<(), file_system::Error> {
-        {
-            let file_cache = self.file_cache.borrow();
-            if file_cache.contains_key(&path.key) {
-                return Ok(());
+    /// Remove the cached contents of the file at `path` (if it has been cached),
+    /// the file will be re-read next time it is used.
+    pub fn invalidate(&self, path: &StdPath) {
+        // The file might have been deleted, in which case we can't canonicalize
+        // the path, but then we'll assume it was already canonical.
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        self.file_cache.borrow_mut().remove(key(&path));
+    }
+
+    /// Remove the cached contents of all files.
+    pub fn invalidate_all(&self) {
+        self.file_cache.borrow_mut().clear();
+    }
+
+    /// Forget which files have changed since they were first read, e.g., once
+    /// the index has been rebuilt from their current contents.
+    pub fn clear_changed(&self) {
+        self.changed.borrow_mut().clear();
+    }
+
+    // The contents of the file at `path`, read into the cache if necessary.
+    // Cached files are read again if they have changed since.
+    fn cached_file(&self, path: Path) -> Result<Rc<File>, file_system::Error> {
+        let std_path = self.std_path(path)?;
+        let stamp = Stamp::of(&std_path);
+        if let Some(file) = self.current_file(path, stamp) {
+            return Ok(file);
+        }
+
+        // The stamp is taken before reading, so a change while the file is
+        // read is noticed next time.
+        let bytes = fs::read(&std_path)?;
+        self.read_with(path, stamp);
+        let (text, lossy) = decode(&bytes);
+        if lossy {
+            warn_lossy(&std_path);
+        }
+        let file = File::new(path, &text);
+        Ok(self.file_cache.borrow_mut().insert(path.key, file))
+    }
+
+    // The cached contents of the file at `path`, if they are cached and the
+    // file still has `stamp`.
+    fn current_file(&self, path: Path, stamp: Option<Stamp>) -> Option<Rc<File>> {
+        let file = self.file_cache.borrow_mut().get(path.key)?;
+        if self.stamps.borrow().get(&path.key) == Some(&stamp) {
+            Some(file)
+        } else {
+            self.file_cache.borrow_mut().remove(path.key);
+            None
+        }
+    }
+
+    // Record that the file at `path` was read when it had `stamp`, and whether
+    // it has changed since it was last read.
+    fn read_with(&self, path: Path, stamp: Option<Stamp>) {
+        let old = self.stamps.borrow_mut().insert(path.key, stamp);
+        if matches!(old, Some(old) if old != stamp) {
+            log::debug!("file changed since it was read: {:?}", self.std_path(path));
+            self.changed.borrow_mut().insert(path.key);
+        }
+    }
+
+    fn std_path(&self, path: Path) -> Result<PathBuf, file_system::Error> {
+        match self.path_map.borrow().get(&path.key) {
+            Some(p) => Ok(p.clone()),
+            None => Err(file_system::Error::InternalError(
+                "path missing from path_map".to_owned(),
+            )),
+        }
+    }
+}
+
+fn warn_lossy(path: &StdPath) {
+    eprintln!(
+        "warning: {} is not valid UTF-8, invalid characters have been replaced",
+        path.display()
+    );
+}
+
+impl PhysicalFs {
+    // Members outside the workspace root are prefixed with their directory.
+    fn display_path<'a>(&self, path: &'a StdPath) -> std::borrow::Cow<'a, StdPath> {
+        if let Ok(p) = path.strip_prefix(&self.root) {
+            return p.into();
+        }
+        for member in &self.members {
+            if let (Ok(p), Some(name)) = (path.strip_prefix(member), member.file_name()) {
+                return StdPath::new(name).join(p).into();
             }
         }
+        path.into()
+    }
+}
 
-        let file = {
-            let path_map = self.path_map.borrow();
-            let std_path = match path_map.get(&path.key) {
-                Some(p) => p,
-                None => {
-                    return Err(file_system::Error::InternalError(
-                        "path missing from path_map".to_owned(),
-                    ))
-                }
-            };
-            StdFile::open(std_path)?
-        };
-        let reader = BufReader::new(file);
-        let file = File {
-            path,
-            lines: reader.lines().collect::<Result<Vec<_>, _>>()?,
-        };
+// `canonicalize` does not normalize the case of a path.
+const CASE_INSENSITIVE: bool = cfg!(any(windows, target_os = "macos"));
 
-        let mut file_cache = self.file_cache.borrow_mut();
-        file_cache.insert(path.key, file);
-        Ok(())
+// Paths which refer to the same file have the same key.
+fn key(abs_path: &StdPath) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    comparable(abs_path, CASE_INSENSITIVE).hash(&mut hasher);
+    hasher.finish()
+}
+
+// A form of `abs_path` for comparing with other paths, the case of each
+// component is folded if `case_insensitive`.
+fn comparable(abs_path: &StdPath, case_insensitive: bool) -> std::borrow::Cow<'_, StdPath> {
+    if !case_insensitive {
+        return abs_path.into();
+    }
+    let folded: PathBuf = abs_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+        .collect();
+    folded.into()
+}
+
+// `path` with `/` as the separator, as file names are matched.
+fn slashed(path: &StdPath) -> String {
+    let path = path.to_string_lossy();
+    if std::path::MAIN_SEPARATOR == '/' {
+        path.into_owned()
+    } else {
+        path.replace(std::path::MAIN_SEPARATOR, "/")
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Matches {
+    Suffix(Vec<usize>),
+    Fuzzy(Vec<usize>),
+}
+
+// The indices of the paths which match `name`, best first. Fuzzy matches are
+// only used if no path ends with `name`.
+fn rank(name: &str, paths: &[String]) -> Option<Matches> {
+    let name = name.trim_start_matches("./").trim_end_matches('/');
+    if name.is_empty() {
+        return None;
+    }
+
+    let suffixes = [
+        name.to_owned(),
+        format!("{}.rs", name),
+        format!("{}/mod.rs", name),
+    ];
+    let mut suffixes: Vec<usize> = (0..paths.len())
+        .filter(|&i| {
+            suffixes
+                .iter()
+                .any(|s| paths[i] == *s || paths[i].ends_with(&format!("/{}", s)))
+        })
+        .collect();
+    if !suffixes.is_empty() {
+        suffixes.sort_by(|&a, &b| (paths[a].len(), &paths[a]).cmp(&(paths[b].len(), &paths[b])));
+        return Some(Matches::Suffix(suffixes));
+    }
+
+    let mut fuzzy: Vec<(usize, usize)> = (0..paths.len())
+        .filter_map(|i| fuzzy_gaps(name, &paths[i]).map(|gaps| (gaps, i)))
+        .collect();
+    if fuzzy.is_empty() {
+        return None;
+    }
+    fuzzy.sort_by(|&(ga, a), &(gb, b)| {
+        (ga, paths[a].len(), &paths[a]).cmp(&(gb, paths[b].len(), &paths[b]))
+    });
+    fuzzy.truncate(MAX_FUZZY_MATCHES);
+    Some(Matches::Fuzzy(fuzzy.into_iter().map(|(_, i)| i).collect()))
+}
+
+// Matched from the end, to prefer matches in the file name.
+fn fuzzy_gaps(name: &str, path: &str) -> Option<usize> {
+    let path: Vec<char> = path.chars().flat_map(char::to_lowercase).collect();
+    let mut end = path.len();
+    let mut gaps = 0;
+    let mut last = None;
+    for c in name
+        .chars()
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        let i = path[..end].iter().rposition(|&p| p == c)?;
+        if last.is_some_and(|last| last != i + 1) {
+            gaps += 1;
+        }
+        last = Some(i);
+        end = i;
+    }
+    Some(gaps)
+}
+
+// The bool is true if there was invalid UTF-8.
+fn decode(bytes: &[u8]) -> (String, bool) {
+    let bytes = if bytes.starts_with(BOM) {
+        &bytes[BOM.len()..]
+    } else {
+        bytes
+    };
+    match String::from_utf8(bytes.to_owned()) {
+        Ok(s) => (s, false),
+        Err(_) => (String::from_utf8_lossy(bytes).into_owned(), true),
     }
 }
 
 impl FileSystem for PhysicalFs {
     fn with_file<F, T>(&self, path: Path, f: F) -> Result<T, file_system::Error>
     where
-        F: FnOnce(&File) -> T,
+        F: FnOnce(&File) -> Result<T, file_system::Error>,
     {
-        self.ensure_path(path)?;
-        let file_cache = self.file_cache.borrow();
-        let result = f(&file_cache[&path.key]);
-        Ok(result)
+        f(&*self.cached_file(path)?)
+    }
+
+    // Files which are cached (and haven't changed) are used as they are,
+    // others are read a line at a time and are not cached.
+    fn for_each_line<F>(&self, path: Path, mut f: F) -> Result<(), file_system::Error>
+    where
+        F: FnMut(Line, &str) -> Result<bool, file_system::Error>,
+    {
+        let std_path = self.std_path(path)?;
+        let stamp = Stamp::of(&std_path);
+        if let Some(file) = self.current_file(path, stamp) {
+            for (i, text) in file.lines.iter().enumerate() {
+                if !f(Line::new_zero_indexed(i), text)? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        let mut reader = BufReader::new(fs::File::open(&std_path)?);
+        self.read_with(path, stamp);
+        let mut buf = Vec::new();
+        let mut lossy = false;
+        for i in 0.. {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            let mut bytes = &buf[..];
+            if i == 0 && bytes.starts_with(BOM) {
+                bytes = &bytes[BOM.len()..];
+            }
+            let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+            let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+            let text = String::from_utf8_lossy(bytes);
+            lossy |= matches!(text, std::borrow::Cow::Owned(_));
+            if !f(Line::new_zero_indexed(i), &text)? {
+                break;
+            }
+        }
+        if lossy {
+            warn_lossy(&std_path);
+        }
+        Ok(())
+    }
+
+    // Paths are compared by key (see `key`), rather than by name as in the
+    // default implementation.
+    fn resolve_path(&self, path: &StdPath) -> Result<Path, file_system::Error> {
+        self.insert_path(path.canonicalize()?)
     }
 
     fn find(&self, pat: SearchPattern) -> Result<Vec<Path>, file_system::Error> {
         // FIXME pat might be a plain name, but still be a directory and thus give a MultiFile result.
         match pat {
-            SearchPattern::Name(name) => {
-                let path = self.insert_path(name.into())?;
-                Ok(vec![path])
-            }
+            SearchPattern::Name(name) => self.lookup(name.into()),
         }
     }
 
-    fn resolve_location(&self, loc: ast::Location) -> Result<front::Locator, file_system::Error> {
+    fn resolve_location(
+        &self,
+        loc: ast::Location,
+        focus: Option<Path>,
+    ) -> Result<front::Locator, file_system::Error> {
         // FIXME pre-cache the file?
-        file_system::resolve_location(loc, self)
+        file_system::resolve_location(loc, focus, self)
     }
 
     fn show_path(&self, path: Path, w: &mut dyn Write) -> Result<(), file_system::Error> {
         // TODO unwraps should return errors
         let path_map = self.path_map.borrow();
         let path = path_map.get(&path.key).unwrap();
-        let path = path.strip_prefix(&self.root).unwrap();
-        write!(w, "{}", path.display()).map_err(Into::into)
+        write!(w, "{}", self.display_path(path).display()).map_err(Into::into)
     }
 
     fn snippet(&self, range: &Range) -> Result<String, file_system::Error> {
         match range {
-            Range::File(p) => self.with_file(*p, |f| f.lines.join("\n")),
+            Range::File(p) => self.with_file(*p, |f| Ok(f.lines.join("\n"))),
             Range::MultiFile(_) => unimplemented!(),
-            // FIXME line out of range should be an error, not panic
-            Range::Line(p, line) => self.with_file(*p, |f| f.lines[*line].clone()),
+            Range::Line(p, line) => self.get_line(*p, *line),
             Range::Span(span) => self.with_file(span.file, |f| {
+                let line = |n: Line| {
+                    f.line(n)
+                        .ok_or_else(|| self.line_out_of_range(span.file, n, f.lines.len()))
+                };
+                let columns = |text: &str, n: Line, start: Column, end: Option<Column>| {
+                    let end = end.map_or(text.len(), Column::zero_indexed);
+                    text.get(start.zero_indexed()..end)
+                        .map(|s| s.to_owned())
+                        .ok_or_else(|| {
+                            file_system::Error::BadLocation(format!(
+                                "columns {}->{} are out of range for line {} ({} columns)",
+                                start,
+                                end + 1,
+                                n,
+                                text.len()
+                            ))
+                        })
+                };
+
+                let first = line(span.start_line)?;
                 if span.end_line == span.start_line {
-                    return f.lines[span.start_line][span.start_column..span.end_column].to_owned();
+                    return columns(
+                        first,
+                        span.start_line,
+                        span.start_column,
+                        Some(span.end_column),
+                    );
                 }
-                let mut result = f.lines[span.start_line][span.start_column..].to_owned();
+                let last = line(span.end_line)?;
+                let mut result = columns(first, span.start_line, span.start_column, None)?;
                 result.push('\n');
-                if span.end_line - span.start_line >= 2 {
-                    let lines = f.lines[span.start_line + 1..span.end_line - 1].join("\n");
-                    result.push_str(&lines);
+                let (start, end) = (span.start_line.zero_indexed(), span.end_line.zero_indexed());
+                if end - start >= 2 {
+                    result.push_str(&f.lines[start + 1..end].join("\n"));
                     result.push('\n');
                 }
-                result.push_str(&f.lines[span.end_line][..span.end_column]);
-                result
+                result.push_str(&columns(
+                    last,
+                    span.end_line,
+                    Column::new_zero_indexed(0),
+                    Some(span.end_column),
+                )?);
+                Ok(result)
             }),
         }
     }
 
+    // Cargo allows `-` in package names, but crate names use `_` instead, so
+    // either is accepted.
+    fn crate_files(&self, name: &str) -> Result<Vec<Path>, file_system::Error> {
+        let crates = self.crates();
+        let same_name = |n: &str| n.replace('-', "_") == name.replace('-', "_");
+        let dir = match crates.iter().find(|(n, _)| same_name(n)) {
+            Some((_, dir)) => *dir,
+            None => {
+                let names: Vec<_> = crates.iter().map(|(n, _)| format!("`{}`", n)).collect();
+                let expected = if names.is_empty() {
+                    String::new()
+                } else {
+                    format!(", expected one of {}", names.join(", "))
+                };
+                return Err(file_system::Error::BadLocation(format!(
+                    "no crate named `{}` in the workspace{}",
+                    name, expected
+                )));
+            }
+        };
+        // Members in the crate's directory are crates of their own.
+        let nested: Vec<&StdPath> = crates
+            .iter()
+            .map(|(_, d)| *d)
+            .filter(|d| *d != dir && d.starts_with(dir))
+            .collect();
+        let paths: Vec<Path> = workspace::source_files(&[dir])
+            .into_iter()
+            .filter(|f| !nested.iter().any(|d| f.starts_with(d)))
+            .map(|f| self.insert_path(f))
+            .collect::<Result<_, _>>()?;
+        if paths.is_empty() {
+            return Err(file_system::Error::BadLocation(format!(
+                "crate `{}` has no source files",
+                name
+            )));
+        }
+        Ok(paths)
+    }
+
+    fn module_files(&self, name: &str) -> Result<Vec<Path>, file_system::Error> {
+        let paths: Vec<Path> = workspace::source_files(&self.roots())
+            .into_iter()
+            .filter(|f| file_system::in_module(&slashed(&self.display_path(f)), name))
+            .map(|f| self.insert_path(f))
+            .collect::<Result<_, _>>()?;
+        if paths.is_empty() {
+            return Err(file_system::Error::BadLocation(format!(
+                "no module `{}` in the workspace",
+                name
+            )));
+        }
+        Ok(paths)
+    }
+
     fn physical_path(&self, path: &Path) -> Result<PathBuf, file_system::Error> {
         let path_map = self.path_map.borrow();
         let path = path_map
@@ -140,11 +561,16 @@ impl FileSystem for PhysicalFs {
             .expect(&format!("could not find {:?}", path));
         Ok(path.to_owned())
     }
+
+    fn has_changed(&self, path: Path) -> bool {
+        self.changed.borrow().contains(&path.key)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::front::data::Span;
     use std::fs;
     use std::time::SystemTime;
 
@@ -205,6 +631,259 @@ mod test {
             fs.path_map.borrow().get(&results[0].key).unwrap(),
             &env.path("foo.rs").canonicalize().unwrap()
         );
+
+        // Found by name or by path, it is the same file.
+        let resolved = fs.resolve_path(&env.path("foo.rs")).unwrap();
+        assert_eq!(resolved, results[0]);
+    }
+
+    #[test]
+    fn test_find_similar() {
+        let env = TestEnv::init();
+        for file in &[
+            "src/lib.rs",
+            "src/parse/mod.rs",
+            "src/parser.rs",
+            "target/lib.rs",
+        ] {
+            fs::create_dir_all(env.path(file).parent().unwrap()).unwrap();
+            env.create_file(file);
+        }
+        let fs = env.fs();
+        let find = |name: &str| -> Result<Vec<String>, file_system::Error> {
+            let paths = fs.find(name.to_owned().into())?;
+            Ok(paths.into_iter().map(|p| fs.path_name(p)).collect())
+        };
+
+        // By suffix, the target directory is not searched.
+        assert_eq!(find("lib.rs").unwrap(), vec!["src/lib.rs"]);
+        // A module.
+        assert_eq!(find("parse").unwrap(), vec!["src/parse/mod.rs"]);
+        // Fuzzy, with one match.
+        assert_eq!(find("lb").unwrap(), vec!["src/lib.rs"]);
+        // Fuzzy, with several matches the user must choose.
+        match find("srcpa") {
+            Err(file_system::Error::Ambiguous(name, files)) => {
+                assert_eq!(name, "srcpa");
+                let names: Vec<_> = files.into_iter().map(|(_, n)| n).collect();
+                assert_eq!(names, vec!["src/parser.rs", "src/parse/mod.rs"]);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(find("xyzzy").is_err());
+    }
+
+    #[test]
+    fn test_rank() {
+        let paths: Vec<String> = [
+            "src/lib.rs",
+            "crates/a/src/lib.rs",
+            "src/parse/mod.rs",
+            "src/front/parse.rs",
+            "src/parser.rs",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(rank("lib.rs", &paths), Some(Matches::Suffix(vec![0, 1])));
+        assert_eq!(
+            rank("src/lib.rs", &paths),
+            Some(Matches::Suffix(vec![0, 1]))
+        );
+        assert_eq!(rank("parse", &paths), Some(Matches::Suffix(vec![2, 3])));
+        assert_eq!(rank("ib.rs", &paths), Some(Matches::Fuzzy(vec![0, 1])));
+        assert_eq!(rank("PRSR", &paths), Some(Matches::Fuzzy(vec![4, 2, 3])));
+        assert_eq!(rank("fp", &paths), Some(Matches::Fuzzy(vec![3])));
+        assert_eq!(rank("xyz", &paths), None);
+        assert_eq!(rank("", &paths), None);
+    }
+
+    #[test]
+    fn test_comparable() {
+        let path = StdPath::new("/Src/Main.rs");
+        assert_eq!(comparable(path, false), path);
+        assert_eq!(comparable(path, true), StdPath::new("/src/main.rs"));
+        assert_eq!(
+            comparable(StdPath::new("/src/MAIN.rs"), true),
+            comparable(path, true)
+        );
+    }
+
+    #[test]
+    fn test_workspace() {
+        let env = TestEnv::init();
+        for member in &["crates/a", "crates/b", "crates/c"] {
+            fs::create_dir_all(env.path(member).join("src")).unwrap();
+            fs::write(env.path(member).join("Cargo.toml"), "").unwrap();
+            env.create_file(&format!("{}/src/lib.rs", member));
+        }
+        env.create_file("crates/a/src/a.rs");
+        fs::write(
+            env.path("crates/a/Cargo.toml"),
+            "[package]\nname = \"my-a\"\n",
+        )
+        .unwrap();
+        let outside = env.root.with_file_name(format!(
+            "{}-outside",
+            env.root.file_name().unwrap().to_str().unwrap()
+        ));
+        fs::create_dir_all(outside.join("src")).unwrap();
+        let outside = outside.canonicalize().unwrap();
+        fs::write(
+            env.path("Cargo.toml"),
+            format!(
+                "[workspace]\nmembers = [\"crates/*\", {:?}]\nexclude = [\"crates/c\"]\n",
+                outside.display().to_string()
+            ),
+        )
+        .unwrap();
+        fs::write(outside.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+        let fs = env.fs();
+        assert_eq!(fs.roots().len(), 4);
+
+        let show = |p: Path| {
+            let mut buf = Vec::new();
+            fs.show_path(p, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+
+        // Relative to the workspace root.
+        let paths = fs.find("crates/b/src/lib.rs".to_owned().into()).unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(show(paths[0]), "crates/b/src/lib.rs");
+        // Relative to a member.
+        let paths = fs.find("src/a.rs".to_owned().into()).unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(show(paths[0]), "crates/a/src/a.rs");
+        // Ambiguous, the excluded member is not searched.
+        let paths = fs.find("src/lib.rs".to_owned().into()).unwrap();
+        assert_eq!(paths.len(), 2);
+        // A member outside the workspace root.
+        let paths = fs.find("src/main.rs".to_owned().into()).unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(
+            show(paths[0]),
+            format!(
+                "{}/src/main.rs",
+                outside.file_name().unwrap().to_str().unwrap()
+            )
+        );
+        assert!(fs.find("src/missing.rs".to_owned().into()).is_err());
+
+        // Crates by package name, or by directory if there is none.
+        let names = |paths: Vec<Path>| {
+            paths
+                .into_iter()
+                .map(|p| fs.path_name(p))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(fs.crate_files("my_a").unwrap()),
+            vec!["crates/a/src/a.rs", "crates/a/src/lib.rs"]
+        );
+        assert_eq!(
+            names(fs.crate_files("b").unwrap()),
+            vec!["crates/b/src/lib.rs"]
+        );
+        assert!(fs.crate_files("c").unwrap_err().to_string().starts_with(
+            "Invalid location: no crate named `c` in the workspace, expected one of `my-a`"
+        ));
+        assert_eq!(
+            names(fs.module_files("a").unwrap()),
+            vec!["crates/a/src/a.rs"]
+        );
+        assert!(fs.module_files("b").is_err());
+
+        let _ = fs::remove_dir_all(&outside);
+    }
+
+    #[test]
+    fn test_signature() {
+        let env = TestEnv::init();
+        fs::write(
+            env.path("sig.rs"),
+            "fn foo(\n    x: u32,\n) -> u32 {\n    x\n}\n\nstruct Bar;\nconst BAZ: u32\n\n",
+        )
+        .unwrap();
+        let fs = env.fs();
+        let path = fs.find("sig.rs".to_owned().into()).unwrap().pop().unwrap();
+
+        let (start, lines) = fs
+            .signature(&Span::new_zero_indexed(path, 0, 3, 0, 6))
+            .unwrap();
+        assert_eq!(start.zero_indexed(), 0);
+        assert_eq!(lines, vec!["fn foo(", "    x: u32,", ") -> u32 {"]);
+        let (start, lines) = fs
+            .signature(&Span::new_zero_indexed(path, 6, 7, 6, 10))
+            .unwrap();
+        assert_eq!(start.zero_indexed(), 6);
+        assert_eq!(lines, vec!["struct Bar;"]);
+        // No brace, so runs to the end of the file, but trailing blank lines are trimmed.
+        let (_, lines) = fs
+            .signature(&Span::new_zero_indexed(path, 7, 6, 7, 9))
+            .unwrap();
+        assert_eq!(lines, vec!["const BAZ: u32"]);
+    }
+
+    #[test]
+    fn test_line_bounds() {
+        let env = TestEnv::init();
+        fs::write(env.path("short.rs"), "fn foo() {\n    bar();\n}\n").unwrap();
+        let fs = env.fs();
+        let path = fs
+            .find("short.rs".to_owned().into())
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        assert_eq!(
+            fs.get_line(path, Line::new_zero_indexed(1)).unwrap(),
+            "    bar();"
+        );
+        assert_eq!(
+            fs.get_line(path, Line::new_zero_indexed(3))
+                .unwrap_err()
+                .to_string(),
+            "Invalid location: line 4 of short.rs does not exist, the file has 3 lines"
+        );
+        assert_eq!(
+            fs.snippet(&Range::Span(Span::new_zero_indexed(path, 0, 3, 2, 1)))
+                .unwrap(),
+            "foo() {\n    bar();\n}"
+        );
+        assert!(fs
+            .snippet(&Range::Line(path, Line::new_zero_indexed(10)))
+            .is_err());
+        assert!(fs
+            .snippet(&Range::Span(Span::new_zero_indexed(path, 1, 4, 5, 0)))
+            .is_err());
+        assert_eq!(
+            fs.snippet(&Range::Span(Span::new_zero_indexed(path, 1, 4, 1, 40)))
+                .unwrap_err()
+                .to_string(),
+            "Invalid location: columns 5->41 are out of range for line 2 (10 columns)"
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"foo\nbar"), ("foo\nbar".to_owned(), false));
+        assert_eq!(decode(b"\xEF\xBB\xBFfoo"), ("foo".to_owned(), false));
+        assert_eq!(decode(b"f\xFFo"), ("f\u{FFFD}o".to_owned(), true));
+    }
+
+    #[test]
+    fn test_with_file_encodings() {
+        let env = TestEnv::init();
+        fs::write(env.path("crlf.rs"), b"\xEF\xBB\xBFfirst\r\nsecond\r\n\xFF").unwrap();
+        let fs = env.fs();
+        let path = fs.find("crlf.rs".to_owned().into()).unwrap().pop().unwrap();
+        fs.with_file(path, |file| {
+            assert_eq!(file.lines, vec!["first", "second", "\u{FFFD}"]);
+            Ok(())
+        })
+        .unwrap();
     }
 
     #[test]
@@ -216,7 +895,91 @@ mod test {
             assert_eq!(file.path.key, path.key);
             assert_eq!(file.lines.len(), 100);
             assert_eq!(file.lines[32], "line 32 of foo.rs");
+            Ok(())
         })
         .unwrap();
     }
+
+    #[test]
+    fn test_for_each_line() {
+        let env = TestEnv::init();
+        fs::write(env.path("crlf.rs"), b"\xEF\xBB\xBFfirst\r\nsecond\r\n\xFF").unwrap();
+        let fs = env.fs();
+        let lines = |name: &str, stop: usize| {
+            let path = fs.find(name.to_owned().into()).unwrap().pop().unwrap();
+            let mut lines = Vec::new();
+            fs.for_each_line(path, |line, text| {
+                lines.push((line.zero_indexed(), text.to_owned()));
+                Ok(lines.len() < stop)
+            })
+            .unwrap();
+            (path, lines)
+        };
+
+        // Lines are the same as those read by `with_file`.
+        let (path, crlf) = lines("crlf.rs", 10);
+        assert_eq!(
+            crlf,
+            vec![
+                (0, "first".to_owned()),
+                (1, "second".to_owned()),
+                (2, "\u{FFFD}".to_owned())
+            ]
+        );
+        // Streamed files are not cached.
+        assert!(fs.file_cache.borrow_mut().get(path.key).is_none());
+
+        let (path, foo) = lines("foo.rs", 3);
+        assert_eq!(foo.len(), 3);
+        assert_eq!(foo[2], (2, "line 2 of foo.rs".to_owned()));
+        // Cached files are used if they are there.
+        fs.with_file(path, |_| Ok(())).unwrap();
+        assert_eq!(lines("foo.rs", 100).1.len(), 100);
+    }
+
+    #[test]
+    fn test_changed_files() {
+        let env = TestEnv::init();
+        let fs = env.fs();
+        let foo = fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap();
+        let line = Line::new_zero_indexed(1);
+        assert_eq!(fs.get_line(foo, line).unwrap(), "line 1 of foo.rs");
+        assert!(!fs.has_changed(foo));
+
+        // The cached file is read again, for both ways of reading it.
+        fs::write(env.path("foo.rs"), "fn foo() {}\n    changed();\n").unwrap();
+        assert_eq!(fs.get_line(foo, line).unwrap(), "    changed();");
+        assert!(fs.has_changed(foo));
+        fs::write(env.path("foo.rs"), "fn foo() {}\n    changed again();\n").unwrap();
+        let mut lines = Vec::new();
+        fs.for_each_line(foo, |_, text| {
+            lines.push(text.to_owned());
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(lines, vec!["fn foo() {}", "    changed again();"]);
+
+        fs.clear_changed();
+        assert!(!fs.has_changed(foo));
+        assert_eq!(fs.get_line(foo, line).unwrap(), "    changed again();");
+        assert!(!fs.has_changed(foo));
+    }
+
+    #[test]
+    fn test_cache_size() {
+        let env = TestEnv::init();
+        let fs = env.fs();
+        fs.set_cache_size(1);
+        let foo = fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap();
+        let bar = fs.find("bar.rs".to_owned().into()).unwrap().pop().unwrap();
+        fs.with_file(foo, |_| Ok(())).unwrap();
+        fs.with_file(bar, |_| Ok(())).unwrap();
+        assert!(fs.file_cache.borrow_mut().get(foo.key).is_none());
+        assert!(fs.file_cache.borrow_mut().get(bar.key).is_some());
+        // Evicted files are read again when they are used.
+        assert_eq!(
+            fs.get_line(foo, Line::new_zero_indexed(4)).unwrap(),
+            "line 4 of foo.rs"
+        );
+    }
 }
@@ -0,0 +1,143 @@
+//! Support for Cargo workspaces.
+
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The extension of the files returned by `source_files`.
+const SOURCE_EXTENSION: &str = "rs";
+
+#[derive(Deserialize)]
+struct Manifest {
+    package: Option<PackageSection>,
+    workspace: Option<WorkspaceSection>,
+}
+
+#[derive(Deserialize)]
+struct PackageSection {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct WorkspaceSection {
+    #[serde(default)]
+    members: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+/// The root directories of the members of the workspace at `root`, not
+/// including the root itself.
+pub fn members(root: &Path) -> Vec<PathBuf> {
+    let text = match fs::read_to_string(root.join("Cargo.toml")) {
+        Ok(text) => text,
+        Err(_) => return Vec::new(),
+    };
+    let workspace = match toml::from_str::<Manifest>(&text) {
+        Ok(Manifest {
+            workspace: Some(ws),
+            ..
+        }) => ws,
+        Ok(_) => return Vec::new(),
+        Err(e) => {
+            eprintln!("warning: could not read workspace manifest: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let exclude: Vec<PathBuf> = workspace
+        .exclude
+        .iter()
+        .filter_map(|e| root.join(e).canonicalize().ok())
+        .collect();
+    let mut result = Vec::new();
+    for member in &workspace.members {
+        for path in expand(root, member) {
+            let path = match path.canonicalize() {
+                Ok(p) => p,
+                Err(_) => {
+                    eprintln!("warning: workspace member not found: {}", member);
+                    continue;
+                }
+            };
+            if path != root && !exclude.contains(&path) && !result.contains(&path) {
+                result.push(path);
+            }
+        }
+    }
+    result
+}
+
+/// The name of the package whose manifest is in `dir`, `None` if there is no
+/// manifest or it has no `[package]` section.
+pub fn package_name(dir: &Path) -> Option<String> {
+    let text = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    toml::from_str::<Manifest>(&text)
+        .ok()?
+        .package
+        .map(|p| p.name)
+}
+
+/// Hidden and target directories, and links to directories, are skipped.
+pub fn source_files(roots: &[&Path]) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    for (i, root) in roots.iter().enumerate() {
+        if !roots[..i].iter().any(|r| root.starts_with(r)) {
+            collect_source_files(root, &mut result);
+        }
+    }
+    result
+}
+
+fn collect_source_files(dir: &Path, result: &mut Vec<PathBuf>) {
+    let mut entries: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).collect(),
+        Err(_) => return,
+    };
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+        if file_type.is_dir() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !name.starts_with('.') && name != "target" {
+                collect_source_files(&path, result);
+            }
+        } else if file_type.is_file() && path.extension().is_some_and(|e| e == SOURCE_EXTENSION) {
+            result.push(path);
+        }
+    }
+}
+
+// Expand a member path to the directories it names. Only a trailing `*` is
+// supported as a glob, e.g., `crates/*`.
+fn expand(root: &Path, member: &str) -> Vec<PathBuf> {
+    if !member.ends_with('*') {
+        return vec![root.join(member)];
+    }
+
+    let (dir, prefix) = match member.rfind('/') {
+        Some(i) => (root.join(&member[..i]), &member[i + 1..member.len() - 1]),
+        None => (root.to_owned(), &member[..member.len() - 1]),
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut result: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.join("Cargo.toml").is_file()
+                && p.file_name()
+                    .map(|n| n.to_string_lossy().starts_with(prefix))
+                    .unwrap_or(false)
+        })
+        .collect();
+    result.sort();
+    result
+}
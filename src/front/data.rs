@@ -1,9 +1,13 @@
-use super::{query::Query, Error, Show};
-use crate::env::Environment;
-use crate::file_system::{FileSystem, Path};
+use super::{query::Query, types, Color, Error, Show, ShowCtx};
+use crate::ast;
+use crate::env::{DisplayMode, Environment};
+use crate::file_system::{self, FileSystem, Path};
 use derive_new::new;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io::Write;
+use std::ops::{Add, Sub};
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct MetaVar {
@@ -24,15 +28,22 @@ impl fmt::Display for MetaVar {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Value {
     pub ty: Type,
     pub kind: ValueKind,
+    /// Where the value came from, e.g., "def of `foo`", shown in verbose mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
 }
 
 impl Show for Value {
-    fn show(&self, w: &mut dyn Write, env: &impl Environment) -> Result<(), Error> {
-        self.kind.show(w, env)
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, ctx: &ShowCtx) -> Result<(), Error> {
+        // The label goes first, since the value may span several lines.
+        if let (Some(label), DisplayMode::Verbose) = (&self.label, ctx.display) {
+            write!(w, "{}: ", label)?;
+        }
+        self.kind.show(w, env, ctx)
     }
 }
 
@@ -56,6 +67,7 @@ impl Value {
         Value {
             ty: Type::Void,
             kind: ValueKind::Void,
+            label: None,
         }
     }
 
@@ -63,6 +75,7 @@ impl Value {
         Value {
             ty: Type::Number,
             kind: ValueKind::Number(n),
+            label: None,
         }
     }
 
@@ -70,6 +83,490 @@ impl Value {
         Value {
             ty: Type::String,
             kind: ValueKind::String(s),
+            label: None,
+        }
+    }
+
+    pub fn bool(b: bool) -> Value {
+        Value {
+            ty: Type::Bool,
+            kind: ValueKind::Bool(b),
+            label: None,
+        }
+    }
+
+    /// Definitions are coerced to their spans if `elem_ty` is a location.
+    pub fn set(elements: Vec<Value>, elem_ty: Type) -> Result<Value, Error> {
+        let coerce = matches!(elem_ty, Type::Location | Type::Range);
+        let elements = elements
+            .into_iter()
+            .enumerate()
+            .map(|(i, e)| {
+                let e = if coerce { e.coerce_to_location() } else { e };
+                if types::is_subtype(&e.ty, &elem_ty) {
+                    Ok(e)
+                } else {
+                    Err(Error::TypeError(format!(
+                        "Set element {} has type {:?}, expected {:?}",
+                        i, e.ty, elem_ty
+                    )))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Value {
+            kind: ValueKind::Set(elements),
+            ty: Type::Set(Box::new(elem_ty)),
+            label: None,
+        })
+    }
+
+    /// Replace every path in `self` with the result of applying `f` to it.
+    pub fn try_map_paths<E>(
+        &mut self,
+        f: &mut dyn FnMut(Path) -> Result<Path, E>,
+    ) -> Result<(), E> {
+        match &mut self.kind {
+            ValueKind::Void
+            | ValueKind::Number(_)
+            | ValueKind::Bool(_)
+            | ValueKind::String(_)
+            | ValueKind::Query(_)
+            | ValueKind::Fn(_) => {}
+            ValueKind::Set(vs) => {
+                for v in vs {
+                    v.try_map_paths(f)?;
+                }
+            }
+            ValueKind::Partial(v) => v.try_map_paths(f)?,
+            ValueKind::Position(p) => p.file = f(p.file)?,
+            ValueKind::Range(r) => match r {
+                Range::File(p) | Range::Line(p, _) => *p = f(*p)?,
+                Range::MultiFile(ps) => {
+                    for p in ps {
+                        *p = f(*p)?;
+                    }
+                }
+                Range::Span(s) => s.file = f(s.file)?,
+            },
+            ValueKind::Identifier(id) => id.span.file = f(id.span.file)?,
+            ValueKind::Definition(def) => def.span.file = f(def.span.file)?,
+            ValueKind::Diagnostic(d) => d.span.file = f(d.span.file)?,
+            ValueKind::Tree(trees) => {
+                for t in trees {
+                    t.try_map_paths(f)?;
+                }
+            }
+            ValueKind::Changes(c) => {
+                for v in c.removed.iter_mut().chain(&mut c.added) {
+                    v.try_map_paths(f)?;
+                }
+            }
+            ValueKind::Map(entries) => {
+                for (k, v) in entries {
+                    k.try_map_paths(f)?;
+                    v.try_map_paths(f)?;
+                }
+            }
+            ValueKind::Info(info) => {
+                info.ident.span.file = f(info.ident.span.file)?;
+                if let Some(def) = &mut info.def {
+                    def.span.file = f(def.span.file)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The file `self` is in, if it is entirely in a single file.
+    pub fn file(&self) -> Option<Path> {
+        match &self.kind {
+            ValueKind::Void
+            | ValueKind::Number(_)
+            | ValueKind::Bool(_)
+            | ValueKind::String(_)
+            | ValueKind::Query(_)
+            | ValueKind::Tree(_)
+            | ValueKind::Changes(_)
+            | ValueKind::Map(_)
+            | ValueKind::Fn(_) => None,
+            ValueKind::Set(vs) => {
+                let file = vs.first()?.file()?;
+                if vs[1..].iter().all(|v| v.file() == Some(file)) {
+                    Some(file)
+                } else {
+                    None
+                }
+            }
+            ValueKind::Partial(v) => v.file(),
+            ValueKind::Position(p) => Some(p.file),
+            ValueKind::Range(r) => match r {
+                Range::File(p) | Range::Line(p, _) => Some(*p),
+                Range::MultiFile(_) => None,
+                Range::Span(s) => Some(s.file),
+            },
+            ValueKind::Identifier(id) => Some(id.span.file),
+            ValueKind::Definition(def) => Some(def.span.file),
+            ValueKind::Diagnostic(d) => Some(d.span.file),
+            ValueKind::Info(info) => Some(info.ident.span.file),
+        }
+    }
+
+    /// The text which `self` covers. Sets have no extent.
+    pub fn extent(&self) -> Option<Extent> {
+        match &self.kind {
+            ValueKind::Position(p) => Some(Extent::Text(
+                p.file,
+                (p.line, p.column),
+                (p.line, p.column + 1),
+            )),
+            ValueKind::Range(Range::File(p)) => Some(Extent::Files(vec![*p])),
+            ValueKind::Range(Range::MultiFile(ps)) => Some(Extent::Files(ps.clone())),
+            ValueKind::Range(Range::Line(p, line)) => Some(Extent::Text(
+                *p,
+                (*line, Column::new_zero_indexed(0)),
+                (*line + 1, Column::new_zero_indexed(0)),
+            )),
+            ValueKind::Range(Range::Span(s)) => Some(Extent::of_span(s)),
+            ValueKind::Identifier(id) => Some(Extent::of_span(&id.span)),
+            ValueKind::Definition(def) => Some(Extent::of_span(&def.span)),
+            ValueKind::Diagnostic(d) => Some(Extent::of_span(&d.span)),
+            ValueKind::Info(info) => Some(Extent::of_span(&info.ident.span)),
+            ValueKind::Partial(v) => v.extent(),
+            _ => None,
+        }
+    }
+
+    /// Show each location in `self` on its own line as `path:line:col: text`.
+    pub fn show_quickfix(
+        &self,
+        w: &mut dyn Write,
+        env: &impl Environment,
+        ctx: &ShowCtx,
+    ) -> Result<(), Error> {
+        let mut entries = Vec::new();
+        self.quickfix_entries(env, &mut entries)?;
+        if entries.is_empty() {
+            return self.show(w, env, ctx);
+        }
+        for (i, (file, line, column, text)) in entries.into_iter().enumerate() {
+            if i > 0 {
+                writeln!(w)?;
+            }
+            env.file_system().show_path(file, w)?;
+            write!(w, ":{}:{}: {}", line, column, text)?;
+        }
+        Ok(())
+    }
+
+    pub fn show_table(
+        &self,
+        w: &mut dyn Write,
+        env: &impl Environment,
+        ctx: &ShowCtx,
+        sep: char,
+    ) -> Result<(), Error> {
+        let mut rows = Vec::new();
+        self.table_rows(&mut rows);
+        match &self.kind {
+            ValueKind::Set(_) | ValueKind::Partial(_) => {}
+            _ if rows.is_empty() => return self.show(w, env, ctx),
+            _ => {}
+        }
+        write!(w, "{}", self.to_table(env, sep).trim_end())?;
+        Ok(())
+    }
+
+    /// A header row and a row for each location in `self`, as CSV if `sep` is
+    /// `,` or TSV if it is a tab.
+    pub fn to_table(&self, env: &impl Environment, sep: char) -> String {
+        let mut rows = Vec::new();
+        self.table_rows(&mut rows);
+        let mut table = ["file", "line", "col", "name", "kind"].join(&sep.to_string());
+        table.push('\n');
+        for (file, line, column, name, kind) in rows {
+            let fields = [
+                env.file_system().path_name(file),
+                line.to_string(),
+                column.to_string(),
+                name,
+                kind.to_owned(),
+            ];
+            let fields: Vec<_> = fields.iter().map(|f| table_field(f, sep)).collect();
+            table.push_str(&fields.join(&sep.to_string()));
+            table.push('\n');
+        }
+        table
+    }
+
+    fn table_rows(&self, rows: &mut Vec<(Path, Line, Column, String, String)>) {
+        let row = |span: &Span, name: &str, kind: &str| {
+            (
+                span.file,
+                span.start_line,
+                span.start_column,
+                name.to_owned(),
+                kind.to_owned(),
+            )
+        };
+        let def_row = |def: &Definition| row(&def.span, &def.name, &def.kind.to_string());
+        let start = Column::new_zero_indexed(0);
+        match &self.kind {
+            ValueKind::Set(vs) => {
+                for v in vs {
+                    v.table_rows(rows);
+                }
+            }
+            ValueKind::Partial(v) => v.table_rows(rows),
+            ValueKind::Position(p) => rows.push((
+                p.file,
+                p.line,
+                p.column,
+                String::new(),
+                "position".to_owned(),
+            )),
+            ValueKind::Range(Range::Span(s)) => rows.push(row(s, "", "span")),
+            ValueKind::Range(Range::Line(file, line)) => {
+                rows.push((*file, *line, start, String::new(), "line".to_owned()))
+            }
+            ValueKind::Range(Range::File(file)) => rows.push((
+                *file,
+                Line::new_zero_indexed(0),
+                start,
+                String::new(),
+                "file".to_owned(),
+            )),
+            ValueKind::Range(Range::MultiFile(files)) => {
+                for file in files {
+                    rows.push((
+                        *file,
+                        Line::new_zero_indexed(0),
+                        start,
+                        String::new(),
+                        "file".to_owned(),
+                    ));
+                }
+            }
+            ValueKind::Identifier(id) => rows.push(row(&id.span, &id.name, "identifier")),
+            ValueKind::Definition(def) => rows.push(def_row(def)),
+            ValueKind::Tree(trees) => {
+                let mut stack: Vec<&Tree> = trees.iter().rev().collect();
+                while let Some(t) = stack.pop() {
+                    rows.push(def_row(&t.def));
+                    stack.extend(t.children.iter().rev());
+                }
+            }
+            ValueKind::Diagnostic(d) => {
+                rows.push(row(&d.span, &d.message, &d.severity.to_string()))
+            }
+            ValueKind::Info(info) => match &info.def {
+                Some(def) => rows.push(def_row(def)),
+                None => rows.push(row(&info.ident.span, &info.ident.name, "identifier")),
+            },
+            ValueKind::Changes(c) => {
+                for v in c.removed.iter().chain(c.added.iter()) {
+                    v.table_rows(rows);
+                }
+            }
+            ValueKind::Void
+            | ValueKind::Number(_)
+            | ValueKind::Bool(_)
+            | ValueKind::String(_)
+            | ValueKind::Query(_)
+            | ValueKind::Map(_)
+            | ValueKind::Fn(_) => {}
+        }
+    }
+
+    pub fn locations(
+        &self,
+        env: &impl Environment,
+    ) -> Result<Vec<(Path, Line, Column, String)>, Error> {
+        let mut entries = Vec::new();
+        self.quickfix_entries(env, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn quickfix_entries(
+        &self,
+        env: &impl Environment,
+        entries: &mut Vec<(Path, Line, Column, String)>,
+    ) -> Result<(), Error> {
+        let fs = env.file_system();
+        let text = |file, line| {
+            fs.get_line(file, line)
+                .map(|s| s.trim().to_owned())
+                .unwrap_or_default()
+        };
+        let start = Column::new_zero_indexed(0);
+        match &self.kind {
+            ValueKind::Set(vs) => {
+                for v in vs {
+                    v.quickfix_entries(env, entries)?;
+                }
+            }
+            ValueKind::Partial(v) => v.quickfix_entries(env, entries)?,
+            ValueKind::Position(p) => {
+                entries.push((p.file, p.line, p.column, text(p.file, p.line)))
+            }
+            ValueKind::Range(Range::Span(s)) => entries.push((
+                s.file,
+                s.start_line,
+                s.start_column,
+                text(s.file, s.start_line),
+            )),
+            ValueKind::Range(Range::Line(file, line)) => {
+                entries.push((*file, *line, start, text(*file, *line)))
+            }
+            ValueKind::Range(Range::File(file)) => {
+                entries.push((*file, Line::new_zero_indexed(0), start, String::new()))
+            }
+            ValueKind::Range(Range::MultiFile(files)) => {
+                for file in files {
+                    entries.push((*file, Line::new_zero_indexed(0), start, String::new()));
+                }
+            }
+            ValueKind::Identifier(id) => entries.push((
+                id.span.file,
+                id.span.start_line,
+                id.span.start_column,
+                id.show_name(),
+            )),
+            ValueKind::Definition(def) => entries.push(def.quickfix_entry()?),
+            ValueKind::Tree(trees) => {
+                let mut stack: Vec<&Tree> = trees.iter().rev().collect();
+                while let Some(t) = stack.pop() {
+                    entries.push(t.def.quickfix_entry()?);
+                    stack.extend(t.children.iter().rev());
+                }
+            }
+            ValueKind::Diagnostic(d) => entries.push((
+                d.span.file,
+                d.span.start_line,
+                d.span.start_column,
+                format!("{}: {}", d.severity, d.message),
+            )),
+            // Jump to the definition, as from an editor's hover.
+            ValueKind::Info(info) => match &info.def {
+                Some(def) => {
+                    let mut entry = def.quickfix_entry()?;
+                    entry.3 = info.summary();
+                    entries.push(entry);
+                }
+                None => entries.push((
+                    info.ident.span.file,
+                    info.ident.span.start_line,
+                    info.ident.span.start_column,
+                    info.summary(),
+                )),
+            },
+            ValueKind::Changes(c) => {
+                for (prefix, vs) in &[("-", &c.removed), ("+", &c.added)] {
+                    let start = entries.len();
+                    for v in vs.iter() {
+                        v.quickfix_entries(env, entries)?;
+                    }
+                    for entry in &mut entries[start..] {
+                        entry.3 = format!("{} {}", prefix, entry.3);
+                    }
+                }
+            }
+            ValueKind::Void
+            | ValueKind::Number(_)
+            | ValueKind::Bool(_)
+            | ValueKind::String(_)
+            | ValueKind::Query(_)
+            | ValueKind::Map(_)
+            | ValueKind::Fn(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Mark `self` as possibly incomplete, if it is a set.
+    pub fn partial(self) -> Value {
+        match self.kind {
+            ValueKind::Set(_) => Value {
+                ty: self.ty.clone(),
+                kind: ValueKind::Partial(Box::new(self)),
+                label: None,
+            },
+            _ => self,
+        }
+    }
+
+    /// Remove any mark that `self` is incomplete (see `partial`).
+    pub fn strip_partial(self) -> Value {
+        match self.kind {
+            ValueKind::Partial(v) => *v,
+            _ => self,
+        }
+    }
+
+    pub fn coerce_to_location(self) -> Value {
+        match self.kind {
+            ValueKind::Definition(def) => Value {
+                ty: Type::Range,
+                kind: ValueKind::Range(Range::Span(def.span)),
+                label: None,
+            },
+            _ => self,
+        }
+    }
+
+    /// Flatten a set of sets, other values are unchanged.
+    pub fn flatten(self) -> Value {
+        let ty = match (&self.kind, self.ty.flatten()) {
+            (ValueKind::Set(_), Some(ty)) => ty,
+            _ => return self,
+        };
+        let mut elements = Vec::new();
+        if let ValueKind::Set(sets) = self.kind {
+            for set in sets {
+                match set.kind {
+                    ValueKind::Set(inner) => elements.extend(inner),
+                    // An empty set may have type void.
+                    ValueKind::Void => {}
+                    _ => elements.push(set),
+                }
+            }
+        }
+        Value {
+            kind: ValueKind::Set(elements),
+            ty,
+            label: None,
+        }
+    }
+
+    /// True if `self` and `other` are the same element of a set. Backend ids
+    /// may change when the project is rebuilt, so are not compared.
+    pub fn same_as(&self, other: &Value) -> bool {
+        match (&self.kind, &other.kind) {
+            (ValueKind::Partial(v), _) => v.same_as(other),
+            (_, ValueKind::Partial(v)) => self.same_as(v),
+            (ValueKind::Set(a), ValueKind::Set(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same_as(b))
+            }
+            (ValueKind::Void, ValueKind::Void) => true,
+            (ValueKind::Number(a), ValueKind::Number(b)) => a == b,
+            (ValueKind::Bool(a), ValueKind::Bool(b)) => a == b,
+            (ValueKind::String(a), ValueKind::String(b)) => a == b,
+            (ValueKind::Position(a), ValueKind::Position(b)) => a == b,
+            (ValueKind::Range(a), ValueKind::Range(b)) => a == b,
+            (ValueKind::Identifier(a), ValueKind::Identifier(b)) => {
+                a.name == b.name && a.span == b.span
+            }
+            (ValueKind::Definition(a), ValueKind::Definition(b)) => a.same_as(b),
+            (ValueKind::Tree(a), ValueKind::Tree(b)) => a == b,
+            (ValueKind::Diagnostic(a), ValueKind::Diagnostic(b)) => a == b,
+            (ValueKind::Info(a), ValueKind::Info(b)) => {
+                a.ident.name == b.ident.name && a.ident.span == b.ident.span
+            }
+            (ValueKind::Map(a), ValueKind::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|((ka, va), (kb, vb))| ka.same_as(kb) && va.same_as(vb))
+            }
+            _ => false,
         }
     }
 
@@ -88,24 +585,13 @@ impl Value {
     }
 }
 
-// Subtype rules
-//
-// T <= Set(T)
-// Void == Set(v) if v.is_empty()
-// T <= Query(T)
-// Position <= Location
-// Range <= Location
-//
-// Special rules (coercion in evaluation)
-//
-// Set(T) << T
-// Query(T) << T
-
-#[derive(Clone, Eq, PartialEq, Debug)]
+// See `types` for subtyping and coercions between types.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Type {
     Void,
     Query(Box<Type>),
     Number,
+    Bool,
     Set(Box<Type>),
     Identifier,
     Location,
@@ -113,6 +599,17 @@ pub enum Type {
     Range,
     String,
     Definition,
+    // A tree of definitions.
+    Tree,
+    Diagnostic,
+    // The elements added to and removed from a set of the inner type.
+    Changes(Box<Type>),
+    // A summary of an identifier.
+    Info,
+    // Keys of the first type, each with a value of the second.
+    Map(Box<Type>, Box<Type>),
+    // A lambda, e.g., `|x| x->idents`.
+    Fn,
 }
 
 impl Type {
@@ -123,21 +620,43 @@ impl Type {
         }
     }
 
-    pub fn is_location(&self) -> bool {
+    pub fn unquery(&self) -> Type {
         match self {
-            Type::Location | Type::Position | Type::Range => true,
-            Type::Query(ty) | Type::Set(ty) => ty.is_location(),
-            _ => false,
+            Type::Query(inner) => inner.unquery(),
+            _ => self.clone(),
         }
     }
 
-    pub fn unquery(&self) -> Type {
+    /// `self` with every query type in it replaced by the type of its result.
+    pub fn unquery_all(&self) -> Type {
         match self {
-            Type::Query(inner) => inner.unquery(),
+            Type::Query(inner) => inner.unquery_all(),
+            Type::Set(inner) => Type::Set(Box::new(inner.unquery_all())),
+            Type::Map(k, v) => Type::Map(Box::new(k.unquery_all()), Box::new(v.unquery_all())),
             _ => self.clone(),
         }
     }
 
+    /// E.g., `set<set<T>>` gives `set<T>`.
+    pub fn flatten(&self) -> Option<Type> {
+        match self {
+            Type::Query(inner) => Some(Type::Query(Box::new(inner.flatten()?))),
+            Type::Set(inner) => match &**inner {
+                Type::Set(_) => Some((**inner).clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn flatten_all(&self) -> Type {
+        let mut result = self.clone();
+        while let Some(ty) = result.flatten() {
+            result = ty;
+        }
+        result
+    }
+
     pub fn expect_set_inner(&self) -> Type {
         match self {
             Type::Set(inner) => (**inner).clone(),
@@ -159,6 +678,7 @@ impl fmt::Display for Type {
             Type::Void => write!(f, "void"),
             Type::Query(t) => write!(f, "query<{}>", t),
             Type::Number => write!(f, "number"),
+            Type::Bool => write!(f, "bool"),
             Type::Set(t) => write!(f, "set<{}>", t),
             Type::Identifier => write!(f, "identifier"),
             Type::Location => write!(f, "location"),
@@ -166,40 +686,69 @@ impl fmt::Display for Type {
             Type::Range => write!(f, "range"),
             Type::String => write!(f, "string"),
             Type::Definition => write!(f, "def"),
+            Type::Tree => write!(f, "tree"),
+            Type::Diagnostic => write!(f, "diagnostic"),
+            Type::Changes(t) => write!(f, "changes<{}>", t),
+            Type::Info => write!(f, "info"),
+            Type::Map(k, v) => write!(f, "map<{}, {}>", k, v),
+            Type::Fn => write!(f, "fn"),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum ValueKind {
     Void,
     Number(usize),
+    Bool(bool),
     Set(Vec<Value>),
     Position(Position),
     Range(Range),
+    // Queries must be evaluated before they can be serialized.
+    #[serde(skip)]
     Query(Query),
     Identifier(Identifier),
     String(String),
     Definition(Definition),
+    Tree(Vec<Tree>),
+    Diagnostic(Diagnostic),
+    // A set which may be incomplete, see `Value::partial`.
+    Partial(Box<Value>),
+    Changes(Changes),
+    Info(Info),
+    // In the order the keys were found.
+    Map(Vec<(Value, Value)>),
+    // Lambdas can be stored in variables, but not saved.
+    #[serde(skip)]
+    Fn(ast::Lambda),
 }
 
 impl ValueKind {
     pub fn is_void(&self) -> bool {
         match self {
             ValueKind::Void => true,
-            ValueKind::Set(v) if v.is_empty() => true,
+            _ => false,
+        }
+    }
+
+    /// True for a set with no elements, including a partial one.
+    pub fn is_empty_set(&self) -> bool {
+        match self {
+            ValueKind::Set(v) => v.is_empty(),
+            ValueKind::Partial(v) => v.kind.is_empty_set(),
             _ => false,
         }
     }
 }
 
 impl Show for ValueKind {
-    fn show(&self, w: &mut dyn Write, env: &impl Environment) -> Result<(), Error> {
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, ctx: &ShowCtx) -> Result<(), Error> {
         match self {
             ValueKind::Void => write!(w, "()").map_err(Into::into),
             ValueKind::Number(n) => write!(w, "{}", n).map_err(Into::into),
+            ValueKind::Bool(b) => write!(w, "{}", b).map_err(Into::into),
             ValueKind::Set(v) => {
-                if v.len() < 5 {
+                if v.len() <= ctx.max_set_len {
                     write!(w, "[")?;
                     let mut first = true;
                     for v in v {
@@ -208,38 +757,516 @@ impl Show for ValueKind {
                         } else {
                             write!(w, ", ")?;
                         }
-                        v.show(w, env)?;
+                        v.show(w, env, ctx)?;
+                        // Show large sets incrementally.
+                        w.flush()?;
                     }
                     write!(w, "]").map_err(Into::into)
                 } else {
                     write!(w, "[...]*{}", v.len()).map_err(Into::into)
                 }
             }
-            ValueKind::Position(p) => p.show(w, env),
-            ValueKind::Range(r) => r.show(w, env),
+            ValueKind::Partial(v) => {
+                v.show(w, env, ctx)?;
+                write!(w, "+").map_err(Into::into)
+            }
+            ValueKind::Position(p) => p.show(w, env, ctx),
+            ValueKind::Range(r) => r.show(w, env, ctx),
             ValueKind::String(s) => write!(w, "\"{}\"", s).map_err(Into::into),
-            ValueKind::Identifier(id) => write!(w, "`{}`", id.name).map_err(Into::into),
+            ValueKind::Identifier(id) => write!(w, "{}", id.show_name()).map_err(Into::into),
             ValueKind::Query(_) => write!(w, "<Query>").map_err(Into::into),
-            ValueKind::Definition(def) => {
-                write!(w, "`{}` at ", def.name)?;
-                def.span.show(w, env)
+            ValueKind::Definition(def) => match ctx.display {
+                DisplayMode::Compact => {
+                    def.show_kind(w)?;
+                    write!(w, "`{}` at ", def.name)?;
+                    def.span.show(w, env, ctx)
+                }
+                DisplayMode::Full | DisplayMode::Verbose => def.show_full(w, env, ctx),
+            },
+            ValueKind::Tree(trees) => {
+                let mut first = true;
+                for t in trees {
+                    if first {
+                        first = false;
+                    } else {
+                        writeln!(w)?;
+                    }
+                    t.show_indented(w, env, 0)?;
+                }
+                Ok(())
             }
+            ValueKind::Diagnostic(d) => d.show(w, env, ctx),
+            ValueKind::Changes(c) => c.show(w, env, ctx),
+            ValueKind::Info(info) => info.show(w, env, ctx),
+            ValueKind::Map(entries) => show_map(entries, w, env, ctx),
+            ValueKind::Fn(l) => write!(w, "{}", l.source).map_err(Into::into),
         }
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+// Counts are shown largest first.
+fn show_map(
+    entries: &[(Value, Value)],
+    w: &mut dyn Write,
+    env: &impl Environment,
+    ctx: &ShowCtx,
+) -> Result<(), Error> {
+    if entries.is_empty() {
+        return write!(w, "{{}}").map_err(Into::into);
+    }
+    let mut entries: Vec<_> = entries.iter().collect();
+    entries.sort_by_key(|(_, v)| match v.kind {
+        ValueKind::Number(n) => std::cmp::Reverse(n),
+        _ => std::cmp::Reverse(0),
+    });
+    let show = |v: &Value, w: &mut dyn Write| match &v.kind {
+        ValueKind::String(s) => write!(w, "{}", s).map_err(Into::into),
+        _ => v.show(w, env, ctx),
+    };
+    for (i, (k, v)) in entries.into_iter().enumerate() {
+        if i > 0 {
+            writeln!(w)?;
+        }
+        show(k, w)?;
+        write!(w, ": ")?;
+        show(v, w)?;
+    }
+    Ok(())
+}
+
+/// The difference between two sets (see `function::Changes`).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Changes {
+    /// Elements of the second set which are not in the first.
+    pub added: Vec<Value>,
+    /// Elements of the first set which are not in the second.
+    pub removed: Vec<Value>,
+}
+
+impl Changes {
+    /// Elements are compared with `Value::same_as`.
+    pub fn new(old: &[Value], new: &[Value]) -> Changes {
+        let missing_from = |vs: &[Value], other: &[Value]| {
+            vs.iter()
+                .filter(|v| !other.iter().any(|o| o.same_as(v)))
+                .cloned()
+                .collect()
+        };
+        Changes {
+            added: missing_from(new, old),
+            removed: missing_from(old, new),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Show for Changes {
+    // One element per line, removed elements first, as in a diff.
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, ctx: &ShowCtx) -> Result<(), Error> {
+        if self.is_empty() {
+            return write!(w, "no changes").map_err(Into::into);
+        }
+        let mut first = true;
+        for (prefix, color, vs) in &[
+            ("-", Color::Red, &self.removed),
+            ("+", Color::Green, &self.added),
+        ] {
+            for v in vs.iter() {
+                if first {
+                    first = false;
+                } else {
+                    writeln!(w)?;
+                }
+                write!(w, "{}", ctx.start_color(*color))?;
+                write!(w, "{} ", prefix)?;
+                v.show(w, env, ctx)?;
+                write!(w, "{}", ctx.end_color())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What is known about an identifier, as an editor shows on hover.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Info {
+    pub ident: Identifier,
+    pub def: Option<Definition>,
+    /// The type of the identifier, or the signature of a function.
+    #[serde(rename = "type")]
+    pub ty: Option<String>,
+    /// The first line of the definition's documentation.
+    pub docs: Option<String>,
+}
+
+impl Info {
+    fn summary(&self) -> String {
+        match &self.ty {
+            Some(ty) => format!("{}: {}", self.ident.show_name(), ty),
+            None => self.ident.show_name(),
+        }
+    }
+}
+
+impl Show for Info {
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, _: &ShowCtx) -> Result<(), Error> {
+        write!(w, "{}", self.summary())?;
+        if let Some(def) = &self.def {
+            write!(w, "\n  ")?;
+            def.show_kind(w)?;
+            write!(w, "`{}` at ", def.name)?;
+            env.file_system().show_path(def.span.file, w)?;
+            write!(w, ":{}:{}", def.span.start_line, def.span.start_column)?;
+        }
+        if let Some(docs) = &self.docs {
+            write!(w, "\n  /// {}", docs)?;
+        }
+        Ok(())
+    }
+}
+
+/// A definition and the definitions nested inside it.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Tree {
+    pub def: Definition,
+    pub children: Vec<Tree>,
+}
+
+impl Tree {
+    fn show_indented(
+        &self,
+        w: &mut dyn Write,
+        env: &impl Environment,
+        depth: usize,
+    ) -> Result<(), Error> {
+        write!(w, "{:width$}", "", width = depth * 2)?;
+        self.def.show_kind(w)?;
+        write!(w, "{} (", self.def.name)?;
+        env.file_system().show_path(self.def.span.file, w)?;
+        write!(w, ":{})", self.def.span.start_line)?;
+        for child in &self.children {
+            writeln!(w)?;
+            child.show_indented(w, env, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn try_map_paths<E>(&mut self, f: &mut dyn FnMut(Path) -> Result<Path, E>) -> Result<(), E> {
+        self.def.span.file = f(self.def.span.file)?;
+        for child in &mut self.children {
+            child.try_map_paths(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Definition {
     pub id: u64,
     pub span: Span,
     pub name: String,
+    // Values saved before definitions had kinds are loaded with an unknown kind.
+    #[serde(default)]
+    pub kind: DefKind,
+    /// `None` if the backend can't tell us the path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<DefPath>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// A crate-qualified name for a definition, which unlike its id is stable
+/// between builds.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct DefPath {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    /// Relative to the crate root, e.g., `front::Value`.
+    pub path: String,
+}
+
+impl fmt::Display for DefPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.krate)
+        } else {
+            write!(f, "{}::{}", self.krate, self.path)
+        }
+    }
+}
+
+/// The kind of item (or other thing) which a definition defines.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum DefKind {
+    Function,
+    Method,
+    Struct,
+    Enum,
+    Variant,
+    Union,
+    Trait,
+    Type,
+    Mod,
+    Macro,
+    Const,
+    Static,
+    Field,
+    Local,
+    #[default]
+    Unknown,
+}
+
+impl DefKind {
+    pub const ALL: &'static [DefKind] = &[
+        DefKind::Function,
+        DefKind::Method,
+        DefKind::Struct,
+        DefKind::Enum,
+        DefKind::Variant,
+        DefKind::Union,
+        DefKind::Trait,
+        DefKind::Type,
+        DefKind::Mod,
+        DefKind::Macro,
+        DefKind::Const,
+        DefKind::Static,
+        DefKind::Field,
+        DefKind::Local,
+    ];
+
+    /// Parse a kind from its name, as shown to the user (e.g., `fn`).
+    pub fn parse(s: &str) -> Result<DefKind, Error> {
+        DefKind::ALL
+            .iter()
+            .find(|k| k.to_string() == s)
+            .cloned()
+            .ok_or_else(|| {
+                let names: Vec<_> = DefKind::ALL.iter().map(|k| k.to_string()).collect();
+                Error::Other(format!(
+                    "Unknown kind: `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+impl fmt::Display for DefKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            DefKind::Function => "fn",
+            DefKind::Method => "method",
+            DefKind::Struct => "struct",
+            DefKind::Enum => "enum",
+            DefKind::Variant => "variant",
+            DefKind::Union => "union",
+            DefKind::Trait => "trait",
+            DefKind::Type => "type",
+            DefKind::Mod => "mod",
+            DefKind::Macro => "macro",
+            DefKind::Const => "const",
+            DefKind::Static => "static",
+            DefKind::Field => "field",
+            DefKind::Local => "local",
+            DefKind::Unknown => "unknown",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Definition {
+    /// Compared by path if both have one, otherwise by id.
+    pub fn same_as(&self, other: &Definition) -> bool {
+        match (&self.path, &other.path) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.id == other.id,
+        }
+    }
+
+    // See `Value::quickfix_entries`.
+    fn quickfix_entry(&self) -> Result<(Path, Line, Column, String), Error> {
+        let mut text = Vec::new();
+        self.show_kind(&mut text)?;
+        write!(text, "`{}`", self.name)?;
+        Ok((
+            self.span.file,
+            self.span.start_line,
+            self.span.start_column,
+            String::from_utf8_lossy(&text).into_owned(),
+        ))
+    }
+
+    fn show_kind(&self, w: &mut dyn Write) -> Result<(), Error> {
+        if self.kind != DefKind::Unknown {
+            write!(w, "{} ", self.kind)?;
+        }
+        Ok(())
+    }
+
+    // The location of the definition and its signature.
+    fn show_full(
+        &self,
+        w: &mut dyn Write,
+        env: &impl Environment,
+        ctx: &ShowCtx,
+    ) -> Result<(), Error> {
+        self.show_kind(w)?;
+        write!(w, "`{}` ", self.name)?;
+        if let (Some(path), DisplayMode::Verbose) = (&self.path, ctx.display) {
+            write!(w, "({}) ", path)?;
+        }
+        write!(w, "at ")?;
+        env.file_system().show_path(self.span.file, w)?;
+        write!(w, ":{}:{}", self.span.start_line, self.span.start_column)?;
+        let (start, lines) = env.file_system().signature(&self.span)?;
+        let last = start + lines.len().saturating_sub(1);
+        let width = last.to_string().len();
+        for (i, line) in lines.iter().enumerate() {
+            let (line, _, _) = ctx.fit_line(line, width + 3, 0, 0);
+            write!(w, "\n{:>width$} | {}", start + i, line, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+/// An error, warning, etc. emitted by the compiler.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // The primary span of the diagnostic.
+    pub span: Span,
+}
+
+impl Show for Diagnostic {
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, ctx: &ShowCtx) -> Result<(), Error> {
+        writeln!(w, "{}: {}", self.severity, self.message)?;
+        self.span.show(w, env, ctx)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+            Severity::Help => write!(f, "help"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Identifier {
     pub id: u64,
     pub span: Span,
     pub name: String,
+    /// The span of an identifier from a macro expansion may be the invocation.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub from_macro: bool,
+    /// What the identifier refers to, if the backend knows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<IdentKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub def: Option<u64>,
+}
+
+impl Identifier {
+    fn show_name(&self) -> String {
+        let mut result = match self.kind {
+            Some(kind) => format!("{} `{}`", kind, self.name),
+            None => format!("`{}`", self.name),
+        };
+        if self.from_macro {
+            result.push_str(" [macro]");
+        }
+        result
+    }
+}
+
+/// Coarser than the kind of its definition.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum IdentKind {
+    Variable,
+    Function,
+    Type,
+    Field,
+    Module,
+    Other,
+}
+
+impl IdentKind {
+    pub const ALL: &'static [IdentKind] = &[
+        IdentKind::Variable,
+        IdentKind::Function,
+        IdentKind::Type,
+        IdentKind::Field,
+        IdentKind::Module,
+        IdentKind::Other,
+    ];
+
+    pub fn of(kind: DefKind) -> Option<IdentKind> {
+        Some(match kind {
+            DefKind::Local | DefKind::Const | DefKind::Static => IdentKind::Variable,
+            DefKind::Function | DefKind::Method => IdentKind::Function,
+            DefKind::Struct | DefKind::Enum | DefKind::Union | DefKind::Trait | DefKind::Type => {
+                IdentKind::Type
+            }
+            DefKind::Field => IdentKind::Field,
+            DefKind::Mod => IdentKind::Module,
+            DefKind::Variant | DefKind::Macro => IdentKind::Other,
+            DefKind::Unknown => return None,
+        })
+    }
+
+    /// Parse a kind from its name, as shown to the user (e.g., `variable`).
+    pub fn parse(s: &str) -> Result<IdentKind, Error> {
+        IdentKind::ALL
+            .iter()
+            .find(|k| k.to_string() == s)
+            .cloned()
+            .ok_or_else(|| {
+                let names: Vec<_> = IdentKind::ALL.iter().map(|k| k.to_string()).collect();
+                Error::Other(format!(
+                    "Unknown identifier kind: `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+impl fmt::Display for IdentKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            IdentKind::Variable => "variable",
+            IdentKind::Function => "function",
+            IdentKind::Type => "type",
+            IdentKind::Field => "field",
+            IdentKind::Module => "module",
+            IdentKind::Other => "other",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl From<Identifier> for Value {
+    fn from(id: Identifier) -> Value {
+        Value {
+            ty: Type::Identifier,
+            kind: ValueKind::Identifier(id),
+            label: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -254,54 +1281,214 @@ impl From<Locator> for Value {
             Locator::Position(p) => Value {
                 ty: Type::Position,
                 kind: ValueKind::Position(p),
+                label: None,
             },
             Locator::Range(r) => Value {
                 ty: Type::Range,
                 kind: ValueKind::Range(r),
+                label: None,
             },
         }
     }
 }
 
-#[derive(new, Clone, Debug, Eq, PartialEq)]
+// Zero-indexed internally, one-indexed for the user.
+macro_rules! coordinate {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(
+            Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+        )]
+        #[serde(transparent)]
+        pub struct $name(usize);
+
+        impl $name {
+            pub const fn new_zero_indexed(n: usize) -> $name {
+                $name(n)
+            }
+
+            /// `None` if `n` is zero.
+            pub fn new_one_indexed(n: usize) -> Option<$name> {
+                n.checked_sub(1).map($name)
+            }
+
+            pub fn zero_indexed(self) -> usize {
+                self.0
+            }
+
+            pub fn one_indexed(self) -> usize {
+                self.0 + 1
+            }
+
+            pub fn saturating_sub(self, n: usize) -> $name {
+                $name(self.0.saturating_sub(n))
+            }
+
+            pub fn saturating_add(self, n: usize) -> $name {
+                $name(self.0.saturating_add(n))
+            }
+        }
+
+        impl Add<usize> for $name {
+            type Output = $name;
+
+            fn add(self, n: usize) -> $name {
+                $name(self.0 + n)
+            }
+        }
+
+        impl Sub<usize> for $name {
+            type Output = $name;
+
+            fn sub(self, n: usize) -> $name {
+                $name(self.0 - n)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.one_indexed().fmt(f)
+            }
+        }
+    };
+}
+
+coordinate! {
+    Line
+}
+
+coordinate! {
+    /// A byte offset into a line, not a count of chars.
+    Column
+}
+
+#[derive(new, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Position {
     pub file: Path,
-    pub line: usize,
-    pub column: usize,
+    pub line: Line,
+    pub column: Column,
+}
+
+impl Position {
+    pub fn new_zero_indexed(file: Path, line: usize, column: usize) -> Position {
+        Position::new(
+            file,
+            Line::new_zero_indexed(line),
+            Column::new_zero_indexed(column),
+        )
+    }
 }
 
 impl Show for Position {
-    fn show(&self, w: &mut dyn Write, env: &impl Environment) -> Result<(), Error> {
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, ctx: &ShowCtx) -> Result<(), Error> {
         write!(w, " --> ")?;
         env.file_system().show_path(self.file, w)?;
         let text = env.file_system().with_file(self.file, |file| {
-            file.lines.get(self.line).map(|s| s.to_owned())
+            let column = file.char_column(self.line, self.column);
+            Ok(file.line(self.line).map(|s| (s.to_owned(), column)))
         })?;
-        write!(w, ":{}:{}\n", self.line + 1, self.column + 1)?;
+        write!(w, ":{}:{}", self.line, self.column)?;
+        show_changed(w, env, self.file)?;
+        writeln!(w)?;
+        let (before, after) = context_lines(env, ctx, self.file, self.line)?;
+        let width = gutter_width(self.line, &after);
+        let offset = width + 3;
+        // The file may have changed since the position was found.
+        let (text, column, _) = match text {
+            // `fit_line` takes chars rather than bytes.
+            Some((text, column)) => ctx.fit_line(&text, offset, column, column + 1),
+            None => (
+                "<error - line out of range>".to_owned(),
+                self.column.zero_indexed(),
+                0,
+            ),
+        };
+        show_context_lines(w, ctx, &before, width, false)?;
+        writeln!(
+            w,
+            "{:>width$} | {}",
+            self.line.to_string(),
+            text,
+            width = width
+        )?;
+        write!(w, "{:width$}^", "", width = offset + column)?;
+        show_context_lines(w, ctx, &after, width, true)
+    }
+}
+
+// Lines of source text, each with its number.
+type NumberedLines = Vec<(Line, String)>;
+
+fn context_lines(
+    env: &impl Environment,
+    ctx: &ShowCtx,
+    path: Path,
+    line: Line,
+) -> Result<(NumberedLines, NumberedLines), Error> {
+    if ctx.context == 0 {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let n = line.zero_indexed();
+    let result = env.file_system().with_file(path, |file| {
+        let numbered = |lines: std::ops::Range<usize>| {
+            lines
+                .filter_map(|i| Some((Line::new_zero_indexed(i), file.lines.get(i)?.clone())))
+                .collect()
+        };
+        Ok((
+            numbered(n.saturating_sub(ctx.context)..n),
+            numbered(n + 1..n + 1 + ctx.context),
+        ))
+    })?;
+    Ok(result)
+}
+
+fn gutter_width(line: Line, after: &[(Line, String)]) -> usize {
+    after.last().map_or(line, |(l, _)| *l).to_string().len()
+}
+
+// Line breaks come before each line if `after`, otherwise after each.
+fn show_context_lines(
+    w: &mut dyn Write,
+    ctx: &ShowCtx,
+    lines: &[(Line, String)],
+    width: usize,
+    after: bool,
+) -> Result<(), Error> {
+    for (line, text) in lines {
+        if after {
+            writeln!(w)?;
+        }
+        let text = ctx.fit_line(text, width + 3, 0, 0).0;
         write!(
             w,
-            "{} | {}\n",
-            self.line + 1,
-            text.unwrap_or_else(|| "<error - line out of range>".to_owned())
+            "{}{:>width$} | {}{}",
+            ctx.start_color(Color::Dim),
+            line.to_string(),
+            text,
+            ctx.end_color(),
+            width = width
         )?;
-        let offset = (self.line + 1).to_string().len() + 3;
-        write!(w, "{:width$}^", "", width = offset + self.column).map_err(Into::into)
+        if !after {
+            writeln!(w)?;
+        }
     }
+    Ok(())
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Range {
     File(Path),
     MultiFile(Vec<Path>),
-    Line(Path, usize),
+    Line(Path, Line),
     Span(Span),
 }
 
 impl Show for Range {
-    fn show(&self, w: &mut dyn Write, env: &impl Environment) -> Result<(), Error> {
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, ctx: &ShowCtx) -> Result<(), Error> {
         match self {
             Range::File(path) => env.file_system().show_path(*path, w).map_err(Into::into),
-            Range::MultiFile(paths) if paths.len() < 5 => {
+            Range::MultiFile(paths) if paths.len() <= ctx.max_set_len => {
                 write!(w, "[")?;
                 let mut first = true;
                 for p in paths {
@@ -320,76 +1507,277 @@ impl Show for Range {
                 env.file_system().show_path(*path, w)?;
                 let text = env
                     .file_system()
-                    .with_file(*path, |file| file.lines.get(*line).map(|s| s.to_owned()))?;
-                write!(w, ":{}\n", line + 1)?;
-                write!(
-                    w,
-                    "{} | {}",
-                    line + 1,
-                    text.unwrap_or_else(|| "<error - line out of range>".to_owned())
-                )
-                .map_err(Into::into)
+                    .with_file(*path, |file| Ok(file.line(*line).map(|s| s.to_owned())))?;
+                write!(w, ":{}", line)?;
+                show_changed(w, env, *path)?;
+                writeln!(w)?;
+                let (before, after) = context_lines(env, ctx, *path, *line)?;
+                let width = gutter_width(*line, &after);
+                let text = match text {
+                    Some(text) => ctx.fit_line(&text, width + 3, 0, 0).0,
+                    None => "<error - line out of range>".to_owned(),
+                };
+                show_context_lines(w, ctx, &before, width, false)?;
+                write!(w, "{:>width$} | {}", line.to_string(), text, width = width)?;
+                show_context_lines(w, ctx, &after, width, true)
             }
-            Range::Span(s) => s.show(w, env),
+            Range::Span(s) => s.show(w, env, ctx),
         }
     }
 }
 
-#[derive(new, Clone, Debug, Eq, PartialEq)]
+// Offsets are not compared.
+#[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct Span {
     pub file: Path,
-    pub start_line: usize,
-    pub start_column: usize,
-    pub end_line: usize,
-    pub end_column: usize,
+    pub start_line: Line,
+    pub start_column: Column,
+    pub end_line: Line,
+    /// Exclusive.
+    pub end_column: Column,
+    /// Byte offsets in the file, if known.
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offsets: Option<(usize, usize)>,
+}
+
+impl PartialEq for Span {
+    fn eq(&self, other: &Span) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Span {}
+
+impl Hash for Span {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl Span {
+    fn key(&self) -> (Path, Line, Column, Line, Column) {
+        (
+            self.file,
+            self.start_line,
+            self.start_column,
+            self.end_line,
+            self.end_column,
+        )
+    }
+
+    pub fn new_zero_indexed(
+        file: Path,
+        start_line: usize,
+        start_column: usize,
+        end_line: usize,
+        end_column: usize,
+    ) -> Span {
+        Span::new(
+            file,
+            Line::new_zero_indexed(start_line),
+            Column::new_zero_indexed(start_column),
+            Line::new_zero_indexed(end_line),
+            Column::new_zero_indexed(end_column),
+        )
+    }
+
+    /// `self` with its byte offsets filled in, if they can be found.
+    pub fn with_offsets(mut self, fs: &impl FileSystem) -> Span {
+        self.offsets = self.find_offsets(fs).ok();
+        self
+    }
+
+    pub fn byte_offsets(&self, fs: &impl FileSystem) -> Result<(usize, usize), Error> {
+        match self.offsets {
+            Some(offsets) => Ok(offsets),
+            None => self.find_offsets(fs),
+        }
+    }
+
+    fn find_offsets(&self, fs: &impl FileSystem) -> Result<(usize, usize), Error> {
+        fs.with_file(self.file, |f| {
+            let offset = |line: Line, column: Column| {
+                f.offset(line, column).ok_or_else(|| {
+                    file_system::Error::BadLocation(format!(
+                        "{}:{} is not in {}",
+                        line,
+                        column,
+                        fs.path_name(self.file)
+                    ))
+                })
+            };
+            Ok((
+                offset(self.start_line, self.start_column)?,
+                offset(self.end_line, self.end_column)?,
+            ))
+        })
+        .map_err(Into::into)
+    }
+
+    /// The empty span at `position`.
+    pub fn at(position: &Position) -> Span {
+        Span::new(
+            position.file,
+            position.line,
+            position.column,
+            position.line,
+            position.column,
+        )
+    }
+
+    /// Spans which touch at their ends overlap.
+    pub fn intersects(&self, range: &Range) -> bool {
+        match range {
+            Range::File(p) => self.file == *p,
+            Range::MultiFile(ps) => ps.contains(&self.file),
+            Range::Line(p, line) => {
+                self.file == *p && self.start_line <= *line && self.end_line >= *line
+            }
+            Range::Span(other) => {
+                self.file == other.file
+                    && (self.start_line, self.start_column) <= (other.end_line, other.end_column)
+                    && (other.start_line, other.start_column) <= (self.end_line, self.end_column)
+            }
+        }
+    }
+
+    /// The end of a span is exclusive.
+    pub fn contains(&self, position: &Position) -> bool {
+        self.file == position.file
+            && (self.start_line, self.start_column) <= (position.line, position.column)
+            && (position.line, position.column) < (self.end_line, self.end_column)
+    }
+}
+
+/// The text covered by a value, see `Value::extent`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Extent {
+    Files(Vec<Path>),
+    /// From a start (inclusive) to an end (exclusive).
+    Text(Path, (Line, Column), (Line, Column)),
+}
+
+impl Extent {
+    /// An empty span covers the character it is at, like a position.
+    pub fn of_span(span: &Span) -> Extent {
+        let start = (span.start_line, span.start_column);
+        let end = (span.end_line, span.end_column);
+        if start < end {
+            Extent::Text(span.file, start, end)
+        } else {
+            Extent::Text(span.file, start, (start.0, start.1 + 1))
+        }
+    }
+
+    pub fn within(&self, other: &Extent) -> bool {
+        match (self, other) {
+            (Extent::Files(files), Extent::Files(others)) => {
+                files.iter().all(|f| others.contains(f))
+            }
+            (Extent::Text(file, ..), Extent::Files(others)) => others.contains(file),
+            (Extent::Files(_), Extent::Text(..)) => false,
+            (Extent::Text(file, start, end), Extent::Text(other, other_start, other_end)) => {
+                file == other && other_start <= start && end <= other_end
+            }
+        }
+    }
+
+    /// Extents which only touch don't overlap.
+    pub fn overlaps(&self, other: &Extent) -> bool {
+        match (self, other) {
+            (Extent::Files(files), Extent::Files(others)) => {
+                files.iter().any(|f| others.contains(f))
+            }
+            (Extent::Text(file, ..), Extent::Files(files))
+            | (Extent::Files(files), Extent::Text(file, ..)) => files.contains(file),
+            (Extent::Text(file, start, end), Extent::Text(other, other_start, other_end)) => {
+                file == other && start < other_end && other_start < end
+            }
+        }
+    }
 }
 
 impl Show for Span {
-    fn show(&self, w: &mut dyn Write, env: &impl Environment) -> Result<(), Error> {
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, ctx: &ShowCtx) -> Result<(), Error> {
         write!(w, " --> ")?;
         env.file_system().show_path(self.file, w)?;
         if self.start_line == self.end_line {
             // A span on one line
             let text = env.file_system().with_file(self.file, |file| {
-                file.lines.get(self.start_line).map(|s| s.to_owned())
+                let start = file.char_column(self.start_line, self.start_column);
+                let end = file.char_column(self.end_line, self.end_column);
+                Ok(file
+                    .line(self.start_line)
+                    .map(|s| (s.to_owned(), start, end)))
             })?;
             write!(
                 w,
-                ":{}:{}->{}\n",
-                self.start_line + 1,
-                self.start_column + 1,
-                self.end_column + 1
+                ":{}:{}->{}",
+                self.start_line, self.start_column, self.end_column
             )?;
-            write!(
+            show_changed(w, env, self.file)?;
+            writeln!(w)?;
+            let (before, after) = context_lines(env, ctx, self.file, self.start_line)?;
+            let width = gutter_width(self.start_line, &after);
+            let offset = width + 3;
+            let (text, start, end) = match text {
+                Some((text, start, end)) => ctx.fit_line(&text, offset, start, end),
+                None => (
+                    "<error - line out of range>".to_owned(),
+                    self.start_column.zero_indexed(),
+                    self.end_column.zero_indexed(),
+                ),
+            };
+            show_context_lines(w, ctx, &before, width, false)?;
+            writeln!(
                 w,
-                "{} | {}\n",
-                self.start_line + 1,
-                text.unwrap_or_else(|| "<error - line out of range>".to_owned())
+                "{:>width$} | {}",
+                self.start_line.to_string(),
+                text,
+                width = width
             )?;
-            let offset = (self.start_line + 1).to_string().len() + 3;
             write!(
                 w,
                 "{:width1$}{}",
                 "",
-                "^".repeat(self.end_column - self.start_column),
-                width1 = offset + self.start_column
-            )
-            .map_err(Into::into)
+                "^".repeat(end - start),
+                width1 = offset + start
+            )?;
+            show_context_lines(w, ctx, &after, width, true)
         } else {
             // A multispan range
             write!(
                 w,
-                ":{}:{}->{}:{}\n",
-                self.start_line + 1,
-                self.start_column + 1,
-                self.end_line + 1,
-                self.end_column + 1
-            )
-            .map_err(Into::into)
+                ":{}:{}->{}:{}",
+                self.start_line, self.start_column, self.end_line, self.end_column
+            )?;
+            show_changed(w, env, self.file)?;
+            writeln!(w).map_err(Into::into)
         }
     }
 }
 
+// The text shown may not be where the location was found.
+fn show_changed(w: &mut dyn Write, env: &impl Environment, path: Path) -> Result<(), Error> {
+    if env.file_system().has_changed(path) {
+        write!(w, " (file changed, may be stale)")?;
+    }
+    Ok(())
+}
+
+// TSV has no quoting, so tabs and line breaks are replaced with spaces.
+fn table_field(s: &str, sep: char) -> String {
+    let special = |c: char| c == sep || c == '"' || c == '\n' || c == '\r';
+    if sep == '\t' {
+        s.replace(['\t', '\n', '\r'], " ")
+    } else if s.contains(special) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -402,6 +1790,7 @@ mod test {
         let set = Value {
             kind: ValueKind::Set(vec![Value::number(1), Value::number(2), Value::number(3)]),
             ty: Type::Set(Box::new(Type::Number)),
+            label: None,
         };
         assert_eq!(set.show_str(&MockEnv), "[1, 2, 3]");
         let set = Value {
@@ -416,8 +1805,339 @@ mod test {
                 Value::number(3),
             ]),
             ty: Type::Set(Box::new(Type::Number)),
+            label: None,
         };
         assert_eq!(set.show_str(&MockEnv), "[...]*8");
+        assert_eq!(set.clone().partial().show_str(&MockEnv), "[...]*8+");
+        assert_eq!(Value::number(42).partial().show_str(&MockEnv), "42");
+
+        let mut ctx = MockEnv.show_ctx();
+        ctx.max_set_len = 8;
+        let mut buf = Vec::new();
+        set.show(&mut buf, &MockEnv, &ctx).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "[1, 2, 3, 3, 3, 3, 3, 3]");
+
+        let labelled = Value {
+            label: Some("answer".to_owned()),
+            ..Value::number(42)
+        };
+        assert_eq!(labelled.show_str(&MockEnv), "42");
+        ctx.display = DisplayMode::Verbose;
+        let mut buf = Vec::new();
+        labelled.show(&mut buf, &MockEnv, &ctx).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "answer: 42");
+        // Labels are saved, but only if there is one.
+        let json = serde_json::to_string(&labelled).unwrap();
+        assert!(json.contains("\"label\":\"answer\""));
+        assert!(!serde_json::to_string(&Value::number(42))
+            .unwrap()
+            .contains("label"));
+    }
+
+    // Records what had been written each time the writer is flushed.
+    #[derive(Default)]
+    struct FlushLog {
+        buf: Vec<u8>,
+        flushed: Vec<String>,
+    }
+
+    impl Write for FlushLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buf.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushed
+                .push(String::from_utf8(self.buf.clone()).unwrap());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_set_show_incremental() {
+        let set = Value {
+            kind: ValueKind::Set(vec![Value::number(1), Value::number(2), Value::number(3)]),
+            ty: Type::Set(Box::new(Type::Number)),
+            label: None,
+        };
+        let mut log = FlushLog::default();
+        set.show(&mut log, &MockEnv, &MockEnv.show_ctx()).unwrap();
+        assert_eq!(log.flushed, vec!["[1", "[1, 2", "[1, 2, 3"]);
+    }
+
+    #[test]
+    fn test_coerce_to_location() {
+        let file = MockEnv
+            .file_system()
+            .find("foo.rs".to_owned().into())
+            .unwrap()
+            .pop()
+            .unwrap();
+        let span = Span::new_zero_indexed(file, 3, 1, 3, 10);
+        let def = Value {
+            ty: Type::Definition,
+            kind: ValueKind::Definition(Definition {
+                id: 42,
+                span: span.clone(),
+                name: "foo".to_owned(),
+                kind: DefKind::Function,
+                path: None,
+            }),
+            label: None,
+        };
+        assert!(types::coerce(&def.ty, &Type::Location).is_some());
+        let loc = def.coerce_to_location();
+        assert_eq!(loc.ty, Type::Range);
+        match loc.kind {
+            ValueKind::Range(Range::Span(s)) => assert_eq!(s, span),
+            _ => panic!(),
+        }
+
+        let n = Value::number(42).coerce_to_location();
+        assert_eq!(n.ty, Type::Number);
+    }
+
+    #[test]
+    fn test_value_set() {
+        let file = MockEnv
+            .file_system()
+            .find("foo.rs".to_owned().into())
+            .unwrap()
+            .pop()
+            .unwrap();
+        let position = Value {
+            ty: Type::Position,
+            kind: ValueKind::Position(Position::new_zero_indexed(file, 1, 2)),
+            label: None,
+        };
+        let def = Value {
+            ty: Type::Definition,
+            kind: ValueKind::Definition(Definition {
+                id: 42,
+                span: Span::new_zero_indexed(file, 3, 1, 3, 10),
+                name: "foo".to_owned(),
+                kind: DefKind::Function,
+                path: None,
+            }),
+            label: None,
+        };
+
+        // Locations of different kinds may be mixed, definitions are coerced.
+        let set = Value::set(vec![position.clone(), def.clone()], Type::Location).unwrap();
+        assert_eq!(set.ty, Type::Set(Box::new(Type::Location)));
+        match set.kind {
+            ValueKind::Set(elements) => assert_eq!(elements[1].ty, Type::Range),
+            _ => panic!(),
+        }
+        assert!(Value::set(vec![def.clone()], Type::Definition).is_ok());
+        assert!(Value::set(vec![Value::void()], Type::Set(Box::new(Type::Number))).is_ok());
+
+        match Value::set(vec![def, Value::number(1)], Type::Definition) {
+            Err(Error::TypeError(msg)) => {
+                assert_eq!(msg, "Set element 1 has type Number, expected Definition")
+            }
+            r => panic!("{:?}", r),
+        }
+        match Value::set(vec![position], Type::Range) {
+            Err(Error::TypeError(msg)) => {
+                assert_eq!(msg, "Set element 0 has type Position, expected Range")
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_tree_show() {
+        let env = MockEnv;
+        let file = env
+            .file_system()
+            .find("foo.rs".to_owned().into())
+            .unwrap()
+            .pop()
+            .unwrap();
+        let def = |name: &str, line, kind| Definition {
+            id: 0,
+            span: Span::new_zero_indexed(file, line, 0, line, 4),
+            name: name.to_owned(),
+            kind,
+            path: None,
+        };
+        let tree = Value {
+            kind: ValueKind::Tree(vec![
+                Tree {
+                    def: def("a", 0, DefKind::Unknown),
+                    children: vec![Tree {
+                        def: def("b", 2, DefKind::Function),
+                        children: vec![],
+                    }],
+                },
+                Tree {
+                    def: def("c", 9, DefKind::Struct),
+                    children: vec![],
+                },
+            ]),
+            ty: Type::Tree,
+            label: None,
+        };
+        assert_eq!(
+            tree.show_str(&env),
+            "a (foo.rs:1)\n  fn b (foo.rs:3)\nstruct c (foo.rs:10)"
+        );
+    }
+
+    #[test]
+    fn test_def_kind() {
+        for kind in DefKind::ALL {
+            assert_eq!(DefKind::parse(&kind.to_string()).unwrap(), *kind);
+        }
+        assert_eq!(DefKind::parse("fn").unwrap(), DefKind::Function);
+        assert!(DefKind::parse("unknown").is_err());
+        assert!(DefKind::parse("function").is_err());
+    }
+
+    #[test]
+    fn test_ident_kind() {
+        for kind in IdentKind::ALL {
+            assert_eq!(IdentKind::parse(&kind.to_string()).unwrap(), *kind);
+        }
+        assert!(IdentKind::parse("fn").is_err());
+        assert_eq!(IdentKind::of(DefKind::Method), Some(IdentKind::Function));
+        assert_eq!(IdentKind::of(DefKind::Static), Some(IdentKind::Variable));
+        assert_eq!(IdentKind::of(DefKind::Trait), Some(IdentKind::Type));
+        assert_eq!(IdentKind::of(DefKind::Unknown), None);
+    }
+
+    #[test]
+    fn test_coordinates() {
+        let line = Line::new_one_indexed(3).unwrap();
+        assert_eq!(line, Line::new_zero_indexed(2));
+        assert_eq!((line.zero_indexed(), line.one_indexed()), (2, 3));
+        assert_eq!(line.to_string(), "3");
+        assert_eq!(line.saturating_sub(5), Line::new_zero_indexed(0));
+        assert!(Column::new_one_indexed(0).is_none());
+        assert_eq!(
+            Column::new_zero_indexed(4) + 1,
+            Column::new_one_indexed(6).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_span_intersects() {
+        let fs = MockEnv.file_system();
+        let foo = fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap();
+        let bar = fs.find("bar.rs".to_owned().into()).unwrap().pop().unwrap();
+        let span = Span::new_zero_indexed(foo, 3, 4, 5, 2);
+
+        assert!(span.intersects(&Range::File(foo)));
+        assert!(!span.intersects(&Range::File(bar)));
+        assert!(span.intersects(&Range::MultiFile(vec![bar, foo])));
+        assert!(span.intersects(&Range::Line(foo, Line::new_zero_indexed(4))));
+        assert!(!span.intersects(&Range::Line(foo, Line::new_zero_indexed(6))));
+        assert!(!span.intersects(&Range::Line(bar, Line::new_zero_indexed(4))));
+        assert!(span.intersects(&Range::Span(Span::new_zero_indexed(foo, 5, 2, 7, 0))));
+        assert!(span.intersects(&Range::Span(Span::new_zero_indexed(foo, 0, 0, 3, 4))));
+        assert!(!span.intersects(&Range::Span(Span::new_zero_indexed(foo, 5, 3, 7, 0))));
+        assert!(!span.intersects(&Range::Span(Span::new_zero_indexed(foo, 0, 0, 3, 3))));
+    }
+
+    #[test]
+    fn test_span_contains() {
+        let fs = MockEnv.file_system();
+        let foo = fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap();
+        let bar = fs.find("bar.rs".to_owned().into()).unwrap().pop().unwrap();
+        let span = Span::new_zero_indexed(foo, 3, 4, 3, 9);
+
+        assert!(span.contains(&Position::new_zero_indexed(foo, 3, 4)));
+        assert!(span.contains(&Position::new_zero_indexed(foo, 3, 6)));
+        assert!(span.contains(&Position::new_zero_indexed(foo, 3, 8)));
+        assert!(!span.contains(&Position::new_zero_indexed(foo, 3, 9)));
+        assert!(!span.contains(&Position::new_zero_indexed(foo, 3, 3)));
+        assert!(!span.contains(&Position::new_zero_indexed(foo, 4, 6)));
+        assert!(!span.contains(&Position::new_zero_indexed(bar, 3, 6)));
+        assert!(!Span::at(&Position::new_zero_indexed(foo, 3, 6))
+            .contains(&Position::new_zero_indexed(foo, 3, 6)));
+    }
+
+    #[test]
+    fn test_table_field() {
+        assert_eq!(table_field("foo", ','), "foo");
+        assert_eq!(table_field("a, \"b\"", ','), "\"a, \"\"b\"\"\"");
+        assert_eq!(table_field("a\nb", ','), "\"a\nb\"");
+        assert_eq!(table_field("a, b", '\t'), "a, b");
+        assert_eq!(table_field("a\tb\nc", '\t'), "a b c");
+    }
+
+    #[test]
+    fn test_extent() {
+        let fs = MockEnv.file_system();
+        let foo = fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap();
+        let bar = fs.find("bar.rs".to_owned().into()).unwrap().pop().unwrap();
+        let extent = |kind| {
+            Value {
+                kind,
+                ty: Type::Location,
+                label: None,
+            }
+            .extent()
+            .unwrap()
+        };
+        let span = |sl, sc, el, ec| {
+            extent(ValueKind::Range(Range::Span(Span::new_zero_indexed(
+                foo, sl, sc, el, ec,
+            ))))
+        };
+        let position =
+            |file, l, c| extent(ValueKind::Position(Position::new_zero_indexed(file, l, c)));
+        let body = span(3, 4, 6, 1);
+
+        assert!(body.within(&body));
+        assert!(span(4, 0, 4, 8).within(&body));
+        assert!(!span(3, 3, 4, 8).within(&body));
+        assert!(!body.within(&span(4, 0, 4, 8)));
+        assert!(position(foo, 3, 4).within(&body));
+        assert!(!position(foo, 6, 1).within(&body));
+        assert!(!position(bar, 4, 0).within(&body));
+        // An empty span is inside the text around it.
+        assert!(span(6, 0, 6, 0).within(&body));
+        assert!(body.within(&extent(ValueKind::Range(Range::File(foo)))));
+        assert!(!body.within(&extent(ValueKind::Range(Range::File(bar)))));
+        let line = extent(ValueKind::Range(Range::Line(
+            foo,
+            Line::new_zero_indexed(4),
+        )));
+        assert!(line.within(&body));
+        assert!(!extent(ValueKind::Range(Range::File(foo))).within(&body));
+        let both = extent(ValueKind::Range(Range::MultiFile(vec![foo, bar])));
+        assert!(extent(ValueKind::Range(Range::File(bar))).within(&both));
+        assert!(!both.within(&extent(ValueKind::Range(Range::File(bar)))));
+
+        assert!(body.overlaps(&span(6, 0, 8, 0)));
+        assert!(span(0, 0, 3, 5).overlaps(&body));
+        // Touching isn't overlapping.
+        assert!(!body.overlaps(&span(6, 1, 8, 0)));
+        assert!(!span(0, 0, 3, 4).overlaps(&body));
+        assert!(body.overlaps(&both));
+        assert!(!body.overlaps(&extent(ValueKind::Range(Range::File(bar)))));
+        assert!(both.overlaps(&extent(ValueKind::Range(Range::File(bar)))));
+        assert_eq!(Value::number(3).extent(), None);
+    }
+
+    #[test]
+    fn test_span_offsets() {
+        let fs = MockEnv.file_system();
+        let foo = fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap();
+        // Each line of a mock file is 40 bytes, including the line ending.
+        let span = Span::new_zero_indexed(foo, 1, 5, 2, 4);
+        assert_eq!(span.offsets, None);
+        assert_eq!(span.byte_offsets(fs).unwrap(), (45, 84));
+        let with_offsets = span.clone().with_offsets(fs);
+        assert_eq!(with_offsets.offsets, Some((45, 84)));
+        // Offsets don't affect equality.
+        assert_eq!(with_offsets, span);
+
+        let span = Span::new_zero_indexed(foo, 1, 5, 2, 400);
+        assert_eq!(span.clone().with_offsets(fs).offsets, None);
+        assert!(span.byte_offsets(fs).is_err());
     }
 
     #[test]
@@ -425,7 +2145,7 @@ mod test {
         let env = MockEnv;
         let fs = env.file_system();
 
-        let pos = Position::new(
+        let pos = Position::new_zero_indexed(
             fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap(),
             2,
             3,
@@ -436,13 +2156,13 @@ mod test {
 
         let range = Range::Line(
             fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap(),
-            3,
+            Line::new_zero_indexed(3),
         );
         let s = range.show_str(&env);
         assert!(s.contains("foo.rs:4"));
         assert!(s.contains("This is line 3 of a file with number 1."));
 
-        let span = Span::new(
+        let span = Span::new_zero_indexed(
             fs.find("foo.rs".to_owned().into()).unwrap().pop().unwrap(),
             3,
             1,
@@ -0,0 +1,250 @@
+//! Query plans (see `^explain`): the tree of functions a statement applies,
+//! with their types and the backend methods they may call. Plans are found by
+//! type checking the statement, nothing is evaluated.
+
+use super::data::{Type, ValueKind};
+use super::{Error, Interpreter};
+use crate::ast;
+use crate::env::Environment;
+use std::fmt;
+
+/// A function application or a value in a statement.
+pub struct Plan {
+    /// The function and its arguments, or the value, as they were written.
+    pub label: String,
+    pub ty: Type,
+    /// The `Backend` methods which the function may call.
+    pub calls: Vec<&'static str>,
+    /// The number of elements, if the node is a set and that is known without
+    /// evaluating anything.
+    pub len: Option<Len>,
+    /// The lhs of a function, then any of its arguments which apply functions
+    /// themselves; or the elements of a set.
+    pub children: Vec<Plan>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Len {
+    Exactly(usize),
+    AtMost(usize),
+}
+
+/// The plan for `stmt`, or an error if it doesn't type check.
+pub fn explain<Env: Environment>(env: &Env, stmt: ast::Statement) -> Result<Plan, Error> {
+    let mut interpreter = Interpreter::new(env);
+    match stmt.kind {
+        ast::StatementKind::Expr(expr) => interpreter.plan(&expr),
+        ast::StatementKind::ApplyShorthand(apply) => interpreter.plan_apply(&apply),
+        ast::StatementKind::Assign(ident, expr) => {
+            let mut plan = interpreter.plan(&expr.kind)?;
+            plan.label = format!("{} = {}", ident.name, plan.label);
+            Ok(plan)
+        }
+        ast::StatementKind::Meta(_) => {
+            Err(Error::Other("Meta-commands can't be explained".to_owned()))
+        }
+    }
+}
+
+impl Plan {
+    fn leaf(label: String, ty: Type) -> Plan {
+        Plan {
+            label,
+            ty,
+            calls: Vec::new(),
+            len: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{}{}: {}", "  ".repeat(depth), self.label, self.ty)?;
+        match self.len {
+            Some(Len::Exactly(n)) => write!(f, ", {} element{}", n, plural(n))?,
+            Some(Len::AtMost(n)) => write!(f, ", at most {} element{}", n, plural(n))?,
+            None => {}
+        }
+        if !self.calls.is_empty() {
+            write!(f, ", calls {}", self.calls.join(", "))?;
+        }
+        for child in &self.children {
+            writeln!(f)?;
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+// One line for each node, indented under its parent.
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl<'a, Env: Environment> Interpreter<'a, Env> {
+    fn plan(&mut self, expr: &ast::ExprKind) -> Result<Plan, Error> {
+        let ty = self.type_expr(expr)?;
+        let mut plan = Plan::leaf(source(expr), ty);
+        match expr {
+            ast::ExprKind::Apply(apply) => return self.plan_apply(apply),
+            ast::ExprKind::Projection(p) => return self.plan_apply(&(*p).clone().into()),
+            ast::ExprKind::Void => plan.len = Some(Len::Exactly(0)),
+            ast::ExprKind::MetaVar(kind) => {
+                if let ValueKind::Set(elements) = self.lookup_var(kind)?.kind {
+                    plan.len = Some(Len::Exactly(elements.len()));
+                }
+            }
+            ast::ExprKind::Set(elements) => {
+                plan.label = "[...]".to_owned();
+                plan.children = elements
+                    .iter()
+                    .map(|e| self.plan(&e.kind))
+                    .collect::<Result<_, _>>()?;
+                // Duplicates are removed and nested sets are flattened.
+                if plan.children.iter().all(|c| !is_set(&c.ty)) {
+                    plan.len = Some(Len::AtMost(elements.len()));
+                }
+            }
+            ast::ExprKind::Index(index) => {
+                let lhs = self.plan(&index.lhs.kind)?;
+                plan.label = slice(index.slice);
+                plan.len = slice_len(index.slice, lhs.len);
+                plan.children = vec![lhs];
+            }
+            _ => {}
+        }
+        Ok(plan)
+    }
+
+    fn plan_apply(&mut self, apply: &ast::Apply) -> Result<Plan, Error> {
+        let ty = self.type_apply(apply)?;
+        let calls = match self.functions.lookup(&apply.ident.name) {
+            Some(f) => f
+                .requires
+                .iter()
+                .flat_map(|c| c.functions())
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut children = vec![self.plan(&apply.lhs.kind)?];
+        // Arguments evaluated for each element are only shown in the label.
+        for arg in &apply.args {
+            if is_compound(&arg.kind) {
+                if let Ok(plan) = self.plan(&arg.kind) {
+                    children.push(plan);
+                }
+            }
+        }
+        let args: Vec<_> = apply.args.iter().map(|a| source(&a.kind)).collect();
+        let label = match &*args {
+            [] => apply.ident.name.clone(),
+            [arg] => format!("{} {}", apply.ident.name, arg),
+            _ => format!("{}({})", apply.ident.name, args.join(", ")),
+        };
+        Ok(Plan {
+            label,
+            ty,
+            calls,
+            len: None,
+            children,
+        })
+    }
+}
+
+// `expr` written as it would be in a statement (lambdas as they were written).
+fn source(expr: &ast::ExprKind) -> String {
+    match expr {
+        ast::ExprKind::Void => "()".to_owned(),
+        ast::ExprKind::Number(n) => n.to_string(),
+        ast::ExprKind::String(s) => format!("{:?}", s),
+        ast::ExprKind::MetaVar(kind) => match kind {
+            ast::MetaVarKind::Dollar => "$".to_owned(),
+            ast::MetaVarKind::Numeric(n) => format!("${}", n),
+            ast::MetaVarKind::Named(id) => id.name.clone(),
+            ast::MetaVarKind::All => "$all".to_owned(),
+        },
+        ast::ExprKind::Location(loc) => {
+            let mut result = format!("(:{}", loc.file.as_deref().unwrap_or_default());
+            for n in loc.line.iter().chain(&loc.column) {
+                result.push_str(&format!(":{}", n));
+            }
+            result + ")"
+        }
+        ast::ExprKind::Apply(apply) => {
+            let args: Vec<_> = apply.args.iter().map(|a| source(&a.kind)).collect();
+            let args = match &*args {
+                [] => String::new(),
+                [arg] => format!(" {}", arg),
+                _ => format!("({})", args.join(", ")),
+            };
+            format!("{}->{}{}", source(&apply.lhs.kind), apply.ident.name, args)
+        }
+        ast::ExprKind::Projection(p) => format!("{}.{}", source(&p.lhs.kind), p.ident.name),
+        ast::ExprKind::Set(elements) => {
+            let elements: Vec<_> = elements.iter().map(|e| source(&e.kind)).collect();
+            format!("[{}]", elements.join(", "))
+        }
+        ast::ExprKind::Index(index) => format!("{}{}", source(&index.lhs.kind), slice(index.slice)),
+        ast::ExprKind::Lambda(lambda) => lambda.source.clone(),
+    }
+}
+
+fn slice(slice: ast::Slice) -> String {
+    let bound = |b: Option<isize>| b.map(|b| b.to_string()).unwrap_or_default();
+    match slice {
+        ast::Slice::Element(i) => format!("[{}]", i),
+        ast::Slice::Range(start, end) => format!("[{}..{}]", bound(start), bound(end)),
+    }
+}
+
+// The length of a slice of a set of length `len`.
+fn slice_len(slice: ast::Slice, len: Option<Len>) -> Option<Len> {
+    let (start, end) = match slice {
+        ast::Slice::Element(_) => return None,
+        ast::Slice::Range(start, end) => (start.unwrap_or(0), end),
+    };
+    match len {
+        Some(Len::Exactly(len)) => {
+            // Negative indices count from the end, indices are clamped to the set.
+            let index = |i: isize| {
+                if i < 0 {
+                    len.saturating_sub(i.unsigned_abs())
+                } else {
+                    (i as usize).min(len)
+                }
+            };
+            let end = end.map_or(len, index);
+            Some(Len::Exactly(end.saturating_sub(index(start))))
+        }
+        _ => match end {
+            Some(end) if start >= 0 && end >= 0 => {
+                Some(Len::AtMost((end as usize).saturating_sub(start as usize)))
+            }
+            _ => None,
+        },
+    }
+}
+
+fn is_set(ty: &Type) -> bool {
+    matches!(ty.unquery(), Type::Set(_))
+}
+
+fn is_compound(expr: &ast::ExprKind) -> bool {
+    matches!(
+        expr,
+        ast::ExprKind::Apply(_)
+            | ast::ExprKind::Projection(_)
+            | ast::ExprKind::Set(_)
+            | ast::ExprKind::Index(_)
+    )
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
@@ -1,27 +1,70 @@
 use crate::ast;
-use crate::env::Environment;
-use crate::front::data::{Type, Value, ValueKind};
-use crate::front::{query, Error, Interpreter};
+use crate::back::{Backend, Capabilities, Capability, Symbol};
+use crate::env::{Environment, MacroMode};
+use crate::file_system::{FileSystem, Path, TextSearch};
+use crate::front::data::{
+    self, Column, DefKind, Extent, IdentKind, Identifier, Line, MetaVar, Position, Range, Span,
+    Tree, Type, Value, ValueKind,
+};
+use crate::front::query::{self, EvalCtx, Fun, Query};
+use crate::front::syntax::{self, Item};
+use crate::front::types::{self, Shape};
+use crate::front::{eval_query, eval_with_ctx, graph, Error, Interpreter};
+use crate::parse::Context;
+use std::collections::{hash_map, HashMap, HashSet};
 use std::fmt;
 
 pub enum Arity {
     None,
     Exactly(usize),
     AtLeast(usize),
+    /// Between `min` and `max` arguments (inclusive), see `Function::DEFAULTS`.
+    Range(usize, usize),
 }
 
 impl Arity {
-    pub fn check(&self, args: &[ast::Expr]) -> Result<(), Error> {
+    pub fn check(&self, args: &[ast::Expr], defaults: &[DefaultArg]) -> Result<(), Error> {
         match (self, args.len()) {
             (Arity::None, 0) => Ok(()),
             (Arity::Exactly(n), l) if l == *n => Ok(()),
             (Arity::AtLeast(n), l) if l >= *n => Ok(()),
+            (Arity::Range(min, max), l) if l >= *min && l <= *max => Ok(()),
+            (Arity::Range(min, _), l) => {
+                let defaults: Vec<_> = defaults.iter().map(ToString::to_string).collect();
+                let optional = match min {
+                    0 => "all arguments are optional".to_owned(),
+                    1 => "arguments after the first are optional".to_owned(),
+                    n => format!("arguments after the first {} are optional", n),
+                };
+                Err(Error::TypeError(format!(
+                    "Incorrect arguments, expected: {}, found {} ({}, defaulting to {})",
+                    self,
+                    l,
+                    optional,
+                    defaults.join(", ")
+                )))
+            }
             (_, l) => Err(Error::TypeError(format!(
                 "Incorrect arguments, expected: {}, found {}",
                 self, l
             ))),
         }
     }
+
+    pub fn optional(&self) -> usize {
+        match self {
+            Arity::Range(min, max) => max - min,
+            _ => 0,
+        }
+    }
+
+    fn fill_defaults(&self, args: &mut Vec<ast::Expr>, defaults: &[DefaultArg], ctx: &Context) {
+        if let Arity::Range(min, max) = *self {
+            for default in &defaults[args.len() - min..max - min] {
+                args.push(default.expr(ctx));
+            }
+        }
+    }
 }
 
 impl fmt::Display for Arity {
@@ -30,20 +73,201 @@ impl fmt::Display for Arity {
             Arity::None => write!(f, "0"),
             Arity::Exactly(n) => n.fmt(f),
             Arity::AtLeast(n) => write!(f, "{} or more", n),
+            Arity::Range(min, max) if max - min == 1 => write!(f, "{} or {}", min, max),
+            Arity::Range(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+/// The value of an optional argument which is omitted.
+#[derive(Clone, Copy, Debug)]
+pub enum DefaultArg {
+    Number(usize),
+    String(&'static str),
+}
+
+impl DefaultArg {
+    fn expr(&self, ctx: &Context) -> ast::Expr {
+        let kind = match *self {
+            DefaultArg::Number(n) => ast::ExprKind::Number(n),
+            DefaultArg::String(s) => ast::ExprKind::String(s.to_owned()),
+        };
+        ast::Expr {
+            kind,
+            ctx: ctx.clone(),
+        }
+    }
+}
+
+impl fmt::Display for DefaultArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefaultArg::Number(n) => n.fmt(f),
+            DefaultArg::String(s) => write!(f, "\"{}\"", s),
         }
     }
 }
 
-pub trait Function {
+type EvalFn<Env> = for<'a> fn(&mut Interpreter<'a, Env>, ast::Apply) -> Result<Value, Error>;
+type TyFn<Env> = for<'a> fn(&mut Interpreter<'a, Env>, &ast::Apply) -> Result<Type, Error>;
+
+/// A function which can be called by name.
+pub struct Entry<Env: Environment> {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// The backend capabilities the function requires.
+    pub requires: &'static [Capability],
+    pub eval: EvalFn<Env>,
+    pub ty: TyFn<Env>,
+}
+
+/// All functions which can be called from the language.
+pub struct Registry<Env: Environment> {
+    functions: HashMap<&'static str, Entry<Env>>,
+}
+
+impl<Env: Environment> Registry<Env> {
+    pub fn new() -> Registry<Env> {
+        let mut result = Registry {
+            functions: HashMap::new(),
+        };
+        result.register::<Show>();
+        result.register::<Select>();
+        result.register::<All>();
+        result.register::<Pick>();
+        result.register::<Flatten>();
+        result.register::<Changes>();
+        result.register::<Map>();
+        result.register::<Group>();
+        result.register::<Idents>();
+        result.register::<Definition>();
+        result.register::<Decl>();
+        result.register::<DefIdent>();
+        result.register::<Outline>();
+        result.register::<Enclosing>();
+        result.register::<Diagnostics>();
+        result.register::<Kind>();
+        result.register::<NoMacro>();
+        result.register::<Name>();
+        result.register::<Contains>();
+        result.register::<Starts>();
+        result.register::<Matches>();
+        result.register::<Within>();
+        result.register::<Overlaps>();
+        result.register::<TypeOf>();
+        result.register::<Info>();
+        result.register::<Supertraits>();
+        result.register::<Subtraits>();
+        result.register::<Fields>();
+        result.register::<Methods>();
+        result.register::<Start>();
+        result.register::<End>();
+        result.register::<File>();
+        result.register::<Crate>();
+        result.register::<Mod>();
+        result.register::<At>();
+        result.register::<Expand>();
+        result.register::<Before>();
+        result.register::<After>();
+        result.register::<Changed>();
+        result.register::<Graph>();
+        result.register::<Export>();
+        result.register::<Grep>();
+        result.register::<Dups>();
+        result.register::<Unsafes>();
+        result.register::<Tests>();
+        result.register::<Derives>();
+        result
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Entry<Env>> {
+        self.functions.get(name)
+    }
+
+    /// All registered functions, sorted by name.
+    pub fn functions(&self) -> Vec<&Entry<Env>> {
+        let mut result: Vec<_> = self.functions.values().collect();
+        result.sort_by_key(|e| e.name);
+        result
+    }
+
+    fn register<F: Function>(&mut self) {
+        debug_assert_eq!(
+            F::DEFAULTS.len(),
+            F::ARITY.optional(),
+            "`{}` must have a default for each optional argument",
+            F::NAME
+        );
+        self.functions.insert(
+            F::NAME,
+            Entry {
+                name: F::NAME,
+                help: F::HELP,
+                requires: F::REQUIRES,
+                eval: eval_fn::<F, Env>,
+                ty: ty_fn::<F, Env>,
+            },
+        );
+    }
+}
+
+fn eval_fn<F: Function, Env: Environment>(
+    interpreter: &mut Interpreter<'_, Env>,
+    mut apply: ast::Apply,
+) -> Result<Value, Error> {
+    let fun = F::default();
+    F::ARITY.check(&apply.args, F::DEFAULTS)?;
+    F::ARITY.fill_defaults(&mut apply.args, F::DEFAULTS, &apply.ctx);
+    check_capabilities(F::NAME, F::REQUIRES, interpreter.env.capabilities())?;
+    let ty = fun.ty(interpreter, &apply.lhs, &apply.args)?;
+    fun.eval(interpreter, *apply.lhs, apply.args, ty)
+}
+
+fn ty_fn<F: Function, Env: Environment>(
+    interpreter: &mut Interpreter<'_, Env>,
+    apply: &ast::Apply,
+) -> Result<Type, Error> {
+    let fun = F::default();
+    F::ARITY.check(&apply.args, F::DEFAULTS)?;
+    let mut args = apply.args.clone();
+    F::ARITY.fill_defaults(&mut args, F::DEFAULTS, &apply.ctx);
+    check_capabilities(F::NAME, F::REQUIRES, interpreter.env.capabilities())?;
+    fun.ty(interpreter, &apply.lhs, &args)
+}
+
+pub fn check_capabilities(
+    name: &'static str,
+    required: &[Capability],
+    available: Capabilities,
+) -> Result<(), Error> {
+    match required.iter().find(|c| !available.contains(**c)) {
+        Some(c) => Err(Error::Unsupported(name, *c)),
+        None => Ok(()),
+    }
+}
+
+pub trait Function: Default {
     const NAME: &'static str;
     const ARITY: Arity;
+    const HELP: &'static str;
+    const REQUIRES: &'static [Capability] = &[];
+    /// The values of omitted optional arguments, in order.
+    const DEFAULTS: &'static [DefaultArg] = &[];
 
     fn eval(
         &self,
         interpreter: &mut Interpreter<'_, impl Environment>,
-        lhs: Box<ast::Expr>,
+        lhs: ast::Expr,
         args: Vec<ast::Expr>,
-    ) -> Result<Value, Error>;
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let args = args
+            .into_iter()
+            .map(|a| interpreter.interpret_expr(a.kind))
+            .collect::<Result<_, _>>()?;
+        let lhs = interpreter.interpret_expr(lhs.kind)?;
+        lift::<Self>(lhs, args, ty)
+    }
 
     fn ty(
         &self,
@@ -51,23 +275,60 @@ pub trait Function {
         lhs: &ast::Expr,
         args: &[ast::Expr],
     ) -> Result<Type, Error>;
+
+    /// `ty` is the type of the result.
+    fn apply(_lhs: Value, _args: &[Value], _ty: &Type) -> Result<Value, Error> {
+        Err(Error::Other(format!(
+            "`{}` can only be applied to a query",
+            Self::NAME
+        )))
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        Self::apply(f.lhs.eval(ctx)?, &f.args, &f.ty)
+    }
+
+    fn lazy(lhs: Query, args: Vec<Value>, ty: Type) -> Query {
+        Query::Function(Fun {
+            eval: Self::eval_query,
+            ty,
+            lhs: Box::new(lhs),
+            args,
+        })
+    }
+}
+
+fn lift<F: Function>(lhs: Value, args: Vec<Value>, ty: Type) -> Result<Value, Error> {
+    let lhs = flatten_lhs(lhs);
+    if ty.is_query() {
+        Ok(Value {
+            kind: ValueKind::Query(F::lazy(lhs.into(), args, ty.unquery())),
+            ty,
+            label: None,
+        })
+    } else {
+        F::apply(lhs, &args, &ty)
+    }
 }
 
+#[derive(Default)]
 pub struct Show {}
 
 impl Function for Show {
     const NAME: &'static str = "show";
     const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "print a value";
 
     fn eval(
         &self,
         interpreter: &mut Interpreter<'_, impl Environment>,
-        lhs: Box<ast::Expr>,
+        lhs: ast::Expr,
         _: Vec<ast::Expr>,
+        _: Type,
     ) -> Result<Value, Error> {
         let lhs = interpreter.interpret_expr(lhs.kind)?;
         if lhs.ty.is_query() {
-            let value = lhs.expect_query().eval(&*interpreter.env.backend())?;
+            let value = eval_query(&lhs.expect_query(), interpreter.env)?;
             interpreter.env.show(&value)?;
         } else {
             interpreter.env.show(&lhs)?;
@@ -85,21 +346,24 @@ impl Function for Show {
     }
 }
 
+#[derive(Default)]
 pub struct Select {}
 
 impl Function for Select {
     const NAME: &'static str = "select";
     const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "evaluate a query";
 
     fn eval(
         &self,
         interpreter: &mut Interpreter<'_, impl Environment>,
-        lhs: Box<ast::Expr>,
+        lhs: ast::Expr,
         _: Vec<ast::Expr>,
+        _: Type,
     ) -> Result<Value, Error> {
         let lhs = interpreter.interpret_expr(lhs.kind)?;
         match &lhs.kind {
-            ValueKind::Query(q) => q.eval(&*interpreter.env.backend()),
+            ValueKind::Query(q) => eval_query(q, interpreter.env),
             _ => Err(Error::TypeError(format!(
                 "Expected query, found {:?}",
                 lhs.ty
@@ -120,29 +384,91 @@ impl Function for Select {
     }
 }
 
+#[derive(Default)]
+pub struct All {}
+
+impl Function for All {
+    const NAME: &'static str = "all";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "a query which is evaluated without the result limit";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let lhs = interpreter.interpret_expr(lhs.kind)?;
+        Ok(Value {
+            kind: ValueKind::Query(query::Query::unlimited(lhs.expect_query())),
+            ty,
+            label: None,
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        match interpreter.type_expr(&lhs.kind)? {
+            ty @ Type::Query(_) => Ok(ty),
+            ty => Err(Error::TypeError(format!("Expected query, found {:?}", ty))),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct Pick {}
 
 impl Function for Pick {
     const NAME: &'static str = "pick";
     const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "pick one element from a set";
 
     fn eval(
         &self,
         interpreter: &mut Interpreter<'_, impl Environment>,
-        lhs: Box<ast::Expr>,
+        lhs: ast::Expr,
         _: Vec<ast::Expr>,
+        _: Type,
     ) -> Result<Value, Error> {
         let lhs = interpreter.interpret_expr(lhs.kind)?;
         match &lhs.kind {
+            // The user can only choose from a set they can see.
+            ValueKind::Query(q) if interpreter.env.can_prompt() => {
+                let ty = lhs.ty.unquery().expect_set_inner();
+                let value = eval_query(q, interpreter.env)?;
+                let mut value = &value;
+                while let ValueKind::Partial(v) = &value.kind {
+                    value = v;
+                }
+                let chosen = match &value.kind {
+                    ValueKind::Set(vs) => choose(interpreter.env, vs)?,
+                    _ => {
+                        return Err(Error::TypeError(format!(
+                            "Expected set, found {:?}",
+                            value.ty
+                        )))
+                    }
+                };
+                Ok(Value {
+                    kind: ValueKind::Query(Query::ready(chosen)),
+                    ty: Type::Query(Box::new(ty)),
+                    label: None,
+                })
+            }
             ValueKind::Query(_) => {
                 let ty = lhs.ty.unquery().expect_set_inner();
                 Ok(Value {
-                    kind: ValueKind::Query(query::Pick::new(lhs.into(), ty.clone())),
+                    kind: ValueKind::Query(Pick::lazy(lhs.into(), vec![], ty.clone())),
                     ty: Type::Query(Box::new(ty)),
+                    label: None,
                 })
             }
-            ValueKind::Set(vs) if vs.is_empty() => Err(Error::EmptySet),
-            ValueKind::Set(vs) => Ok(vs[0].clone()),
+            ValueKind::Set(vs) => choose(interpreter.env, vs),
             _ => Err(Error::TypeError(format!(
                 "Expected set, found {:?}",
                 lhs.ty
@@ -173,25 +499,44 @@ impl Function for Pick {
             Ok(inner)
         }
     }
+
+    // The first element, the user can't be asked when the query is evaluated.
+    fn apply(lhs: Value, _: &[Value], _: &Type) -> Result<Value, Error> {
+        match lhs.kind {
+            ValueKind::Set(s) => s.into_iter().next().ok_or(Error::EmptySet("pick")),
+            _ => Err(Error::TypeError(format!(
+                "Unexpected runtime type, expected: set, found: {:?}",
+                lhs.ty
+            ))),
+        }
+    }
 }
 
-pub struct Idents {}
+#[derive(Default)]
+pub struct Flatten {}
 
-impl Function for Idents {
-    const NAME: &'static str = "idents";
+impl Function for Flatten {
+    const NAME: &'static str = "flatten";
     const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "flatten a set of sets into a set";
 
     fn eval(
         &self,
         interpreter: &mut Interpreter<'_, impl Environment>,
-        lhs: Box<ast::Expr>,
+        lhs: ast::Expr,
         _: Vec<ast::Expr>,
+        ty: Type,
     ) -> Result<Value, Error> {
+        // The lhs is not flattened first, as it is for most functions.
         let lhs = interpreter.interpret_expr(lhs.kind)?;
-        Ok(Value {
-            kind: ValueKind::Query(query::Idents::new(lhs.into())),
-            ty: Type::Query(Box::new(Type::Set(Box::new(Type::Identifier)))),
-        })
+        match lhs.kind {
+            ValueKind::Query(q) => Ok(Value {
+                kind: ValueKind::Query(Flatten::lazy(q, vec![], ty.unquery())),
+                ty,
+                label: None,
+            }),
+            _ => Ok(lhs.flatten()),
+        }
     }
 
     fn ty(
@@ -201,34 +546,365 @@ impl Function for Idents {
         _: &[ast::Expr],
     ) -> Result<Type, Error> {
         let ty_lhs = interpreter.type_expr(&lhs.kind)?;
-        if !ty_lhs.is_location() {
+        ty_lhs
+            .flatten()
+            .ok_or_else(|| Error::TypeError(format!("Expected set of sets, found {:?}", ty_lhs)))
+    }
+
+    // `ty` is the type of the flattened set.
+    fn apply(lhs: Value, _: &[Value], ty: &Type) -> Result<Value, Error> {
+        match lhs.kind {
+            ValueKind::Set(sets) => Ok(Value {
+                kind: ValueKind::Set(sets),
+                ty: Type::Set(Box::new(ty.clone())),
+                label: None,
+            }
+            .flatten()),
+            _ => Err(Error::TypeError(format!(
+                "Unexpected runtime type, expected: set, found: {:?}",
+                lhs.ty
+            ))),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Changes {}
+
+impl Function for Changes {
+    const NAME: &'static str = "changes";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str =
+        "the elements added to and removed from a set to give another, e.g., `$1->changes $2`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        mut args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let old = eval_set(interpreter, lhs)?;
+        let new = eval_set(interpreter, args.remove(0))?;
+        Ok(Value {
+            kind: ValueKind::Changes(data::Changes::new(&old, &new)),
+            ty,
+            label: None,
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?.unquery();
+        let ty_arg = interpreter.type_expr(&args[0].kind)?.unquery();
+        for ty in &[&ty_lhs, &ty_arg] {
+            match ty {
+                // An empty set.
+                Type::Void | Type::Set(_) => {}
+                _ => return Err(Error::TypeError(format!("Expected set, found {:?}", ty))),
+            }
+        }
+        match types::unify(&ty_lhs, &ty_arg) {
+            Some(Type::Set(ty)) => Ok(Type::Changes(ty)),
+            Some(_) => Ok(Type::Changes(Box::new(Type::Void))),
+            None => Err(Error::TypeError(format!(
+                "Can't compare sets of different types, found {} and {}",
+                ty_lhs, ty_arg
+            ))),
+        }
+    }
+}
+
+// The placeholder for each element in the expression applied by `map`.
+const MAP_ELEM: &str = "_";
+
+#[derive(Default)]
+pub struct Map {}
+
+impl Function for Map {
+    const NAME: &'static str = "map";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str =
+        "apply an expression to each element of a set, e.g., `$->map _->def` or `$->map |x| x->def`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        mut args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let (var, body) = map_template(interpreter, args.remove(0));
+        // Each result is evaluated, so that the results form a set.
+        let elements = eval_set(interpreter, lhs)?
+            .into_iter()
+            .map(|elem| {
+                let value = interpreter.interpret_bound(var.clone(), elem, body.clone())?;
+                if value.ty.is_query() {
+                    eval_query(&value.expect_query(), interpreter.env)
+                } else {
+                    Ok(value)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Value::set(elements, ty.expect_set_inner())
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let elem = match interpreter.type_expr(&lhs.kind)?.unquery() {
+            Type::Set(elem) => *elem,
+            // An empty set.
+            Type::Void => Type::Void,
+            ty => return Err(Error::TypeError(format!("Expected set, found {:?}", ty))),
+        };
+        let (var, body) = map_template(interpreter, args[0].clone());
+        let ty = interpreter.type_bound(var, elem, &body)?;
+        Ok(Type::Set(Box::new(ty.unquery())))
+    }
+}
+
+// The parameter and body of a lambda, or `_` and the whole expression.
+fn map_template(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    arg: ast::Expr,
+) -> (MetaVar, ast::ExprKind) {
+    let lambda = match &arg.kind {
+        ast::ExprKind::Lambda(l) => Some(l.clone()),
+        ast::ExprKind::MetaVar(kind @ ast::MetaVarKind::Named(_)) => {
+            match interpreter.lookup_var(kind) {
+                Ok(Value {
+                    kind: ValueKind::Fn(l),
+                    ..
+                }) => Some(l),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    match lambda {
+        Some(l) => (MetaVar::new(&l.param.name), l.body.kind),
+        None => (MetaVar::new(MAP_ELEM), arg.kind),
+    }
+}
+
+#[derive(Default)]
+pub struct Group {}
+
+impl Function for Group {
+    const NAME: &'static str = "group";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str =
+        "count the elements of a set by their `file`, `kind`, or `name`, e.g., `group \"file\"`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        mut args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let key = match interpreter.interpret_expr(args.remove(0).kind)?.kind {
+            ValueKind::String(s) => GroupKey::parse(&s)?,
+            _ => return Err(Error::TypeError("Expected string".to_owned())),
+        };
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for elem in eval_set(interpreter, lhs)? {
+            key.count(elem, interpreter.env.file_system(), &mut counts)?;
+        }
+        Ok(Value {
+            kind: ValueKind::Map(
+                counts
+                    .into_iter()
+                    .map(|(k, n)| (Value::string(k), Value::number(n)))
+                    .collect(),
+            ),
+            ty,
+            label: None,
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_arg = interpreter.type_expr(&args[0].kind)?;
+        if !types::is_subtype(&ty_arg, &Type::String) {
             return Err(Error::TypeError(format!(
-                "Expected location, found {:?}",
-                ty_lhs
+                "Expected string, found {:?}",
+                ty_arg
             )));
         }
+        match interpreter.type_expr(&lhs.kind)?.unquery() {
+            // An empty set.
+            Type::Void | Type::Set(_) => {}
+            ty => return Err(Error::TypeError(format!("Expected set, found {:?}", ty))),
+        }
+        Ok(Type::Map(Box::new(Type::String), Box::new(Type::Number)))
+    }
+}
 
-        Ok(Type::Query(Box::new(Type::Set(Box::new(Type::Identifier)))))
+// What `group` counts elements by.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum GroupKey {
+    File,
+    Kind,
+    Name,
+}
+
+impl GroupKey {
+    fn parse(s: &str) -> Result<GroupKey, Error> {
+        match s {
+            "file" => Ok(GroupKey::File),
+            "kind" => Ok(GroupKey::Kind),
+            "name" => Ok(GroupKey::Name),
+            _ => Err(Error::Other(format!(
+                "Unknown key for `group`: `{}`, expected `file`, `kind`, or `name`",
+                s
+            ))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            GroupKey::File => "file",
+            GroupKey::Kind => "kind",
+            GroupKey::Name => "name",
+        }
+    }
+
+    fn count(
+        self,
+        elem: Value,
+        fs: &impl FileSystem,
+        counts: &mut Vec<(String, usize)>,
+    ) -> Result<(), Error> {
+        let key = match (self, elem.kind) {
+            (_, ValueKind::Set(vs)) => {
+                for v in vs {
+                    self.count(v, fs, counts)?;
+                }
+                return Ok(());
+            }
+            (_, ValueKind::Partial(v)) => return self.count(*v, fs, counts),
+            (GroupKey::Kind, ValueKind::Definition(def)) => def.kind.to_string(),
+            (GroupKey::Name, ValueKind::Identifier(id)) => id.name,
+            (GroupKey::Name, ValueKind::Definition(def)) => def.name,
+            (GroupKey::Name, ValueKind::String(s)) => s,
+            (GroupKey::File, kind) => {
+                let elem = Value {
+                    kind,
+                    ty: elem.ty,
+                    label: None,
+                };
+                match elem.file() {
+                    Some(file) => fs.path_name(file),
+                    None => {
+                        return Err(Error::TypeError(format!(
+                            "Can't group by file, {} is not in a single file",
+                            elem.ty
+                        )))
+                    }
+                }
+            }
+            (_, _) => {
+                return Err(Error::TypeError(format!(
+                    "Can't group {} by {}",
+                    elem.ty,
+                    self.name()
+                )))
+            }
+        };
+        match counts.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((key, 1)),
+        }
+        Ok(())
     }
 }
 
-pub struct Definition {}
+fn eval_set(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    expr: ast::Expr,
+) -> Result<Vec<Value>, Error> {
+    let value = interpreter.interpret_expr(expr.kind)?;
+    let value = if value.ty.is_query() {
+        eval_query(&value.expect_query(), interpreter.env)?
+    } else {
+        value
+    };
+    match value.strip_partial().kind {
+        ValueKind::Set(vs) => Ok(vs),
+        ValueKind::Void => Ok(Vec::new()),
+        _ => Err(Error::TypeError("Expected set".to_owned())),
+    }
+}
 
-impl Function for Definition {
-    const NAME: &'static str = "def";
+// Sets of sets (e.g., the results of several queries) are flattened.
+fn flatten_lhs(mut lhs: Value) -> Value {
+    while let Some(ty) = lhs.ty.flatten() {
+        lhs = match lhs.kind {
+            ValueKind::Query(q) => Value {
+                kind: ValueKind::Query(Flatten::lazy(q, vec![], ty.unquery())),
+                ty,
+                label: None,
+            },
+            kind => Value {
+                kind,
+                ty: lhs.ty,
+                label: None,
+            }
+            .flatten(),
+        };
+    }
+    lhs
+}
+
+#[derive(Default)]
+pub struct Idents {}
+
+impl Function for Idents {
+    const NAME: &'static str = "idents";
     const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the identifiers in a location";
+    const REQUIRES: &'static [Capability] = &[Capability::Idents];
 
     fn eval(
         &self,
         interpreter: &mut Interpreter<'_, impl Environment>,
-        lhs: Box<ast::Expr>,
+        lhs: ast::Expr,
         _: Vec<ast::Expr>,
+        _: Type,
     ) -> Result<Value, Error> {
-        let lhs = interpreter.interpret_expr(lhs.kind)?;
-        Ok(Value {
-            kind: ValueKind::Query(query::Definition::new(lhs.clone().into(), lhs.ty.clone())),
-            ty: lhs.ty,
-        })
+        let lhs = flatten_lhs(interpreter.interpret_expr(lhs.kind)?);
+        let ty = Type::Query(Box::new(Type::Set(Box::new(Type::Identifier))));
+        if let ValueKind::Position(p) = &lhs.kind {
+            if interpreter.env.chooses_idents() {
+                // Choose now, rather than when the query is evaluated.
+                let idents = idents_at(interpreter, p.clone())?;
+                let idents = Value {
+                    kind: ValueKind::Set(idents.into_iter().map(Into::into).collect()),
+                    ty: ty.unquery(),
+                    label: None,
+                };
+                return Ok(Value {
+                    kind: ValueKind::Query(Query::ready(idents)),
+                    ty,
+                    label: None,
+                });
+            }
+        }
+        lift::<Self>(lhs, vec![], ty)
     }
 
     fn ty(
@@ -238,15 +914,2364 @@ impl Function for Definition {
         _: &[ast::Expr],
     ) -> Result<Type, Error> {
         let ty_lhs = interpreter.type_expr(&lhs.kind)?;
-        match ty_lhs.unquery() {
-            Type::Identifier => Ok(Type::Query(Box::new(Type::Definition))),
-            Type::Set(ref inner) if &**inner == &Type::Identifier => {
-                Ok(Type::Query(Box::new(Type::Set(Box::new(Type::Definition)))))
+        if types::coerce(&ty_lhs, &Type::Set(Box::new(Type::Location))).is_none() {
+            return Err(Error::TypeError(format!(
+                "Expected location, found {:?}",
+                ty_lhs
+            )));
+        }
+
+        Ok(Type::Query(Box::new(Type::Set(Box::new(Type::Identifier)))))
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        let lhs = f.lhs.eval(ctx)?.coerce_to_location();
+        let idents = match lhs.kind {
+            ValueKind::Position(p) => ctx.back.idents_at(p.clone())?,
+            // A range may cover many files.
+            ValueKind::Range(r) => ctx
+                .back
+                .idents_in_many(&[r], ctx.progress)?
+                .into_iter()
+                .flatten()
+                .collect(),
+            ValueKind::Set(locs) => {
+                let mut idents = Vec::new();
+                let mut ranges = Vec::new();
+                for loc in locs {
+                    if ctx.out_of_time() {
+                        break;
+                    }
+                    let loc = loc.coerce_to_location();
+                    match loc.kind {
+                        ValueKind::Position(p) => idents.extend(ctx.back.idents_at(p)?),
+                        ValueKind::Range(r) => ranges.push(r),
+                        _ => {
+                            return Err(Error::TypeError(format!(
+                                "Unexpected runtime type, expected: location, found: {:?}",
+                                loc.ty
+                            )))
+                        }
+                    }
+                }
+                if !ranges.is_empty() && !ctx.out_of_time() {
+                    idents.extend(
+                        ctx.back
+                            .idents_in_many(&ranges, ctx.progress)?
+                            .into_iter()
+                            .flatten(),
+                    );
+                }
+                idents
             }
-            _ => Err(Error::TypeError(format!(
+            _ => {
+                return Err(Error::TypeError(format!(
+                    "Unexpected runtime type, expected: location, found: {:?}",
+                    lhs.ty
+                )))
+            }
+        };
+
+        Value::set(
+            idents
+                .into_iter()
+                .filter(|i| !(ctx.hide_macros && i.from_macro))
+                .map(|i| Value {
+                    kind: ValueKind::Identifier(i),
+                    ty: Type::Identifier,
+                    label: None,
+                })
+                .collect(),
+            Type::Identifier,
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Definition {}
+
+impl Function for Definition {
+    const NAME: &'static str = "def";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the definition of an identifier";
+    const REQUIRES: &'static [Capability] = &[Capability::Definitions];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        match types::shape(&ty_lhs, &[Type::Identifier]) {
+            Some((shape, _)) => Ok(Type::Query(Box::new(shape.of(Type::Definition)))),
+            None => Err(Error::TypeError(format!(
                 "Expected identifier, found {:?}",
-                ty_lhs
+                ty_lhs.flatten_all()
             ))),
         }
     }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        let lhs = f.lhs.eval(ctx)?;
+        match lhs.kind {
+            ValueKind::Identifier(id) => Ok(Value {
+                label: Some(format!("def of `{}`", id.name)),
+                kind: ValueKind::Definition(ctx.back.definition(id)?),
+                ty: Type::Definition,
+            }),
+            ValueKind::Set(values) => {
+                let ids = values
+                    .into_iter()
+                    .map(|v| match v.kind {
+                        ValueKind::Identifier(id) => Ok(id),
+                        _ => Err(Error::TypeError(format!(
+                            "Unexpected runtime type, expected: identifier, found: {:?}",
+                            v.ty
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // Look up all the definitions in a single request.
+                let defs = ctx.back.definitions(&ids, ctx.progress)?;
+                let mut result: Vec<(data::Definition, data::Identifier)> = Vec::new();
+                for (d, id) in defs.into_iter().zip(ids) {
+                    if !result.iter().any(|(r, _)| r.same_as(&d)) {
+                        result.push((d, id));
+                    }
+                }
+                Value::set(
+                    result
+                        .into_iter()
+                        .map(|(d, id)| Value {
+                            kind: ValueKind::Definition(d),
+                            ty: Type::Definition,
+                            label: Some(format!("def of `{}`", id.name)),
+                        })
+                        .collect(),
+                    Type::Definition,
+                )
+            }
+            _ => Err(Error::TypeError(format!(
+                "Unexpected runtime type, expected: identifier, found: {:?}",
+                lhs.ty
+            ))),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Decl {}
+
+impl Function for Decl {
+    const NAME: &'static str = "decl";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str =
+        "the identifier which declares what an identifier refers to, e.g., `foo` in `fn foo`";
+    const REQUIRES: &'static [Capability] = &[Capability::Idents, Capability::Definitions];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_declaration(interpreter, lhs, Type::Identifier)
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        query::Declaration::Decl.eval(f, ctx)
+    }
+}
+
+#[derive(Default)]
+pub struct DefIdent {}
+
+impl Function for DefIdent {
+    const NAME: &'static str = "ident";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the identifier which names a definition where it is declared";
+    const REQUIRES: &'static [Capability] = &[Capability::Idents, Capability::Definitions];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_declaration(interpreter, lhs, Type::Definition)
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        query::Declaration::Ident.eval(f, ctx)
+    }
+}
+
+// The type of `decl` or `ident`, whose lhs is an `elem` or a set of them.
+fn type_declaration(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: &ast::Expr,
+    elem: Type,
+) -> Result<Type, Error> {
+    let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+    match types::shape(&ty_lhs, std::slice::from_ref(&elem)) {
+        Some((shape, _)) => Ok(Type::Query(Box::new(shape.of(Type::Identifier)))),
+        None => Err(Error::TypeError(format!(
+            "Expected {}, found {:?}",
+            elem,
+            ty_lhs.flatten_all()
+        ))),
+    }
+}
+
+#[derive(Default)]
+pub struct TypeOf {}
+
+impl Function for TypeOf {
+    const NAME: &'static str = "typeof";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the type of an identifier, e.g., `foo: Vec<String>`";
+    const REQUIRES: &'static [Capability] = &[Capability::Idents, Capability::Types];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let mut lhs = flatten_lhs(interpreter.interpret_expr(lhs.kind)?);
+        if let ValueKind::Position(p) = &lhs.kind {
+            if interpreter.env.chooses_idents() {
+                let mut idents = idents_at(interpreter, p.clone())?;
+                if idents.len() == 1 {
+                    lhs = idents.pop().unwrap().into();
+                }
+            }
+        }
+        lift::<Self>(lhs, vec![], ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_ident_summary(interpreter, lhs, Type::String)
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        each_in_time(f.lhs.eval(ctx)?, Type::String, ctx, |v| {
+            let id = ident_of(v, ctx.back)?;
+            let ty = ctx.back.type_of(id.clone())?;
+            Ok(Value::string(format!("{}: {}", id.name, ty)))
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Info {}
+
+impl Function for Info {
+    const NAME: &'static str = "info";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str =
+        "a summary of an identifier: its type, definition, and the first line of its docs";
+    const REQUIRES: &'static [Capability] = &[Capability::Idents, Capability::Definitions];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let mut lhs = flatten_lhs(interpreter.interpret_expr(lhs.kind)?);
+        if let ValueKind::Position(p) = &lhs.kind {
+            if interpreter.env.chooses_idents() {
+                let mut idents = idents_at(interpreter, p.clone())?;
+                if idents.len() == 1 {
+                    lhs = idents.pop().unwrap().into();
+                }
+            }
+        }
+        lift::<Self>(lhs, vec![], ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_ident_summary(interpreter, lhs, Type::Info)
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        each_in_time(f.lhs.eval(ctx)?, Type::Info, ctx, |v| {
+            ident_info(v, ctx.back)
+        })
+    }
+}
+
+// `typeof` and `info` take an identifier or position, or a set of identifiers.
+fn type_ident_summary(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: &ast::Expr,
+    result: Type,
+) -> Result<Type, Error> {
+    let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+    match types::shape(&ty_lhs, &[Type::Identifier, Type::Location]) {
+        Some((shape, elem)) if shape == Shape::Single || elem == Type::Identifier => {
+            Ok(Type::Query(Box::new(shape.of(result))))
+        }
+        _ => Err(Error::TypeError(format!(
+            "Expected identifier or position, found {:?}",
+            ty_lhs.flatten_all()
+        ))),
+    }
+}
+
+// Parts which the backend can't find are left out.
+fn ident_info(value: Value, back: &dyn Backend) -> Result<Value, Error> {
+    let ident = ident_of(value, back)?;
+    let caps = back.capabilities();
+    let def = back.definition(ident.clone()).ok();
+    let ty = if caps.contains(Capability::Types) {
+        back.type_of(ident.clone()).ok()
+    } else {
+        None
+    };
+    let docs = match &def {
+        Some(def) if caps.contains(Capability::Docs) => back
+            .docs(def.clone())
+            .ok()
+            .and_then(|d| d.lines().next().map(|l| l.trim().to_owned()))
+            .filter(|l| !l.is_empty()),
+        _ => None,
+    };
+    Ok(Value {
+        kind: ValueKind::Info(data::Info {
+            ident,
+            def,
+            ty,
+            docs,
+        }),
+        ty: Type::Info,
+        label: None,
+    })
+}
+
+fn ident_of(value: Value, back: &dyn Backend) -> Result<data::Identifier, Error> {
+    match value.kind {
+        ValueKind::Identifier(id) => Ok(id),
+        ValueKind::Position(p) => {
+            let mut idents = back.idents_at(p)?;
+            match idents.len() {
+                0 => Err(Error::Other("No identifier at position".to_owned())),
+                1 => Ok(idents.pop().unwrap()),
+                n => {
+                    let names: Vec<_> = idents.iter().map(|i| format!("`{}`", i.name)).collect();
+                    Err(Error::Other(format!(
+                        "{} identifiers at position ({}), use `idents` to choose one",
+                        n,
+                        names.join(", ")
+                    )))
+                }
+            }
+        }
+        _ => Err(Error::TypeError(format!(
+            "Unexpected runtime type, expected: identifier or position, found: {:?}",
+            value.ty
+        ))),
+    }
+}
+
+fn each(
+    value: Value,
+    elem: Type,
+    f: impl Fn(Value) -> Result<Value, Error>,
+) -> Result<Value, Error> {
+    match value.kind {
+        ValueKind::Set(values) => {
+            Value::set(values.into_iter().map(f).collect::<Result<_, _>>()?, elem)
+        }
+        _ => f(value),
+    }
+}
+
+// Stops with the elements found so far if evaluation runs out of time.
+fn each_in_time(
+    value: Value,
+    elem: Type,
+    ctx: &EvalCtx,
+    f: impl Fn(Value) -> Result<Value, Error>,
+) -> Result<Value, Error> {
+    match value.kind {
+        ValueKind::Set(values) => {
+            let mut result = Vec::new();
+            for v in values {
+                if ctx.out_of_time() {
+                    break;
+                }
+                result.push(f(v)?);
+            }
+            Value::set(result, elem)
+        }
+        _ => f(value),
+    }
+}
+
+#[derive(Default)]
+pub struct Supertraits {}
+
+impl Function for Supertraits {
+    const NAME: &'static str = "supertraits";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the traits a trait is bounded by, e.g., `Ord` for `Ord: Eq`";
+    const REQUIRES: &'static [Capability] = &[Capability::Definitions, Capability::Traits];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_related(interpreter, lhs)
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        query::TraitHierarchy::Super.eval(f, ctx)
+    }
+}
+
+#[derive(Default)]
+pub struct Subtraits {}
+
+impl Function for Subtraits {
+    const NAME: &'static str = "subtraits";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the traits bounded by a trait, e.g., `Ord` for `Eq`";
+    const REQUIRES: &'static [Capability] = &[Capability::Definitions, Capability::Traits];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_related(interpreter, lhs)
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        query::TraitHierarchy::Sub.eval(f, ctx)
+    }
+}
+
+#[derive(Default)]
+pub struct Fields {}
+
+impl Function for Fields {
+    const NAME: &'static str = "fields";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the fields of a struct, union, or enum variant";
+    const REQUIRES: &'static [Capability] = &[Capability::Definitions, Capability::Members];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_related(interpreter, lhs)
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        query::Members::Fields.eval(f, ctx)
+    }
+}
+
+#[derive(Default)]
+pub struct Methods {}
+
+impl Function for Methods {
+    const NAME: &'static str = "methods";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the methods of a trait, or of a struct, enum, or union";
+    const REQUIRES: &'static [Capability] = &[Capability::Definitions, Capability::Members];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_related(interpreter, lhs)
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        query::Members::Methods.eval(f, ctx)
+    }
+}
+
+// E.g., a trait's supertraits, or a struct's fields.
+fn type_related(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: &ast::Expr,
+) -> Result<Type, Error> {
+    let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+    if types::shape(&ty_lhs, &[Type::Identifier, Type::Definition]).is_none() {
+        return Err(Error::TypeError(format!(
+            "Expected identifier or def, found {:?}",
+            ty_lhs.flatten_all()
+        )));
+    }
+    Ok(Type::Query(Box::new(Type::Set(Box::new(Type::Definition)))))
+}
+
+#[derive(Default)]
+pub struct Outline {}
+
+impl Function for Outline {
+    const NAME: &'static str = "outline";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the items defined in a file, as a tree";
+    const REQUIRES: &'static [Capability] = &[Capability::Symbols];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        if types::coerce(&ty_lhs, &Type::Set(Box::new(Type::Location))).is_none() {
+            return Err(Error::TypeError(format!(
+                "Expected location, found {:?}",
+                ty_lhs
+            )));
+        }
+
+        Ok(Type::Query(Box::new(Type::Tree)))
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        let lhs = f.lhs.eval(ctx)?.coerce_to_location();
+        let symbols = match lhs.kind {
+            ValueKind::Range(Range::File(p)) => ctx.back.symbols_in_file(p)?,
+            _ => {
+                return Err(Error::TypeError(format!(
+                    "Unexpected runtime type, expected: file, found: {:?}",
+                    lhs.ty
+                )))
+            }
+        };
+
+        Ok(Value {
+            kind: ValueKind::Tree(build_trees(&symbols, None)),
+            ty: Type::Tree,
+            label: None,
+        })
+    }
+}
+
+// With no `parent`, the roots are symbols whose parent is not in `symbols`.
+fn build_trees(symbols: &[Symbol], parent: Option<u64>) -> Vec<Tree> {
+    let mut result: Vec<Tree> = symbols
+        .iter()
+        .filter(|s| match parent {
+            Some(_) => s.parent == parent,
+            None => match s.parent {
+                Some(p) => !symbols.iter().any(|s| s.def.id == p),
+                None => true,
+            },
+        })
+        .map(|s| Tree {
+            def: s.def.clone(),
+            children: build_trees(symbols, Some(s.def.id)),
+        })
+        .collect();
+    result.sort_by_key(|t| (t.def.span.start_line, t.def.span.start_column));
+    result
+}
+
+#[derive(Default)]
+pub struct Enclosing {}
+
+impl Function for Enclosing {
+    const NAME: &'static str = "enclosing";
+    const ARITY: Arity = Arity::Range(0, 1);
+    const HELP: &'static str = "the innermost item of a kind (default any kind) which contains a \
+                                location, e.g., `(:foo.rs:12)->enclosing \"fn\"`";
+    const REQUIRES: &'static [Capability] = &[Capability::Symbols];
+    const DEFAULTS: &'static [DefaultArg] = &[DefaultArg::String("")];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        mut args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let kind = match interpreter.interpret_expr(args.remove(0).kind)?.kind {
+            ValueKind::String(s) => s,
+            _ => return Err(Error::TypeError("Expected string".to_owned())),
+        };
+        // Check the kind before anything is evaluated.
+        if !kind.is_empty() {
+            DefKind::parse(&kind)?;
+        }
+        let lhs = interpreter.interpret_expr(lhs.kind)?;
+        lift::<Self>(lhs, vec![Value::string(kind)], ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        for arg in args {
+            let ty = interpreter.type_expr(&arg.kind)?;
+            if !types::is_subtype(&ty, &Type::String) {
+                return Err(Error::TypeError(format!("Expected string, found {}", ty)));
+            }
+        }
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        // Anything with a span will do.
+        let elems = [
+            Type::Location,
+            Type::Identifier,
+            Type::Definition,
+            Type::Diagnostic,
+        ];
+        if types::shape(&ty_lhs, &elems).is_none() {
+            return Err(Error::TypeError(format!(
+                "Expected location, found {}",
+                ty_lhs.flatten_all()
+            )));
+        }
+        Ok(Type::Query(Box::new(Type::Set(Box::new(Type::Definition)))))
+    }
+
+    // The item enclosing a definition is its parent, not itself.
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        let kind = f.args[0].clone().expect_string();
+        let kind = if kind.is_empty() {
+            None
+        } else {
+            Some(DefKind::parse(&kind)?)
+        };
+        let lhs = f.lhs.eval(ctx)?;
+        let values = match lhs.kind {
+            ValueKind::Set(values) => values,
+            _ => vec![lhs],
+        };
+
+        let mut items: HashMap<Path, Vec<(data::Definition, Extent)>> = HashMap::new();
+        let mut result: Vec<Value> = Vec::new();
+        for v in values {
+            if ctx.out_of_time() {
+                break;
+            }
+            let (file, extent) = match v.extent() {
+                Some(Extent::Text(file, start, end)) => (file, Extent::Text(file, start, end)),
+                // A whole file is not inside any item.
+                Some(Extent::Files(_)) => continue,
+                None => {
+                    return Err(Error::TypeError(format!(
+                        "Unexpected runtime type, expected: location, found: {:?}",
+                        v.ty
+                    )))
+                }
+            };
+            let items = match items.entry(file) {
+                hash_map::Entry::Occupied(e) => e.into_mut(),
+                hash_map::Entry::Vacant(e) => {
+                    e.insert(item_extents(ctx.back.symbols_in_file(file)?))
+                }
+            };
+            let own = match &v.kind {
+                ValueKind::Definition(d) => Some(d.id),
+                _ => None,
+            };
+            // The innermost item starts last.
+            let enclosing = items.iter().rev().find(|(d, e)| {
+                Some(d.id) != own && (kind.is_none() || kind == Some(d.kind)) && extent.within(e)
+            });
+            if let Some((d, _)) = enclosing {
+                let found = result.iter().any(|r| match &r.kind {
+                    ValueKind::Definition(r) => r.same_as(d),
+                    _ => false,
+                });
+                if !found {
+                    result.push(Value {
+                        kind: ValueKind::Definition(d.clone()),
+                        ty: Type::Definition,
+                        label: None,
+                    });
+                }
+            }
+        }
+        Value::set(result, Type::Definition)
+    }
+}
+
+// The backend only gives the span of an item's name, so an item extends to the
+// next item which is not nested in it. Locals and fields are only their name.
+fn item_extents(mut symbols: Vec<Symbol>) -> Vec<(data::Definition, Extent)> {
+    symbols.sort_by_key(|s| (s.def.span.start_line, s.def.span.start_column));
+    let parents: HashMap<u64, u64> = symbols
+        .iter()
+        .filter_map(|s| Some((s.def.id, s.parent?)))
+        .collect();
+    let nested = |mut id: u64, ancestor: u64| {
+        // Bounded, in case the backend's parents have a cycle.
+        for _ in 0..parents.len() {
+            match parents.get(&id) {
+                Some(p) if *p == ancestor => return true,
+                Some(p) => id = *p,
+                None => return false,
+            }
+        }
+        false
+    };
+    let end_of_file = (
+        Line::new_zero_indexed(usize::MAX),
+        Column::new_zero_indexed(0),
+    );
+    symbols
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            let span = &s.def.span;
+            if let DefKind::Local | DefKind::Field = s.def.kind {
+                return (s.def.clone(), Extent::of_span(span));
+            }
+            let end = symbols[i + 1..]
+                .iter()
+                .find(|next| !nested(next.def.id, s.def.id))
+                .map_or(end_of_file, |next| {
+                    (next.def.span.start_line, next.def.span.start_column)
+                });
+            let extent = Extent::Text(span.file, (span.start_line, span.start_column), end);
+            (s.def.clone(), extent)
+        })
+        .collect()
+}
+
+#[derive(Default)]
+pub struct Diagnostics {}
+
+impl Function for Diagnostics {
+    const NAME: &'static str = "diagnostics";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "compiler errors and warnings in a location";
+    const REQUIRES: &'static [Capability] = &[Capability::Diagnostics];
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        if types::coerce(&ty_lhs, &Type::Set(Box::new(Type::Location))).is_none() {
+            return Err(Error::TypeError(format!(
+                "Expected location, found {:?}",
+                ty_lhs
+            )));
+        }
+
+        Ok(Type::Query(Box::new(Type::Set(Box::new(Type::Diagnostic)))))
+    }
+
+    fn eval_query(f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        let lhs = f.lhs.eval(ctx)?.coerce_to_location();
+        let range = match lhs.kind {
+            ValueKind::Position(p) => Range::Span(Span::at(&p)),
+            ValueKind::Range(r) => r,
+            _ => {
+                return Err(Error::TypeError(format!(
+                    "Unexpected runtime type, expected: location, found: {:?}",
+                    lhs.ty
+                )))
+            }
+        };
+
+        Value::set(
+            ctx.back
+                .diagnostics(range)?
+                .into_iter()
+                .map(|d| Value {
+                    kind: ValueKind::Diagnostic(d),
+                    ty: Type::Diagnostic,
+                    label: None,
+                })
+                .collect(),
+            Type::Diagnostic,
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct NoMacro {}
+
+impl Function for NoMacro {
+    const NAME: &'static str = "nomacro";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the identifiers which are not from macro expansions";
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        if types::shape(&ty_lhs, &[Type::Identifier]).is_none() {
+            return Err(Error::TypeError(format!(
+                "Expected identifiers, found {:?}",
+                ty_lhs.flatten_all()
+            )));
+        }
+        Ok(types::lift(&ty_lhs, Type::Set(Box::new(Type::Identifier))))
+    }
+
+    fn apply(idents: Value, _: &[Value], _: &Type) -> Result<Value, Error> {
+        let idents = match idents.kind {
+            ValueKind::Set(idents) => idents,
+            ValueKind::Identifier(_) => vec![idents],
+            _ => {
+                return Err(Error::TypeError(format!(
+                    "Unexpected runtime type, expected: set<ident>, found: {:?}",
+                    idents.ty
+                )))
+            }
+        };
+        Value::set(
+            idents
+                .into_iter()
+                .filter(|i| match &i.kind {
+                    ValueKind::Identifier(i) => !i.from_macro,
+                    _ => false,
+                })
+                .collect(),
+            Type::Identifier,
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Kind {}
+
+impl Function for Kind {
+    const NAME: &'static str = "kind";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str =
+        "the definitions or identifiers of a kind, e.g., `kind \"fn\"` or `kind \"variable\"`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        mut args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        let kind = match interpreter.interpret_expr(args.remove(0).kind)?.kind {
+            ValueKind::String(s) => s,
+            _ => return Err(Error::TypeError("Expected string".to_owned())),
+        };
+        // Check the kind before anything is evaluated.
+        match ty.unquery().expect_set_inner() {
+            Type::Identifier => IdentKind::parse(&kind).map(|_| ())?,
+            _ => DefKind::parse(&kind).map(|_| ())?,
+        }
+        let lhs = interpreter.interpret_expr(lhs.kind)?;
+        lift::<Self>(lhs, vec![Value::string(kind)], ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_arg = interpreter.type_expr(&args[0].kind)?;
+        if !types::is_subtype(&ty_arg, &Type::String) {
+            return Err(Error::TypeError(format!(
+                "Expected string, found {:?}",
+                ty_arg
+            )));
+        }
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        match types::shape(&ty_lhs, &[Type::Definition, Type::Identifier]) {
+            Some((_, elem)) => Ok(types::lift(&ty_lhs, Type::Set(Box::new(elem)))),
+            None => Err(Error::TypeError(format!(
+                "Expected definitions or identifiers, found {:?}",
+                ty_lhs.flatten_all()
+            ))),
+        }
+    }
+
+    // Identifiers whose kind is unknown are left out.
+    fn apply(values: Value, args: &[Value], ty: &Type) -> Result<Value, Error> {
+        let kind = args[0].clone().expect_string();
+        let elem = ty.expect_set_inner();
+        let values = match values.kind {
+            ValueKind::Set(values) => values,
+            ValueKind::Definition(_) | ValueKind::Identifier(_) => vec![values],
+            _ => {
+                return Err(Error::TypeError(format!(
+                    "Unexpected runtime type, expected: set<{}>, found: {:?}",
+                    elem, values.ty
+                )))
+            }
+        };
+        let keep: Box<dyn Fn(&ValueKind) -> bool> = match elem {
+            Type::Identifier => {
+                let kind = IdentKind::parse(&kind)?;
+                Box::new(move |v| match v {
+                    ValueKind::Identifier(i) => i.kind == Some(kind),
+                    _ => false,
+                })
+            }
+            _ => {
+                let kind = DefKind::parse(&kind)?;
+                Box::new(move |v| match v {
+                    ValueKind::Definition(d) => d.kind == kind,
+                    _ => false,
+                })
+            }
+        };
+        Value::set(values.into_iter().filter(|v| keep(&v.kind)).collect(), elem)
+    }
+}
+
+#[derive(Default)]
+pub struct Name {}
+
+impl Function for Name {
+    const NAME: &'static str = "name";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the name of an identifier or definition";
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        match types::shape(&ty_lhs, &[Type::Identifier, Type::Definition]) {
+            Some((shape, _)) => Ok(types::lift(&ty_lhs, shape.of(Type::String))),
+            None => Err(Error::TypeError(format!(
+                "Expected identifier or def, found {:?}",
+                ty_lhs.flatten_all()
+            ))),
+        }
+    }
+
+    fn apply(lhs: Value, _: &[Value], _: &Type) -> Result<Value, Error> {
+        each(lhs, Type::String, |v| match query::text(&v) {
+            Some(s) if v.ty != Type::String => Ok(Value::string(s.to_owned())),
+            _ => Err(Error::TypeError(format!(
+                "Unexpected runtime type, expected: identifier or def, found: {:?}",
+                v.ty
+            ))),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Contains {}
+
+impl Function for Contains {
+    const NAME: &'static str = "contains";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str = "test or filter text containing a string, e.g., `contains \"foo\"`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        eval_text_match::<Self>(query::TextMatch::Contains, interpreter, lhs, args, ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_text_match(interpreter, lhs, args)
+    }
+
+    fn apply(lhs: Value, args: &[Value], _: &Type) -> Result<Value, Error> {
+        query::TextMatch::Contains.apply(lhs, &args[0].clone().expect_string())
+    }
+}
+
+#[derive(Default)]
+pub struct Starts {}
+
+impl Function for Starts {
+    const NAME: &'static str = "starts";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str = "test or filter text starting with a string, e.g., `starts \"foo\"`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        eval_text_match::<Self>(query::TextMatch::Starts, interpreter, lhs, args, ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_text_match(interpreter, lhs, args)
+    }
+
+    fn apply(lhs: Value, args: &[Value], _: &Type) -> Result<Value, Error> {
+        query::TextMatch::Starts.apply(lhs, &args[0].clone().expect_string())
+    }
+}
+
+#[derive(Default)]
+pub struct Matches {}
+
+impl Function for Matches {
+    const NAME: &'static str = "matches";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str = "test or filter text matching a regex, e.g., `matches \"^test_\"`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        eval_text_match::<Self>(query::TextMatch::Matches, interpreter, lhs, args, ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_text_match(interpreter, lhs, args)
+    }
+
+    fn apply(lhs: Value, args: &[Value], _: &Type) -> Result<Value, Error> {
+        query::TextMatch::Matches.apply(lhs, &args[0].clone().expect_string())
+    }
+}
+
+fn eval_text_match<F: Function>(
+    test: query::TextMatch,
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: ast::Expr,
+    mut args: Vec<ast::Expr>,
+    ty: Type,
+) -> Result<Value, Error> {
+    let pattern = match interpreter.interpret_expr(args.remove(0).kind)?.kind {
+        ValueKind::String(s) => s,
+        _ => return Err(Error::TypeError("Expected string".to_owned())),
+    };
+    // Report invalid patterns now, rather than when the query is evaluated.
+    test.check(&pattern)?;
+    let lhs = interpreter.interpret_expr(lhs.kind)?;
+    lift::<F>(lhs, vec![Value::string(pattern)], ty)
+}
+
+fn type_text_match(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: &ast::Expr,
+    args: &[ast::Expr],
+) -> Result<Type, Error> {
+    let ty_arg = interpreter.type_expr(&args[0].kind)?;
+    if !types::is_subtype(&ty_arg, &Type::String) {
+        return Err(Error::TypeError(format!(
+            "Expected string, found {:?}",
+            ty_arg
+        )));
+    }
+    let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+    let text = [Type::String, Type::Identifier, Type::Definition];
+    let result = match types::shape(&ty_lhs, &text) {
+        Some((Shape::Single, _)) => Type::Bool,
+        Some((Shape::Set, elem)) => Shape::Set.of(elem),
+        None => {
+            return Err(Error::TypeError(format!(
+                "Expected string, identifier, or def, found {:?}",
+                ty_lhs.flatten_all()
+            )))
+        }
+    };
+    Ok(types::lift(&ty_lhs, result))
+}
+
+#[derive(Default)]
+pub struct Within {}
+
+impl Function for Within {
+    const NAME: &'static str = "within";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str =
+        "test or filter locations inside another location, e.g., `within $def`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        eval_relation::<Self>(interpreter, lhs, args, ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_relation(interpreter, lhs, args)
+    }
+
+    fn apply(lhs: Value, args: &[Value], _: &Type) -> Result<Value, Error> {
+        query::Relation::Within.apply(lhs, &query::Relation::extents(&args[0])?)
+    }
+}
+
+#[derive(Default)]
+pub struct Overlaps {}
+
+impl Function for Overlaps {
+    const NAME: &'static str = "overlaps";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str =
+        "test or filter locations sharing text with another location, e.g., `overlaps (:12)`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        ty: Type,
+    ) -> Result<Value, Error> {
+        eval_relation::<Self>(interpreter, lhs, args, ty)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_relation(interpreter, lhs, args)
+    }
+
+    fn apply(lhs: Value, args: &[Value], _: &Type) -> Result<Value, Error> {
+        query::Relation::Overlaps.apply(lhs, &query::Relation::extents(&args[0])?)
+    }
+}
+
+// The types of values which have an extent (see `Value::extent`).
+const LOCATED: [Type; 5] = [
+    Type::Location,
+    Type::Identifier,
+    Type::Definition,
+    Type::Diagnostic,
+    Type::Info,
+];
+
+// The argument is evaluated straight away, even if the lhs is a query.
+fn eval_relation<F: Function>(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: ast::Expr,
+    mut args: Vec<ast::Expr>,
+    ty: Type,
+) -> Result<Value, Error> {
+    let others = interpreter.interpret_expr(args.remove(0).kind)?;
+    let others = if others.ty.is_query() {
+        eval_query(&others.expect_query(), interpreter.env)?
+    } else {
+        others
+    };
+    // Check the argument before the lhs is evaluated.
+    query::Relation::extents(&others)?;
+    let lhs = interpreter.interpret_expr(lhs.kind)?;
+    lift::<F>(lhs, vec![others], ty)
+}
+
+fn type_relation(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: &ast::Expr,
+    args: &[ast::Expr],
+) -> Result<Type, Error> {
+    let ty_arg = interpreter.type_expr(&args[0].kind)?;
+    if types::shape(&ty_arg, &LOCATED).is_none() {
+        return Err(Error::TypeError(format!(
+            "Expected location, found {:?}",
+            ty_arg.flatten_all()
+        )));
+    }
+    let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+    let result = match types::shape(&ty_lhs, &LOCATED) {
+        Some((Shape::Single, _)) => Type::Bool,
+        Some((Shape::Set, _)) => ty_lhs.unquery().flatten_all(),
+        None => {
+            return Err(Error::TypeError(format!(
+                "Expected location, identifier, def, or diagnostic, found {:?}",
+                ty_lhs.flatten_all()
+            )))
+        }
+    };
+    Ok(types::lift(&ty_lhs, result))
+}
+
+#[derive(Default)]
+pub struct Start {}
+
+impl Function for Start {
+    const NAME: &'static str = "start";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the position at the start of a location";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let span = eval_span(interpreter, lhs)?;
+        Ok(Value {
+            kind: ValueKind::Position(Position::new(span.file, span.start_line, span.start_column)),
+            ty: Type::Position,
+            label: None,
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_location_fn(interpreter, lhs, args, Type::Position)
+    }
+}
+
+#[derive(Default)]
+pub struct End {}
+
+impl Function for End {
+    const NAME: &'static str = "end";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the position at the end of a location";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let span = eval_span(interpreter, lhs)?;
+        Ok(Value {
+            kind: ValueKind::Position(Position::new(span.file, span.end_line, span.end_column)),
+            ty: Type::Position,
+            label: None,
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_location_fn(interpreter, lhs, args, Type::Position)
+    }
+}
+
+#[derive(Default)]
+pub struct File {}
+
+impl Function for File {
+    const NAME: &'static str = "file";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the file (or files) containing a location";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let range = match eval_location(interpreter, lhs)? {
+            ValueKind::Range(Range::MultiFile(paths)) => Range::MultiFile(paths),
+            kind => Range::File(span_of(interpreter.env.file_system(), kind)?.file),
+        };
+        Ok(Value {
+            kind: ValueKind::Range(range),
+            ty: Type::Range,
+            label: None,
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_location_fn(interpreter, lhs, args, Type::Range)
+    }
+}
+
+#[derive(Default)]
+pub struct Crate {}
+
+impl Function for Crate {
+    const NAME: &'static str = "crate";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the source files of a crate in the workspace, by name, e.g., \
+                                `crate \"clyde\"`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        eval_files(interpreter, lhs, |fs, name| fs.crate_files(name))
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_files(interpreter, lhs)
+    }
+}
+
+#[derive(Default)]
+pub struct Mod {}
+
+impl Function for Mod {
+    const NAME: &'static str = "mod";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the source files of a module (including its submodules), by \
+                                path, e.g., `mod \"front::data\"`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        eval_files(interpreter, lhs, |fs, name| fs.module_files(name))
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_files(interpreter, lhs)
+    }
+}
+
+fn eval_files<Env: Environment>(
+    interpreter: &mut Interpreter<'_, Env>,
+    lhs: ast::Expr,
+    files: impl FnOnce(&Env::Fs, &str) -> Result<Vec<Path>, crate::file_system::Error>,
+) -> Result<Value, Error> {
+    let name = match interpreter.interpret_expr(lhs.kind)?.kind {
+        ValueKind::String(s) => s,
+        _ => return Err(Error::TypeError("Expected string".to_owned())),
+    };
+    let mut paths = files(interpreter.env.file_system(), &name)?;
+    let range = if paths.len() == 1 {
+        Range::File(paths.pop().unwrap())
+    } else {
+        Range::MultiFile(paths)
+    };
+    Ok(Value {
+        kind: ValueKind::Range(range),
+        ty: Type::Range,
+        label: None,
+    })
+}
+
+fn ty_files(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: &ast::Expr,
+) -> Result<Type, Error> {
+    let ty = interpreter.type_expr(&lhs.kind)?;
+    if !types::is_subtype(&ty, &Type::String) {
+        return Err(Error::TypeError(format!("Expected string, found {}", ty)));
+    }
+    Ok(Type::Range)
+}
+
+#[derive(Default)]
+pub struct At {}
+
+impl Function for At {
+    const NAME: &'static str = "at";
+    const ARITY: Arity = Arity::Range(1, 2);
+    const HELP: &'static str =
+        "the position at line `l` and column `c` (default 1) of the file containing a location";
+    const DEFAULTS: &'static [DefaultArg] = &[DefaultArg::Number(1)];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        mut args: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let column = eval_number(interpreter, args.split_off(1))?;
+        let line = eval_number(interpreter, args)?;
+        let (line, column) = match (Line::new_one_indexed(line), Column::new_one_indexed(column)) {
+            (Some(line), Some(column)) => (line, column),
+            _ => {
+                return Err(Error::Other(format!(
+                    "Lines and columns start at 1, found {}:{}",
+                    line, column
+                )))
+            }
+        };
+        let file = eval_span(interpreter, lhs)?.file;
+        let fs = interpreter.env.file_system();
+        // Columns are bytes, the end of the line is allowed.
+        let text = fs.get_line(file, line)?;
+        if !text.is_char_boundary(column.zero_indexed()) {
+            let reason = if column.zero_indexed() > text.len() {
+                format!("does not exist, the line has {} bytes", text.len())
+            } else {
+                "is inside a char".to_owned()
+            };
+            return Err(crate::file_system::Error::BadLocation(format!(
+                "column {} of line {} of {} {}",
+                column,
+                line,
+                fs.path_name(file),
+                reason
+            ))
+            .into());
+        }
+        Ok(Value {
+            kind: ValueKind::Position(Position::new(file, line, column)),
+            ty: Type::Position,
+            label: None,
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_location_fn(interpreter, lhs, args, Type::Position)
+    }
+}
+
+#[derive(Default)]
+pub struct Expand {}
+
+impl Function for Expand {
+    const NAME: &'static str = "expand";
+    const ARITY: Arity = Arity::Range(0, 1);
+    const HELP: &'static str = "the lines of a location plus `n` (default 3) lines either side";
+    const DEFAULTS: &'static [DefaultArg] = &[DefaultArg::Number(3)];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let n = eval_number(interpreter, args)?;
+        let span = eval_span(interpreter, lhs)?;
+        line_range(
+            interpreter.env.file_system(),
+            span.file,
+            span.start_line.saturating_sub(n),
+            span.end_line.saturating_add(n),
+        )
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_location_fn(interpreter, lhs, args, Type::Range)
+    }
+}
+
+#[derive(Default)]
+pub struct Before {}
+
+impl Function for Before {
+    const NAME: &'static str = "before";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str = "the `n` lines before a location";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let n = eval_number(interpreter, args)?;
+        let span = eval_span(interpreter, lhs)?;
+        if span.start_line.zero_indexed() == 0 || n == 0 {
+            return Err(Error::Other(format!(
+                "There are no lines before line {}",
+                span.start_line
+            )));
+        }
+        line_range(
+            interpreter.env.file_system(),
+            span.file,
+            span.start_line.saturating_sub(n),
+            span.start_line - 1,
+        )
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_location_fn(interpreter, lhs, args, Type::Range)
+    }
+}
+
+#[derive(Default)]
+pub struct After {}
+
+impl Function for After {
+    const NAME: &'static str = "after";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str = "the `n` lines after a location";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let n = eval_number(interpreter, args)?;
+        let span = eval_span(interpreter, lhs)?;
+        let len = file_len(interpreter.env.file_system(), span.file)?;
+        if span.end_line.one_indexed() >= len || n == 0 {
+            return Err(Error::Other(format!(
+                "There are no lines after line {}",
+                span.end_line
+            )));
+        }
+        line_range(
+            interpreter.env.file_system(),
+            span.file,
+            span.end_line + 1,
+            span.end_line.saturating_add(n),
+        )
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        ty_location_fn(interpreter, lhs, args, Type::Range)
+    }
+}
+
+#[derive(Default)]
+pub struct Changed {}
+
+impl Function for Changed {
+    const NAME: &'static str = "changed";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str =
+        "the lines which have changed since a git revision, e.g., `\"HEAD~1\"->changed`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let revision = match interpreter.interpret_expr(lhs.kind)?.kind {
+            ValueKind::String(s) => s,
+            _ => return Err(Error::TypeError("Expected string".to_owned())),
+        };
+        let env = interpreter.env;
+        let changes = env.changed(&revision)?;
+        eval_with_ctx(env, |ctx| {
+            let mut ranges = Vec::new();
+            for (i, &(file, start, end)) in changes.iter().enumerate() {
+                if ctx.out_of_time() {
+                    break;
+                }
+                ranges.push(line_range(env.file_system(), file, start, end)?);
+                ctx.progress.update(i + 1, changes.len());
+            }
+            Value::set(ranges, Type::Range)
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        if !types::is_subtype(&ty_lhs, &Type::String) {
+            return Err(Error::TypeError(format!(
+                "Expected string (a git revision), found {:?}",
+                ty_lhs
+            )));
+        }
+        Ok(Type::Set(Box::new(Type::Range)))
+    }
+}
+
+fn ty_location_fn(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: &ast::Expr,
+    args: &[ast::Expr],
+    result: Type,
+) -> Result<Type, Error> {
+    let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+    match ty_lhs.unquery() {
+        // Anything with a span will do.
+        Type::Identifier | Type::Diagnostic => {}
+        ref ty if types::coerce(ty, &Type::Location).is_some() => {}
+        _ => {
+            return Err(Error::TypeError(format!(
+                "Expected location, found {:?}",
+                ty_lhs
+            )))
+        }
+    }
+    for arg in args {
+        let ty_arg = interpreter.type_expr(&arg.kind)?;
+        if !types::is_subtype(&ty_arg, &Type::Number) {
+            return Err(Error::TypeError(format!(
+                "Expected number, found {:?}",
+                ty_arg
+            )));
+        }
+    }
+    Ok(result)
+}
+
+fn eval_location(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    expr: ast::Expr,
+) -> Result<ValueKind, Error> {
+    let value = interpreter.interpret_expr(expr.kind)?;
+    let value = if value.ty.is_query() {
+        eval_query(&value.expect_query(), interpreter.env)?
+    } else {
+        value
+    };
+    Ok(value.kind)
+}
+
+fn eval_span(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    expr: ast::Expr,
+) -> Result<Span, Error> {
+    let kind = eval_location(interpreter, expr)?;
+    span_of(interpreter.env.file_system(), kind)
+}
+
+#[derive(Default)]
+pub struct Graph {}
+
+impl Function for Graph {
+    const NAME: &'static str = "graph";
+    const ARITY: Arity = Arity::Range(0, 2);
+    const HELP: &'static str = "write the graph of calls to and from definitions, up to `n` \
+                                (default 1) calls away, in DOT format to a file (default the \
+                                output), e.g., `$->graph 2 \"calls.dot\"`";
+    const REQUIRES: &'static [Capability] = &[
+        Capability::Idents,
+        Capability::Definitions,
+        Capability::Symbols,
+    ];
+    const DEFAULTS: &'static [DefaultArg] = &[DefaultArg::Number(1), DefaultArg::String("")];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        mut args: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let path = match interpreter.interpret_expr(args.remove(1).kind)?.kind {
+            ValueKind::String(s) => s,
+            _ => return Err(Error::TypeError("Expected string".to_owned())),
+        };
+        let depth = eval_number(interpreter, args)?;
+        let lhs = flatten_lhs(interpreter.interpret_expr(lhs.kind)?);
+        let lhs = if lhs.ty.is_query() {
+            eval_query(&lhs.expect_query(), interpreter.env)?
+        } else {
+            lhs
+        };
+        let defs = match lhs.strip_partial().kind {
+            ValueKind::Definition(def) => vec![def],
+            ValueKind::Set(vs) => vs
+                .into_iter()
+                .map(|v| match v.kind {
+                    ValueKind::Definition(def) => Ok(def),
+                    _ => Err(Error::TypeError(format!("Expected def, found {:?}", v.ty))),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => Vec::new(),
+        };
+        let graph = graph::call_graph(&*interpreter.env.backend(), defs, depth)?;
+        let dot = graph.to_dot(interpreter.env.file_system());
+        let path = if path.is_empty() { None } else { Some(&*path) };
+        interpreter.env.write_text(path, &dot)?;
+        Ok(Value::void())
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        if types::shape(&ty_lhs, &[Type::Definition]).is_none() && ty_lhs.unquery() != Type::Void {
+            return Err(Error::TypeError(format!(
+                "Expected def, found {}",
+                ty_lhs.flatten_all()
+            )));
+        }
+        for (arg, expected) in args.iter().zip(&[Type::Number, Type::String]) {
+            let ty = interpreter.type_expr(&arg.kind)?;
+            if !types::is_subtype(&ty, expected) {
+                return Err(Error::TypeError(format!(
+                    "Expected {}, found {}",
+                    expected, ty
+                )));
+            }
+        }
+        Ok(Type::Void)
+    }
+}
+
+#[derive(Default)]
+pub struct Export {}
+
+impl Function for Export {
+    const NAME: &'static str = "export";
+    const ARITY: Arity = Arity::Exactly(1);
+    const HELP: &'static str = "write locations to a file as a table with a row for each, in \
+                                TSV format if the file name ends with `.tsv`, otherwise CSV, \
+                                e.g., `$->export \"out.csv\"`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        mut args: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let path = match interpreter.interpret_expr(args.remove(0).kind)?.kind {
+            ValueKind::String(s) => s,
+            _ => return Err(Error::TypeError("Expected string".to_owned())),
+        };
+        let lhs = flatten_lhs(interpreter.interpret_expr(lhs.kind)?);
+        let lhs = if lhs.ty.is_query() {
+            eval_query(&lhs.expect_query(), interpreter.env)?
+        } else {
+            lhs
+        };
+        let sep = if path.ends_with(".tsv") { '\t' } else { ',' };
+        let table = lhs.to_table(interpreter.env, sep);
+        interpreter.env.write_text(Some(&path), &table)?;
+        Ok(Value::void())
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+        if types::shape(&ty_lhs, &LOCATED).is_none() && ty_lhs.unquery() != Type::Void {
+            return Err(Error::TypeError(format!(
+                "Expected location, identifier, def, or diagnostic, found {}",
+                ty_lhs.flatten_all()
+            )));
+        }
+        let ty = interpreter.type_expr(&args[0].kind)?;
+        if !types::is_subtype(&ty, &Type::String) {
+            return Err(Error::TypeError(format!("Expected string, found {}", ty)));
+        }
+        Ok(Type::Void)
+    }
+}
+
+#[derive(Default)]
+pub struct Grep {}
+
+impl Function for Grep {
+    const NAME: &'static str = "grep";
+    const ARITY: Arity = Arity::Range(1, 2);
+    const HELP: &'static str = "the text in a location which matches a regex, with optional flags \
+                                `i` (ignore case), `w` (whole words), and `F` (fixed string), \
+                                e.g., `(:foo.rs)->grep(\"todo\", \"-iw\")`";
+    const DEFAULTS: &'static [DefaultArg] = &[DefaultArg::String("")];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let mut strings = Vec::new();
+        for arg in args {
+            match interpreter.interpret_expr(arg.kind)?.kind {
+                ValueKind::String(s) => strings.push(s),
+                _ => return Err(Error::TypeError("Expected string".to_owned())),
+            }
+        }
+        let flags = strings.pop().unwrap();
+        let search = text_search(strings.pop().unwrap(), &flags)?;
+        // Report an invalid pattern even if there is nothing to search.
+        search.regex()?;
+
+        let ranges = eval_ranges(interpreter, lhs)?;
+        let env = interpreter.env;
+        eval_with_ctx(env, |ctx| {
+            let mut seen = HashSet::new();
+            let mut matches = Vec::new();
+            for (i, range) in ranges.iter().enumerate() {
+                if ctx.out_of_time() {
+                    break;
+                }
+                for span in env.file_system().search_text(range, &search)? {
+                    if seen.insert(span.clone()) {
+                        matches.push(span);
+                    }
+                }
+                ctx.progress.update(i + 1, ranges.len());
+            }
+
+            let label = format!("match of {}", search);
+            Value::set(
+                matches
+                    .into_iter()
+                    .map(|s| Value {
+                        kind: ValueKind::Range(Range::Span(s)),
+                        ty: Type::Range,
+                        label: Some(label.clone()),
+                    })
+                    .collect(),
+                Type::Range,
+            )
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_ranges(interpreter, lhs)?;
+        for arg in args {
+            let ty = interpreter.type_expr(&arg.kind)?;
+            if !types::is_subtype(&ty, &Type::String) {
+                return Err(Error::TypeError(format!("Expected string, found {}", ty)));
+            }
+        }
+        Ok(Type::Set(Box::new(Type::Range)))
+    }
+}
+
+#[derive(Default)]
+pub struct Dups {}
+
+impl Function for Dups {
+    const NAME: &'static str = "dups";
+    const ARITY: Arity = Arity::Range(0, 1);
+    const HELP: &'static str = "groups of locations where the same `n` (default 3) or more lines \
+                                are repeated, ignoring whitespace, e.g., `(crate \"clyde\")->dups`";
+    const DEFAULTS: &'static [DefaultArg] = &[DefaultArg::Number(3)];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        args: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let n = eval_number(interpreter, args)?;
+        if n == 0 {
+            return Err(Error::Other(
+                "`dups` compares at least 1 line, found 0".to_owned(),
+            ));
+        }
+        let ranges = eval_ranges(interpreter, lhs)?;
+        let env = interpreter.env;
+        eval_with_ctx(env, |ctx| {
+            let mut next = |done, total| {
+                ctx.progress.update(done, total);
+                !ctx.out_of_time()
+            };
+            let groups = env.file_system().duplicates(&ranges, n, &mut next)?;
+
+            let ty = Type::Set(Box::new(Type::Range));
+            let groups = groups
+                .into_iter()
+                .map(|spans| {
+                    Value::set(
+                        spans
+                            .into_iter()
+                            .map(|s| Value {
+                                kind: ValueKind::Range(Range::Span(s)),
+                                ty: Type::Range,
+                                label: None,
+                            })
+                            .collect(),
+                        Type::Range,
+                    )
+                })
+                .collect::<Result<_, _>>()?;
+            Value::set(groups, ty)
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        args: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_ranges(interpreter, lhs)?;
+        for arg in args {
+            let ty = interpreter.type_expr(&arg.kind)?;
+            if !types::is_subtype(&ty, &Type::Number) {
+                return Err(Error::TypeError(format!("Expected number, found {}", ty)));
+            }
+        }
+        Ok(Type::Set(Box::new(Type::Set(Box::new(Type::Range)))))
+    }
+}
+
+#[derive(Default)]
+pub struct Unsafes {}
+
+impl Function for Unsafes {
+    const NAME: &'static str = "unsafes";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the unsafe blocks in a location, e.g., `(:src/lib.rs)->unsafes`";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        eval_syntax(interpreter, lhs, Item::Unsafe)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_ranges(interpreter, lhs)?;
+        Ok(Type::Set(Box::new(Type::Range)))
+    }
+}
+
+#[derive(Default)]
+pub struct Derives {}
+
+impl Function for Derives {
+    const NAME: &'static str = "derives";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the `#[derive(...)]` attributes in a location";
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        eval_syntax(interpreter, lhs, Item::Derive)
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_ranges(interpreter, lhs)?;
+        Ok(Type::Set(Box::new(Type::Range)))
+    }
+}
+
+#[derive(Default)]
+pub struct Tests {}
+
+impl Function for Tests {
+    const NAME: &'static str = "tests";
+    const ARITY: Arity = Arity::None;
+    const HELP: &'static str = "the `#[test]` functions in a location";
+    const REQUIRES: &'static [Capability] = &[Capability::Symbols];
+
+    fn eval(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: ast::Expr,
+        _: Vec<ast::Expr>,
+        _: Type,
+    ) -> Result<Value, Error> {
+        let ranges = eval_ranges(interpreter, lhs)?;
+        let env = interpreter.env;
+        eval_with_ctx(env, |ctx| {
+            let fs = env.file_system();
+            let mut symbols = HashMap::new();
+            let mut result: Vec<Value> = Vec::new();
+            for (i, range) in ranges.iter().enumerate() {
+                if ctx.out_of_time() {
+                    break;
+                }
+                for name in syntax::find(fs, range, Item::Test)? {
+                    let file_symbols = match symbols.entry(name.file) {
+                        hash_map::Entry::Occupied(e) => e.into_mut(),
+                        hash_map::Entry::Vacant(e) => {
+                            e.insert(ctx.back.symbols_in_file(name.file)?)
+                        }
+                    };
+                    let text = fs.snippet(&Range::Span(name.clone()))?;
+                    // Tests which were not built when the index was are not found.
+                    let def = file_symbols.iter().map(|s| &s.def).find(|d| {
+                        (d.kind == DefKind::Function || d.kind == DefKind::Method)
+                            && d.name == text
+                            && d.span.start_line <= name.start_line
+                            && name.start_line <= d.span.end_line
+                    });
+                    if let Some(def) = def {
+                        let def = Value {
+                            kind: ValueKind::Definition(def.clone()),
+                            ty: Type::Definition,
+                            label: None,
+                        };
+                        if !result.iter().any(|v| v.same_as(&def)) {
+                            result.push(def);
+                        }
+                    }
+                }
+                ctx.progress.update(i + 1, ranges.len());
+            }
+            Value::set(result, Type::Definition)
+        })
+    }
+
+    fn ty(
+        &self,
+        interpreter: &mut Interpreter<'_, impl Environment>,
+        lhs: &ast::Expr,
+        _: &[ast::Expr],
+    ) -> Result<Type, Error> {
+        type_ranges(interpreter, lhs)?;
+        Ok(Type::Set(Box::new(Type::Definition)))
+    }
+}
+
+// Multi-file ranges are split so that a search can stop between files.
+fn eval_ranges(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    expr: ast::Expr,
+) -> Result<Vec<Range>, Error> {
+    let value = interpreter.interpret_expr(expr.kind)?;
+    let value = if value.ty.is_query() {
+        eval_query(&value.expect_query(), interpreter.env)?
+    } else {
+        value
+    };
+    let locations = match value.strip_partial().kind {
+        ValueKind::Set(vs) => vs.into_iter().map(|v| v.strip_partial().kind).collect(),
+        kind => vec![kind],
+    };
+    let fs = interpreter.env.file_system();
+    let mut ranges = Vec::new();
+    for kind in locations {
+        match kind {
+            ValueKind::Range(Range::MultiFile(paths)) => {
+                ranges.extend(paths.into_iter().map(Range::File))
+            }
+            ValueKind::Range(r) => ranges.push(r),
+            ValueKind::Position(p) => ranges.push(Range::Line(p.file, p.line)),
+            kind => ranges.push(Range::Span(span_of(fs, kind)?)),
+        }
+    }
+    Ok(ranges)
+}
+
+fn type_ranges(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: &ast::Expr,
+) -> Result<(), Error> {
+    let ty_lhs = interpreter.type_expr(&lhs.kind)?;
+    if types::coerce(&ty_lhs.unquery(), &Type::Set(Box::new(Type::Location))).is_none() {
+        return Err(Error::TypeError(format!(
+            "Expected location, found {:?}",
+            ty_lhs.flatten_all()
+        )));
+    }
+    Ok(())
+}
+
+fn eval_syntax(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    lhs: ast::Expr,
+    item: Item,
+) -> Result<Value, Error> {
+    let ranges = eval_ranges(interpreter, lhs)?;
+    let env = interpreter.env;
+    eval_with_ctx(env, |ctx| {
+        let mut seen = HashSet::new();
+        let mut spans = Vec::new();
+        for (i, range) in ranges.iter().enumerate() {
+            if ctx.out_of_time() {
+                break;
+            }
+            for span in syntax::find(env.file_system(), range, item)? {
+                if seen.insert(span.clone()) {
+                    spans.push(span);
+                }
+            }
+            ctx.progress.update(i + 1, ranges.len());
+        }
+        Value::set(
+            spans
+                .into_iter()
+                .map(|s| Value {
+                    kind: ValueKind::Range(Range::Span(s)),
+                    ty: Type::Range,
+                    label: None,
+                })
+                .collect(),
+            Type::Range,
+        )
+    })
+}
+
+// `flags` are grep's, e.g., `-iw` (the `-` is optional).
+fn text_search(pattern: String, flags: &str) -> Result<TextSearch, Error> {
+    let mut search = TextSearch::new(pattern);
+    for flag in flags.strip_prefix('-').unwrap_or(flags).chars() {
+        match flag {
+            'i' => search.ignore_case = true,
+            'w' => search.whole_words = true,
+            'F' => search.fixed = true,
+            _ => {
+                return Err(Error::Other(format!(
+                    "Unknown flag for `grep`: `{}`, expected `i` (ignore case), `w` (whole \
+                     words), or `F` (fixed string)",
+                    flag
+                )))
+            }
+        }
+    }
+    Ok(search)
+}
+
+fn eval_number(
+    interpreter: &mut Interpreter<'_, impl Environment>,
+    mut args: Vec<ast::Expr>,
+) -> Result<usize, Error> {
+    match interpreter.interpret_expr(args.remove(0).kind)?.kind {
+        ValueKind::Number(n) => Ok(n),
+        _ => Err(Error::TypeError("Expected number".to_owned())),
+    }
+}
+
+fn span_of(fs: &impl FileSystem, kind: ValueKind) -> Result<Span, Error> {
+    match kind {
+        ValueKind::Position(p) => Ok(Span::at(&p)),
+        ValueKind::Range(Range::Span(s)) => Ok(s),
+        ValueKind::Range(Range::Line(file, line)) => Ok(Span::new(
+            file,
+            line,
+            Column::new_zero_indexed(0),
+            line,
+            line_end(fs, file, line)?,
+        )),
+        ValueKind::Range(Range::File(file)) => {
+            let last = Line::new_zero_indexed(file_len(fs, file)?.saturating_sub(1));
+            Ok(Span::new(
+                file,
+                Line::new_zero_indexed(0),
+                Column::new_zero_indexed(0),
+                last,
+                line_end(fs, file, last)?,
+            ))
+        }
+        ValueKind::Range(Range::MultiFile(_)) => Err(Error::Other(
+            "Expected a location in a single file, found multiple files".to_owned(),
+        )),
+        ValueKind::Definition(d) => Ok(d.span),
+        ValueKind::Identifier(id) => Ok(id.span),
+        ValueKind::Diagnostic(d) => Ok(d.span),
+        _ => Err(Error::TypeError("Expected location".to_owned())),
+    }
+}
+
+fn choose(env: &impl Environment, elements: &[Value]) -> Result<Value, Error> {
+    if elements.is_empty() {
+        return Err(Error::EmptySet(Pick::NAME));
+    }
+    if elements.len() == 1 || !env.can_prompt() {
+        return Ok(elements[0].clone());
+    }
+
+    let ctx = env.show_ctx();
+    let options = elements
+        .iter()
+        .map(|e| {
+            let mut option = Vec::new();
+            e.show_quickfix(&mut option, env, &ctx)?;
+            Ok(String::from_utf8_lossy(&option).into_owned())
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let header = format!("{} elements:", elements.len());
+    Ok(elements[ask(env, &header, &options)?].clone())
+}
+
+// The index of the chosen option, the first by default.
+pub(super) fn ask(
+    env: &impl Environment,
+    header: &str,
+    options: &[String],
+) -> Result<usize, Error> {
+    let mut list = format!("{}\n", header);
+    for (i, option) in options.iter().enumerate() {
+        list += &format!("{}: {}\n", i, option);
+    }
+    let question = format!(
+        "choose one (0-{}), or press enter for the first: ",
+        options.len() - 1
+    );
+    let mut message = list + &question;
+    loop {
+        let answer = match env.prompt_user(&message)? {
+            Some(answer) => answer,
+            // End of input.
+            None => return Ok(0),
+        };
+        match answer.trim() {
+            "" => return Ok(0),
+            s => match s.parse::<usize>() {
+                Ok(i) if i < options.len() => return Ok(i),
+                _ => {
+                    message = format!(
+                        "expected a number from 0 to {}\n{}",
+                        options.len() - 1,
+                        question
+                    )
+                }
+            },
+        }
+    }
+}
+
+fn idents_at(
+    interpreter: &Interpreter<'_, impl Environment>,
+    position: Position,
+) -> Result<Vec<Identifier>, Error> {
+    let mut idents = interpreter.env.backend().idents_at(position.clone())?;
+    if interpreter.env.settings().macros == MacroMode::Hide {
+        idents.retain(|i| !i.from_macro);
+    }
+    if idents.len() > 1 {
+        interpreter.env.choose_idents(&position, idents)
+    } else {
+        Ok(idents)
+    }
+}
+
+// Clamped to the length of the file.
+fn line_range(fs: &impl FileSystem, file: Path, start: Line, end: Line) -> Result<Value, Error> {
+    let end = end.min(Line::new_zero_indexed(
+        file_len(fs, file)?.saturating_sub(1),
+    ));
+    let range = if start == end {
+        Range::Line(file, start)
+    } else {
+        Range::Span(Span::new(
+            file,
+            start,
+            Column::new_zero_indexed(0),
+            end,
+            line_end(fs, file, end)?,
+        ))
+    };
+    Ok(Value {
+        kind: ValueKind::Range(range),
+        ty: Type::Range,
+        label: None,
+    })
+}
+
+fn file_len(fs: &impl FileSystem, file: Path) -> Result<usize, Error> {
+    Ok(fs.with_file(file, |f| Ok(f.lines.len()))?)
+}
+
+fn line_end(fs: &impl FileSystem, file: Path, line: Line) -> Result<Column, Error> {
+    Ok(Column::new_zero_indexed(
+        fs.get_line(file, line)?.chars().count(),
+    ))
 }
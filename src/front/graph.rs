@@ -0,0 +1,272 @@
+//! Graphs of the calls between functions (see the `graph` function), which are
+//! exported in the DOT format of Graphviz.
+
+use crate::back::Backend;
+use crate::file_system::{FileSystem, Path};
+use crate::front::data::{DefKind, Definition, Range};
+use crate::front::Error;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Definitions (usually functions and methods) and the calls between them.
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    pub nodes: Vec<Definition>,
+    /// Indices into `nodes`, from the caller to the callee, in the order they
+    /// were found.
+    pub edges: Vec<(usize, usize)>,
+}
+
+impl CallGraph {
+    /// The graph in DOT format, with each node labelled `name (file:line)`.
+    pub fn to_dot(&self, fs: &impl FileSystem) -> String {
+        let mut result = "digraph {\n".to_owned();
+        for (i, def) in self.nodes.iter().enumerate() {
+            let label = format!(
+                "{} ({}:{})",
+                def.name,
+                fs.path_name(def.span.file),
+                def.span.start_line.one_indexed()
+            );
+            writeln!(result, "    n{} [label=\"{}\"];", i, escape(&label)).unwrap();
+        }
+        for (from, to) in &self.edges {
+            writeln!(result, "    n{} -> n{};", from, to).unwrap();
+        }
+        result.push_str("}\n");
+        result
+    }
+}
+
+/// The graph of the calls up to `depth` calls away from `defs`. Callers are
+/// only looked for in the files of the definitions they call.
+pub fn call_graph(
+    back: &dyn Backend,
+    defs: Vec<Definition>,
+    depth: usize,
+) -> Result<CallGraph, Error> {
+    let mut builder = Builder {
+        back,
+        graph: CallGraph::default(),
+        indices: HashMap::new(),
+        edges: HashSet::new(),
+        callees: HashMap::new(),
+        functions: HashMap::new(),
+    };
+    let mut frontier: Vec<usize> = defs
+        .into_iter()
+        .filter_map(|def| builder.node(def))
+        .collect();
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for i in frontier {
+            let def = builder.graph.nodes[i].clone();
+            for callee in builder.callees(&def)? {
+                let j = builder.index(callee, &mut next);
+                builder.edge(i, j);
+            }
+            for caller in builder.callers(&def)? {
+                let j = builder.index(caller, &mut next);
+                builder.edge(j, i);
+            }
+        }
+        frontier = next;
+    }
+    Ok(builder.graph)
+}
+
+struct Builder<'a> {
+    back: &'a dyn Backend,
+    graph: CallGraph,
+    // Indices of nodes by the id of their definition.
+    indices: HashMap<u64, usize>,
+    edges: HashSet<(usize, usize)>,
+    // Caches of what the backend tells us, by definition id and by file.
+    callees: HashMap<u64, Vec<Definition>>,
+    functions: HashMap<Path, Vec<Definition>>,
+}
+
+impl<'a> Builder<'a> {
+    // Add `def` to the graph, giving its index, or `None` if it was already in
+    // the graph.
+    fn node(&mut self, def: Definition) -> Option<usize> {
+        if self.indices.contains_key(&def.id) {
+            return None;
+        }
+        let i = self.graph.nodes.len();
+        self.indices.insert(def.id, i);
+        self.graph.nodes.push(def);
+        Some(i)
+    }
+
+    // The index of `def` in the graph, adding it (and to `new`) if it is not
+    // there yet.
+    fn index(&mut self, def: Definition, new: &mut Vec<usize>) -> usize {
+        match self.indices.get(&def.id) {
+            Some(i) => *i,
+            None => {
+                let i = self.node(def).unwrap();
+                new.push(i);
+                i
+            }
+        }
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        if self.edges.insert((from, to)) {
+            self.graph.edges.push((from, to));
+        }
+    }
+
+    // The functions and methods which `def` refers to, other than itself.
+    fn callees(&mut self, def: &Definition) -> Result<Vec<Definition>, Error> {
+        if let Some(callees) = self.callees.get(&def.id) {
+            return Ok(callees.clone());
+        }
+        let mut result: Vec<Definition> = Vec::new();
+        for ident in self.back.idents_in(Range::Span(def.span.clone()))? {
+            let callee = match self.back.definition(ident) {
+                Ok(callee) => callee,
+                Err(_) => continue,
+            };
+            if is_function(&callee)
+                && callee.id != def.id
+                && !result.iter().any(|d| d.id == callee.id)
+            {
+                result.push(callee);
+            }
+        }
+        self.callees.insert(def.id, result.clone());
+        Ok(result)
+    }
+
+    // The functions and methods in the file of `def` which refer to it.
+    fn callers(&mut self, def: &Definition) -> Result<Vec<Definition>, Error> {
+        let file = def.span.file;
+        if !self.functions.contains_key(&file) {
+            let functions = self
+                .back
+                .symbols_in_file(file)?
+                .into_iter()
+                .map(|s| s.def)
+                .filter(is_function)
+                .collect();
+            self.functions.insert(file, functions);
+        }
+        let mut result = Vec::new();
+        for f in self.functions[&file].clone() {
+            if f.id != def.id && self.callees(&f)?.iter().any(|c| c.id == def.id) {
+                result.push(f);
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn is_function(def: &Definition) -> bool {
+    def.kind == DefKind::Function || def.kind == DefKind::Method
+}
+
+// Escape `s` for a quoted string in DOT.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::back::MockBackend;
+    use crate::file_system::MockFs;
+    use crate::front::data::{Identifier, Span};
+
+    // `main` calls `a` and `b`, and `a` calls `b`.
+    fn backend() -> (MockBackend, Vec<Definition>) {
+        let foo = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let def = |id, name: &str, line| Definition {
+            id,
+            span: Span::new_zero_indexed(foo, line, 0, line + 2, 10),
+            name: name.to_owned(),
+            kind: DefKind::Function,
+            path: None,
+        };
+        let defs = vec![def(10, "main", 0), def(11, "a", 3), def(12, "b", 6)];
+        let ident = |id, line, def: &Definition| Identifier {
+            id,
+            span: Span::new_zero_indexed(foo, line, 4, line, 5),
+            name: def.name.clone(),
+            from_macro: false,
+            kind: None,
+            def: None,
+        };
+        let mut back = MockBackend::new()
+            // A function's own name is not a call.
+            .ident(ident(1, 0, &defs[0]), Some(defs[0].clone()))
+            .ident(ident(2, 1, &defs[1]), Some(defs[1].clone()))
+            .ident(ident(3, 2, &defs[2]), Some(defs[2].clone()))
+            .ident(ident(4, 4, &defs[2]), Some(defs[2].clone()))
+            // No definition.
+            .ident(ident(5, 7, &defs[0]), None);
+        for def in &defs {
+            back = back.symbol(def.clone(), None);
+        }
+        (back, defs)
+    }
+
+    fn names(graph: &CallGraph) -> (Vec<&str>, Vec<(&str, &str)>) {
+        let name = |i: usize| &*graph.nodes[i].name;
+        (
+            graph.nodes.iter().map(|d| &*d.name).collect(),
+            graph
+                .edges
+                .iter()
+                .map(|(a, b)| (name(*a), name(*b)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let (back, defs) = backend();
+        let graph = call_graph(&back, vec![defs[2].clone()], 0).unwrap();
+        assert_eq!(names(&graph), (vec!["b"], vec![]));
+
+        let graph = call_graph(&back, vec![defs[2].clone()], 1).unwrap();
+        assert_eq!(
+            names(&graph),
+            (vec!["b", "main", "a"], vec![("main", "b"), ("a", "b")])
+        );
+
+        let graph = call_graph(&back, vec![defs[2].clone(), defs[2].clone()], 2).unwrap();
+        assert_eq!(
+            names(&graph),
+            (
+                vec!["b", "main", "a"],
+                vec![("main", "b"), ("a", "b"), ("main", "a")]
+            )
+        );
+
+        let graph = call_graph(&back, vec![defs[0].clone()], 1).unwrap();
+        assert_eq!(
+            names(&graph),
+            (vec!["main", "a", "b"], vec![("main", "a"), ("main", "b")])
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let (back, defs) = backend();
+        let mut graph = call_graph(&back, vec![defs[0].clone()], 1).unwrap();
+        // Labels are escaped.
+        graph.nodes[0].name = "say \"hi\"".to_owned();
+        assert_eq!(
+            graph.to_dot(&MockFs),
+            "digraph {\n    \
+             n0 [label=\"say \\\"hi\\\" (foo.rs:1)\"];\n    \
+             n1 [label=\"a (foo.rs:4)\"];\n    \
+             n2 [label=\"b (foo.rs:7)\"];\n    \
+             n0 -> n1;\n    \
+             n0 -> n2;\n\
+             }\n"
+        );
+    }
+}
@@ -1,20 +1,40 @@
+use self::data::ValueKind;
 pub use self::data::{Locator, MetaVar, Type, Value};
-use self::function::Function;
+pub use self::explain::explain;
+pub use self::function::Registry;
+pub use self::query::Budget;
+pub use self::show::{Color, DisplayPolicy, ShowCtx};
 use crate::ast;
 use crate::back;
-use crate::env::Environment;
+use crate::env::{DisplayDuration, Environment, MacroMode, SessionState};
 use crate::file_system::{self, FileSystem};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, Write};
+use std::time::Duration;
 
 pub mod data;
+mod explain;
 mod function;
+mod graph;
 mod query;
+mod show;
+#[cfg(test)]
+pub mod snapshot;
+pub(crate) mod syntax;
+mod types;
+
+// How deeply applications of lambdas may be nested, so that a lambda which
+// applies itself is an error rather than a stack overflow.
+const MAX_LAMBDA_DEPTH: usize = 32;
 
 pub struct Interpreter<'a, Env: Environment> {
     env: &'a Env,
     symbols: SymbolTable,
+    functions: Registry<Env>,
+    // The number of lambdas currently being applied (or typed).
+    lambda_depth: usize,
 }
 
 impl<'a, Env: Environment> Interpreter<'a, Env> {
@@ -22,38 +42,58 @@ impl<'a, Env: Environment> Interpreter<'a, Env> {
         Interpreter {
             env,
             symbols: SymbolTable::default(),
+            functions: Registry::new(),
+            lambda_depth: 0,
         }
     }
 
     pub fn interpret(mut self, program: ast::Program) -> Result<SymbolTable, Error> {
         for stmt in program.stmts {
-            self.interpret_stmt(stmt)?;
+            if let Outcome::Meta(_) = self.interpret_stmt(stmt)? {
+                return Err(Error::Other(
+                    "Meta-commands can't be used in a program".to_owned(),
+                ));
+            }
         }
 
         Ok(self.symbols)
     }
 
-    pub fn interpret_stmt(&mut self, stmt: ast::Statement) -> Result<Value, Error> {
+    /// Evaluate `stmt`, showing its result. The environment's state is not
+    /// changed, what needs changing is returned instead (see `eval_stmt`).
+    pub fn interpret_stmt(&mut self, stmt: ast::Statement) -> Result<Outcome, Error> {
         match stmt.kind {
             ast::StatementKind::Expr(expr) => {
                 let value = self.interpret_expr(expr)?;
                 self.show_result(&value);
-                Ok(value)
+                Ok(Outcome::Value(value))
             }
             ast::StatementKind::ApplyShorthand(a) => {
                 let value = self.interpret_apply(a)?;
                 self.show_result(&value);
-                Ok(value)
+                Ok(Outcome::Value(value))
             }
-            ast::StatementKind::Meta(mk) => {
-                self.env.exec_meta(mk)?;
-                Ok(Value::void())
+            ast::StatementKind::Assign(ident, expr) => {
+                // The variable could never be applied.
+                if self.functions.lookup(&ident.name).is_some() {
+                    return Err(Error::Other(format!(
+                        "Can't assign to `{}`, it is the name of a function",
+                        ident.name
+                    )));
+                }
+                let value = self.interpret_expr(expr.kind)?;
+                let var = MetaVar { name: ident.name };
+                self.symbols.variables.insert(var.clone(), value.clone());
+                Ok(Outcome::Assign(var, value))
             }
+            ast::StatementKind::Meta(mk) => Ok(Outcome::Meta(mk)),
         }
     }
 
     fn show_result(&self, value: &Value) -> Result<(), Error> {
-        if !value.kind.is_void() {
+        if value.kind.is_empty_set() {
+            self.env.show_empty(value)?;
+        } else if !value.kind.is_void() {
             self.env.show(value)?;
         }
         Ok(())
@@ -62,59 +102,234 @@ impl<'a, Env: Environment> Interpreter<'a, Env> {
     fn interpret_expr(&mut self, expr: ast::ExprKind) -> Result<Value, Error> {
         match expr {
             ast::ExprKind::Void => Ok(Value::void()),
-            ast::ExprKind::MetaVar(kind) => self.lookup_var(&kind),
+            ast::ExprKind::Number(n) => Ok(Value::number(n)),
+            ast::ExprKind::String(s) => Ok(Value::string(s)),
+            // Results marked as partial (see `eval_query`) are used like any
+            // other set.
+            ast::ExprKind::MetaVar(kind) => self.lookup_var(&kind).map(Value::strip_partial),
             ast::ExprKind::Location(loc) => {
-                let loc = self.env.file_system().resolve_location(loc)?;
+                let fs = self.env.file_system();
+                let loc = match fs.resolve_location(loc.clone(), self.env.focus()) {
+                    // The user can choose which file they meant, the location
+                    // is then resolved as if it were in that file.
+                    Err(file_system::Error::Ambiguous(name, files)) if self.env.can_prompt() => {
+                        let names: Vec<_> = files.iter().map(|(_, n)| n.clone()).collect();
+                        let header = format!("`{}` matches {} files:", name, files.len());
+                        let file = files[function::ask(self.env, &header, &names)?].0;
+                        let loc = ast::Location { file: None, ..loc };
+                        fs.resolve_location(loc, Some(file))?
+                    }
+                    result => result?,
+                };
                 Ok(loc.into())
             }
             ast::ExprKind::Apply(a) => self.interpret_apply(a),
             ast::ExprKind::Projection(p) => self.interpret_apply(p.into()),
+            ast::ExprKind::Set(elements) => self.interpret_set(elements),
+            ast::ExprKind::Index(i) => self.interpret_index(i),
+            ast::ExprKind::Lambda(l) => Ok(Value {
+                kind: ValueKind::Fn(l),
+                ty: Type::Fn,
+                label: None,
+            }),
+        }
+    }
+
+    // Indexing or slicing a query is evaluated lazily, by another query.
+    fn interpret_index(&mut self, index: ast::Index) -> Result<Value, Error> {
+        let ty = self.type_index(&index)?;
+        let lhs = self.interpret_expr(index.lhs.kind)?;
+        match lhs.kind {
+            ValueKind::Query(q) => Ok(Value {
+                kind: ValueKind::Query(query::Query::slice(q, index.slice, ty.unquery())),
+                ty,
+                label: None,
+            }),
+            _ => query::slice(lhs, index.slice, ty),
+        }
+    }
+
+    // If any element is a query, then the set is a query which evaluates all
+    // the elements.
+    fn interpret_set(&mut self, elements: Vec<ast::Expr>) -> Result<Value, Error> {
+        let ty = self.type_set(&elements)?;
+        let elements = elements
+            .into_iter()
+            .map(|e| self.interpret_expr(e.kind))
+            .collect::<Result<Vec<_>, _>>()?;
+        if ty.is_query() {
+            Ok(Value {
+                kind: ValueKind::Query(query::Query::set(elements, ty.unquery())),
+                ty,
+                label: None,
+            })
+        } else {
+            Value::set(elements, ty.expect_set_inner())
         }
     }
 
     fn type_expr(&mut self, expr: &ast::ExprKind) -> Result<Type, Error> {
         match expr {
             ast::ExprKind::Void => Ok(Type::Void),
-            ast::ExprKind::MetaVar(kind) => self.lookup_var(kind).map(|val| val.ty),
+            ast::ExprKind::Number(_) => Ok(Type::Number),
+            ast::ExprKind::String(_) => Ok(Type::String),
+            ast::ExprKind::MetaVar(kind) => {
+                // The parameter of a lambda which is being typed.
+                if let ast::MetaVarKind::Named(id) = kind {
+                    let var = MetaVar {
+                        name: id.name.clone(),
+                    };
+                    if let Some(ty) = self.symbols.types.get(&var) {
+                        return Ok(ty.clone());
+                    }
+                }
+                self.lookup_var(kind).map(|val| val.ty)
+            }
             ast::ExprKind::Location(_) => Ok(Type::Location),
             ast::ExprKind::Apply(a) => self.type_apply(a),
             ast::ExprKind::Projection(p) => self.type_apply(&(*p).clone().into()),
+            ast::ExprKind::Set(elements) => self.type_set(elements),
+            ast::ExprKind::Index(i) => self.type_index(i),
+            ast::ExprKind::Lambda(_) => Ok(Type::Fn),
         }
     }
 
-    fn interpret_apply(&mut self, apply: ast::Apply) -> Result<Value, Error> {
-        macro_rules! interpret {
-            ($e: expr, $($fn: ident),*) => {
-                match &*$e {
-                    $(function::$fn::NAME => {
-                        let fun = function::$fn {};
-                        function::$fn::ARITY.check(&apply.args)?;
-                        fun.ty(self, &apply.lhs, &apply.args)?;
-                        fun.eval(self, apply.lhs, apply.args)
-                    })*
-                    _ => Err(Error::UnknownFunction($e))
-                }
-            }
+    // An index gives an element of a set, a slice gives a set of the same type.
+    fn type_index(&mut self, index: &ast::Index) -> Result<Type, Error> {
+        let ty = self.type_expr(&index.lhs.kind)?;
+        let result = match (ty.unquery(), index.slice) {
+            (Type::Set(inner), ast::Slice::Element(_)) => *inner,
+            (set @ Type::Set(_), ast::Slice::Range(..)) => set,
+            _ => return Err(Error::TypeError(format!("Expected set, found {:?}", ty))),
         };
+        if ty.is_query() {
+            Ok(Type::Query(Box::new(result)))
+        } else {
+            Ok(result)
+        }
+    }
 
-        interpret!(apply.ident.name, Select, Show, Idents, Definition, Pick)
+    // The elements of a set must all have the same type, except that locations
+    // of different kinds may be mixed.
+    fn type_set(&mut self, elements: &[ast::Expr]) -> Result<Type, Error> {
+        let mut result: Option<Type> = None;
+        let mut is_query = false;
+        for e in elements {
+            let ty = self.type_expr(&e.kind)?;
+            is_query |= ty.is_query();
+            let ty = ty.unquery();
+            result = Some(match result {
+                None => ty,
+                Some(prev) => types::unify(&prev, &ty).ok_or_else(|| {
+                    Error::TypeError(format!(
+                        "Set elements must have the same type, found {:?} and {:?}",
+                        prev, ty
+                    ))
+                })?,
+            });
+        }
+        let ty = Type::Set(Box::new(result.unwrap_or(Type::Void)));
+        if is_query {
+            Ok(Type::Query(Box::new(ty)))
+        } else {
+            Ok(ty)
+        }
+    }
+
+    fn interpret_apply(&mut self, apply: ast::Apply) -> Result<Value, Error> {
+        let eval = match self.functions.lookup(&apply.ident.name) {
+            Some(f) => f.eval,
+            None => return self.apply_lambda(apply),
+        };
+        eval(self, apply)
     }
 
     fn type_apply(&mut self, apply: &ast::Apply) -> Result<Type, Error> {
-        macro_rules! typ {
-            ($e: expr, $($fn: ident),*) => {
-                match &*$e {
-                    $(function::$fn::NAME => {
-                        let fun = function::$fn {};
-                        function::$fn::ARITY.check(&apply.args)?;
-                        fun.ty(self, &apply.lhs, &apply.args)
-                    })*
-                    _ => Err(Error::UnknownFunction($e.to_owned()))
-                }
+        let ty = match self.functions.lookup(&apply.ident.name) {
+            Some(f) => f.ty,
+            None => return self.type_lambda(apply),
+        };
+        ty(self, apply)
+    }
+
+    // Other variables are looked up when the lambda is applied.
+    fn apply_lambda(&mut self, apply: ast::Apply) -> Result<Value, Error> {
+        let lambda = self.lookup_lambda(&apply)?;
+        let lhs = self.interpret_expr(apply.lhs.kind)?;
+        let var = MetaVar {
+            name: lambda.param.name,
+        };
+        self.interpret_bound(var, lhs, lambda.body.kind)
+    }
+
+    fn type_lambda(&mut self, apply: &ast::Apply) -> Result<Type, Error> {
+        let lambda = self.lookup_lambda(apply)?;
+        let lhs = self.type_expr(&apply.lhs.kind)?;
+        let var = MetaVar {
+            name: lambda.param.name,
+        };
+        self.type_bound(var, lhs, &lambda.body.kind)
+    }
+
+    // Evaluate `body` with `var` bound to `value` (e.g., the parameter of a
+    // lambda), any other binding of `var` is restored afterwards.
+    fn interpret_bound(
+        &mut self,
+        var: MetaVar,
+        value: Value,
+        body: ast::ExprKind,
+    ) -> Result<Value, Error> {
+        let prev = self.symbols.variables.insert(var.clone(), value);
+        let result = self.nested(|this| this.interpret_expr(body));
+        restore(&mut self.symbols.variables, var, prev);
+        result
+    }
+
+    // Like `interpret_bound`, but only type `body`, with `var` of type `ty`.
+    fn type_bound(&mut self, var: MetaVar, ty: Type, body: &ast::ExprKind) -> Result<Type, Error> {
+        let prev = self.symbols.types.insert(var.clone(), ty);
+        let result = self.nested(|this| this.type_expr(body));
+        restore(&mut self.symbols.types, var, prev);
+        result
+    }
+
+    // The lambda applied by `apply`, which is not a built-in function.
+    fn lookup_lambda(&mut self, apply: &ast::Apply) -> Result<ast::Lambda, Error> {
+        let name = &apply.ident.name;
+        let var = ast::MetaVarKind::Named(apply.ident.clone());
+        let lambda = match self.lookup_var(&var) {
+            Ok(Value {
+                kind: ValueKind::Fn(lambda),
+                ..
+            }) => lambda,
+            Ok(value) => {
+                return Err(Error::TypeError(format!(
+                    "`{}` is not a function, found {:?}",
+                    name, value.ty
+                )))
             }
+            Err(_) => return Err(Error::UnknownFunction(name.clone())),
         };
+        if !apply.args.is_empty() {
+            return Err(Error::TypeError(format!(
+                "`{}` takes no arguments, found {}",
+                name,
+                apply.args.len()
+            )));
+        }
+        Ok(lambda)
+    }
 
-        typ!(apply.ident.name, Select, Show, Idents, Definition, Pick)
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.lambda_depth >= MAX_LAMBDA_DEPTH {
+            return Err(Error::Other(
+                "Functions are nested too deeply (is one recursive?)".to_owned(),
+            ));
+        }
+        self.lambda_depth += 1;
+        let result = f(self);
+        self.lambda_depth -= 1;
+        result
     }
 
     fn lookup_var(&mut self, kind: &ast::MetaVarKind) -> Result<Value, Error> {
@@ -134,12 +349,148 @@ impl<'a, Env: Environment> Interpreter<'a, Env> {
                     }
                 }
             }
+            ast::MetaVarKind::All => all_results(self.env),
+        }
+    }
+}
+
+/// What evaluating a statement needs the environment to do once evaluation is
+/// finished.
+pub enum Outcome {
+    // Which has been shown.
+    Value(Value),
+    Assign(MetaVar, Value),
+    Meta(ast::MetaKind),
+}
+
+/// Evaluate `stmt` as the REPL does, then update the environment's state.
+pub fn eval_stmt<Env: SessionState>(env: &mut Env, stmt: ast::Statement) -> Result<Value, Error> {
+    let outcome = Interpreter::new(&*env).interpret_stmt(stmt);
+    let result = match outcome {
+        Ok(Outcome::Value(value)) => Ok(value),
+        Ok(Outcome::Assign(var, value)) => {
+            env.set_var(var, value);
+            Ok(Value::void())
+        }
+        Ok(Outcome::Meta(mk)) => env.exec_meta(mk).map(|()| Value::void()),
+        Err(e) => Err(e),
+    };
+    match &result {
+        Ok(value) => {
+            // The focus follows the results the user is looking at.
+            if let Some(file) = value.file() {
+                env.set_focus(file);
+            }
+            env.push_result(Some(value.clone()));
         }
+        Err(_) => env.push_result(None),
+    }
+    result
+}
+
+/// `$all`. Results whose types can't be unified with later ones are left out.
+pub fn all_results(env: &impl Environment) -> Result<Value, Error> {
+    let mut ty: Option<Type> = None;
+    let mut is_query = false;
+    let mut elements = Vec::new();
+    for value in env.all_results().into_iter().rev() {
+        let elem_ty = value.ty.unquery();
+        if elem_ty == Type::Void {
+            continue;
+        }
+        let unified = match &ty {
+            None => Some(elem_ty),
+            Some(prev) => types::unify(prev, &elem_ty),
+        };
+        if let Some(unified) = unified {
+            is_query |= value.ty.is_query();
+            ty = Some(unified);
+            elements.push(value);
+        }
+    }
+    elements.reverse();
+    let ty = ty.unwrap_or(Type::Void);
+    if is_query {
+        let ty = Type::Set(Box::new(ty));
+        Ok(Value {
+            kind: ValueKind::Query(query::Query::set(elements, ty.clone())),
+            ty: Type::Query(Box::new(ty)),
+            label: None,
+        })
+    } else {
+        Value::set(elements, ty)
+    }
+}
+
+/// A set result is marked as partial if it may be incomplete.
+pub fn eval_query(query: &query::Query, env: &impl Environment) -> Result<Value, Error> {
+    with_ctx(env, |ctx| query.eval(ctx))
+}
+
+/// `value` with every query in it evaluated, including those in sets and
+/// maps, as they must be before it can be saved.
+pub fn eval_queries(value: Value, env: &impl Environment) -> Result<Value, Error> {
+    let kind = match value.kind {
+        ValueKind::Query(q) => return eval_queries(eval_query(&q, env)?, env),
+        ValueKind::Set(vs) => ValueKind::Set(
+            vs.into_iter()
+                .map(|v| eval_queries(v, env))
+                .collect::<Result<_, _>>()?,
+        ),
+        ValueKind::Partial(v) => ValueKind::Partial(Box::new(eval_queries(*v, env)?)),
+        ValueKind::Map(entries) => ValueKind::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| Ok((eval_queries(k, env)?, eval_queries(v, env)?)))
+                .collect::<Result<_, Error>>()?,
+        ),
+        kind => kind,
+    };
+    Ok(Value {
+        kind,
+        ty: value.ty.unquery_all(),
+        label: value.label,
+    })
+}
+
+/// For functions which are evaluated eagerly, as `eval_query`.
+pub fn eval_with_ctx(
+    env: &impl Environment,
+    f: impl FnOnce(&query::EvalCtx) -> Result<Value, Error>,
+) -> Result<Value, Error> {
+    with_ctx(env, |ctx| Ok(ctx.truncate(f(ctx)?)))
+}
+
+fn with_ctx(
+    env: &impl Environment,
+    f: impl FnOnce(&query::EvalCtx) -> Result<Value, Error>,
+) -> Result<Value, Error> {
+    let settings = env.settings();
+    let limit = match settings.limit {
+        0 => None,
+        n => Some(n),
+    };
+    let back = env.backend();
+    let truncated = Cell::new(false);
+    let hide_macros = settings.macros == MacroMode::Hide;
+    let progress = env.progress();
+    let ctx = query::EvalCtx::new(&*back, limit, &truncated)
+        .hide_macros(hide_macros)
+        .progress(&*progress)
+        .budget(env.budget());
+    let value = f(&ctx)?;
+    if truncated.get() {
+        Ok(value.partial())
+    } else {
+        Ok(value)
     }
 }
 
 pub struct SymbolTable {
     variables: HashMap<MetaVar, Value>,
+    // The types of the parameters of lambdas which are being typed (but not
+    // applied).
+    types: HashMap<MetaVar, Type>,
     result: Value,
 }
 
@@ -149,26 +500,36 @@ impl SymbolTable {
     }
 }
 
+// Put back the binding of `var` from before a lambda's parameter shadowed it.
+fn restore<T>(map: &mut HashMap<MetaVar, T>, var: MetaVar, prev: Option<T>) {
+    match prev {
+        Some(prev) => map.insert(var, prev),
+        None => map.remove(&var),
+    };
+}
+
 impl Default for SymbolTable {
     fn default() -> SymbolTable {
         SymbolTable {
             variables: HashMap::new(),
+            types: HashMap::new(),
             result: Value::void(),
         }
     }
 }
 
 pub trait Show {
-    fn show(&self, w: &mut dyn Write, env: &impl Environment) -> Result<(), Error>;
+    fn show(&self, w: &mut dyn Write, env: &impl Environment, ctx: &ShowCtx) -> Result<(), Error>;
+    #[cfg(test)]
     fn show_str(&self, env: &impl Environment) -> String {
         let mut buf: Vec<u8> = Vec::new();
-        self.show(&mut buf, env).unwrap();
+        self.show(&mut buf, env, &env.show_ctx()).unwrap();
         String::from_utf8(buf).unwrap()
     }
 }
 
 impl<T: fmt::Display> Show for T {
-    fn show(&self, w: &mut dyn Write, _: &impl Environment) -> Result<(), Error> {
+    fn show(&self, w: &mut dyn Write, _: &impl Environment, _: &ShowCtx) -> Result<(), Error> {
         write!(w, "{}", self).map_err(Into::into)
     }
 }
@@ -177,10 +538,24 @@ impl<T: fmt::Display> Show for T {
 pub enum Error {
     IoError(io::Error),
     VarNotFound(MetaVar),
-    NumericVarNotFound(usize, usize),
+    // The requested variable (which may be relative, e.g., `$-2`), and the
+    // number of results.
+    NumericVarNotFound(isize, usize),
+    // The (absolute) number of a statement which failed.
+    FailedResult(usize),
+    // A file name, given with a line or column, and the names of the files it
+    // matches.
+    AmbiguousFile(String, Vec<String>),
     UnknownFunction(String),
     TypeError(String),
-    EmptySet,
+    // A function requires a capability which the backend does not support.
+    Unsupported(&'static str, back::Capability),
+    // A function (named) requires a non-empty set.
+    EmptySet(&'static str),
+    // The index, and the length of the set.
+    IndexOutOfRange(isize, usize),
+    // Evaluation ran out of time (see `Settings::timeout`).
+    Aborted(Duration),
     Other(String),
 }
 
@@ -189,14 +564,50 @@ impl fmt::Display for Error {
         match self {
             Error::IoError(e) => e.fmt(f),
             Error::VarNotFound(v) => write!(f, "Variable not found: `{}`", v),
-            Error::NumericVarNotFound(v, max) => write!(
+            Error::NumericVarNotFound(v, 0) => {
+                write!(f, "${} requested but there are no results yet", v)
+            }
+            Error::NumericVarNotFound(v, len) => write!(
                 f,
-                "Variable not found: {} (maximum numeric variable: {})",
-                v, max
+                "${} requested but only {} result{} exist{}",
+                v,
+                len,
+                if *len == 1 { "" } else { "s" },
+                if *len == 1 { "s" } else { "" }
             ),
+            Error::AmbiguousFile(name, files) => write!(
+                f,
+                "Invalid location: `{}` matches {} files, give more of the path to choose one \
+                 of: {}",
+                name,
+                files.len(),
+                files.join(", ")
+            ),
+            Error::FailedResult(n) => write!(f, "${} has no value, statement {} failed", n, n),
             Error::UnknownFunction(s) => write!(f, "Unknown function: `{}`", s),
             Error::TypeError(s) => write!(f, "{}", s),
-            Error::EmptySet => write!(f, "empty set"),
+            Error::Unsupported(name, c) => write!(
+                f,
+                "`{}` requires {}, which the current backend does not support (see `^backend`)",
+                name, c
+            ),
+            Error::EmptySet(name) => write!(
+                f,
+                "`{}` requires at least one element, but the set is empty (there are no results)",
+                name
+            ),
+            Error::IndexOutOfRange(i, len) => write!(
+                f,
+                "Index out of range: {} (the set has {} element{})",
+                i,
+                len,
+                if *len == 1 { "" } else { "s" }
+            ),
+            Error::Aborted(timeout) => write!(
+                f,
+                "Query aborted after {} (see `^set timeout`)",
+                DisplayDuration(*timeout)
+            ),
             Error::Other(s) => write!(f, "{}", s),
         }
     }
@@ -204,7 +615,12 @@ impl fmt::Display for Error {
 
 impl From<file_system::Error> for Error {
     fn from(e: file_system::Error) -> Error {
-        Error::Other(e.to_string())
+        match e {
+            file_system::Error::Ambiguous(name, files) => {
+                Error::AmbiguousFile(name, files.into_iter().map(|(_, n)| n).collect())
+            }
+            e => Error::Other(e.to_string()),
+        }
     }
 }
 
@@ -222,20 +638,10 @@ impl From<io::Error> for Error {
 
 #[cfg(test)]
 mod test {
-    use super::data::ValueKind;
     use super::*;
     use crate::ast::builder;
-    use crate::env::mock::MockEnv;
-
-    fn assert_err<T: fmt::Debug>(e: Result<T, Error>, s: &str) {
-        if let Err(Error::Other(msg)) = &e {
-            if msg == s {
-                return;
-            }
-        }
-
-        panic!("Expected `{}`, found {:?}", s, e);
-    }
+    use crate::back::MockBackend;
+    use crate::env::mock::{MockEnv, TestEnv};
 
     #[test]
     fn test_void() {
@@ -262,18 +668,250 @@ mod test {
             .is_ok());
     }
 
+    #[test]
+    fn test_eval_queries() {
+        let env = TestEnv::new(MockBackend::new());
+        let query = Value {
+            kind: ValueKind::Query(query::Query::ready(Value::number(1))),
+            ty: Type::Query(Box::new(Type::Number)),
+            label: None,
+        };
+        let set = Value {
+            kind: ValueKind::Set(vec![query]),
+            ty: Type::Set(Box::new(Type::Query(Box::new(Type::Number)))),
+            label: None,
+        };
+        let value = eval_queries(set, &env).unwrap();
+        assert_eq!(value.ty, Type::Set(Box::new(Type::Number)));
+        match value.kind {
+            ValueKind::Set(vs) => assert!(matches!(
+                vs[..],
+                [Value {
+                    kind: ValueKind::Number(1),
+                    ..
+                }]
+            )),
+            _ => panic!(),
+        }
+    }
+
     #[test]
     fn test_meta() {
         let mut interp = Interpreter::new(&MockEnv);
-        // Returns error because the mock env should always return an error.
-        assert_err(
-            interp.interpret_stmt(builder::meta_stmt(ast::MetaKind::Exit)),
-            "exit",
-        );
-        assert_err(
-            interp.interpret_stmt(builder::meta_stmt(ast::MetaKind::Help)),
-            "help",
-        );
+        // Meta-commands are left to the environment.
+        match interp.interpret_stmt(builder::meta_stmt(ast::MetaKind::Exit)) {
+            Ok(Outcome::Meta(ast::MetaKind::Exit)) => {}
+            _ => panic!(),
+        }
+        match interp.interpret_stmt(builder::meta_stmt(ast::MetaKind::Help)) {
+            Ok(Outcome::Meta(ast::MetaKind::Help)) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::<MockEnv>::new();
+        for name in &[
+            "show",
+            "select",
+            "all",
+            "pick",
+            "flatten",
+            "changes",
+            "idents",
+            "def",
+            "outline",
+            "diagnostics",
+            "kind",
+            "name",
+            "contains",
+            "starts",
+            "matches",
+            "within",
+            "overlaps",
+            "typeof",
+            "info",
+            "supertraits",
+            "subtraits",
+            "fields",
+            "methods",
+            "nomacro",
+            "start",
+            "end",
+            "file",
+            "crate",
+            "mod",
+            "at",
+            "expand",
+            "before",
+            "after",
+            "changed",
+            "export",
+        ] {
+            assert!(registry.lookup(name).is_some());
+        }
+        assert!(registry.lookup("foo").is_none());
+
+        let names: Vec<_> = registry.functions().iter().map(|f| f.name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+
+        let mut interp = Interpreter::new(&MockEnv);
+        match interp.interpret_stmt(builder::apply_stmt("foo", builder::void())) {
+            Err(Error::UnknownFunction(ref s)) if s == "foo" => {}
+            _ => panic!(),
+        }
+    }
+
+    // Functions are dispatched by the registry for both evaluation and typing.
+    #[test]
+    fn test_dispatch() {
+        let mut interp = Interpreter::new(&MockEnv);
+        let set = ast::Expr {
+            kind: ast::ExprKind::Set(vec![builder::number(1), builder::number(2)]),
+            ctx: builder::ctx(),
+        };
+        let pick = builder::apply("pick", set, vec![]);
+        assert_eq!(interp.type_expr(&pick.kind).unwrap(), Type::Number);
+        match interp.interpret_expr(pick.kind).unwrap().kind {
+            ValueKind::Number(1) => {}
+            _ => panic!(),
+        }
+
+        let loc = ast::Expr {
+            kind: ast::ExprKind::Location(builder::location(
+                Some("foo.rs".to_owned()),
+                Some(3),
+                Some(9),
+            )),
+            ctx: builder::ctx(),
+        };
+        let def = builder::apply("def", builder::apply("idents", loc, vec![]), vec![]);
+        let ty = Type::Query(Box::new(Type::Set(Box::new(Type::Definition))));
+        assert_eq!(interp.type_expr(&def.kind).unwrap(), ty);
+        let value = interp.interpret_expr(def.kind).unwrap();
+        assert_eq!(value.ty, ty);
+    }
+
+    #[test]
+    fn test_check_capabilities() {
+        use crate::back::{Capabilities, Capability};
+        use crate::front::function::check_capabilities;
+
+        let available = Capabilities::NONE.with(Capability::Idents);
+        assert!(check_capabilities("foo", &[], available).is_ok());
+        assert!(check_capabilities("foo", &[Capability::Idents], available).is_ok());
+        match check_capabilities("foo", &[Capability::Idents, Capability::Symbols], available) {
+            Err(e @ Error::Unsupported("foo", Capability::Symbols)) => {
+                assert!(e.to_string().contains("symbols"))
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_location_math() {
+        use super::data::{Position, Range, Span};
+
+        let file = MockEnv
+            .file_system()
+            .find("foo.rs".to_owned().into())
+            .unwrap()
+            .pop()
+            .unwrap();
+        let mut interp = Interpreter::new(&MockEnv);
+        let mut eval = |name: &str, line: usize, args: Vec<ast::Expr>| {
+            let loc = ast::Expr {
+                kind: ast::ExprKind::Location(builder::location(
+                    Some("foo.rs".to_owned()),
+                    Some(line),
+                    Some(3),
+                )),
+                ctx: builder::ctx(),
+            };
+            interp
+                .interpret_expr(builder::apply(name, loc, args).kind)
+                .map(|v| v.kind)
+        };
+
+        match eval("expand", 5, vec![builder::number(2)]).unwrap() {
+            ValueKind::Range(Range::Span(s)) => {
+                assert_eq!(
+                    s,
+                    Span::new_zero_indexed(file, 2, 0, 6, s.end_column.zero_indexed())
+                );
+                assert!(s.end_column.zero_indexed() > 0);
+            }
+            _ => panic!(),
+        }
+        // Clamped to the start and end of the file (MockFs files have 20 lines).
+        match eval("expand", 2, vec![builder::number(100)]).unwrap() {
+            ValueKind::Range(Range::Span(s)) => {
+                assert_eq!(
+                    (s.start_line.one_indexed(), s.end_line.one_indexed()),
+                    (1, 20)
+                )
+            }
+            _ => panic!(),
+        }
+        match eval("before", 5, vec![builder::number(1)]).unwrap() {
+            ValueKind::Range(Range::Line(f, l)) if f == file && l.one_indexed() == 4 => {}
+            _ => panic!(),
+        }
+        match eval("after", 5, vec![builder::number(2)]).unwrap() {
+            ValueKind::Range(Range::Span(s)) => {
+                assert_eq!(
+                    (s.start_line.one_indexed(), s.end_line.one_indexed()),
+                    (6, 7)
+                )
+            }
+            _ => panic!(),
+        }
+        assert!(eval("before", 1, vec![builder::number(1)]).is_err());
+        assert!(eval("after", 20, vec![builder::number(1)]).is_err());
+        match eval("start", 5, vec![]).unwrap() {
+            ValueKind::Position(p) => assert_eq!(p, Position::new_zero_indexed(file, 4, 2)),
+            _ => panic!(),
+        }
+        match eval("file", 5, vec![]).unwrap() {
+            ValueKind::Range(Range::File(f)) if f == file => {}
+            _ => panic!(),
+        }
+        match eval("at", 5, vec![builder::number(10), builder::number(4)]).unwrap() {
+            ValueKind::Position(p) => assert_eq!(p, Position::new_zero_indexed(file, 9, 3)),
+            _ => panic!(),
+        }
+        // The column defaults to 1.
+        match eval("at", 5, vec![builder::number(10)]).unwrap() {
+            ValueKind::Position(p) => assert_eq!(p, Position::new_zero_indexed(file, 9, 0)),
+            _ => panic!(),
+        }
+        assert!(eval("at", 5, vec![builder::number(0)]).is_err());
+        assert!(eval("at", 5, vec![builder::number(21)]).is_err());
+        match eval("expand", 5, vec![builder::void()]) {
+            Err(Error::TypeError(_)) => {}
+            _ => panic!(),
+        }
+        // `n` defaults to 3.
+        match eval("expand", 5, vec![]).unwrap() {
+            ValueKind::Range(Range::Span(s)) => {
+                assert_eq!(
+                    (s.start_line.one_indexed(), s.end_line.one_indexed()),
+                    (2, 8)
+                )
+            }
+            _ => panic!(),
+        }
+        match eval("expand", 5, vec![builder::number(1), builder::number(2)]) {
+            Err(Error::TypeError(msg)) => assert_eq!(
+                msg,
+                "Incorrect arguments, expected: 0 or 1, found 2 \
+                 (all arguments are optional, defaulting to 3)"
+            ),
+            _ => panic!(),
+        }
     }
 
     #[test]
@@ -1,11 +1,26 @@
-use crate::back::Backend;
-use crate::front::data::{Type, Value, ValueKind};
+//! Queries: values which are only computed when they are needed, so that the
+//! backend is asked for as little as possible. Functions are applied lazily by
+//! `Function::lazy` (in the `function` module), which also says how to
+//! evaluate them.
+
+use crate::ast;
+use crate::back::{Backend, NoProgress, Progress};
+use crate::front::data::{self, DefKind, Type, Value, ValueKind};
 use crate::front::Error;
+use regex::Regex;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
 
 #[derive(Clone)]
 pub enum Query {
     Ready(Box<Value>),
     Function(Fun),
+    // A set whose elements may be queries, and the type of the set.
+    Set(Vec<Value>, Type),
+    // An element or slice of a set, e.g., `$[2..5]`, and the type of the result.
+    Slice(Box<Query>, ast::Slice, Type),
+    // A query evaluated without the result limit (`q->all`).
+    Unlimited(Box<Query>),
 }
 
 impl Query {
@@ -13,130 +28,803 @@ impl Query {
         Query::Ready(Box::new(value))
     }
 
-    pub fn eval(&self, back: &dyn Backend) -> Result<Value, Error> {
+    /// A set of `elements`, some of which may be queries. `ty` is the type of
+    /// the set.
+    pub fn set(elements: Vec<Value>, ty: Type) -> Query {
+        Query::Set(elements, ty)
+    }
+
+    pub fn slice(lhs: Query, slice: ast::Slice, ty: Type) -> Query {
+        Query::Slice(Box::new(lhs), slice, ty)
+    }
+
+    pub fn unlimited(lhs: Query) -> Query {
+        Query::Unlimited(Box::new(lhs))
+    }
+
+    pub fn eval(&self, ctx: &EvalCtx) -> Result<Value, Error> {
         match self {
             Query::Ready(v) => Ok((**v).clone()),
-            Query::Function(f) => f.def.eval(f, back),
+            Query::Function(f) => {
+                if ctx.out_of_time() {
+                    return ctx.aborted(&f.ty);
+                }
+                Ok(ctx.truncate((f.eval)(f, ctx)?))
+            }
+            Query::Set(elements, ty) => {
+                let elements = elements
+                    .iter()
+                    .map(|v| match &v.kind {
+                        ValueKind::Query(q) => q.eval(ctx),
+                        _ => Ok(v.clone()),
+                    })
+                    .collect::<Result<_, _>>()?;
+                // Elements which were queries may not have the types we expected.
+                Ok(ctx.truncate(Value::set(elements, ty.expect_set_inner())?))
+            }
+            Query::Slice(lhs, s, ty) => slice(lhs.eval(ctx)?, *s, ty.clone()),
+            Query::Unlimited(q) => q.eval(&EvalCtx {
+                limit: None,
+                ..*ctx
+            }),
+        }
+    }
+}
+
+/// What a query is evaluated with.
+#[derive(Clone, Copy)]
+pub struct EvalCtx<'a> {
+    pub back: &'a dyn Backend,
+    /// Sets produced while evaluating a query are truncated to this many elements.
+    pub limit: Option<usize>,
+    /// If true, identifiers from macro expansions are left out of the
+    /// results of `idents`.
+    pub hide_macros: bool,
+    /// Updated by operations over many files or identifiers.
+    pub progress: &'a dyn Progress,
+    /// How long evaluation may take, if it is limited.
+    pub budget: Option<&'a Budget>,
+    // Set if any set was truncated.
+    truncated: &'a Cell<bool>,
+}
+
+impl<'a> EvalCtx<'a> {
+    /// `truncated` is set if any set is truncated during evaluation, in which
+    /// case the result may be incomplete.
+    pub fn new(back: &'a dyn Backend, limit: Option<usize>, truncated: &'a Cell<bool>) -> Self {
+        EvalCtx {
+            back,
+            limit,
+            hide_macros: false,
+            progress: &NoProgress,
+            budget: None,
+            truncated,
+        }
+    }
+
+    pub fn budget(self, budget: Option<&'a Budget>) -> Self {
+        EvalCtx { budget, ..self }
+    }
+
+    /// Sets being built should then be finished with the elements found so far.
+    pub fn out_of_time(&self) -> bool {
+        match self.budget {
+            Some(budget) if budget.exceeded() => {
+                self.truncated.set(true);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // An empty set, or an error if `ty` is not a set.
+    fn aborted(&self, ty: &Type) -> Result<Value, Error> {
+        match ty.unquery() {
+            Type::Set(inner) => Value::set(Vec::new(), *inner),
+            _ => Err(Error::Aborted(
+                self.budget.map_or_else(Duration::default, |b| b.timeout),
+            )),
+        }
+    }
+
+    pub fn progress(self, progress: &'a dyn Progress) -> Self {
+        EvalCtx { progress, ..self }
+    }
+
+    pub fn hide_macros(self, hide_macros: bool) -> Self {
+        EvalCtx {
+            hide_macros,
+            ..self
+        }
+    }
+
+    /// Truncate `value` to the limit, if it is a set.
+    pub fn truncate(&self, mut value: Value) -> Value {
+        if let (ValueKind::Set(elements), Some(limit)) = (&mut value.kind, self.limit) {
+            if elements.len() > limit {
+                elements.truncate(limit);
+                self.truncated.set(true);
+            }
         }
+        value
     }
 }
 
+/// A limit on how long the queries of a statement may be evaluated for (see
+/// `Settings::timeout`).
+pub struct Budget {
+    pub timeout: Duration,
+    deadline: Instant,
+    // Set once the deadline has been found to have passed.
+    exceeded: Cell<bool>,
+}
+
+impl Budget {
+    /// A budget of `timeout`, starting now.
+    pub fn start(timeout: Duration) -> Budget {
+        Budget {
+            timeout,
+            deadline: Instant::now() + timeout,
+            exceeded: Cell::new(false),
+        }
+    }
+
+    /// True if the deadline has passed.
+    pub fn exceeded(&self) -> bool {
+        if !self.exceeded.get() && Instant::now() >= self.deadline {
+            self.exceeded.set(true);
+        }
+        self.exceeded.get()
+    }
+
+    /// True if evaluation ran out of time (i.e., `exceeded` has been true),
+    /// whether or not the deadline has passed since.
+    pub fn was_exceeded(&self) -> bool {
+        self.exceeded.get()
+    }
+}
+
+/// As in Python, negative indices count from the end.
+pub fn slice(value: Value, slice: ast::Slice, ty: Type) -> Result<Value, Error> {
+    let mut elements = match value.kind {
+        ValueKind::Set(elements) => elements,
+        ValueKind::Void => Vec::new(),
+        _ => {
+            return Err(Error::TypeError(format!(
+                "Unexpected runtime type, expected: set, found: {:?}",
+                value.ty
+            )))
+        }
+    };
+    let len = elements.len() as isize;
+    match slice {
+        ast::Slice::Element(i) => {
+            let index = if i < 0 { i + len } else { i };
+            if index < 0 || index >= len {
+                return Err(Error::IndexOutOfRange(i, elements.len()));
+            }
+            Ok(elements.swap_remove(index as usize))
+        }
+        ast::Slice::Range(start, end) => {
+            let clamp = |i: isize| (if i < 0 { i + len } else { i }).max(0).min(len) as usize;
+            let start = start.map(clamp).unwrap_or(0);
+            let end = end.map(clamp).unwrap_or(elements.len());
+            let elements = if start < end {
+                elements.drain(start..end).collect()
+            } else {
+                Vec::new()
+            };
+            Value::set(elements, ty.expect_set_inner())
+        }
+    }
+}
+
+/// Evaluates a function applied lazily, see `Function::eval_query`.
+pub type EvalFn = fn(&Fun, &EvalCtx) -> Result<Value, Error>;
+
+/// A function applied to a query (and the values of its arguments), see
+/// `Function::lazy`.
 #[derive(Clone)]
 pub struct Fun {
-    pub def: &'static dyn Function,
+    pub eval: EvalFn,
+    /// The type of the result.
     pub ty: Type,
     pub lhs: Box<Query>,
     pub args: Vec<Value>,
 }
 
-pub trait Function {
-    fn eval(&self, f: &Fun, back: &dyn Backend) -> Result<Value, Error>;
-}
-
-#[derive(Clone)]
-pub struct Pick;
-
-impl Pick {
-    pub fn new(lhs: Query, ty: Type) -> Query {
-        Query::Function(Fun {
-            def: &Pick,
-            ty,
-            lhs: Box::new(lhs),
-            args: vec![],
-        })
-    }
+/// The identifiers which declare definitions: for `Decl`, of the definition
+/// an identifier refers to, for `Ident`, of a definition itself.
+#[derive(Clone, Copy)]
+pub enum Declaration {
+    Decl,
+    Ident,
 }
 
-impl Function for Pick {
-    fn eval(&self, f: &Fun, back: &dyn Backend) -> Result<Value, Error> {
-        let lhs = f.lhs.eval(back)?;
-        match lhs.kind {
-            ValueKind::Set(s) => Ok(s[0].clone()),
+impl Declaration {
+    // The identifier which declares `value` (or what it refers to).
+    fn declaration(self, value: Value, back: &dyn Backend) -> Result<data::Identifier, Error> {
+        let def = match (self, value.kind) {
+            (Declaration::Decl, ValueKind::Identifier(id)) => back.definition(id)?,
+            (Declaration::Ident, ValueKind::Definition(def)) => def,
             _ => {
                 return Err(Error::TypeError(format!(
-                    "Unexpected runtime type, expected: set, found: {:?}",
-                    lhs.ty
+                    "Unexpected runtime type, expected: {}, found: {:?}",
+                    match self {
+                        Declaration::Decl => "identifier",
+                        Declaration::Ident => "def",
+                    },
+                    value.ty
                 )))
             }
+        };
+        Ok(back.def_ident(def)?)
+    }
+
+    /// The declaring identifiers of the lhs of `f`, an identifier or
+    /// definition, or a set of them.
+    pub fn eval(self, f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        let lhs = f.lhs.eval(ctx)?;
+        match lhs.kind {
+            ValueKind::Set(values) => {
+                // Many identifiers may be declared by the same identifier.
+                let mut result: Vec<Value> = Vec::new();
+                for v in values {
+                    if ctx.out_of_time() {
+                        break;
+                    }
+                    let id = self.declaration(v, ctx.back)?.into();
+                    if !result.iter().any(|r| r.same_as(&id)) {
+                        result.push(id);
+                    }
+                }
+                Value::set(result, Type::Identifier)
+            }
+            _ => Ok(self.declaration(lhs, ctx.back)?.into()),
         }
     }
 }
 
-#[derive(Clone)]
-pub struct Idents;
-
-impl Idents {
-    pub fn new(lhs: Query) -> Query {
-        Query::Function(Fun {
-            def: &Idents,
-            ty: Type::Set(Box::new(Type::Identifier)),
-            lhs: Box::new(lhs),
-            args: vec![],
+/// A test on text: a string, or the name of an identifier or definition.
+#[derive(Clone, Copy, Debug)]
+pub enum TextMatch {
+    Contains,
+    Starts,
+    // As a regular expression, anywhere unless anchored.
+    Matches,
+}
+
+// A `TextMatch` with its pattern.
+enum Matcher<'a> {
+    Contains(&'a str),
+    Starts(&'a str),
+    Matches(Regex),
+}
+
+impl<'a> Matcher<'a> {
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Matcher::Contains(p) => text.contains(p),
+            Matcher::Starts(p) => text.starts_with(p),
+            Matcher::Matches(re) => re.is_match(text),
+        }
+    }
+}
+
+impl TextMatch {
+    /// Check that `pattern` is valid, i.e., that a regex can be compiled.
+    pub fn check(self, pattern: &str) -> Result<(), Error> {
+        self.matcher(pattern).map(|_| ())
+    }
+
+    fn matcher(self, pattern: &str) -> Result<Matcher<'_>, Error> {
+        Ok(match self {
+            TextMatch::Contains => Matcher::Contains(pattern),
+            TextMatch::Starts => Matcher::Starts(pattern),
+            TextMatch::Matches => Matcher::Matches(Regex::new(pattern).map_err(|e| {
+                // Syntax errors are shown over several lines, with the cause last.
+                let msg = e.to_string();
+                let cause = msg.lines().last().unwrap_or("");
+                let cause = cause.trim_start_matches("error: ");
+                Error::Other(format!(
+                    "Invalid regular expression `{}`: {}",
+                    pattern, cause
+                ))
+            })?),
         })
     }
+
+    /// Apply the test to `value`. A single value gives a bool, a set gives the
+    /// elements of the set which pass the test (i.e., the test is a filter).
+    pub fn apply(self, value: Value, pattern: &str) -> Result<Value, Error> {
+        let matcher = self.matcher(pattern)?;
+        match value.kind {
+            ValueKind::Set(values) => {
+                let mut result = Vec::new();
+                for v in values {
+                    if matcher.is_match(expect_text(&v)?) {
+                        result.push(v);
+                    }
+                }
+                Value::set(result, value.ty.expect_set_inner())
+            }
+            _ => Ok(Value::bool(matcher.is_match(expect_text(&value)?))),
+        }
+    }
 }
 
-impl Function for Idents {
-    fn eval(&self, f: &Fun, back: &dyn Backend) -> Result<Value, Error> {
-        let lhs = f.lhs.eval(back)?;
-        let idents = match lhs.kind {
-            ValueKind::Position(p) => back.ident_at(p.clone())?.into_iter().collect(),
-            ValueKind::Range(r) => back.idents_in(r.clone())?,
-            ValueKind::Set(_) => unimplemented!(),
-            _ => {
-                return Err(Error::TypeError(format!(
-                    "Unexpected runtime type, expected: location, found: {:?}",
-                    lhs.ty
-                )))
+/// The text of a string, or the name of an identifier or definition.
+pub fn text(value: &Value) -> Option<&str> {
+    match &value.kind {
+        ValueKind::String(s) => Some(s),
+        ValueKind::Identifier(id) => Some(&id.name),
+        ValueKind::Definition(def) => Some(&def.name),
+        _ => None,
+    }
+}
+
+fn expect_text(value: &Value) -> Result<&str, Error> {
+    text(value).ok_or_else(|| {
+        Error::TypeError(format!(
+            "Unexpected runtime type, expected: string, identifier, or def, found: {:?}",
+            value.ty
+        ))
+    })
+}
+
+/// Tests of where a value is compared to other values (e.g., `within`), which
+/// filter sets like the `TextMatch` tests.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Relation {
+    Within,
+    Overlaps,
+}
+
+impl Relation {
+    /// The extents of `others`, a value or a set of values. Sets are flattened.
+    pub fn extents(others: &Value) -> Result<Vec<data::Extent>, Error> {
+        match &others.kind {
+            ValueKind::Set(vs) => {
+                let mut result = Vec::new();
+                for v in vs {
+                    result.extend(Relation::extents(v)?);
+                }
+                Ok(result)
             }
-        };
+            ValueKind::Partial(v) => Relation::extents(v),
+            _ => Ok(vec![expect_extent(others)?]),
+        }
+    }
 
-        Ok(Value {
-            kind: ValueKind::Set(
-                idents
-                    .into_iter()
-                    .map(|i| Value {
-                        kind: ValueKind::Identifier(i),
-                        ty: Type::Identifier,
-                    })
-                    .collect(),
-            ),
-            ty: f.ty.clone(),
+    fn test(self, extent: &data::Extent, others: &[data::Extent]) -> bool {
+        others.iter().any(|other| match self {
+            Relation::Within => extent.within(other),
+            Relation::Overlaps => extent.overlaps(other),
         })
     }
+
+    /// Apply the test to `value`. A single value gives a bool, a set gives the
+    /// elements of the set which pass the test.
+    pub fn apply(self, value: Value, others: &[data::Extent]) -> Result<Value, Error> {
+        match value.kind {
+            ValueKind::Set(values) => {
+                let mut result = Vec::new();
+                for v in values {
+                    if self.test(&expect_extent(&v)?, others) {
+                        result.push(v);
+                    }
+                }
+                Value::set(result, value.ty.expect_set_inner())
+            }
+            _ => Ok(Value::bool(self.test(&expect_extent(&value)?, others))),
+        }
+    }
 }
 
-#[derive(Clone)]
-pub struct Definition;
-
-impl Definition {
-    pub fn new(lhs: Query, ty: Type) -> Query {
-        Query::Function(Fun {
-            def: &Definition,
-            ty,
-            lhs: Box::new(lhs),
-            args: vec![],
+fn expect_extent(value: &Value) -> Result<data::Extent, Error> {
+    value.extent().ok_or_else(|| {
+        Error::TypeError(format!(
+            "Unexpected runtime type, expected: location, identifier, def, or diagnostic, \
+             found: {:?}",
+            value.ty
+        ))
+    })
+}
+
+/// The supertraits or subtraits of traits.
+#[derive(Clone, Copy)]
+pub enum TraitHierarchy {
+    Super,
+    Sub,
+}
+
+impl TraitHierarchy {
+    /// The related traits of the lhs of `f`, see `eval_related`.
+    pub fn eval(self, f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        eval_related(
+            f,
+            ctx,
+            match self {
+                TraitHierarchy::Super => "supertrait",
+                TraitHierarchy::Sub => "subtrait",
+            },
+            |d| d.kind == DefKind::Trait,
+            |d| self.related(d, ctx.back),
+        )
+    }
+
+    // The traits related to `def`, an error if `def` is not a trait.
+    fn related(
+        self,
+        def: data::Definition,
+        back: &dyn Backend,
+    ) -> Result<Vec<data::Definition>, Error> {
+        if def.kind != DefKind::Trait {
+            return Err(Error::Other(format!("`{}` is not a trait", def.name)));
+        }
+        Ok(match self {
+            TraitHierarchy::Super => back.super_traits(def)?,
+            TraitHierarchy::Sub => back.sub_traits(def)?,
         })
     }
 }
 
-impl Function for Definition {
-    fn eval(&self, f: &Fun, back: &dyn Backend) -> Result<Value, Error> {
-        let lhs = f.lhs.eval(back)?;
-        let def = match lhs.kind {
-            ValueKind::Identifier(id) => back.definition(id.clone())?,
-            ValueKind::Set(_) => unimplemented!(),
-            _ => {
-                return Err(Error::TypeError(format!(
-                    "Unexpected runtime type, expected: identifier, found: {:?}",
-                    lhs.ty
-                )))
+/// The fields or methods of types and traits.
+#[derive(Clone, Copy)]
+pub enum Members {
+    Fields,
+    Methods,
+}
+
+impl Members {
+    /// The members of the lhs of `f`, see `eval_related`.
+    pub fn eval(self, f: &Fun, ctx: &EvalCtx) -> Result<Value, Error> {
+        eval_related(
+            f,
+            ctx,
+            match self {
+                Members::Fields => "field",
+                Members::Methods => "method",
+            },
+            |d| self.applies_to(d.kind),
+            |d| self.members(d, ctx.back),
+        )
+    }
+
+    fn applies_to(self, kind: DefKind) -> bool {
+        matches!(
+            (self, kind),
+            (Members::Fields, DefKind::Struct)
+                | (Members::Fields, DefKind::Union)
+                | (Members::Fields, DefKind::Variant)
+                | (Members::Methods, DefKind::Struct)
+                | (Members::Methods, DefKind::Enum)
+                | (Members::Methods, DefKind::Union)
+                | (Members::Methods, DefKind::Trait)
+        )
+    }
+
+    // The members of `def`, an error if `def` can't have members of this kind.
+    fn members(
+        self,
+        def: data::Definition,
+        back: &dyn Backend,
+    ) -> Result<Vec<data::Definition>, Error> {
+        if !self.applies_to(def.kind) {
+            let expected = match self {
+                Members::Fields => "struct, union, or variant",
+                Members::Methods => "struct, enum, union, or trait",
+            };
+            return Err(Error::Other(format!(
+                "`{}` is not a {}",
+                def.name, expected
+            )));
+        }
+        Ok(match self {
+            Members::Fields => back.fields(def)?,
+            Members::Methods => back.methods(def)?,
+        })
+    }
+}
+
+// Elements of a set which `applies` rejects are ignored, a single definition
+// is always passed to `related`.
+fn eval_related(
+    f: &Fun,
+    ctx: &EvalCtx,
+    relation: &str,
+    applies: impl Fn(&data::Definition) -> bool,
+    related: impl Fn(data::Definition) -> Result<Vec<data::Definition>, Error>,
+) -> Result<Value, Error> {
+    let lhs = f.lhs.eval(ctx)?;
+    // Identifiers are resolved to their definitions.
+    let def = |v: Value| match v.kind {
+        ValueKind::Definition(def) => Ok(def),
+        ValueKind::Identifier(id) => Ok(ctx.back.definition(id)?),
+        _ => Err(Error::TypeError(format!(
+            "Unexpected runtime type, expected: identifier or def, found: {:?}",
+            v.ty
+        ))),
+    };
+    let mut result: Vec<(data::Definition, String)> = Vec::new();
+    let mut add = |from: data::Definition| -> Result<(), Error> {
+        let label = format!("{} of `{}`", relation, from.name);
+        for d in related(from)? {
+            if !result.iter().any(|(r, _)| r.same_as(&d)) {
+                result.push((d, label.clone()));
             }
+        }
+        Ok(())
+    };
+    match lhs.kind {
+        ValueKind::Set(values) => {
+            for v in values {
+                if ctx.out_of_time() {
+                    break;
+                }
+                let d = def(v)?;
+                if applies(&d) {
+                    add(d)?;
+                }
+            }
+        }
+        ValueKind::Void => {}
+        kind => add(def(Value {
+            kind,
+            ty: lhs.ty,
+            label: None,
+        })?)?,
+    }
+
+    Value::set(
+        result
+            .into_iter()
+            .map(|(d, label)| Value {
+                kind: ValueKind::Definition(d),
+                ty: Type::Definition,
+                label: Some(label),
+            })
+            .collect(),
+        Type::Definition,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::back::{self, Capabilities};
+    use crate::file_system::{FileSystem, MockFs, Path};
+    use crate::front::data::{self, Identifier, Line, Range, Span};
+    use crate::front::function::{self, Function};
+    use std::cell::Cell;
+
+    // Counts calls to the backend.
+    #[derive(Default)]
+    struct CountingBackend {
+        single: Cell<usize>,
+        batched: Cell<usize>,
+    }
+
+    impl Backend for CountingBackend {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::NONE
+        }
+
+        fn idents_in(&self, range: Range) -> Result<Vec<Identifier>, back::Error> {
+            self.single.set(self.single.get() + 1);
+            match range {
+                Range::Line(p, l) => Ok(vec![ident(p, l)]),
+                _ => unreachable!(),
+            }
+        }
+
+        fn definition(&self, id: Identifier) -> Result<data::Definition, back::Error> {
+            self.single.set(self.single.get() + 1);
+            Ok(def(id))
+        }
+
+        fn definitions(
+            &self,
+            ids: &[Identifier],
+            _: &dyn back::Progress,
+        ) -> Result<Vec<data::Definition>, back::Error> {
+            self.batched.set(self.batched.get() + 1);
+            Ok(ids.iter().cloned().map(def).collect())
+        }
+    }
+
+    fn ident(file: Path, line: Line) -> Identifier {
+        Identifier {
+            id: line.zero_indexed() as u64,
+            span: Span::new_zero_indexed(file, line.zero_indexed(), 0, line.zero_indexed(), 3),
+            name: "foo".to_owned(),
+            from_macro: false,
+            kind: None,
+            def: None,
+        }
+    }
+
+    fn def(id: Identifier) -> data::Definition {
+        data::Definition {
+            id: id.id,
+            span: id.span,
+            name: id.name,
+            kind: data::DefKind::Function,
+            path: None,
+        }
+    }
+
+    fn lines(n: usize) -> Value {
+        let file = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        Value {
+            kind: ValueKind::Set(
+                (0..n)
+                    .map(|l| Value {
+                        kind: ValueKind::Range(Range::Line(file, Line::new_zero_indexed(l))),
+                        ty: Type::Range,
+                        label: None,
+                    })
+                    .collect(),
+            ),
+            ty: Type::Set(Box::new(Type::Range)),
+            label: None,
+        }
+    }
+
+    // A query for the definitions of the identifiers on the first `n` lines.
+    fn defs(n: usize) -> Query {
+        let idents = function::Idents::lazy(
+            Query::ready(lines(n)),
+            vec![],
+            Type::Set(Box::new(Type::Identifier)),
+        );
+        function::Definition::lazy(idents, vec![], Type::Set(Box::new(Type::Definition)))
+    }
+
+    #[test]
+    fn test_batching() {
+        let back = CountingBackend::default();
+        let query = defs(10);
+        let truncated = Cell::new(false);
+        let result = query.eval(&EvalCtx::new(&back, None, &truncated)).unwrap();
+        match result.kind {
+            ValueKind::Set(defs) => assert_eq!(defs.len(), 10),
+            _ => panic!(),
+        }
+        assert_eq!(result.ty, Type::Set(Box::new(Type::Definition)));
+        // `idents_in_many` falls back to calling `idents_in` for each range.
+        assert_eq!(back.single.get(), 10);
+        // `definitions` is called once rather than calling `definition` ten times.
+        assert_eq!(back.batched.get(), 1);
+    }
+
+    #[test]
+    fn test_kind() {
+        let back = CountingBackend::default();
+        let truncated = Cell::new(false);
+        let count = |query: Query| match query
+            .eval(&EvalCtx::new(&back, None, &truncated))
+            .unwrap()
+            .kind
+        {
+            ValueKind::Set(defs) => defs.len(),
+            _ => panic!(),
         };
+        let kind = |kind: &str| {
+            function::Kind::lazy(
+                defs(3),
+                vec![Value::string(kind.to_owned())],
+                Type::Set(Box::new(Type::Definition)),
+            )
+        };
+        assert_eq!(count(kind("fn")), 3);
+        assert_eq!(count(kind("struct")), 0);
+    }
 
-        Ok(Value {
-            kind: ValueKind::Definition(def),
-            ty: Type::Definition,
-        })
+    #[test]
+    fn test_limit() {
+        let back = CountingBackend::default();
+        let truncated = Cell::new(false);
+        let eval = |query: Query, limit| match query
+            .eval(&EvalCtx::new(&back, limit, &truncated))
+            .unwrap()
+            .kind
+        {
+            ValueKind::Set(defs) => defs.len(),
+            _ => panic!(),
+        };
+
+        assert_eq!(eval(defs(10), Some(10)), 10);
+        assert!(!truncated.get());
+        // Sets are truncated as they are produced, so only the definitions of
+        // the first four identifiers are found.
+        assert_eq!(eval(defs(10), Some(4)), 4);
+        assert!(truncated.get());
+        assert_eq!(back.batched.get(), 2);
+        truncated.set(false);
+        assert_eq!(eval(Query::unlimited(defs(10)), Some(4)), 10);
+        assert!(!truncated.get());
+    }
+
+    #[test]
+    fn test_budget() {
+        let back = CountingBackend::default();
+        let truncated = Cell::new(false);
+        let len = |value: Value| match value.kind {
+            ValueKind::Set(defs) => defs.len(),
+            _ => panic!(),
+        };
+
+        let budget = Budget::start(Duration::from_secs(60));
+        let ctx = EvalCtx::new(&back, None, &truncated).budget(Some(&budget));
+        assert_eq!(len(defs(10).eval(&ctx).unwrap()), 10);
+        assert!(!truncated.get());
+        assert!(!budget.was_exceeded());
+
+        // Out of time before anything is evaluated, so the backend isn't asked
+        // and the result is an empty (partial) set.
+        let calls = (back.single.get(), back.batched.get());
+        let budget = Budget::start(Duration::from_secs(0));
+        let ctx = EvalCtx::new(&back, None, &truncated).budget(Some(&budget));
+        assert_eq!(len(defs(10).eval(&ctx).unwrap()), 0);
+        assert!(truncated.get());
+        assert!(budget.was_exceeded());
+        assert_eq!((back.single.get(), back.batched.get()), calls);
+
+        // A result which is not a set can't be partial.
+        let file = MockFs.find("foo.rs".to_owned().into()).unwrap()[0];
+        let id = ident(file, Line::new_zero_indexed(0));
+        let query = function::Definition::lazy(Query::ready(id.into()), vec![], Type::Definition);
+        match query.eval(&ctx) {
+            Err(Error::Aborted(timeout)) => assert_eq!(timeout, Duration::from_secs(0)),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_text_match() {
+        let strings = || Value {
+            kind: ValueKind::Set(vec![
+                Value::string("test_foo".to_owned()),
+                Value::string("foo_test".to_owned()),
+                Value::string("bar".to_owned()),
+            ]),
+            ty: Type::Set(Box::new(Type::String)),
+            label: None,
+        };
+        let matching = |test: TextMatch, pattern| match test.apply(strings(), pattern).unwrap().kind
+        {
+            ValueKind::Set(s) => s.into_iter().map(|s| s.expect_string()).collect::<Vec<_>>(),
+            _ => panic!(),
+        };
+        assert_eq!(
+            matching(TextMatch::Contains, "test"),
+            ["test_foo", "foo_test"]
+        );
+        assert_eq!(matching(TextMatch::Starts, "test"), ["test_foo"]);
+        assert_eq!(matching(TextMatch::Matches, "^test_"), ["test_foo"]);
+        assert_eq!(
+            matching(TextMatch::Matches, "_(foo|test)$"),
+            ["test_foo", "foo_test"]
+        );
+        assert!(matching(TextMatch::Contains, "baz").is_empty());
+
+        let is_match = |test: TextMatch, pattern| match test
+            .apply(Value::string("foo".to_owned()), pattern)
+            .unwrap()
+            .kind
+        {
+            ValueKind::Bool(b) => b,
+            _ => panic!(),
+        };
+        assert!(is_match(TextMatch::Starts, "f"));
+        assert!(!is_match(TextMatch::Matches, "^o"));
+
+        assert!(TextMatch::Matches.check("(").is_err());
+        assert!(TextMatch::Contains.check("(").is_ok());
     }
 }
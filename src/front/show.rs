@@ -0,0 +1,383 @@
+//! Options for showing values to the user, and helpers for fitting output
+//! into the available space.
+
+use crate::env::{ColorChoice, DisplayMode, Settings};
+use unicode_width::UnicodeWidthChar;
+
+const ELLIPSIS: &str = "...";
+// Source text is never truncated to fewer than this many characters, even if
+// the output is very narrow.
+const MIN_TEXT_WIDTH: usize = 20;
+// Tabs in source text are expanded to the next multiple of this many columns.
+const TAB_WIDTH: usize = 4;
+// ANSI escape sequence to go back to the terminal's usual color.
+const RESET: &str = "\x1b[0m";
+
+/// Colors for highlighting output to a terminal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color {
+    Red,
+    Green,
+    /// Less prominent than other text, e.g., for context.
+    Dim,
+}
+
+impl Color {
+    fn escape(self) -> &'static str {
+        match self {
+            Color::Red => "\x1b[31m",
+            Color::Green => "\x1b[32m",
+            Color::Dim => "\x1b[2m",
+        }
+    }
+}
+
+/// Where output is going, from which its color and width are decided.
+#[derive(Clone, Debug)]
+pub struct DisplayPolicy {
+    /// From the `--color` option or the `color` config setting.
+    pub color: ColorChoice,
+    /// True if output is to a terminal, i.e., it is not redirected.
+    pub tty: bool,
+    /// The width of the terminal, if it is known.
+    pub width: Option<usize>,
+    /// True if the user has asked for no color by setting `NO_COLOR` (see
+    /// https://no-color.org).
+    pub no_color: bool,
+    /// True if the terminal can't handle escape sequences (`TERM=dumb`).
+    pub dumb: bool,
+    /// True if output is also written to a transcript (see `^record`), where
+    /// escape sequences don't belong whatever the user asked for.
+    pub transcript: bool,
+}
+
+impl DisplayPolicy {
+    /// The policy for output which may be to a terminal (`tty`), `var` looks
+    /// up an environment variable by name.
+    pub fn new(
+        color: ColorChoice,
+        tty: bool,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> DisplayPolicy {
+        DisplayPolicy {
+            color,
+            tty,
+            width: None,
+            no_color: var("NO_COLOR").is_some_and(|v| !v.is_empty()),
+            dumb: var("TERM").is_some_and(|v| v == "dumb"),
+            transcript: false,
+        }
+    }
+
+    /// True if output should be colored. `--color=always` overrides `NO_COLOR`
+    /// and the terminal, but never the transcript.
+    pub fn colored(&self) -> bool {
+        if self.transcript {
+            return false;
+        }
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => self.tty && !self.dumb && !self.no_color,
+        }
+    }
+
+    /// How values should be shown under this policy. Only output to a
+    /// terminal has a limited width.
+    pub fn show_ctx(&self, settings: &Settings) -> ShowCtx {
+        let width = if self.tty { self.width } else { None };
+        ShowCtx::new(settings, width).color(self.colored())
+    }
+}
+
+impl Default for DisplayPolicy {
+    fn default() -> DisplayPolicy {
+        DisplayPolicy {
+            color: ColorChoice::Auto,
+            tty: false,
+            width: None,
+            no_color: false,
+            dumb: false,
+            transcript: false,
+        }
+    }
+}
+
+/// How values should be shown.
+#[derive(Clone, Debug)]
+pub struct ShowCtx {
+    /// The width of the output in characters, `None` if lines may be any
+    /// length (e.g., when output is not to a terminal).
+    pub width: Option<usize>,
+    /// Sets (and groups of files) with more elements than this are summarised.
+    pub max_set_len: usize,
+    pub display: DisplayMode,
+    /// If true, output may be colored (e.g., when it is to a terminal).
+    pub color: bool,
+    /// The number of lines shown around the line of a position or span.
+    pub context: usize,
+}
+
+impl ShowCtx {
+    pub fn new(settings: &Settings, width: Option<usize>) -> ShowCtx {
+        ShowCtx {
+            width,
+            max_set_len: settings.max_set_len,
+            display: settings.display,
+            color: false,
+            context: settings.context,
+        }
+    }
+
+    pub fn color(mut self, color: bool) -> ShowCtx {
+        self.color = color;
+        self
+    }
+
+    /// Text to write before output in `color`, empty if output is not
+    /// colored. Follow the output with `end_color`.
+    pub fn start_color(&self, color: Color) -> &'static str {
+        if self.color {
+            color.escape()
+        } else {
+            ""
+        }
+    }
+
+    pub fn end_color(&self) -> &'static str {
+        if self.color {
+            RESET
+        } else {
+            ""
+        }
+    }
+
+    /// Truncate a line of source text to fit after `indent` columns, keeping the
+    /// chars `start..end` visible. Returns the text and the highlighted display
+    /// columns in it.
+    pub fn fit_line(
+        &self,
+        text: &str,
+        indent: usize,
+        start: usize,
+        end: usize,
+    ) -> (String, usize, usize) {
+        let line = DisplayLine::new(text);
+        let start = line.column(start);
+        let end = line.column(end).max(start);
+        let len = line.width();
+        let available = match self.width {
+            Some(width) => width.saturating_sub(indent).max(MIN_TEXT_WIDTH),
+            None => return (line.slice(0, len), start, end),
+        };
+        if len <= available {
+            return (line.slice(0, len), start, end);
+        }
+
+        let end = end.min(len);
+        let e = ELLIPSIS.len();
+
+        // Keep the start of the line if we can.
+        if end + e <= available {
+            let text = line.slice(0, available - e) + ELLIPSIS;
+            return (text, start, end);
+        }
+
+        // Otherwise, show a window of the line around the highlight.
+        let window = available - 2 * e;
+        let highlight = (end - start).min(window);
+        let first = start.saturating_sub((window - highlight) / 2);
+        if first + available - e >= len {
+            // The window reaches the end of the line.
+            let first = len - (available - e);
+            let text = String::from(ELLIPSIS) + &line.slice(first, len);
+            return (text, start - first + e, end - first + e);
+        }
+        if first == 0 {
+            let text = line.slice(0, available - e) + ELLIPSIS;
+            return (text, start, end.min(available - e));
+        }
+        let text = String::from(ELLIPSIS) + &line.slice(first, first + window) + ELLIPSIS;
+        let start = start - first + e;
+        (text, start, (end - first + e).min(window + e))
+    }
+}
+
+// A line of text laid out in display columns.
+struct DisplayLine {
+    // Each char (with tabs expanded to spaces) and its first column.
+    chars: Vec<(String, usize)>,
+    width: usize,
+}
+
+impl DisplayLine {
+    fn new(text: &str) -> DisplayLine {
+        let mut chars = Vec::new();
+        let mut column = 0;
+        for c in text.chars() {
+            let (s, width) = match c {
+                '\t' => {
+                    let width = TAB_WIDTH - column % TAB_WIDTH;
+                    (" ".repeat(width), width)
+                }
+                c => (c.to_string(), c.width().unwrap_or(0)),
+            };
+            chars.push((s, column));
+            column += width;
+        }
+        DisplayLine {
+            chars,
+            width: column,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    // The display column of the char at `offset`; offsets past the end of the
+    // line are counted as one column per char.
+    fn column(&self, offset: usize) -> usize {
+        match self.chars.get(offset) {
+            Some((_, column)) => *column,
+            None => self.width + offset - self.chars.len(),
+        }
+    }
+
+    // Wide chars cut by either end are replaced by spaces.
+    fn slice(&self, from: usize, to: usize) -> String {
+        let mut result = String::new();
+        for (i, (s, start)) in self.chars.iter().enumerate() {
+            let start = *start;
+            let end = self.chars.get(i + 1).map(|c| c.1).unwrap_or(self.width);
+            if start >= to {
+                break;
+            }
+            if start >= from && end <= to {
+                result.push_str(s);
+            } else if end > from {
+                result.push_str(&" ".repeat(end.min(to) - start.max(from)));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unicode_width::UnicodeWidthStr;
+
+    fn ctx(width: Option<usize>) -> ShowCtx {
+        ShowCtx::new(&Settings::default(), width)
+    }
+
+    #[test]
+    fn test_fit_line() {
+        let line: String = (0..100).map(|i| (b'a' + i % 26) as char).collect();
+
+        // No limit, or short enough.
+        assert_eq!(ctx(None).fit_line(&line, 4, 90, 95), (line.clone(), 90, 95));
+        assert_eq!(
+            ctx(Some(80)).fit_line("foo", 4, 1, 2),
+            ("foo".to_owned(), 1, 2)
+        );
+
+        // Truncated at the end.
+        let (text, start, end) = ctx(Some(44)).fit_line(&line, 4, 2, 5);
+        assert_eq!(text.len(), 40);
+        assert!(text.ends_with(ELLIPSIS));
+        assert_eq!((start, end), (2, 5));
+
+        // Truncated at the start.
+        let (text, start, end) = ctx(Some(44)).fit_line(&line, 4, 95, 97);
+        assert_eq!(text.len(), 40);
+        assert!(text.starts_with(ELLIPSIS));
+        assert_eq!(&text[start..end], &line[95..97]);
+
+        // Truncated at both ends.
+        let (text, start, end) = ctx(Some(44)).fit_line(&line, 4, 50, 55);
+        assert_eq!(text.len(), 40);
+        assert!(text.starts_with(ELLIPSIS) && text.ends_with(ELLIPSIS));
+        assert_eq!(&text[start..end], &line[50..55]);
+
+        // Very narrow output still shows some text.
+        let (text, _, _) = ctx(Some(10)).fit_line(&line, 4, 0, 0);
+        assert_eq!(text.len(), MIN_TEXT_WIDTH);
+    }
+
+    #[test]
+    fn test_color() {
+        let ctx = ctx(None);
+        assert_eq!(ctx.start_color(Color::Red), "");
+        assert_eq!(ctx.end_color(), "");
+        let ctx = ctx.color(true);
+        assert_eq!(ctx.start_color(Color::Green), "\x1b[32m");
+        assert_eq!(ctx.end_color(), RESET);
+    }
+
+    #[test]
+    fn test_display_policy() {
+        let settings = Settings::default();
+        let policy = |color, tty, vars: &[(&str, &str)]| {
+            let vars: Vec<(String, String)> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            DisplayPolicy::new(color, tty, |name| {
+                vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+            })
+        };
+
+        assert!(policy(ColorChoice::Auto, true, &[("TERM", "xterm")]).colored());
+        // Redirected output.
+        assert!(!policy(ColorChoice::Auto, false, &[]).colored());
+        assert!(!policy(ColorChoice::Auto, true, &[("TERM", "dumb")]).colored());
+        assert!(!policy(ColorChoice::Auto, true, &[("NO_COLOR", "1")]).colored());
+        // An empty `NO_COLOR` is the same as none.
+        assert!(policy(ColorChoice::Auto, true, &[("NO_COLOR", "")]).colored());
+        assert!(!policy(ColorChoice::Never, true, &[]).colored());
+        assert!(policy(ColorChoice::Always, false, &[("NO_COLOR", "1")]).colored());
+        let mut recording = policy(ColorChoice::Always, true, &[]);
+        recording.transcript = true;
+        assert!(!recording.colored());
+
+        let mut tty = policy(ColorChoice::Auto, true, &[]);
+        tty.width = Some(80);
+        let ctx = tty.show_ctx(&settings);
+        assert_eq!(ctx.width, Some(80));
+        assert!(ctx.color);
+        tty.tty = false;
+        assert_eq!(tty.show_ctx(&settings).width, None);
+        assert!(!DisplayPolicy::default().show_ctx(&settings).color);
+    }
+
+    #[test]
+    fn test_fit_line_columns() {
+        // Tabs are expanded to the next tab stop.
+        assert_eq!(
+            ctx(None).fit_line("\tfoo(\tbar)", 4, 6, 9),
+            ("    foo(    bar)".to_owned(), 12, 15)
+        );
+        // Wide chars take two columns.
+        assert_eq!(
+            ctx(None).fit_line("let 名前 = 1;", 4, 4, 6),
+            ("let 名前 = 1;".to_owned(), 4, 8)
+        );
+        assert_eq!(
+            ctx(None).fit_line("\"é\" + x", 4, 6, 7),
+            ("\"é\" + x".to_owned(), 6, 7)
+        );
+
+        // Truncation counts columns, wide chars cut by an ellipsis are
+        // replaced by spaces.
+        let line = "名".repeat(30) + "foo";
+        let (text, start, end) = ctx(Some(44)).fit_line(&line, 4, 30, 33);
+        assert!(text.starts_with(ELLIPSIS));
+        assert_eq!(UnicodeWidthStr::width(&*text), 40);
+        assert_eq!(&text[text.len() - 3..], "foo");
+        assert_eq!((start, end), (37, 40));
+        let (text, _, _) = ctx(Some(24)).fit_line(&line, 4, 0, 1);
+        assert_eq!(text, "名".repeat(8) + " " + ELLIPSIS);
+    }
+}
@@ -0,0 +1,514 @@
+//! Snapshot tests of how values are shown. Each test shows a series of values
+//! and compares the output with a file in `snapshots/`, so that intended
+//! changes to the output are reviewed along with the code which makes them,
+//! and unintended ones are caught.
+//!
+//! If the output doesn't match its snapshot, the test fails and the new output
+//! is written next to the snapshot (with the extension `.snap.new`). If the
+//! change is intended, run the tests with `CLYDE_UPDATE_SNAPSHOTS=1` to update
+//! the snapshots, which also creates snapshots for new tests.
+
+use super::{Show, ShowCtx};
+use crate::env::Environment;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const UPDATE_VAR: &str = "CLYDE_UPDATE_SNAPSHOTS";
+
+/// The output of a snapshot test, built up one case at a time.
+pub struct Snapshot {
+    name: &'static str,
+    text: String,
+}
+
+impl Snapshot {
+    pub fn new(name: &'static str) -> Snapshot {
+        Snapshot {
+            name,
+            text: String::new(),
+        }
+    }
+
+    /// Add `value` as shown by `env` with `ctx`, under the heading `title`. If
+    /// showing the value fails, the error is part of the output.
+    pub fn show(&mut self, title: &str, value: &impl Show, env: &impl Environment, ctx: &ShowCtx) {
+        let mut buf = Vec::new();
+        let result = value.show(&mut buf, env, ctx);
+        let mut text = String::from_utf8(buf).unwrap();
+        if let Err(e) = result {
+            write!(text, "<error: {}>", e).unwrap();
+        }
+        self.case(title, &text);
+    }
+
+    /// Add `text` under the heading `title`.
+    pub fn case(&mut self, title: &str, text: &str) {
+        writeln!(self.text, "--- {}\n{}", title, text).unwrap();
+    }
+
+    /// Compare the output with the stored snapshot, see the module docs.
+    pub fn check(self) {
+        let path = snapshot_path(self.name);
+        let new_path = path.with_extension("snap.new");
+        let old = fs::read_to_string(&path).ok();
+        if old.as_deref() == Some(&*self.text) {
+            let _ = fs::remove_file(&new_path);
+            return;
+        }
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        if env::var_os(UPDATE_VAR).is_some() {
+            fs::write(&path, &self.text).unwrap();
+            let _ = fs::remove_file(&new_path);
+            return;
+        }
+        fs::write(&new_path, &self.text).unwrap();
+        match old {
+            Some(old) => panic!(
+                "snapshot `{}` does not match (the new output is in {}), run with {}=1 to \
+                 update it:\n{}",
+                self.name,
+                new_path.display(),
+                UPDATE_VAR,
+                diff(&old, &self.text)
+            ),
+            None => panic!(
+                "no snapshot `{}` (the output is in {}), run with {}=1 to create it",
+                self.name,
+                new_path.display(),
+                UPDATE_VAR
+            ),
+        }
+    }
+}
+
+fn snapshot_path(name: &str) -> PathBuf {
+    // `file!()` is relative to the package root, which is where tests are run.
+    Path::new(file!())
+        .with_file_name("snapshots")
+        .join(format!("{}.snap", name))
+}
+
+// The lines which differ between `old` and `new`, with their line numbers.
+// Lines are compared in order, which is enough to spot what has changed.
+fn diff(old: &str, new: &str) -> String {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+    let mut result = String::new();
+    for i in 0..old.len().max(new.len()) {
+        let (a, b) = (old.get(i), new.get(i));
+        if a == b {
+            continue;
+        }
+        if let Some(a) = a {
+            writeln!(result, "{:>4} - {}", i + 1, a).unwrap();
+        }
+        if let Some(b) = b {
+            writeln!(result, "{:>4} + {}", i + 1, b).unwrap();
+        }
+    }
+    result
+}
+
+mod test {
+    use super::*;
+    use crate::back::Capability;
+    use crate::env::mock::MockEnv;
+    use crate::env::{DisplayMode, Settings};
+    use crate::file_system::{FileSystem, MockFs};
+    use crate::front::data::{
+        Changes, DefKind, DefPath, Definition, Diagnostic, Identifier, Info, Line, Position, Range,
+        Severity, Span, Tree, Type, Value, ValueKind,
+    };
+    use crate::front::{Error, MetaVar};
+
+    fn ctx(display: DisplayMode, width: Option<usize>) -> ShowCtx {
+        let settings = Settings {
+            display,
+            ..Settings::default()
+        };
+        ShowCtx::new(&settings, width)
+    }
+
+    fn compact() -> ShowCtx {
+        ctx(DisplayMode::Compact, None)
+    }
+
+    fn file(name: &str) -> crate::file_system::Path {
+        MockFs.find(name.to_owned().into()).unwrap()[0]
+    }
+
+    fn value(kind: ValueKind, ty: Type) -> Value {
+        Value {
+            kind,
+            ty,
+            label: None,
+        }
+    }
+
+    fn ident(name: &str, from_macro: bool) -> Value {
+        Identifier {
+            id: 1,
+            span: Span::new_zero_indexed(file("foo.rs"), 2, 8, 2, 12),
+            name: name.to_owned(),
+            from_macro,
+            kind: None,
+            def: None,
+        }
+        .into()
+    }
+
+    fn def(kind: DefKind, path: Option<DefPath>) -> Definition {
+        Definition {
+            id: 100,
+            span: Span::new_zero_indexed(file("bar.rs"), 0, 8, 0, 12),
+            name: "line".to_owned(),
+            kind,
+            path,
+        }
+    }
+
+    fn def_value(def: Definition, label: Option<&str>) -> Value {
+        Value {
+            kind: ValueKind::Definition(def),
+            ty: Type::Definition,
+            label: label.map(|l| l.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_scalars() {
+        let mut snapshot = Snapshot::new("scalars");
+        let ctx = compact();
+        snapshot.show("void", &Value::void(), &MockEnv, &ctx);
+        snapshot.show("number", &Value::number(42), &MockEnv, &ctx);
+        snapshot.show("bool", &Value::bool(false), &MockEnv, &ctx);
+        snapshot.show("string", &Value::string("foo".to_owned()), &MockEnv, &ctx);
+        snapshot.show(
+            "empty string",
+            &Value::string(String::new()),
+            &MockEnv,
+            &ctx,
+        );
+        snapshot.show("identifier", &ident("line", false), &MockEnv, &ctx);
+        snapshot.show(
+            "identifier from a macro",
+            &ident("line", true),
+            &MockEnv,
+            &ctx,
+        );
+        snapshot.show(
+            "query",
+            &value(
+                ValueKind::Query(Value::number(1).into()),
+                Type::Query(Box::new(Type::Number)),
+            ),
+            &MockEnv,
+            &ctx,
+        );
+        snapshot.check();
+    }
+
+    #[test]
+    fn test_locations() {
+        let mut snapshot = Snapshot::new("locations");
+        let (foo, bar) = (file("foo.rs"), file("bar.rs"));
+        let plain = compact();
+        let narrow = ctx(DisplayMode::Compact, Some(30));
+        let position = Position::new_zero_indexed(foo, 2, 8);
+        snapshot.show("position", &position, &MockEnv, &plain);
+        snapshot.show("position, narrow", &position, &MockEnv, &narrow);
+        snapshot.show(
+            "position past the end of the file",
+            &Position::new_zero_indexed(foo, 99, 0),
+            &MockEnv,
+            &plain,
+        );
+        snapshot.show("file", &Range::File(foo), &MockEnv, &plain);
+        snapshot.show("files", &Range::MultiFile(vec![foo, bar]), &MockEnv, &plain);
+        snapshot.show(
+            "too many files",
+            &Range::MultiFile(vec![foo, bar, foo, bar, foo]),
+            &MockEnv,
+            &plain,
+        );
+        let line = Range::Line(bar, Line::new_zero_indexed(11));
+        snapshot.show("line", &line, &MockEnv, &plain);
+        snapshot.show("line, narrow", &line, &MockEnv, &narrow);
+        snapshot.show(
+            "line past the end of the file",
+            &Range::Line(bar, Line::new_zero_indexed(99)),
+            &MockEnv,
+            &plain,
+        );
+        let span = Span::new_zero_indexed(foo, 9, 20, 9, 38);
+        snapshot.show("span", &span, &MockEnv, &plain);
+        snapshot.show("span, narrow", &span, &MockEnv, &narrow);
+        snapshot.show(
+            "empty span",
+            &Span::new_zero_indexed(foo, 9, 5, 9, 5),
+            &MockEnv,
+            &plain,
+        );
+        snapshot.show(
+            "span over several lines",
+            &Span::new_zero_indexed(foo, 1, 3, 4, 6),
+            &MockEnv,
+            &plain,
+        );
+        snapshot.show(
+            "span past the end of the file",
+            &Span::new_zero_indexed(foo, 99, 0, 99, 2),
+            &MockEnv,
+            &plain,
+        );
+        snapshot.check();
+    }
+
+    #[test]
+    fn test_definitions() {
+        let mut snapshot = Snapshot::new("definitions");
+        let path = DefPath {
+            krate: "lines".to_owned(),
+            path: "file::line".to_owned(),
+        };
+        let line = def_value(def(DefKind::Struct, Some(path)), Some("def of `line`"));
+        for (title, display) in &[
+            ("compact", DisplayMode::Compact),
+            ("full", DisplayMode::Full),
+            ("verbose", DisplayMode::Verbose),
+        ] {
+            snapshot.show(title, &line, &MockEnv, &ctx(*display, None));
+        }
+        snapshot.show(
+            "full, narrow",
+            &line,
+            &MockEnv,
+            &ctx(DisplayMode::Full, Some(30)),
+        );
+        snapshot.show(
+            "unknown kind",
+            &def_value(def(DefKind::Unknown, None), None),
+            &MockEnv,
+            &compact(),
+        );
+
+        let child = |name: &str, kind, line| Tree {
+            def: Definition {
+                name: name.to_owned(),
+                kind,
+                span: Span::new_zero_indexed(file("bar.rs"), line, 4, line, 8),
+                ..def(kind, None)
+            },
+            children: vec![],
+        };
+        let tree = Tree {
+            def: def(DefKind::Struct, None),
+            children: vec![Tree {
+                children: vec![child("a", DefKind::Local, 3)],
+                ..child("new", DefKind::Method, 2)
+            }],
+        };
+        let trees = vec![tree, child("main", DefKind::Function, 7)];
+        snapshot.show(
+            "outline",
+            &value(ValueKind::Tree(trees), Type::Tree),
+            &MockEnv,
+            &compact(),
+        );
+        snapshot.check();
+    }
+
+    #[test]
+    fn test_sets() {
+        let mut snapshot = Snapshot::new("sets");
+        let plain = compact();
+        let numbers = |n| Value::set((1..=n).map(Value::number).collect(), Type::Number).unwrap();
+        snapshot.show("empty", &numbers(0), &MockEnv, &plain);
+        snapshot.show("small", &numbers(4), &MockEnv, &plain);
+        snapshot.show("large", &numbers(5), &MockEnv, &plain);
+        snapshot.show("partial", &numbers(2).partial(), &MockEnv, &plain);
+        snapshot.show("large and partial", &numbers(8).partial(), &MockEnv, &plain);
+        let nested = Value::set(
+            vec![numbers(1), numbers(0), numbers(2)],
+            Type::Set(Box::new(Type::Number)),
+        )
+        .unwrap();
+        snapshot.show("nested", &nested, &MockEnv, &plain);
+        let idents = Value::set(
+            vec![ident("foo", false), ident("bar", true)],
+            Type::Identifier,
+        )
+        .unwrap();
+        snapshot.show("identifiers", &idents, &MockEnv, &plain);
+        let mut labelled = def_value(def(DefKind::Struct, None), Some("def of `line`"));
+        let defs = Value::set(vec![labelled.clone()], Type::Definition).unwrap();
+        snapshot.show("definitions", &defs, &MockEnv, &plain);
+        snapshot.show(
+            "definitions, verbose",
+            &defs,
+            &MockEnv,
+            &ctx(DisplayMode::Verbose, None),
+        );
+        // Labels on sets are shown before their elements.
+        labelled.label = None;
+        let mut defs = Value::set(vec![labelled], Type::Definition).unwrap();
+        defs.label = Some("defs of `line`".to_owned());
+        snapshot.show(
+            "labelled set, verbose",
+            &defs,
+            &MockEnv,
+            &ctx(DisplayMode::Verbose, None),
+        );
+        snapshot.check();
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut snapshot = Snapshot::new("changes");
+        let old = [Value::number(1), Value::number(2), Value::number(3)];
+        let new = [Value::number(3), Value::number(4), Value::number(1)];
+        let changes = Changes::new(&old, &new);
+        snapshot.show("changes", &changes, &MockEnv, &compact());
+        snapshot.show("colored", &changes, &MockEnv, &compact().color(true));
+        snapshot.show(
+            "no changes",
+            &Changes::new(&old, &old),
+            &MockEnv,
+            &compact(),
+        );
+        let position = value(
+            ValueKind::Position(Position::new_zero_indexed(file("foo.rs"), 0, 5)),
+            Type::Position,
+        );
+        let added = Changes::new(&[], &[position]);
+        snapshot.show("multi-line elements", &added, &MockEnv, &compact());
+        snapshot.check();
+    }
+
+    #[test]
+    fn test_info() {
+        let mut snapshot = Snapshot::new("info");
+        let ident = match ident("line", false).kind {
+            ValueKind::Identifier(id) => id,
+            _ => unreachable!(),
+        };
+        let info = Info {
+            ident: ident.clone(),
+            def: Some(def(DefKind::Struct, None)),
+            ty: Some("Vec<String>".to_owned()),
+            docs: Some("A line of a file.".to_owned()),
+        };
+        snapshot.show("everything", &info, &MockEnv, &compact());
+        let info = Info {
+            ident,
+            def: None,
+            ty: None,
+            docs: None,
+        };
+        snapshot.show("nothing known", &info, &MockEnv, &compact());
+        snapshot.check();
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut snapshot = Snapshot::new("diagnostics");
+        let foo = file("foo.rs");
+        let diagnostic = |severity, message: &str, span| Diagnostic {
+            severity,
+            message: message.to_owned(),
+            span,
+        };
+        snapshot.show(
+            "error",
+            &diagnostic(
+                Severity::Error,
+                "mismatched types",
+                Span::new_zero_indexed(foo, 4, 0, 4, 4),
+            ),
+            &MockEnv,
+            &compact(),
+        );
+        snapshot.show(
+            "warning over several lines",
+            &diagnostic(
+                Severity::Warning,
+                "unused variable",
+                Span::new_zero_indexed(foo, 4, 0, 6, 2),
+            ),
+            &MockEnv,
+            &compact(),
+        );
+        let notes = Value::set(
+            vec![
+                value(
+                    ValueKind::Diagnostic(diagnostic(
+                        Severity::Note,
+                        "defined here",
+                        Span::new_zero_indexed(foo, 1, 8, 1, 12),
+                    )),
+                    Type::Diagnostic,
+                ),
+                value(
+                    ValueKind::Diagnostic(diagnostic(
+                        Severity::Help,
+                        "remove this",
+                        Span::new_zero_indexed(foo, 2, 0, 2, 4),
+                    )),
+                    Type::Diagnostic,
+                ),
+            ],
+            Type::Diagnostic,
+        )
+        .unwrap();
+        snapshot.show("set", &notes, &MockEnv, &compact());
+        snapshot.check();
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut snapshot = Snapshot::new("errors");
+        let errors = vec![
+            ("variable", Error::VarNotFound(MetaVar::new("foo"))),
+            ("no results", Error::NumericVarNotFound(-1, 0)),
+            ("one result", Error::NumericVarNotFound(3, 1)),
+            ("several results", Error::NumericVarNotFound(-5, 2)),
+            ("failed result", Error::FailedResult(2)),
+            (
+                "ambiguous file",
+                Error::AmbiguousFile(
+                    "dup.rs".to_owned(),
+                    vec!["a/dup.rs".to_owned(), "b/dup.rs".to_owned()],
+                ),
+            ),
+            ("function", Error::UnknownFunction("foo".to_owned())),
+            (
+                "type",
+                Error::TypeError("Expected number, found string".to_owned()),
+            ),
+            (
+                "unsupported",
+                Error::Unsupported("typeof", Capability::Types),
+            ),
+            ("empty set", Error::EmptySet("pick")),
+            ("index", Error::IndexOutOfRange(-3, 1)),
+            ("index in a set", Error::IndexOutOfRange(7, 2)),
+            ("other", Error::Other("oops".to_owned())),
+        ];
+        for (title, e) in &errors {
+            snapshot.show(title, e, &MockEnv, &compact());
+        }
+        snapshot.check();
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nc\n"), "");
+        assert_eq!(
+            diff("a\nb\nc\n", "a\nx\nc\nd\n"),
+            "   2 - b\n   2 + x\n   4 + d\n"
+        );
+        assert_eq!(diff("a\nb\n", "a\n"), "   2 - b\n");
+    }
+}
@@ -0,0 +1,379 @@
+//! Finding things in the source text of files, rather than asking the backend
+//! (see the `unsafes`, `tests`, and `derives` functions). Comments and the
+//! contents of string and character literals are skipped, but the text is not
+//! parsed, so only the usual ways of writing things are recognised.
+
+use crate::file_system::{File, FileSystem, Path};
+use crate::front::data::{Column, Line, Range, Span};
+use crate::front::Error;
+use regex::Regex;
+
+/// What to find in the source text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Item {
+    Unsafe,
+    // The names of functions with an attribute ending with `test`.
+    Test,
+    Derive,
+    // Impl blocks, from `impl` to the closing brace.
+    Impl,
+}
+
+/// The spans of `item` which start inside `range`, in the order they are in
+/// the source.
+pub fn find(fs: &impl FileSystem, range: &Range, item: Item) -> Result<Vec<Span>, Error> {
+    let mut result = Vec::new();
+    let mut find_in = |path: Path, within: Option<&Span>| {
+        fs.with_file(path, |file| {
+            let spans = find_in_file(file, item);
+            result.extend(spans.into_iter().filter(|s| match within {
+                Some(w) => {
+                    (w.start_line, w.start_column) <= (s.start_line, s.start_column)
+                        && (s.start_line, s.start_column) < (w.end_line, w.end_column)
+                }
+                None => true,
+            }));
+            Ok(())
+        })
+    };
+    match range {
+        Range::File(path) => find_in(*path, None)?,
+        Range::MultiFile(paths) => {
+            for path in paths {
+                find_in(*path, None)?;
+            }
+        }
+        Range::Line(path, line) => {
+            let line = Span::new(
+                *path,
+                *line,
+                Column::new_zero_indexed(0),
+                Line::new_zero_indexed(line.zero_indexed() + 1),
+                Column::new_zero_indexed(0),
+            );
+            find_in(*path, Some(&line))?;
+        }
+        Range::Span(span) => find_in(span.file, Some(span))?,
+    }
+    Ok(result)
+}
+
+/// The spans of `item` in `file`, in the order they are in the source.
+pub fn find_in_file(file: &File, item: Item) -> Vec<Span> {
+    Source::new(file).find(item)
+}
+
+// The text of a file with comments and the contents of literals blanked out.
+struct Source {
+    path: Path,
+    text: String,
+    // The offset in `text` of the start of each line.
+    line_starts: Vec<usize>,
+}
+
+impl Source {
+    fn new(file: &File) -> Source {
+        let mut line_starts = Vec::new();
+        let mut start = 0;
+        for line in &file.lines {
+            line_starts.push(start);
+            start += line.len() + 1;
+        }
+        Source {
+            path: file.path,
+            text: mask(&file.lines.join("\n")),
+            line_starts,
+        }
+    }
+
+    fn find(&self, item: Item) -> Vec<Span> {
+        match item {
+            Item::Unsafe => self.delimited(r"\bunsafe\s*\{", b'{', b'}'),
+            Item::Derive => self.delimited(r"#\s*\[\s*derive\s*\(", b'[', b']'),
+            Item::Impl => self.delimited(r"(?m)^[ \t]*(?:unsafe\s+)?impl\b[^{;]*\{", b'{', b'}'),
+            Item::Test => self.tests(),
+        }
+    }
+
+    // The spans from each match of `pattern` to the delimiter which closes the
+    // last `open` in the match (or the end of the file, if it is not closed).
+    fn delimited(&self, pattern: &str, open: u8, close: u8) -> Vec<Span> {
+        let regex = Regex::new(pattern).unwrap();
+        let bytes = self.text.as_bytes();
+        regex
+            .find_iter(&self.text)
+            .map(|m| {
+                let start = self.text[..m.end()].rfind(open as char).unwrap();
+                let mut depth = 0;
+                let mut end = bytes.len();
+                for (i, b) in bytes.iter().enumerate().skip(start) {
+                    if *b == open {
+                        depth += 1;
+                    } else if *b == close {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = i + 1;
+                            break;
+                        }
+                    }
+                }
+                let indent = m.as_str().len() - m.as_str().trim_start().len();
+                self.span(m.start() + indent, end)
+            })
+            .collect()
+    }
+
+    // The names of test functions. Other attributes, visibility, and
+    // qualifiers (e.g., `async`) may come between the attribute and `fn`.
+    fn tests(&self) -> Vec<Span> {
+        let attr = Regex::new(r"#\s*\[\s*(?:[A-Za-z_][A-Za-z0-9_]*\s*::\s*)*test\s*\]").unwrap();
+        let item = Regex::new(
+            r#"\A(?:\s|#\s*\[[^\]]*\])*(?:(?:pub(?:\s*\([^)]*\))?|async|const|unsafe|extern(?:\s*"[^"]*")?)\s+)*fn\s+([A-Za-z_][A-Za-z0-9_]*)"#,
+        )
+        .unwrap();
+        attr.find_iter(&self.text)
+            .filter_map(|m| {
+                let name = item.captures(&self.text[m.end()..])?.get(1)?;
+                Some(self.span(m.end() + name.start(), m.end() + name.end()))
+            })
+            .collect()
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        let (start_line, start_column) = self.position(start);
+        let (end_line, end_column) = self.position(end);
+        Span::new(self.path, start_line, start_column, end_line, end_column)
+    }
+
+    fn position(&self, offset: usize) -> (Line, Column) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i.saturating_sub(1),
+        };
+        let start = self.line_starts.get(line).cloned().unwrap_or(0);
+        (
+            Line::new_zero_indexed(line),
+            Column::new_zero_indexed(offset - start),
+        )
+    }
+}
+
+// Blank out comments and literals, keeping offsets.
+fn mask(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut result = bytes.to_vec();
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    // True if a token can start at `i`, rather than it being in an identifier.
+    let starts_token = |i: usize| i == 0 || !is_ident(bytes[i - 1]);
+    let blank = |result: &mut Vec<u8>, from: usize, to: usize| {
+        for b in &mut result[from..to.min(bytes.len())] {
+            if *b != b'\n' {
+                *b = b' ';
+            }
+        }
+    };
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                let end = text[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                blank(&mut result, i, end);
+                i = end;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // Block comments nest.
+                let mut depth = 0;
+                let mut j = i;
+                while j < bytes.len() {
+                    if bytes[j..].starts_with(b"/*") {
+                        depth += 1;
+                        j += 2;
+                    } else if bytes[j..].starts_with(b"*/") {
+                        depth -= 1;
+                        j += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        j += 1;
+                    }
+                }
+                blank(&mut result, i, j);
+                i = j;
+            }
+            b'r' if starts_token(i) || (bytes[i - 1] == b'b' && starts_token(i - 1)) => {
+                // A raw string, e.g., `r#"..."#`, otherwise an identifier.
+                let hashes = bytes[i + 1..].iter().take_while(|b| **b == b'#').count();
+                let open = i + 1 + hashes;
+                if bytes.get(open) != Some(&b'"') {
+                    i += 1;
+                    continue;
+                }
+                let closing = format!("\"{}", "#".repeat(hashes));
+                let closing = closing.as_bytes();
+                let end = (open + 1..bytes.len())
+                    .find(|j| bytes[*j..].starts_with(closing))
+                    .unwrap_or(bytes.len());
+                blank(&mut result, open + 1, end);
+                i = end + closing.len();
+            }
+            b'"' => {
+                let mut j = i + 1;
+                while j < bytes.len() && bytes[j] != b'"' {
+                    j += if bytes[j] == b'\\' { 2 } else { 1 };
+                }
+                blank(&mut result, i + 1, j);
+                i = j + 1;
+            }
+            b'\'' => {
+                // A character literal, otherwise a lifetime or label.
+                let end = if bytes.get(i + 1) == Some(&b'\\') {
+                    // Skip the escaped character, which may be a quote.
+                    text.get(i + 3..)
+                        .and_then(|s| s.find('\''))
+                        .map(|n| i + 3 + n)
+                } else {
+                    text[i + 1..]
+                        .chars()
+                        .next()
+                        .map(|c| i + 1 + c.len_utf8())
+                        .filter(|j| bytes.get(*j) == Some(&b'\''))
+                };
+                match end {
+                    Some(end) => {
+                        blank(&mut result, i + 1, end);
+                        i = end + 1;
+                    }
+                    None => i += 1,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    // Only whole characters are replaced (with ASCII), so the result is valid.
+    String::from_utf8(result).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_system::MockFs;
+
+    fn path() -> Path {
+        MockFs.find("foo.rs".to_owned().into()).unwrap()[0]
+    }
+
+    fn source(text: &str) -> Source {
+        Source::new(&File::new(path(), text))
+    }
+
+    // The text of each span as (line, text), one-indexed.
+    fn found(text: &str, item: Item) -> Vec<(usize, String)> {
+        let file = File::new(path(), text);
+        Source::new(&file)
+            .find(item)
+            .into_iter()
+            .map(|s| {
+                let start = file.offset(s.start_line, s.start_column).unwrap();
+                let end = file.offset(s.end_line, s.end_column).unwrap();
+                (s.start_line.one_indexed(), text[start..end].to_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(
+            mask("a // b\nc /* d /* e */ f */ g"),
+            format!("a     \nc {} g", " ".repeat(17))
+        );
+        assert_eq!(
+            mask(r#"x("{", '}', '\'', "\"{")"#),
+            r#"x(" ", ' ', '  ', "   ")"#
+        );
+        assert_eq!(
+            mask(r###"r#"a"b"# br"{" for_r"x""###),
+            r###"r#"   "# br" " for_r" ""###
+        );
+        // Lifetimes and labels are not literals.
+        assert_eq!(mask("&'a str, 'é', 'b: {"), "&'a str, '  ', 'b: {");
+    }
+
+    #[test]
+    fn test_unsafes() {
+        let text = "fn f() {\n    unsafe { g({ 1 }) };\n    // unsafe { no }\n}\n\
+                    unsafe fn h() {\n    let s = \"unsafe {\";\n    unsafe {\n        i()\n    }\n}\n\
+                    unsafe impl Send for X {}\n";
+        assert_eq!(
+            found(text, Item::Unsafe),
+            vec![
+                (2, "unsafe { g({ 1 }) }".to_owned()),
+                (7, "unsafe {\n        i()\n    }".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_derives() {
+        let text = "#[derive(Clone, Debug)]\n#[doc = \"#[derive(Foo)]\"]\n\
+                    # [ derive ( PartialEq,\n  Eq ) ] struct X;\n#[derive_more(Y)]\n";
+        assert_eq!(
+            found(text, Item::Derive),
+            vec![
+                (1, "#[derive(Clone, Debug)]".to_owned()),
+                (3, "# [ derive ( PartialEq,\n  Eq ) ]".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_impls() {
+        let text = "impl Marker for Foo {}\nfn f() -> impl Iterator<Item = u8> {\n    \
+                    unsafe impl<T> Send for X<T>\n    where\n        T: Send,\n    {\n    }\n}\n\
+                    // impl Foo {}\n";
+        assert_eq!(
+            found(text, Item::Impl),
+            vec![
+                (1, "impl Marker for Foo {}".to_owned()),
+                (
+                    3,
+                    "unsafe impl<T> Send for X<T>\n    where\n        T: Send,\n    {\n    }"
+                        .to_owned()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tests() {
+        let text = "#[test]\nfn a() {}\n\
+                    #[test]\n#[should_panic(expected = \"]\")]\npub(crate) async fn b() {}\n\
+                    #[tokio::test] async fn c() {}\n\
+                    #[test_case(1)]\nfn d() {}\n\
+                    // #[test]\nfn e() {}\n\
+                    #[test]\nmod f {}\n";
+        let names: Vec<_> = found(text, Item::Test);
+        assert_eq!(
+            names,
+            vec![
+                (2, "a".to_owned()),
+                (5, "b".to_owned()),
+                (6, "c".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_position() {
+        let source = source("ab\n\ncd");
+        let position = |offset| {
+            let (line, column) = source.position(offset);
+            (line.zero_indexed(), column.zero_indexed())
+        };
+        assert_eq!(position(0), (0, 0));
+        assert_eq!(position(2), (0, 2));
+        assert_eq!(position(3), (1, 0));
+        assert_eq!(position(5), (2, 1));
+        assert_eq!(position(6), (2, 2));
+    }
+}
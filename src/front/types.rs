@@ -0,0 +1,303 @@
+//! Relations between types, used when type checking statements.
+//!
+//! A value of a subtype can be used as a value of its supertype as it is (see
+//! `is_subtype`):
+//!
+//! * `position <= location` and `range <= location`,
+//! * `() <= set<T>` and `set<()> <= set<T>` (an empty set is a set of anything),
+//! * `set<A> <= set<B>`, `query<A> <= query<B>`, and `changes<A> <= changes<B>`
+//!   if `A <= B`,
+//! * `map<K, A> <= map<L, B>` if `K <= L` and `A <= B`.
+//!
+//! Other values are converted when they are used as another type (see
+//! `coerce`), by the function they are passed to or when the query they belong
+//! to is evaluated:
+//!
+//! * `def -> range` and `def -> location` (the span of the definition),
+//! * `T -> set<T>` (a single element where a set is expected),
+//! * `set<set<T>> -> set<T>` (nested sets are flattened),
+//! * `T -> query<T>`, and a query can be used wherever its result could be (the
+//!   result is still a query).
+
+use super::data::Type;
+
+/// True if any value of type `sub` is also a value of type `sup`.
+pub fn is_subtype(sub: &Type, sup: &Type) -> bool {
+    if sub == sup {
+        return true;
+    }
+    match (sub, sup) {
+        (Type::Position, Type::Location) | (Type::Range, Type::Location) => true,
+        (Type::Void, Type::Set(_)) => true,
+        (Type::Set(a), Type::Set(b)) => **a == Type::Void || is_subtype(a, b),
+        (Type::Query(a), Type::Query(b)) | (Type::Changes(a), Type::Changes(b)) => is_subtype(a, b),
+        (Type::Map(ka, a), Type::Map(kb, b)) => is_subtype(ka, kb) && is_subtype(a, b),
+        _ => false,
+    }
+}
+
+/// The smallest type which includes both `a` and `b`, if there is one, e.g.,
+/// `location` for `position` and `range`.
+pub fn unify(a: &Type, b: &Type) -> Option<Type> {
+    if is_subtype(a, b) {
+        return Some(b.clone());
+    }
+    if is_subtype(b, a) {
+        return Some(a.clone());
+    }
+    match (a, b) {
+        (Type::Set(a), Type::Set(b)) => Some(Type::Set(Box::new(unify(a, b)?))),
+        (Type::Query(a), Type::Query(b)) => Some(Type::Query(Box::new(unify(a, b)?))),
+        (Type::Changes(a), Type::Changes(b)) => Some(Type::Changes(Box::new(unify(a, b)?))),
+        (Type::Map(ka, a), Type::Map(kb, b)) => {
+            Some(Type::Map(Box::new(unify(ka, kb)?), Box::new(unify(a, b)?)))
+        }
+        _ if is_subtype(a, &Type::Location) && is_subtype(b, &Type::Location) => {
+            Some(Type::Location)
+        }
+        _ => None,
+    }
+}
+
+/// `expected`, or a query for it if `ty` is a query.
+pub fn coerce(ty: &Type, expected: &Type) -> Option<Type> {
+    if let Type::Query(inner) = ty {
+        return Some(Type::Query(Box::new(coerce(inner, &expected.unquery())?)));
+    }
+    if let Type::Query(inner) = expected {
+        return Some(Type::Query(Box::new(coerce(ty, inner)?)));
+    }
+    if is_subtype(ty, expected) {
+        return Some(expected.clone());
+    }
+    match (ty, expected) {
+        (Type::Definition, Type::Location) | (Type::Definition, Type::Range) => {
+            Some(expected.clone())
+        }
+        (Type::Set(inner), Type::Set(elem)) => coerce(inner, elem)
+            .or_else(|| match **inner {
+                Type::Set(_) => coerce(inner, expected),
+                _ => None,
+            })
+            .map(|_| expected.clone()),
+        (_, Type::Set(elem)) => coerce(ty, elem).map(|_| expected.clone()),
+        _ => None,
+    }
+}
+
+/// How a function which takes either a single element or a set of elements
+/// uses its lhs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shape {
+    Single,
+    Set,
+}
+
+impl Shape {
+    /// The type of a result in this shape, e.g., `set<ty>` for `Shape::Set`.
+    pub fn of(self, ty: Type) -> Type {
+        match self {
+            Shape::Single => ty,
+            Shape::Set => Type::Set(Box::new(ty)),
+        }
+    }
+}
+
+/// Whether `ty` is one or a set of one of `elems`, and which it matched.
+pub fn shape(ty: &Type, elems: &[Type]) -> Option<(Shape, Type)> {
+    let ty = ty.unquery().flatten_all();
+    // `()` is only an empty set where a set is expected.
+    if ty == Type::Void {
+        return None;
+    }
+    elems.iter().find_map(|elem| {
+        if is_subtype(&ty, elem) {
+            Some((Shape::Single, elem.clone()))
+        } else if is_subtype(&ty, &Type::Set(Box::new(elem.clone()))) {
+            Some((Shape::Set, elem.clone()))
+        } else {
+            None
+        }
+    })
+}
+
+/// `result` as a query if `ty` (the type of a function's lhs) is a query.
+pub fn lift(ty: &Type, result: Type) -> Type {
+    if ty.is_query() {
+        Type::Query(Box::new(result))
+    } else {
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn set(ty: Type) -> Type {
+        Type::Set(Box::new(ty))
+    }
+
+    fn query(ty: Type) -> Type {
+        Type::Query(Box::new(ty))
+    }
+
+    fn map(key: Type, value: Type) -> Type {
+        Type::Map(Box::new(key), Box::new(value))
+    }
+
+    #[test]
+    fn test_is_subtype() {
+        let cases = [
+            (Type::Number, Type::Number, true),
+            (Type::Position, Type::Location, true),
+            (Type::Range, Type::Location, true),
+            (Type::Location, Type::Position, false),
+            (Type::Position, Type::Range, false),
+            (Type::Definition, Type::Location, false),
+            (Type::Void, set(Type::Number), true),
+            (set(Type::Void), set(Type::Identifier), true),
+            (Type::Void, Type::Number, false),
+            (set(Type::Position), set(Type::Location), true),
+            (set(Type::Location), set(Type::Position), false),
+            (Type::Identifier, set(Type::Identifier), false),
+            (query(Type::Range), query(Type::Location), true),
+            (Type::Range, query(Type::Range), false),
+            (
+                Type::Changes(Box::new(Type::Position)),
+                Type::Changes(Box::new(Type::Location)),
+                true,
+            ),
+            (
+                map(Type::String, Type::Position),
+                map(Type::String, Type::Location),
+                true,
+            ),
+            (
+                map(Type::String, Type::Number),
+                map(Type::Number, Type::Number),
+                false,
+            ),
+        ];
+        for (sub, sup, expected) in &cases {
+            assert_eq!(is_subtype(sub, sup), *expected, "{:?} <= {:?}", sub, sup);
+        }
+    }
+
+    #[test]
+    fn test_unify() {
+        let cases = [
+            (Type::String, Type::String, Some(Type::String)),
+            (Type::Position, Type::Range, Some(Type::Location)),
+            (Type::Location, Type::Range, Some(Type::Location)),
+            (Type::Position, Type::Definition, None),
+            (Type::Number, Type::String, None),
+            (
+                set(Type::Position),
+                set(Type::Range),
+                Some(set(Type::Location)),
+            ),
+            (
+                set(Type::Void),
+                set(Type::Identifier),
+                Some(set(Type::Identifier)),
+            ),
+            (Type::Void, set(Type::Number), Some(set(Type::Number))),
+            (set(Type::Number), set(Type::String), None),
+            (
+                query(Type::Position),
+                query(Type::Range),
+                Some(query(Type::Location)),
+            ),
+            (Type::Identifier, set(Type::Identifier), None),
+            (
+                map(Type::String, Type::Position),
+                map(Type::String, Type::Range),
+                Some(map(Type::String, Type::Location)),
+            ),
+        ];
+        for (a, b, expected) in &cases {
+            assert_eq!(unify(a, b), *expected, "{:?} | {:?}", a, b);
+            assert_eq!(unify(b, a), *expected, "{:?} | {:?}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_coerce() {
+        let locations = set(Type::Location);
+        let cases = [
+            (Type::Position, Type::Location, Some(Type::Location)),
+            (Type::Definition, Type::Range, Some(Type::Range)),
+            (Type::Definition, Type::Location, Some(Type::Location)),
+            (Type::Definition, Type::Position, None),
+            (Type::Identifier, Type::Location, None),
+            (Type::Position, locations.clone(), Some(locations.clone())),
+            (
+                set(Type::Definition),
+                locations.clone(),
+                Some(locations.clone()),
+            ),
+            (
+                set(set(set(Type::Range))),
+                locations.clone(),
+                Some(locations.clone()),
+            ),
+            (set(Type::Number), locations.clone(), None),
+            (set(Type::Location), Type::Location, None),
+            (Type::Void, locations.clone(), Some(locations.clone())),
+            (
+                query(set(Type::Position)),
+                locations.clone(),
+                Some(query(locations.clone())),
+            ),
+            (
+                Type::Range,
+                query(Type::Location),
+                Some(query(Type::Location)),
+            ),
+            (
+                query(Type::Definition),
+                query(Type::Range),
+                Some(query(Type::Range)),
+            ),
+            (query(Type::String), Type::Number, None),
+        ];
+        for (ty, expected_ty, expected) in &cases {
+            assert_eq!(
+                coerce(ty, expected_ty),
+                *expected,
+                "{:?} -> {:?}",
+                ty,
+                expected_ty
+            );
+        }
+    }
+
+    #[test]
+    fn test_shape() {
+        let text = [Type::String, Type::Identifier, Type::Definition];
+        let cases = [
+            (Type::String, Some((Shape::Single, Type::String))),
+            (Type::Definition, Some((Shape::Single, Type::Definition))),
+            (set(Type::Identifier), Some((Shape::Set, Type::Identifier))),
+            (
+                query(set(set(Type::Definition))),
+                Some((Shape::Set, Type::Definition)),
+            ),
+            (set(Type::Void), Some((Shape::Set, Type::String))),
+            (Type::Void, None),
+            (Type::Position, None),
+            (set(Type::Number), None),
+        ];
+        for (ty, expected) in &cases {
+            assert_eq!(shape(ty, &text), *expected, "{:?}", ty);
+        }
+
+        assert_eq!(Shape::Set.of(Type::Bool), set(Type::Bool),);
+        assert_eq!(
+            lift(&query(Type::Identifier), Type::Bool),
+            query(Type::Bool)
+        );
+        assert_eq!(lift(&Type::Identifier, Type::Bool), Type::Bool);
+    }
+}
@@ -4,5 +4,13 @@ pub(crate) mod file_system;
 pub(crate) mod front;
 pub(crate) mod parse;
 
-pub use crate::env::repl::{Config as ReplConfig, Repl};
+pub use crate::env::config::{
+    ColorChoice, Config as ReplConfig, Overrides as ConfigOverrides, Verbosity,
+};
+pub use crate::env::daemon::{run_client, run_daemon};
+pub use crate::env::lsp_server::run_lsp_server;
+pub use crate::env::repl::{eval_once, run_script, Repl};
+pub use crate::env::session::{EvalError, Format, Session};
 pub use crate::parse::ast;
+#[doc(hidden)]
+pub use crate::parse::check_parse;
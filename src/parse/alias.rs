@@ -0,0 +1,354 @@
+//! Aliases are user-defined names for queries, e.g., `^alias refsof = idents->def->refs`.
+//!
+//! An alias is used like a function (`$->refsof`, `$.refsof`, or `refsof $`)
+//! and is expanded when a statement is parsed. The body of an alias is a chain
+//! of function applications which is applied to the alias's receiver. In the
+//! body, `$1`, `$2`, etc. refer to the arguments of the alias and `$0` refers
+//! to the receiver, e.g., `^alias within = idents->pick $1`.
+
+use super::{ast, lexer, parser, tokens, Context, Error};
+use std::collections::BTreeMap;
+
+/// Aliases by name.
+pub type Aliases = BTreeMap<String, Alias>;
+
+// Aliases may use other aliases, but only to this depth (so that recursive
+// aliases are an error rather than a stack overflow).
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone)]
+pub struct Alias {
+    /// The body of the alias as written by the user.
+    pub source: String,
+    // The body parsed as an application to `$0`.
+    body: ast::Expr,
+    // The number of arguments, i.e., the highest numbered placeholder.
+    arity: usize,
+}
+
+impl Alias {
+    pub fn new(source: &str) -> Result<Alias, Error> {
+        let source = source.trim();
+        let toks = lexer::lex(source, 0)?;
+        if toks.is_empty() {
+            return Err(Error::EmptyInput);
+        }
+
+        // Parse the body as if it were written `$0->body`.
+        let (mut tt, span) = toks.expect_tree();
+        let prefix = [
+            tokens::TokenKind::Symbol(tokens::SymbolKind::Dollar),
+            tokens::TokenKind::Number(0),
+            tokens::TokenKind::Symbol(tokens::SymbolKind::ArrowRight),
+        ];
+        for (i, kind) in prefix.iter().enumerate() {
+            tt.tokens.insert(
+                i,
+                tokens::Token::new(kind.clone(), tokens::Span::new(0, String::new())),
+            );
+        }
+        let body = parser::parse_expr(tt.tokens, span.start + span.text.len(), Context::default())?;
+
+        let mut arity = 0;
+        visit_placeholders(&body, &mut |n| arity = arity.max(n));
+        Ok(Alias {
+            source: source.to_owned(),
+            body,
+            arity,
+        })
+    }
+
+    // Replace the placeholders in the body with `lhs` and `args`.
+    fn instantiate(&self, lhs: ast::Expr, args: Vec<ast::Expr>) -> ast::Expr {
+        let mut values = vec![lhs];
+        values.extend(args);
+        substitute(self.body.clone(), &values)
+    }
+}
+
+pub(super) fn expand_stmt(
+    stmt: ast::Statement,
+    aliases: &Aliases,
+) -> Result<ast::Statement, Error> {
+    if aliases.is_empty() {
+        return Ok(stmt);
+    }
+    let kind = match stmt.kind {
+        ast::StatementKind::Expr(kind) => {
+            let expr = ast::Expr {
+                kind,
+                ctx: stmt.ctx.clone(),
+            };
+            ast::StatementKind::Expr(expand(expr, aliases, 0)?.kind)
+        }
+        ast::StatementKind::ApplyShorthand(a) => match aliases.get(&a.ident.name) {
+            Some(_) => {
+                let expr = ast::Expr {
+                    kind: ast::ExprKind::Apply(a),
+                    ctx: stmt.ctx.clone(),
+                };
+                ast::StatementKind::Expr(expand(expr, aliases, 0)?.kind)
+            }
+            None => ast::StatementKind::ApplyShorthand(expand_apply(a, aliases, 0)?),
+        },
+        ast::StatementKind::Assign(ident, expr) => {
+            ast::StatementKind::Assign(ident, expand(expr, aliases, 0)?)
+        }
+        kind => kind,
+    };
+    Ok(ast::Statement {
+        kind,
+        ctx: stmt.ctx,
+    })
+}
+
+fn expand(expr: ast::Expr, aliases: &Aliases, depth: usize) -> Result<ast::Expr, Error> {
+    let apply = match expr.kind {
+        ast::ExprKind::Apply(a) => a,
+        ast::ExprKind::Set(elements) => {
+            return Ok(ast::Expr {
+                kind: ast::ExprKind::Set(
+                    elements
+                        .into_iter()
+                        .map(|e| expand(e, aliases, depth))
+                        .collect::<Result<_, _>>()?,
+                ),
+                ctx: expr.ctx,
+            })
+        }
+        ast::ExprKind::Projection(p) => {
+            if !aliases.contains_key(&p.ident.name) {
+                return Ok(ast::Expr {
+                    kind: ast::ExprKind::Projection(ast::Projection {
+                        lhs: Box::new(expand(*p.lhs, aliases, depth)?),
+                        ..p
+                    }),
+                    ctx: expr.ctx,
+                });
+            }
+            p.into()
+        }
+        ast::ExprKind::Index(i) => {
+            return Ok(ast::Expr {
+                kind: ast::ExprKind::Index(ast::Index {
+                    lhs: Box::new(expand(*i.lhs, aliases, depth)?),
+                    ..i
+                }),
+                ctx: expr.ctx,
+            })
+        }
+        // The source of the lambda is kept as the user wrote it.
+        ast::ExprKind::Lambda(l) => {
+            return Ok(ast::Expr {
+                kind: ast::ExprKind::Lambda(ast::Lambda {
+                    body: Box::new(expand(*l.body, aliases, depth)?),
+                    ..l
+                }),
+                ctx: expr.ctx,
+            })
+        }
+        kind => {
+            return Ok(ast::Expr {
+                kind,
+                ctx: expr.ctx,
+            })
+        }
+    };
+
+    let alias = match aliases.get(&apply.ident.name) {
+        Some(alias) => alias,
+        None => {
+            return Ok(ast::Expr {
+                kind: ast::ExprKind::Apply(expand_apply(apply, aliases, depth)?),
+                ctx: expr.ctx,
+            })
+        }
+    };
+
+    let name = apply.ident.name;
+    if depth >= MAX_DEPTH {
+        return Err(Error::Other(format!(
+            "Alias `{}` is nested too deeply (is it recursive?)",
+            name
+        )));
+    }
+    if apply.args.len() != alias.arity {
+        return Err(Error::Other(format!(
+            "Alias `{}` expects {} argument(s), found {}",
+            name,
+            alias.arity,
+            apply.args.len()
+        )));
+    }
+    let lhs = expand(*apply.lhs, aliases, depth)?;
+    let args = apply
+        .args
+        .into_iter()
+        .map(|a| expand(a, aliases, depth))
+        .collect::<Result<_, _>>()?;
+    // Expand aliases used by the alias before substituting, so that the
+    // substituted expressions are not expanded twice.
+    let body = Alias {
+        body: expand(alias.body.clone(), aliases, depth + 1)?,
+        ..alias.clone()
+    };
+    Ok(body.instantiate(lhs, args))
+}
+
+fn expand_apply(apply: ast::Apply, aliases: &Aliases, depth: usize) -> Result<ast::Apply, Error> {
+    Ok(ast::Apply {
+        lhs: Box::new(expand(*apply.lhs, aliases, depth)?),
+        args: apply
+            .args
+            .into_iter()
+            .map(|a| expand(a, aliases, depth))
+            .collect::<Result<_, _>>()?,
+        ..apply
+    })
+}
+
+// Replace `$n` with `values[n]`.
+fn substitute(expr: ast::Expr, values: &[ast::Expr]) -> ast::Expr {
+    let kind = match expr.kind {
+        ast::ExprKind::MetaVar(ast::MetaVarKind::Numeric(n))
+            if n >= 0 && (n as usize) < values.len() =>
+        {
+            return values[n as usize].clone();
+        }
+        ast::ExprKind::Apply(a) => ast::ExprKind::Apply(ast::Apply {
+            lhs: Box::new(substitute(*a.lhs, values)),
+            args: a.args.into_iter().map(|a| substitute(a, values)).collect(),
+            ..a
+        }),
+        ast::ExprKind::Projection(p) => ast::ExprKind::Projection(ast::Projection {
+            lhs: Box::new(substitute(*p.lhs, values)),
+            ..p
+        }),
+        ast::ExprKind::Set(elements) => ast::ExprKind::Set(
+            elements
+                .into_iter()
+                .map(|e| substitute(e, values))
+                .collect(),
+        ),
+        ast::ExprKind::Index(i) => ast::ExprKind::Index(ast::Index {
+            lhs: Box::new(substitute(*i.lhs, values)),
+            ..i
+        }),
+        kind => kind,
+    };
+    ast::Expr {
+        kind,
+        ctx: expr.ctx,
+    }
+}
+
+fn visit_placeholders(expr: &ast::Expr, f: &mut impl FnMut(usize)) {
+    match &expr.kind {
+        ast::ExprKind::MetaVar(ast::MetaVarKind::Numeric(n)) if *n > 0 => f(*n as usize),
+        ast::ExprKind::Apply(a) => {
+            visit_placeholders(&a.lhs, f);
+            for arg in &a.args {
+                visit_placeholders(arg, f);
+            }
+        }
+        ast::ExprKind::Projection(p) => visit_placeholders(&p.lhs, f),
+        ast::ExprKind::Index(i) => visit_placeholders(&i.lhs, f),
+        ast::ExprKind::Set(elements) => {
+            for e in elements {
+                visit_placeholders(e, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::parse_stmt;
+
+    fn aliases(defs: &[(&str, &str)]) -> Aliases {
+        defs.iter()
+            .map(|(name, source)| (name.to_string(), Alias::new(source).unwrap()))
+            .collect()
+    }
+
+    // Render an expression as a chain of applications, e.g., `$.idents->def`.
+    fn render(expr: &ast::ExprKind) -> String {
+        match expr {
+            ast::ExprKind::MetaVar(ast::MetaVarKind::Dollar) => "$".to_owned(),
+            ast::ExprKind::MetaVar(ast::MetaVarKind::Numeric(n)) => format!("${}", n),
+            ast::ExprKind::MetaVar(ast::MetaVarKind::Named(id)) => id.name.clone(),
+            ast::ExprKind::MetaVar(ast::MetaVarKind::All) => "$all".to_owned(),
+            ast::ExprKind::Apply(a) => {
+                let mut result = format!("{}->{}", render(&a.lhs.kind), a.ident.name);
+                for arg in &a.args {
+                    result.push(' ');
+                    result.push_str(&render(&arg.kind));
+                }
+                result
+            }
+            ast::ExprKind::Projection(p) => format!("{}.{}", render(&p.lhs.kind), p.ident.name),
+            ast::ExprKind::Lambda(l) => format!("|{}| {}", l.param.name, render(&l.body.kind)),
+            _ => "?".to_owned(),
+        }
+    }
+
+    fn expand_str(input: &str, aliases: &Aliases) -> Result<String, Error> {
+        match parse_stmt(input, None, aliases)?.kind {
+            ast::StatementKind::Expr(kind) => Ok(render(&kind)),
+            ast::StatementKind::ApplyShorthand(a) => {
+                Ok(format!("{} {}", a.ident.name, render(&a.lhs.kind)))
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let aliases = aliases(&[
+            ("defs", "idents->def"),
+            ("refsof", "defs.refs"),
+            ("within", "idents->pick $1"),
+        ]);
+        assert_eq!(Alias::new("idents->pick $1").unwrap().arity, 1);
+
+        assert_eq!(expand_str("$->defs", &aliases).unwrap(), "$->idents->def");
+        assert_eq!(expand_str("$.defs", &aliases).unwrap(), "$->idents->def");
+        assert_eq!(expand_str("defs $3", &aliases).unwrap(), "$3->idents->def");
+        assert_eq!(
+            expand_str("show $->refsof", &aliases).unwrap(),
+            "show $->idents->def.refs"
+        );
+        assert_eq!(
+            expand_str("$->within foo", &aliases).unwrap(),
+            "$->idents->pick foo"
+        );
+        // Aliases in arguments are expanded too.
+        assert_eq!(
+            expand_str("$->within ($->defs)", &aliases).unwrap(),
+            "$->idents->pick $->idents->def"
+        );
+        assert_eq!(expand_str("$->idents", &aliases).unwrap(), "$->idents");
+        // And in the bodies of lambdas.
+        assert_eq!(
+            expand_str("|x| x->defs", &aliases).unwrap(),
+            "|x| x->idents->def"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Alias::new("").is_err());
+        assert!(Alias::new("->foo").is_err());
+        assert!(Alias::new("idents->").is_err());
+
+        let aliases = aliases(&[("within", "idents->pick $1"), ("a", "b"), ("b", "a")]);
+        assert!(expand_str("$->within", &aliases).is_err());
+        assert!(expand_str("$->within x y", &aliases).is_err());
+        match expand_str("$->a", &aliases) {
+            Err(Error::Other(msg)) => assert!(msg.contains("recursive")),
+            _ => panic!(),
+        }
+    }
+}
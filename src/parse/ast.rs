@@ -24,6 +24,8 @@ pub enum StatementKind {
     Expr(ExprKind),
     // foo expr
     ApplyShorthand(Apply),
+    // foo = expr
+    Assign(Identifier, Expr),
     Meta(MetaKind),
 }
 
@@ -46,6 +48,16 @@ pub enum ExprKind {
     Location(Location),
     // expr.foo
     Projection(Projection),
+    // 42
+    Number(usize),
+    // "foo"
+    String(String),
+    // [expr, expr, ...]
+    Set(Vec<Expr>),
+    // expr[i], expr[start..end]
+    Index(Index),
+    // |x| expr
+    Lambda(Lambda),
 }
 
 #[derive(Clone)]
@@ -58,6 +70,18 @@ pub struct Apply {
 
 impl Node for Apply {}
 
+/// A function of one parameter, e.g., `|x| x->idents->def`.
+#[derive(Clone)]
+pub struct Lambda {
+    pub param: Identifier,
+    pub body: Box<Expr>,
+    // The text of the lambda as it was written, for showing it.
+    pub source: String,
+    pub ctx: Context,
+}
+
+impl Node for Lambda {}
+
 #[derive(Clone)]
 pub struct Projection {
     pub ident: Identifier,
@@ -78,6 +102,25 @@ impl From<Projection> for Apply {
     }
 }
 
+#[derive(Clone)]
+pub struct Index {
+    pub lhs: Box<Expr>,
+    pub slice: Slice,
+    pub ctx: Context,
+}
+
+impl Node for Index {}
+
+/// An index into a set, or a slice of a set. Indices count from the start of
+/// the set, or from the end if they are negative (`-1` is the last element).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Slice {
+    // [i]
+    Element(isize),
+    // [start..end], either may be omitted; `end` is exclusive.
+    Range(Option<isize>, Option<isize>),
+}
+
 #[derive(new, Clone)]
 pub struct Location {
     pub file: Option<String>,
@@ -93,12 +136,40 @@ pub enum MetaVarKind {
     Dollar,
     Numeric(isize),
     Named(Identifier),
+    // `$all`, every previous result.
+    All,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Clone)]
 pub enum MetaKind {
     Exit,
     Help,
+    // ^save var path
+    Save(MetaVarKind, String),
+    // ^load name path
+    Load(Identifier, String),
+    Reload,
+    // ^set [name value]
+    Set(Option<(String, String)>),
+    Backend,
+    // ^config reload
+    ReloadConfig,
+    // ^alias [name = query]
+    Alias(Option<(String, String)>),
+    // ^alias save
+    SaveAliases,
+    // ^record [--output] path
+    Record(String, bool),
+    // ^stop
+    StopRecording,
+    // ^log
+    Log,
+    // ^log tail [n]
+    LogTail(Option<usize>),
+    // ^focus [path]
+    Focus(Option<String>),
+    // ^explain statement
+    Explain(String),
 }
 
 #[derive(new, Clone)]
@@ -125,9 +196,13 @@ pub mod builder {
     }
 
     pub fn show(e: Expr) -> Statement {
+        apply_stmt("show", e)
+    }
+
+    pub fn apply_stmt(name: &str, e: Expr) -> Statement {
         Statement {
             kind: StatementKind::ApplyShorthand(Apply {
-                ident: ident("show"),
+                ident: ident(name),
                 lhs: Box::new(e),
                 args: vec![],
                 ctx: ctx(),
@@ -136,6 +211,25 @@ pub mod builder {
         }
     }
 
+    pub fn apply(name: &str, lhs: Expr, args: Vec<Expr>) -> Expr {
+        Expr {
+            kind: ExprKind::Apply(Apply {
+                ident: ident(name),
+                lhs: Box::new(lhs),
+                args,
+                ctx: ctx(),
+            }),
+            ctx: ctx(),
+        }
+    }
+
+    pub fn number(n: usize) -> Expr {
+        Expr {
+            kind: ExprKind::Number(n),
+            ctx: ctx(),
+        }
+    }
+
     pub fn void() -> Expr {
         Expr {
             kind: ExprKind::Void,
@@ -0,0 +1,172 @@
+//! Checking that arbitrary input can't crash the parser. The lexer indexes and
+//! slices the raw input, so it is fuzzed (see `fuzz/` in the repository) as
+//! well as tested with statements generated from the grammar.
+
+use super::{is_continuation, is_incomplete, parse_stmt, Aliases, Error};
+
+/// Panics if parsing panics, or an error's offset is not in `input`.
+pub fn check_parse(input: &str) {
+    is_incomplete(input);
+    is_continuation(input);
+    if let Err(e) = parse_stmt(input, None, &Aliases::new()) {
+        let errors = match e {
+            Error::Multiple(errors) => errors,
+            e => vec![e],
+        };
+        for e in errors {
+            if let Error::Lexing(_, offset) | Error::Parsing(_, offset) = e {
+                assert!(
+                    offset <= input.len(),
+                    "offset {} of error `{}` is past the end of {:?}",
+                    offset,
+                    e,
+                    input
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::parser::META_COMMANDS;
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::{collection, option};
+
+    fn name() -> impl Strategy<Value = String> {
+        "[a-z][a-z0-9_]{0,6}"
+    }
+
+    fn location() -> impl Strategy<Value = String> {
+        (
+            "[a-z_/]{0,8}(\\.rs)?",
+            option::of(1usize..500),
+            option::of(1usize..80),
+        )
+            .prop_map(|(file, line, column)| {
+                let mut result = format!("(:{}", file);
+                for n in line.iter().chain(&column) {
+                    result.push_str(&format!(":{}", n));
+                }
+                result + ")"
+            })
+    }
+
+    fn expr() -> impl Strategy<Value = String> {
+        let leaf = prop_oneof![
+            (0usize..1000).prop_map(|n| n.to_string()),
+            "\"([a-z0-9 _]|\\\\[\\\\\"nt]){0,8}\"",
+            Just("$".to_owned()),
+            (-3isize..10).prop_map(|n| format!("${}", n)),
+            Just("$all".to_owned()),
+            name(),
+            location(),
+        ];
+        leaf.prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                (
+                    inner.clone(),
+                    name(),
+                    collection::vec(inner.clone(), 0..3),
+                    any::<bool>()
+                )
+                    .prop_map(|(lhs, f, args, parens)| {
+                        if parens {
+                            format!("{}->{}({})", lhs, f, args.join(", "))
+                        } else {
+                            let args: String = args.iter().map(|a| format!(" {}", a)).collect();
+                            format!("{}->{}{}", lhs, f, args)
+                        }
+                    }),
+                (inner.clone(), name()).prop_map(|(lhs, f)| format!("{}.{}", lhs, f)),
+                collection::vec(inner.clone(), 0..4).prop_map(|es| format!("[{}]", es.join(", "))),
+                (
+                    inner.clone(),
+                    option::of(-5isize..5),
+                    option::of(-5isize..5),
+                    any::<bool>()
+                )
+                    .prop_map(|(lhs, start, end, range)| {
+                        // `a [-1]` would be the shorthand for `a->a [-1]` at the
+                        // start of a statement, where a set can't contain `-1`.
+                        let lhs = if lhs.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                            format!("({})", lhs)
+                        } else {
+                            lhs
+                        };
+                        let bound = |b: Option<isize>| b.map(|b| b.to_string()).unwrap_or_default();
+                        if range {
+                            format!("{}[{}..{}]", lhs, bound(start), bound(end))
+                        } else {
+                            format!("{}[{}]", lhs, start.unwrap_or(0))
+                        }
+                    }),
+                inner.clone().prop_map(|e| format!("({})", e)),
+                (name(), inner).prop_map(|(x, e)| format!("|{}| {}", x, e)),
+            ]
+        })
+    }
+
+    // Statements which should parse.
+    fn stmt() -> impl Strategy<Value = String> {
+        let stmt = prop_oneof![
+            expr(),
+            (name(), expr()).prop_map(|(x, e)| format!("{} = {}", x, e)),
+            (name(), expr()).prop_map(|(f, e)| format!("{} {}", f, e)),
+            expr().prop_map(|e| format!("^explain {}", e)),
+        ];
+        (stmt, any::<bool>()).prop_map(|(s, semi)| if semi { s + ";" } else { s })
+    }
+
+    // Meta-commands, whose arguments may be wrong.
+    fn meta() -> impl Strategy<Value = String> {
+        (prop::sample::select(META_COMMANDS), "[a-z0-9 ./]{0,10}")
+            .prop_map(|(c, args)| format!("^{} {}", c, args))
+    }
+
+    // A char boundary in `s`, chosen by `i`.
+    fn boundary(s: &str, i: prop::sample::Index) -> usize {
+        let boundaries: Vec<_> = (0..=s.len()).filter(|b| s.is_char_boundary(*b)).collect();
+        boundaries[i.index(boundaries.len())]
+    }
+
+    proptest! {
+        #[test]
+        fn test_arbitrary(input in any::<String>()) {
+            check_parse(&input);
+        }
+
+        #[test]
+        fn test_tokens(input in "([-$>.,;:|#^=+<&\\[\\](){}\"\\\\ \n]|[a-z0-9]{1,3}|é){0,20}") {
+            check_parse(&input);
+        }
+
+        #[test]
+        fn test_grammar(stmt in stmt()) {
+            check_parse(&stmt);
+            prop_assert!(parse_stmt(&stmt, None, &Aliases::new()).is_ok());
+        }
+
+        #[test]
+        fn test_meta(stmt in meta()) {
+            check_parse(&stmt);
+        }
+
+        #[test]
+        fn test_truncated(stmt in stmt(), i in any::<prop::sample::Index>()) {
+            check_parse(&stmt[..boundary(&stmt, i)]);
+        }
+
+        #[test]
+        fn test_inserted(
+            stmt in stmt(),
+            i in any::<prop::sample::Index>(),
+            c in any::<char>(),
+        ) {
+            let mut stmt = stmt;
+            stmt.insert(boundary(&stmt, i), c);
+            check_parse(&stmt);
+        }
+    }
+}
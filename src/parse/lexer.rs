@@ -24,21 +24,49 @@ impl<'a> Lexer<'a> {
     /// Postcondition: if result is Ok, then Token.kind is TokenTree.
     fn lex_tree(&mut self) -> Result<Token, parse::Error> {
         let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        // The end of the tree, not including any trailing comment.
+        let mut end = self.position;
         loop {
             let current_input = &self.input[self.position..];
             if current_input.is_empty() {
                 break;
             }
-            match self.lex_tok()? {
+            let tok = match self.lex_tok() {
+                Ok(tok) => tok,
+                Err(e) => {
+                    // Skip the offending character and carry on lexing so that
+                    // we can report any further errors.
+                    errors.push(e);
+                    self.position += match current_input.chars().next().unwrap() {
+                        // Don't try to lex the contents of a bad string.
+                        '"' => string_len(current_input),
+                        // Or a tree with bad delimiters, which would
+                        // report the same error for each nested tree.
+                        '(' | '[' => current_input.len(),
+                        c => c.len_utf8(),
+                    };
+                    continue;
+                }
+            };
+            match tok {
                 Some((t, len)) => match &t.kind {
+                    // A comment runs to the end of the line.
                     TokenKind::Symbol(SymbolKind::Hash) => {
-                        break;
+                        self.position += comment_len(current_input);
+                        continue;
                     }
                     TokenKind::Symbol(SymbolKind::SemiColon) => {
                         tokens.push(t);
                         self.position += len;
+                        end = self.position;
                         break;
                     }
+                    TokenKind::Symbol(SymbolKind::Caret) if tokens.is_empty() => {
+                        tokens.push(t);
+                        self.position += len;
+                        self.lex_meta(&mut tokens)?;
+                    }
                     _ => {
                         tokens.push(t);
                         self.position += len;
@@ -46,13 +74,17 @@ impl<'a> Lexer<'a> {
                 },
                 // Whitespace
                 None => {
-                    self.position += 1;
+                    self.position += current_input.chars().next().unwrap().len_utf8();
                 }
             }
+            end = self.position;
+        }
+        if !errors.is_empty() {
+            return Err(parse::Error::from_errors(errors));
         }
         Ok(Token {
             kind: TokenKind::Tree(TokenTree { tokens }),
-            span: Span::new(self.offset, self.input[..self.position].to_owned()),
+            span: Span::new(self.offset, self.input[..end].to_owned()),
         })
     }
 
@@ -66,9 +98,43 @@ impl<'a> Lexer<'a> {
             '^' => Ok(Some((self.make_symbol(SymbolKind::Caret), 1))),
             '$' => Ok(Some((self.make_symbol(SymbolKind::Dollar), 1))),
             '.' => Ok(Some((self.make_symbol(SymbolKind::Dot), 1))),
-            '=' => Ok(Some((self.make_symbol(SymbolKind::Eq), 1))),
+            ',' => Ok(Some((self.make_symbol(SymbolKind::Comma), 1))),
+            ':' => Ok(Some((self.make_symbol(SymbolKind::Colon), 1))),
             '#' => Ok(Some((self.make_symbol(SymbolKind::Hash), 1))),
             ';' => Ok(Some((self.make_symbol(SymbolKind::SemiColon), 1))),
+            '>' => Ok(Some((self.make_symbol(SymbolKind::Gt), 1))),
+            // Multi-char symbols take precedence over their prefixes, e.g.,
+            // `<-1` is `<-` followed by `1`, not `<` followed by `-1`.
+            '=' => Ok(Some(self.lex_symbol(
+                chars.next(),
+                &[('=', SymbolKind::EqEq)],
+                Some(SymbolKind::Eq),
+            )?)),
+            '!' => Ok(Some(self.lex_symbol(
+                chars.next(),
+                &[('=', SymbolKind::NotEq)],
+                Some(SymbolKind::Bang),
+            )?)),
+            '<' => Ok(Some(self.lex_symbol(
+                chars.next(),
+                &[('-', SymbolKind::ArrowLeft)],
+                Some(SymbolKind::Lt),
+            )?)),
+            '+' => Ok(Some(self.lex_symbol(
+                chars.next(),
+                &[('=', SymbolKind::PlusEq)],
+                None,
+            )?)),
+            '&' => Ok(Some(self.lex_symbol(
+                chars.next(),
+                &[('&', SymbolKind::AndAnd)],
+                None,
+            )?)),
+            '|' => Ok(Some(self.lex_symbol(
+                chars.next(),
+                &[('|', SymbolKind::OrOr)],
+                Some(SymbolKind::Bar),
+            )?)),
             // `->`
             '-' => match chars.next() {
                 None => Err(self.make_err("Unexpected end of input, expected `>`".to_owned(), 1)),
@@ -81,7 +147,8 @@ impl<'a> Lexer<'a> {
             },
             // A nested token tree, we don't lex this beyond matching delimiters, and
             // store the result as a RawTree.
-            '(' => self.lex_raw_tree(),
+            '(' | '[' => self.lex_raw_tree(),
+            '"' => self.lex_string(),
             c if c.is_alphabetic() || c == '_' => self.lex_ident(),
             c if c.is_numeric() => self.lex_number(),
             c if c.is_whitespace() => Ok(None),
@@ -89,6 +156,38 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    // A two-character symbol if `next` is in `two_char`, otherwise `one_char`.
+    fn lex_symbol(
+        &self,
+        next: Option<char>,
+        two_char: &[(char, SymbolKind)],
+        one_char: Option<SymbolKind>,
+    ) -> Result<(Token, usize), parse::Error> {
+        if let Some(next) = next {
+            if let Some((_, kind)) = two_char.iter().find(|(c, _)| *c == next) {
+                return Ok((Token::new(TokenKind::Symbol(*kind), self.make_span(2)), 2));
+            }
+        }
+        match one_char {
+            Some(kind) => Ok((self.make_symbol(kind), 1)),
+            None => {
+                let c = self.input[self.position..].chars().next().unwrap();
+                let expected: Vec<String> = two_char
+                    .iter()
+                    .map(|(next, _)| format!("`{}{}`", c, next))
+                    .collect();
+                Err(self.make_err(
+                    format!(
+                        "Unexpected token `{}`, expected {}",
+                        c,
+                        expected.join(" or ")
+                    ),
+                    0,
+                ))
+            }
+        }
+    }
+
     // Lex an identifier. If the current character is not alphanumeric, this
     // function will return an Ident token with zero length.
     fn lex_ident(&self) -> Result<Option<(Token, usize)>, parse::Error> {
@@ -108,6 +207,39 @@ impl<'a> Lexer<'a> {
         )))
     }
 
+    // The arguments are kept as a single Text token.
+    fn lex_meta(&mut self, tokens: &mut Vec<Token>) -> Result<(), parse::Error> {
+        self.skip_whitespace();
+        match self.input[self.position..].chars().next() {
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let (t, len) = self.lex_ident()?.unwrap();
+                tokens.push(t);
+                self.position += len;
+            }
+            _ => return Ok(()),
+        }
+
+        self.skip_whitespace();
+        let len = self.input[self.position..]
+            .find([';', '#'])
+            .unwrap_or_else(|| self.input.len() - self.position);
+        let text = self.input[self.position..self.position + len].trim_end();
+        if !text.is_empty() {
+            tokens.push(Token::new(TokenKind::Text, self.make_span(text.len())));
+        }
+        self.position += len;
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.input[self.position..].chars().next() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.position += c.len_utf8();
+        }
+    }
+
     fn lex_number(&self) -> Result<Option<(Token, usize)>, parse::Error> {
         let mut chars = self.input[self.position..].chars();
         let mut number = String::new();
@@ -115,7 +247,9 @@ impl<'a> Lexer<'a> {
         let mut len = 0;
         loop {
             match chars.next() {
-                Some('-') => negator *= -1,
+                // Only a leading `-` is part of the number, e.g., `$1->foo` is
+                // `$1` followed by `->`.
+                Some('-') if len == 0 => negator = -1,
                 Some(c) if c.is_numeric() => {
                     number.push(c);
                 }
@@ -130,6 +264,36 @@ impl<'a> Lexer<'a> {
         )))
     }
 
+    // Lex a string literal. The escapes `\"`, `\\`, `\n`, and `\t` are
+    // replaced in the token's value.
+    fn lex_string(&self) -> Result<Option<(Token, usize)>, parse::Error> {
+        let mut chars = self.input[self.position..].char_indices().skip(1);
+        let mut value = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    let len = i + 1;
+                    return Ok(Some((
+                        Token::new(TokenKind::Str(value), self.make_span(len)),
+                        len,
+                    )));
+                }
+                '\\' => match chars.next() {
+                    Some((_, '"')) => value.push('"'),
+                    Some((_, '\\')) => value.push('\\'),
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, c)) => {
+                        return Err(self.make_err(format!("Unknown escape: `\\{}`", c), i));
+                    }
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(self.make_err("Unterminated string, expected `\"`".to_owned(), 0))
+    }
+
     // Lex a raw tree from the input. This will lex until either the input is
     // empty or until opening delimiters are closed. Note that if there are no
     // opening delimiters, then this function will succeed but produce an odd
@@ -138,12 +302,31 @@ impl<'a> Lexer<'a> {
         let mut chars = self.input[self.position..].chars();
         let mut len = 0;
         let mut delim_stack = Vec::new();
+        let delimiter = match self.input[self.position..].chars().next() {
+            Some('[') => Delimiter::Bracket,
+            _ => Delimiter::Paren,
+        };
         loop {
             match chars.next() {
                 Some('(') => {
                     len += 1;
                     delim_stack.push(')');
                 }
+                Some('[') => {
+                    len += 1;
+                    delim_stack.push(']');
+                }
+                // Skip string literals and comments, which might contain
+                // delimiters.
+                Some('"') => {
+                    let string_len = string_len(&self.input[self.position + len..]);
+                    len += string_len;
+                    chars = self.input[self.position + len..].chars();
+                }
+                Some('#') => {
+                    len += comment_len(&self.input[self.position + len..]);
+                    chars = self.input[self.position + len..].chars();
+                }
                 Some(c) if c == *delim_stack.last().unwrap() => {
                     len += 1;
                     delim_stack.pop().unwrap();
@@ -151,6 +334,16 @@ impl<'a> Lexer<'a> {
                         break;
                     }
                 }
+                Some(c @ ')') | Some(c @ ']') => {
+                    return Err(self.make_err(
+                        format!(
+                            "Mismatched delimiter, expected `{}`, found `{}`",
+                            delim_stack.last().unwrap(),
+                            c
+                        ),
+                        len,
+                    ))
+                }
                 Some(c) => {
                     len += c.len_utf8();
                 }
@@ -161,7 +354,7 @@ impl<'a> Lexer<'a> {
                     return Err(self.make_err(
                         format!(
                             "Unexpected end of input (unclosed delimiters), expected `{}`",
-                            encode_ascii(&delim_stack)
+                            encode_ascii(&delim_stack.iter().rev().cloned().collect::<Vec<_>>())
                         ),
                         len - 1,
                     ))
@@ -169,7 +362,7 @@ impl<'a> Lexer<'a> {
             }
         }
         Ok(Some((
-            Token::new(TokenKind::RawTree, self.make_span(len)),
+            Token::new(TokenKind::RawTree(delimiter), self.make_span(len)),
             len,
         )))
     }
@@ -209,6 +402,62 @@ impl<'a> Lexer<'a> {
     }
 }
 
+// The length in bytes of the string literal at the start of `input`, including
+// the quotes. If the string is unterminated, this is the length of `input`.
+fn string_len(input: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in input.char_indices().skip(1) {
+        match c {
+            '"' if !escaped => return i + 1,
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    input.len()
+}
+
+// The length in bytes of the comment at the start of `input`, not including
+// the newline which ends it.
+fn comment_len(input: &str) -> usize {
+    input.find('\n').unwrap_or(input.len())
+}
+
+/// True if `input` ends inside a bracket or string literal.
+pub fn is_unclosed(input: &str) -> bool {
+    let mut depth = 0usize;
+    let mut position = 0;
+    while let Some(c) = input[position..].chars().next() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            '"' => {
+                let len = string_len(&input[position..]);
+                if !is_terminated(&input[position..position + len]) {
+                    return true;
+                }
+                position += len;
+                continue;
+            }
+            '#' => {
+                position += comment_len(&input[position..]);
+                continue;
+            }
+            _ => {}
+        }
+        position += c.len_utf8();
+    }
+    depth > 0
+}
+
+// True if `string` (a string literal, see `string_len`) ends with an unescaped
+// quote.
+fn is_terminated(string: &str) -> bool {
+    match string[1..].strip_suffix('"') {
+        Some(body) => (body.len() - body.trim_end_matches('\\').len()) % 2 == 0,
+        None => false,
+    }
+}
+
 /// Precondition: each char is one byte wide
 fn encode_ascii(chars: &[char]) -> String {
     let mut result = vec![0; chars.len()];
@@ -273,7 +522,7 @@ mod test {
                             span: Span::new(2, "foo".to_owned())
                         },
                         Token {
-                            kind: TokenKind::RawTree,
+                            kind: TokenKind::RawTree(Delimiter::Paren),
                             span: Span::new(7, "(fd && dfs: Foo( )  )".to_owned())
                         },
                     ]
@@ -349,6 +598,107 @@ mod test {
                 span: Span::new(0, "-42".to_owned()),
             }
         );
+        let (tt, _) = lex("42->foo", 0).unwrap().expect_tree();
+        assert_eq!(tt.tokens[0].kind, TokenKind::Number(42));
+        assert_eq!(tt.tokens[1].kind, TokenKind::Symbol(SymbolKind::ArrowRight));
+    }
+
+    #[test]
+    fn lex_meta() {
+        assert_eq!(
+            lex("^save $2 out/foo.json ; # a comment", 0).unwrap(),
+            Token {
+                kind: TokenKind::Tree(TokenTree {
+                    tokens: vec![
+                        Token {
+                            kind: TokenKind::Symbol(SymbolKind::Caret),
+                            span: Span::new(0, "^".to_owned())
+                        },
+                        Token {
+                            kind: TokenKind::Ident,
+                            span: Span::new(1, "save".to_owned())
+                        },
+                        Token {
+                            kind: TokenKind::Text,
+                            span: Span::new(6, "$2 out/foo.json".to_owned())
+                        },
+                        Token {
+                            kind: TokenKind::Symbol(SymbolKind::SemiColon),
+                            span: Span::new(22, ";".to_owned())
+                        },
+                    ]
+                }),
+                span: Span::new(0, "^save $2 out/foo.json ;".to_owned()),
+            }
+        );
+        assert_eq!(
+            lex("^help", 0).unwrap(),
+            Token {
+                kind: TokenKind::Tree(TokenTree {
+                    tokens: vec![
+                        Token {
+                            kind: TokenKind::Symbol(SymbolKind::Caret),
+                            span: Span::new(0, "^".to_owned())
+                        },
+                        Token {
+                            kind: TokenKind::Ident,
+                            span: Span::new(1, "help".to_owned())
+                        },
+                    ]
+                }),
+                span: Span::new(0, "^help".to_owned()),
+            }
+        );
+    }
+
+    // Lex `input` and return the kinds of the resulting tokens.
+    fn symbols(input: &str) -> Vec<SymbolKind> {
+        lex(input, 0)
+            .unwrap()
+            .expect_tree()
+            .0
+            .tokens
+            .into_iter()
+            .map(|t| match t.kind {
+                TokenKind::Symbol(s) => s,
+                k => panic!("Expected symbol, found {:?}", k),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lex_symbols() {
+        use SymbolKind::*;
+
+        assert_eq!(
+            symbols(". , : < > ! && || | == != = <- -> += $"),
+            vec![
+                Dot, Comma, Colon, Lt, Gt, Bang, AndAnd, OrOr, Bar, EqEq, NotEq, Eq, ArrowLeft,
+                ArrowRight, PlusEq, Dollar
+            ]
+        );
+        // Multi-char symbols are preferred to single char ones.
+        assert_eq!(symbols("==="), vec![EqEq, Eq]);
+        assert_eq!(symbols("!=="), vec![NotEq, Eq]);
+        assert_eq!(symbols("!!="), vec![Bang, NotEq]);
+        assert_eq!(symbols("<->"), vec![ArrowLeft, Gt]);
+        assert_eq!(symbols("<<-"), vec![Lt, ArrowLeft]);
+        assert_eq!(symbols("&&&&"), vec![AndAnd, AndAnd]);
+        assert_eq!(symbols("|||| "), vec![OrOr, OrOr]);
+        assert_eq!(symbols("|||"), vec![OrOr, Bar]);
+        assert_eq!(symbols("->>"), vec![ArrowRight, Gt]);
+        assert_eq!(symbols(":::"), vec![Colon, Colon, Colon]);
+        assert_eq!(symbols("!<>"), vec![Bang, Lt, Gt]);
+
+        // Display round-trips.
+        let s = ". , : < > ! && || == != = <- -> +=";
+        let displayed: Vec<String> = symbols(s).iter().map(|s| s.to_string()).collect();
+        assert_eq!(displayed.join(" "), s);
+
+        // A number after `<` is part of `<-`.
+        let tokens = lex("<-1", 0).unwrap().expect_tree().0.tokens;
+        assert_eq!(tokens[0].kind, TokenKind::Symbol(ArrowLeft));
+        assert_eq!(tokens[1].kind, TokenKind::Number(1));
     }
 
     #[test]
@@ -357,5 +707,68 @@ mod test {
         assert!(lex("%", 0).is_err());
         assert!(lex("-", 0).is_err());
         assert!(lex("(foo", 0).is_err());
+        for input in &["&", "+", "& &", "+-", "&|"] {
+            match lex(input, 0) {
+                Err(parse::Error::Lexing(_, 0)) | Err(parse::Error::Multiple(_)) => {}
+                r => panic!("{}: {:?}", input, r),
+            }
+        }
+    }
+
+    #[test]
+    fn lex_brackets() {
+        let (tt, _) = lex("[(:foo.rs:3), (a[0])] ->idents", 0)
+            .unwrap()
+            .expect_tree();
+        assert_eq!(tt.tokens.len(), 3);
+        assert_eq!(tt.tokens[0].kind, TokenKind::RawTree(Delimiter::Bracket));
+        assert_eq!(tt.tokens[0].span.text, "[(:foo.rs:3), (a[0])]");
+        let (inner, _) = tt.tokens[0].expect_raw_tree().unwrap();
+        assert_eq!(inner.tokens.len(), 3);
+        assert_eq!(inner.tokens[2].kind, TokenKind::RawTree(Delimiter::Paren));
+        assert_eq!(inner.tokens[2].span, Span::new(14, "(a[0])".to_owned()));
+
+        match lex("[(]", 0) {
+            Err(parse::Error::Lexing(msg, 2)) => assert!(msg.contains("Mismatched")),
+            r => panic!("{:?}", r),
+        }
+        match lex("[(", 0) {
+            Err(parse::Error::Lexing(msg, _)) => assert!(msg.ends_with("expected `)]`")),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn lex_string() {
+        let (tt, _) = lex(r#"kind "f\"n\\" ("a)")"#, 0).unwrap().expect_tree();
+        assert_eq!(tt.tokens.len(), 3);
+        assert_eq!(tt.tokens[1].kind, TokenKind::Str("f\"n\\".to_owned()));
+        assert_eq!(tt.tokens[1].span, Span::new(5, r#""f\"n\\""#.to_owned()));
+        // Delimiters in strings don't close a tree.
+        assert_eq!(tt.tokens[2].kind, TokenKind::RawTree(Delimiter::Paren));
+        assert_eq!(tt.tokens[2].span.text, r#"("a)")"#);
+
+        match lex(r#""foo"#, 0) {
+            Err(parse::Error::Lexing(_, 0)) => {}
+            r => panic!("{:?}", r),
+        }
+        match lex(r#"x "\q""#, 0) {
+            Err(parse::Error::Lexing(_, 3)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn multiple_errors() {
+        match lex("$ % ->foo & bar", 0) {
+            Err(parse::Error::Multiple(errs)) => {
+                assert_eq!(errs.len(), 2);
+                match (&errs[0], &errs[1]) {
+                    (parse::Error::Lexing(_, 2), parse::Error::Lexing(_, 10)) => {}
+                    _ => panic!("{:?}", errs),
+                }
+            }
+            r => panic!("{:?}", r),
+        }
     }
 }
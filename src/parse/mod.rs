@@ -1,17 +1,114 @@
+mod alias;
 pub mod ast;
+mod fuzz;
 mod lexer;
 mod parser;
 mod tokens;
 
+pub use self::alias::{Alias, Aliases};
+pub use self::fuzz::check_parse;
+
+use self::tokens::{SymbolKind, TokenKind};
+use std::fmt;
+use unicode_width::UnicodeWidthStr;
+
 #[derive(Debug, Clone)]
 pub enum Error {
-    // String is the error message, usize is the offset into the input.
+    // String is the error message, usize is the offset into the input in
+    // bytes (see `column`).
     Lexing(String, usize),
-    Parsing(String),
+    Parsing(String, usize),
+    // More than one error was found in the input, these are never nested.
+    Multiple(Vec<Error>),
     EmptyInput,
     Other(String),
 }
 
+impl Error {
+    fn from_errors(mut errors: Vec<Error>) -> Error {
+        assert!(!errors.is_empty());
+        if errors.len() == 1 {
+            return errors.pop().unwrap();
+        }
+        let mut result = Vec::new();
+        for e in errors {
+            match e {
+                Error::Multiple(es) => result.extend(es),
+                e => result.push(e),
+            }
+        }
+        Error::Multiple(result)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lexing(msg, offset) | Error::Parsing(msg, offset) => {
+                write!(f, "{} (at offset {})", msg, offset)
+            }
+            Error::Multiple(errs) => {
+                for (i, e) in errs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    e.fmt(f)?;
+                }
+                Ok(())
+            }
+            Error::EmptyInput => write!(f, "empty input"),
+            Error::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// The display column in `input` of the byte offset `offset` (e.g., from an
+/// error), for pointing at the input.
+pub fn column(input: &str, offset: usize) -> usize {
+    match input.get(..offset) {
+        Some(s) => s.width(),
+        None => input.width() + offset.saturating_sub(input.len()),
+    }
+}
+
+/// True if the statement continues on the next line of a script, e.g., it has
+/// unclosed brackets.
+pub fn is_incomplete(input: &str) -> bool {
+    if input.trim_start().starts_with('^') {
+        return false;
+    }
+    if lexer::is_unclosed(input) {
+        return true;
+    }
+    let tokens = match lexer::lex(input, 0) {
+        Ok(tree) => tree.expect_tree().0.tokens,
+        Err(_) => return false,
+    };
+    match tokens.last().map(|t| &t.kind) {
+        Some(TokenKind::Symbol(s)) => matches!(
+            s,
+            SymbolKind::ArrowRight
+                | SymbolKind::ArrowLeft
+                | SymbolKind::Comma
+                | SymbolKind::AndAnd
+                | SymbolKind::OrOr
+                | SymbolKind::Eq
+                | SymbolKind::PlusEq
+        ),
+        _ => false,
+    }
+}
+
+/// True if the first line of `input` which is not blank or a comment continues
+/// the previous statement of a script, i.e., it starts with `->` or `.`.
+pub fn is_continuation(input: &str) -> bool {
+    input
+        .lines()
+        .map(str::trim_start)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .is_some_and(|l| l.starts_with("->") || l.starts_with('.'))
+}
+
 // FIXME we include this context with each node, it should include information
 // specific to the node, e.g. tokens/spans
 /// Contextual information about input or output to parsing.
@@ -34,7 +131,12 @@ pub trait EnvContext {
     fn clone(&self) -> Box<dyn EnvContext>;
 }
 
-pub fn parse_stmt(s: &str, env_ctx: Option<Box<dyn EnvContext>>) -> Result<ast::Statement, Error> {
+/// Parse a single statement, expanding any uses of `aliases`.
+pub fn parse_stmt(
+    s: &str,
+    env_ctx: Option<Box<dyn EnvContext>>,
+    aliases: &Aliases,
+) -> Result<ast::Statement, Error> {
     let mut ctx = Context::default();
     ctx.input = Some(s.to_owned());
     ctx.env_ctx = env_ctx;
@@ -42,5 +144,74 @@ pub fn parse_stmt(s: &str, env_ctx: Option<Box<dyn EnvContext>>) -> Result<ast::
     if toks.is_empty() {
         return Err(Error::EmptyInput);
     }
-    parser::parse_stmt(toks, ctx.clone())
+    let stmt = parser::parse_stmt(toks, ctx.clone())?;
+    alias::expand_stmt(stmt, aliases)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The offsets of the errors from parsing `input`.
+    fn error_offsets(input: &str) -> Vec<usize> {
+        let errors = match parse_stmt(input, None, &Aliases::new()) {
+            Err(Error::Multiple(errs)) => errs,
+            Err(e) => vec![e],
+            Ok(_) => panic!("no error: {}", input),
+        };
+        errors
+            .into_iter()
+            .map(|e| match e {
+                Error::Lexing(_, offset) | Error::Parsing(_, offset) => offset,
+                e => panic!("{:?}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_multi_line() {
+        assert!(is_incomplete("select (:foo.rs)->"));
+        assert!(is_incomplete("show [1, # a comment\n"));
+        assert!(is_incomplete("let x = # a comment"));
+        assert!(is_incomplete("show \"foo\\\""));
+        assert!(!is_incomplete("show $"));
+        assert!(!is_incomplete("show (# )\n)"));
+        assert!(!is_incomplete("show \"(\\\\\""));
+        assert!(!is_incomplete("show $->;"));
+        assert!(!is_incomplete("^alias defs = idents->"));
+
+        assert!(is_continuation("    ->def\n"));
+        assert!(is_continuation("\n# a comment\n  .name"));
+        assert!(!is_continuation("show $->def"));
+        assert!(!is_continuation("# ->def"));
+
+        let stmt = "select (:foo.rs # all of it\n)\n    ->idents # then the defs\n    ->def";
+        assert!(parse_stmt(stmt, None, &Aliases::new()).is_ok());
+    }
+
+    #[test]
+    fn test_nested_offsets() {
+        // Errors in nested trees, with whitespace inside the delimiters.
+        assert_eq!(error_offsets("show (  $ %)"), vec![10]);
+        assert_eq!(error_offsets("show ( ( ($ %) ) )"), vec![12]);
+        assert_eq!(error_offsets("show [$, ( $ $ )]"), vec![13]);
+        assert_eq!(error_offsets("show ( ($->foo $ %) )"), vec![17]);
+        // Errors in locations point at the offending part.
+        assert_eq!(error_offsets("show ( :foo.rs: x)"), vec![16]);
+        assert_eq!(error_offsets("show ((:3:4: 5))"), vec![13]);
+        assert_eq!(error_offsets("show ( ( :foo.rs:1:2:3 ))"), vec![21]);
+    }
+
+    #[test]
+    fn test_unicode_offsets() {
+        // Offsets are in bytes.
+        let input = "show (\"名前\"->foo (:é.rs:x))";
+        let offset = error_offsets(input)[0];
+        assert_eq!(&input[offset..offset + 1], "x");
+        // Columns are in display columns.
+        assert_eq!(column(input, offset), 25);
+        assert_eq!(column("名前", 0), 0);
+        assert_eq!(column("名前", 3), 2);
+        assert_eq!(column("ab", 4), 4);
+    }
 }
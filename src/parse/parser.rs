@@ -1,24 +1,82 @@
 use crate::parse::{self, ast, tokens, Context, Error};
 
+// The name of the variable for every previous result, `$all`.
+const ALL_VAR: &str = "all";
+
+// The names of meta-commands, listed when an unknown one is used (`^q` and `^h`
+// are short for `^exit` and `^help`).
+pub(super) const META_COMMANDS: &[&str] = &[
+    "alias", "backend", "config", "exit", "explain", "focus", "help", "load", "log", "paste",
+    "record", "reload", "save", "set", "stop",
+];
+
 pub fn parse_stmt(toks: tokens::Token, ctx: Context) -> Result<ast::Statement, Error> {
-    let (tt, _) = toks.expect_tree();
-    let mut parser = Parser {
-        tokens: tt.tokens,
-        position: 0,
-        ctx,
-    };
-    let result = parser.parse_stmt()?;
-    parser.end()?;
-    Ok(result)
+    let (tt, span) = toks.expect_tree();
+    let mut parser = Parser::new(tt.tokens, span.start + span.text.len(), ctx);
+    let result = parser.parse_stmt().and_then(|stmt| {
+        parser.end()?;
+        Ok(stmt)
+    });
+    parser.finish(result)
+}
+
+/// Parse an expression from `toks`, `end` is the offset of the end of input.
+pub fn parse_expr(toks: Vec<tokens::Token>, end: usize, ctx: Context) -> Result<ast::Expr, Error> {
+    let mut parser = Parser::new(toks, end, ctx);
+    let result = parser.parse_expr().and_then(|expr| {
+        parser.end()?;
+        Ok(expr)
+    });
+    parser.finish(result)
 }
 
 struct Parser {
     tokens: Vec<tokens::Token>,
     position: usize,
+    // The offset of the end of the input, used for errors at the end of input.
+    end: usize,
     ctx: Context,
+    // Errors which we have recovered from.
+    errors: Vec<Error>,
 }
 
 impl Parser {
+    fn new(tokens: Vec<tokens::Token>, end: usize, ctx: Context) -> Parser {
+        Parser {
+            tokens,
+            position: 0,
+            end,
+            ctx,
+            errors: Vec::new(),
+        }
+    }
+
+    // Combine `result` with any errors we recovered from during parsing.
+    fn finish<T>(self, result: Result<T, Error>) -> Result<T, Error> {
+        let mut errors = self.errors;
+        match result {
+            Ok(_) if errors.is_empty() => result,
+            Ok(_) => Err(Error::from_errors(errors)),
+            Err(e) => {
+                errors.push(e);
+                Err(Error::from_errors(errors))
+            }
+        }
+    }
+
+    // Record an error and skip to the next point where we can resume parsing
+    // (a `->` or the end of the statement).
+    fn recover(&mut self, e: Error) {
+        self.errors.push(e);
+        while let Some(tok) = self.peek() {
+            match tok.kind {
+                tokens::TokenKind::Symbol(tokens::SymbolKind::ArrowRight)
+                | tokens::TokenKind::Symbol(tokens::SymbolKind::SemiColon) => break,
+                _ => self.bump(),
+            }
+        }
+    }
+
     fn parse_stmt(&mut self) -> Result<ast::Statement, Error> {
         let tok = match self.peek() {
             Some(tok) => tok,
@@ -28,9 +86,16 @@ impl Parser {
 
         let mut kind = None;
         match tok.kind {
-            tokens::TokenKind::Ident => {
+            // If the identifier is followed by an expression, then it is a function
+            // name, otherwise it is the name of a variable.
+            tokens::TokenKind::Ident if self.peek_nth(1).map(starts_expr).unwrap_or(false) => {
                 kind = Some(ast::StatementKind::ApplyShorthand(self.apply_shorthand()?));
             }
+            tokens::TokenKind::Ident if self.peek_nth(1).map(is_eq).unwrap_or(false) => {
+                let ident = self.identifier()?;
+                self.bump();
+                kind = Some(ast::StatementKind::Assign(ident, self.parse_expr()?));
+            }
             tokens::TokenKind::Symbol(sym) if sym == tokens::SymbolKind::Caret => {
                 kind = Some(ast::StatementKind::Meta(self.meta()?));
             }
@@ -67,38 +132,83 @@ impl Parser {
         let kind = match tok.kind {
             tokens::TokenKind::Symbol(sym) => match sym {
                 tokens::SymbolKind::Dollar => {
+                    let end = tok.span.start + 1;
                     self.bump();
                     let mut number = None;
-                    if let Some(tokens::TokenKind::Number(n)) = self.peek().map(|t| &t.kind) {
-                        number = Some(*n as isize);
+                    let mut all = false;
+                    match self.peek() {
+                        Some(tokens::Token {
+                            kind: tokens::TokenKind::Number(n),
+                            ..
+                        }) => number = Some(*n as isize),
+                        // Only `$all` as one word, `$ all` is `$` followed by
+                        // a variable.
+                        Some(tokens::Token {
+                            kind: tokens::TokenKind::Ident,
+                            span,
+                        }) => all = span.text == ALL_VAR && span.start == end,
+                        _ => {}
                     }
 
-                    if let Some(n) = number {
+                    if all {
+                        self.bump();
+                        ast::ExprKind::MetaVar(ast::MetaVarKind::All)
+                    } else if let Some(n) = number {
                         self.bump();
                         ast::ExprKind::MetaVar(ast::MetaVarKind::Numeric(n))
                     } else {
                         ast::ExprKind::MetaVar(ast::MetaVarKind::Dollar)
                     }
                 }
+                // The body of a lambda extends as far as possible, so there is
+                // nothing to apply to the lambda itself.
+                tokens::SymbolKind::Bar => return self.lambda().map(Some),
                 _ => return Ok(None),
             },
-            tokens::TokenKind::RawTree => {
-                let inner = tok.span.inner();
-                if inner.starts_with(':') {
-                    let loc_parser = LocationParser::new(inner, self.ctx.clone());
+            tokens::TokenKind::Ident => {
+                let ident = self.identifier()?;
+                ast::ExprKind::MetaVar(ast::MetaVarKind::Named(ident))
+            }
+            tokens::TokenKind::Number(n) => {
+                if n < 0 {
+                    return Err(self.make_err(format!("Expected a positive number, found `{}`", n)));
+                }
+                self.bump();
+                ast::ExprKind::Number(n as usize)
+            }
+            tokens::TokenKind::Str(ref s) => {
+                let s = s.clone();
+                self.bump();
+                ast::ExprKind::String(s)
+            }
+            tokens::TokenKind::RawTree(tokens::Delimiter::Bracket) => {
+                let end = tok.span.start + tok.span.text.len() - 1;
+                let (tt, _) = tok.expect_raw_tree()?;
+                self.bump();
+                let mut parser = Parser::new(tt.tokens, end, self.ctx.clone());
+                let elements = parser.comma_separated("]");
+                self.errors.append(&mut parser.errors);
+                ast::ExprKind::Set(elements?)
+            }
+            tokens::TokenKind::RawTree(tokens::Delimiter::Paren) => {
+                if tok.span.inner().starts_with(':') {
+                    let loc_parser = LocationParser::new(
+                        tok.span.contents(),
+                        tok.span.start + 1,
+                        self.ctx.clone(),
+                    );
                     let loc = loc_parser.location()?;
                     self.bump();
                     ast::ExprKind::Location(loc)
                 } else {
+                    let end = tok.span.start + tok.span.text.len() - 1;
                     let (tt, _) = tok.expect_raw_tree()?;
                     self.bump();
-                    let mut parser = Parser {
-                        tokens: tt.tokens,
-                        position: 0,
-                        ctx: self.ctx.clone(),
-                    };
-                    match parser.maybe_expr()? {
-                        Some(expr) => return Ok(Some(expr)),
+                    let mut parser = Parser::new(tt.tokens, end, self.ctx.clone());
+                    let expr = parser.paren_expr();
+                    self.errors.append(&mut parser.errors);
+                    match expr? {
+                        Some(expr) => expr.kind,
                         None => ast::ExprKind::Void,
                     }
                 }
@@ -111,32 +221,106 @@ impl Parser {
             ctx: self.ctx.clone(),
         };
 
-        // FIXME should allow mixing `.` and `->`
-        while let Some(tokens::Token {
-            kind: tokens::TokenKind::Symbol(tokens::SymbolKind::ArrowRight),
-            ..
-        }) = &self.peek()
-        {
-            let fun = self.apply(Box::new(expr))?;
-            expr = ast::Expr {
-                kind: ast::ExprKind::Apply(fun),
-                ctx: self.ctx.clone(),
+        loop {
+            let kind = match self.peek().map(|t| &t.kind) {
+                Some(tokens::TokenKind::Symbol(tokens::SymbolKind::ArrowRight)) => {
+                    self.apply().map(|(ident, args)| {
+                        ast::ExprKind::Apply(ast::Apply {
+                            ident,
+                            lhs: Box::new(expr.clone()),
+                            args,
+                            ctx: self.ctx.clone(),
+                        })
+                    })
+                }
+                Some(tokens::TokenKind::Symbol(tokens::SymbolKind::Dot)) => {
+                    self.field().map(|ident| {
+                        ast::ExprKind::Projection(ast::Projection {
+                            ident,
+                            lhs: Box::new(expr.clone()),
+                            ctx: self.ctx.clone(),
+                        })
+                    })
+                }
+                Some(tokens::TokenKind::RawTree(tokens::Delimiter::Bracket)) if self.at_index() => {
+                    self.index().map(|slice| {
+                        ast::ExprKind::Index(ast::Index {
+                            lhs: Box::new(expr.clone()),
+                            slice,
+                            ctx: self.ctx.clone(),
+                        })
+                    })
+                }
+                _ => break,
             };
+            match kind {
+                Ok(kind) => {
+                    expr = ast::Expr {
+                        kind,
+                        ctx: self.ctx.clone(),
+                    }
+                }
+                Err(e) => self.recover(e),
+            }
         }
 
-        while let Some(tokens::Token {
-            kind: tokens::TokenKind::Symbol(tokens::SymbolKind::Dot),
-            ..
-        }) = &self.peek()
-        {
-            let field = self.field(Box::new(expr))?;
-            expr = ast::Expr {
-                kind: ast::ExprKind::Projection(field),
+        Ok(Some(expr))
+    }
+
+    // Parse the contents of parentheses, which may be an application in the
+    // shorthand form (e.g., `(show $)`), and must be the whole input.
+    fn paren_expr(&mut self) -> Result<Option<ast::Expr>, Error> {
+        let shorthand = match self.peek() {
+            Some(tok) if tok.kind == tokens::TokenKind::Ident => {
+                self.peek_nth(1).map(starts_expr).unwrap_or(false)
+            }
+            _ => false,
+        };
+        let expr = if shorthand {
+            let apply = self.apply_shorthand()?;
+            Some(ast::Expr {
+                kind: ast::ExprKind::Apply(apply),
                 ctx: self.ctx.clone(),
-            };
-        }
+            })
+        } else {
+            self.maybe_expr()?
+        };
+        self.end()?;
+        Ok(expr)
+    }
 
-        Ok(Some(expr))
+    // Parse a lambda, e.g., `|x| x->idents->def`.
+    fn lambda(&mut self) -> Result<ast::Expr, Error> {
+        let start = self.position;
+        self.assert_sym(tokens::SymbolKind::Bar)?;
+        let param = self.identifier()?;
+        self.assert_sym(tokens::SymbolKind::Bar)?;
+        let body = self.parse_expr()?;
+        let kind = ast::ExprKind::Lambda(ast::Lambda {
+            param,
+            body: Box::new(body),
+            source: self.source(start),
+            ctx: self.ctx.clone(),
+        });
+        Ok(ast::Expr {
+            kind,
+            ctx: self.ctx.clone(),
+        })
+    }
+
+    // The text of the tokens from `start` up to the current position, with
+    // any whitespace between tokens replaced by a single space.
+    fn source(&self, start: usize) -> String {
+        let mut result = String::new();
+        let mut prev_end = None;
+        for tok in &self.tokens[start..self.position] {
+            if prev_end.map(|end| end < tok.span.start).unwrap_or(false) {
+                result.push(' ');
+            }
+            result.push_str(&tok.span.text);
+            prev_end = Some(tok.span.start + tok.span.text.len());
+        }
+        result
     }
 
     fn apply_shorthand(&mut self) -> Result<ast::Apply, Error> {
@@ -150,34 +334,112 @@ impl Parser {
         })
     }
 
-    fn apply(&mut self, lhs: Box<ast::Expr>) -> Result<ast::Apply, Error> {
+    // Arguments are either `$->f a b` or `$->f(a, b)`.
+    fn apply(&mut self) -> Result<(ast::Identifier, Vec<ast::Expr>), Error> {
         self.assert_sym(tokens::SymbolKind::ArrowRight)?;
         let ident = self.identifier()?;
-        let args = self.one_or_more("expression", |this| this.maybe_expr())?;
-        Ok(ast::Apply {
-            ident,
-            lhs,
-            args,
-            ctx: self.ctx.clone(),
-        })
+        // An index directly after the name applies to the result, e.g.,
+        // `$->idents[0]`, it is not an argument.
+        if self.at_index() {
+            return Ok((ident, Vec::new()));
+        }
+        if self.at_args() {
+            return Ok((ident, self.args()?));
+        }
+        let args = self.zero_or_more(|this| this.maybe_expr())?;
+        Ok((ident, args))
+    }
+
+    // Parse a parenthesised, comma-separated argument list, e.g., `(a, b)`.
+    fn args(&mut self) -> Result<Vec<ast::Expr>, Error> {
+        let tok = self.expect_peek()?;
+        let end = tok.span.start + tok.span.text.len() - 1;
+        let (tt, _) = tok.expect_raw_tree()?;
+        self.bump();
+        let mut parser = Parser::new(tt.tokens, end, self.ctx.clone());
+        let args = parser.comma_separated(")");
+        self.errors.append(&mut parser.errors);
+        args
     }
 
-    fn field(&mut self, lhs: Box<ast::Expr>) -> Result<ast::Projection, Error> {
+    // Parse an index or slice in brackets, e.g., `[2]`, `[-1]`, `[2..5]`, or
+    // `[..3]`.
+    fn index(&mut self) -> Result<ast::Slice, Error> {
+        let tok = self.expect_peek()?;
+        let end = tok.span.start + tok.span.text.len() - 1;
+        let (tt, _) = tok.expect_raw_tree()?;
+        self.bump();
+        let mut parser = Parser::new(tt.tokens, end, self.ctx.clone());
+        let start = parser.maybe_index();
+        let slice = match parser.peek().map(|t| &t.kind) {
+            Some(tokens::TokenKind::Symbol(tokens::SymbolKind::Dot)) => {
+                parser.bump();
+                parser.assert_sym(tokens::SymbolKind::Dot)?;
+                ast::Slice::Range(start, parser.maybe_index())
+            }
+            _ => match start {
+                Some(i) => ast::Slice::Element(i),
+                None => {
+                    return Err(parser.make_err(
+                        "Expected an index or range, e.g., `[2]` or `[2..5]`".to_owned(),
+                    ))
+                }
+            },
+        };
+        parser.end()?;
+        Ok(slice)
+    }
+
+    // Parse a (possibly negative) index, if there is one.
+    fn maybe_index(&mut self) -> Option<isize> {
+        match self.peek().map(|t| &t.kind) {
+            Some(tokens::TokenKind::Number(n)) => {
+                let n = *n as isize;
+                self.bump();
+                Some(n)
+            }
+            _ => None,
+        }
+    }
+
+    // Brackets after whitespace are a set, e.g., `$->foo [1, 2]`.
+    fn at_index(&self) -> bool {
+        self.at_adjacent(tokens::Delimiter::Bracket)
+    }
+
+    // Parentheses after whitespace are an argument, e.g., `$->foo (:bar.rs)`.
+    fn at_args(&self) -> bool {
+        self.at_adjacent(tokens::Delimiter::Paren)
+            && !self.peek().unwrap().span.inner().starts_with(':')
+    }
+
+    // True if the current token is delimited by `delimiter` and immediately
+    // follows the previous token.
+    fn at_adjacent(&self, delimiter: tokens::Delimiter) -> bool {
+        match (self.position.checked_sub(1), self.peek()) {
+            (Some(prev), Some(tok)) => {
+                let prev = &self.tokens[prev].span;
+                tok.kind == tokens::TokenKind::RawTree(delimiter)
+                    && prev.start + prev.text.len() == tok.span.start
+            }
+            _ => false,
+        }
+    }
+
+    // Parse the field name following `.`.
+    fn field(&mut self) -> Result<ast::Identifier, Error> {
         self.assert_sym(tokens::SymbolKind::Dot)?;
-        let ident = self.identifier()?;
-        Ok(ast::Projection {
-            ident,
-            lhs,
-            ctx: self.ctx.clone(),
-        })
+        self.identifier()
     }
 
     fn identifier(&mut self) -> Result<ast::Identifier, Error> {
-        let next = self.next()?;
+        let next = self.expect_peek()?;
         match next.kind {
             tokens::TokenKind::Ident => {
+                let name = next.span.text.clone();
+                self.bump();
                 return Ok(ast::Identifier {
-                    name: next.span.text.clone(),
+                    name,
                     ctx: self.ctx.clone(),
                 });
             }
@@ -190,18 +452,161 @@ impl Parser {
 
     fn meta(&mut self) -> Result<ast::MetaKind, Error> {
         self.assert_sym(tokens::SymbolKind::Caret)?;
-        let next = self.next()?;
-        match next.kind {
-            tokens::TokenKind::Ident => match &*next.span.text {
-                "exit" | "q" => return Ok(ast::MetaKind::Exit),
-                "help" | "h" => return Ok(ast::MetaKind::Help),
-                _ => {}
+        let next = self.expect_peek()?;
+        let name = match next.kind {
+            tokens::TokenKind::Ident => next.span.text.clone(),
+            _ => {
+                let next = next.to_string();
+                return Err(self.make_err(unknown_meta(&format!("^{}", next))));
+            }
+        };
+        let offset = next.span.start;
+        self.bump();
+        let text = self.meta_text();
+        let args: Vec<String> = text
+            .as_ref()
+            .map(|t| t.split_whitespace().map(|s| s.to_owned()).collect())
+            .unwrap_or_default();
+
+        match &*name {
+            "exit" | "q" if args.is_empty() => Ok(ast::MetaKind::Exit),
+            "help" | "h" if args.is_empty() => Ok(ast::MetaKind::Help),
+            "reload" if args.is_empty() => Ok(ast::MetaKind::Reload),
+            "backend" if args.is_empty() => Ok(ast::MetaKind::Backend),
+            "config" if args.len() == 1 && args[0] == "reload" => Ok(ast::MetaKind::ReloadConfig),
+            "config" => Err(Error::Parsing(
+                "Expected `^config reload`".to_owned(),
+                offset,
+            )),
+            "save" if args.len() == 2 => {
+                let var = self.meta_var(&args[0])?;
+                Ok(ast::MetaKind::Save(var, args[1].clone()))
+            }
+            "alias" if args.is_empty() => Ok(ast::MetaKind::Alias(None)),
+            "alias" if args.len() == 1 && args[0] == "save" => Ok(ast::MetaKind::SaveAliases),
+            "alias" => {
+                let text = text.unwrap();
+                let mut parts = text.splitn(2, '=');
+                let name = parts.next().unwrap().trim();
+                match (self.meta_var(name), parts.next()) {
+                    (Ok(ast::MetaVarKind::Named(_)), Some(body)) if !body.trim().is_empty() => Ok(
+                        ast::MetaKind::Alias(Some((name.to_owned(), body.trim().to_owned()))),
+                    ),
+                    _ => Err(Error::Parsing(
+                        "Expected `^alias name = query`".to_owned(),
+                        offset,
+                    )),
+                }
+            }
+            "record" if args.len() == 1 => Ok(ast::MetaKind::Record(args[0].clone(), false)),
+            "record" if args.len() == 2 && args[0] == "--output" => {
+                Ok(ast::MetaKind::Record(args[1].clone(), true))
+            }
+            "record" => Err(Error::Parsing(
+                "Expected `^record [--output] path`".to_owned(),
+                offset,
+            )),
+            "stop" if args.is_empty() => Ok(ast::MetaKind::StopRecording),
+            "log" if args.is_empty() => Ok(ast::MetaKind::Log),
+            "log" if args[0] == "tail" && args.len() <= 2 => match args.get(1) {
+                None => Ok(ast::MetaKind::LogTail(None)),
+                Some(n) => n
+                    .parse()
+                    .map(|n| ast::MetaKind::LogTail(Some(n)))
+                    .map_err(|_| {
+                        Error::Parsing(format!("Expected number of lines, found `{}`", n), offset)
+                    }),
             },
-            _ => {}
+            "focus" if args.len() <= 1 => Ok(ast::MetaKind::Focus(args.first().cloned())),
+            // The statement is parsed when it is explained, as for an alias.
+            "explain" => match text {
+                Some(text) if !text.trim().is_empty() => {
+                    Ok(ast::MetaKind::Explain(text.trim().to_owned()))
+                }
+                _ => Err(Error::Parsing(
+                    "Expected `^explain statement`".to_owned(),
+                    offset,
+                )),
+            },
+            "log" => Err(Error::Parsing(
+                "Expected `^log [tail [n]]`".to_owned(),
+                offset,
+            )),
+            // Blocks of pasted statements are read by the REPL, not parsed.
+            "paste" => Err(Error::Parsing(
+                "`^paste` can only be used at the REPL's prompt".to_owned(),
+                offset,
+            )),
+            "set" if args.is_empty() => Ok(ast::MetaKind::Set(None)),
+            "set" if args.len() >= 2 => {
+                // The value is the rest of the text, it may contain spaces and
+                // may be quoted to keep leading or trailing spaces.
+                let value = text.as_ref().unwrap().trim_start()[args[0].len()..].trim();
+                let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                    &value[1..value.len() - 1]
+                } else {
+                    value
+                };
+                Ok(ast::MetaKind::Set(Some((
+                    args[0].clone(),
+                    value.to_owned(),
+                ))))
+            }
+            "load" if args.len() == 2 => match self.meta_var(&args[0])? {
+                ast::MetaVarKind::Named(id) => Ok(ast::MetaKind::Load(id, args[1].clone())),
+                _ => Err(self.make_err(format!("Expected variable name, found `{}`", args[0]))),
+            },
+            "exit" | "q" | "help" | "h" | "save" | "load" | "reload" | "set" | "backend"
+            | "stop" | "focus" => Err(Error::Parsing(
+                format!(
+                    "Wrong number of arguments to `^{}`, found {}",
+                    name,
+                    args.len()
+                ),
+                offset,
+            )),
+            _ => Err(Error::Parsing(unknown_meta(&format!("^{}", name)), offset)),
         }
+    }
 
-        let next = next.to_string();
-        Err(self.make_err(format!("Expected meta-command, found `{}`", next)))
+    // The unparsed arguments to a meta-command, if any.
+    fn meta_text(&mut self) -> Option<String> {
+        if let Some(tokens::Token {
+            kind: tokens::TokenKind::Text,
+            span,
+        }) = self.peek()
+        {
+            let text = span.text.clone();
+            self.bump();
+            return Some(text);
+        }
+        None
+    }
+
+    // Parse a variable from a meta-command argument, e.g., `$`, `$-1`, `$4`, or `foo`.
+    fn meta_var(&self, s: &str) -> Result<ast::MetaVarKind, Error> {
+        if s == "$" {
+            return Ok(ast::MetaVarKind::Dollar);
+        }
+        if s == format!("${}", ALL_VAR) {
+            return Ok(ast::MetaVarKind::All);
+        }
+        if let Some(n) = s.strip_prefix('$') {
+            return match n.parse::<isize>() {
+                Ok(n) => Ok(ast::MetaVarKind::Numeric(n)),
+                Err(_) => Err(self.make_err(format!("Expected variable, found `{}`", s))),
+            };
+        }
+        let mut chars = s.chars();
+        match chars.next() {
+            Some(c) if c.is_alphabetic() && chars.all(|c| c.is_alphanumeric() || c == '_') => {
+                Ok(ast::MetaVarKind::Named(ast::Identifier {
+                    name: s.to_owned(),
+                    ctx: self.ctx.clone(),
+                }))
+            }
+            _ => Err(self.make_err(format!("Expected variable, found `{}`", s))),
+        }
     }
 
     fn maybe_semi(&mut self) -> Result<(), Error> {
@@ -211,7 +616,16 @@ impl Parser {
                     self.bump();
                 }
                 _ => {
-                    return Err(self.make_err(format!("Unexpected token: `{}`", tok)));
+                    let e = self.make_err(format!("Unexpected token: `{}`", tok));
+                    self.recover(e);
+                    // The only way to resume parsing is at the start of a new statement.
+                    if let Some(tokens::Token {
+                        kind: tokens::TokenKind::Symbol(tokens::SymbolKind::ArrowRight),
+                        ..
+                    }) = self.peek()
+                    {
+                        self.position = self.tokens.len();
+                    }
                 }
             }
         }
@@ -237,26 +651,29 @@ impl Parser {
         }
     }
 
+    fn peek_nth(&self, n: usize) -> Option<&tokens::Token> {
+        self.tokens.get(self.position + n)
+    }
+
     fn bump(&mut self) {
         if self.position < self.tokens.len() {
             self.position += 1;
         }
     }
 
-    fn next(&mut self) -> Result<&tokens::Token, Error> {
-        if self.position < self.tokens.len() {
-            let pos = self.position;
-            self.bump();
-            Ok(&self.tokens[pos])
-        } else {
-            Err(self.make_err("Unexpected end of statement".to_owned()))
+    // Like `peek`, but the end of input is an error.
+    fn expect_peek(&self) -> Result<&tokens::Token, Error> {
+        match self.peek() {
+            Some(tok) => Ok(tok),
+            None => Err(self.make_err("Unexpected end of statement".to_owned())),
         }
     }
 
     fn assert_ident(&mut self, s: &str) -> Result<(), Error> {
-        let next = self.next()?;
+        let next = self.expect_peek()?;
         match next.kind {
             tokens::TokenKind::Ident if next.span.text == s => {
+                self.bump();
                 return Ok(());
             }
             _ => {}
@@ -267,9 +684,10 @@ impl Parser {
     }
 
     fn assert_sym(&mut self, s: tokens::SymbolKind) -> Result<(), Error> {
-        let next = self.next()?;
+        let next = self.expect_peek()?;
         match next.kind {
             tokens::TokenKind::Symbol(kind) if kind == s => {
+                self.bump();
                 return Ok(());
             }
             _ => {}
@@ -279,27 +697,37 @@ impl Parser {
         Err(self.make_err(format!("Expected `{}`, found `{}`", s, next)))
     }
 
-    fn zero_or_more<F, T>(&mut self, mut f: F) -> Result<Vec<T>, Error>
-    where
-        F: FnMut(&mut Self) -> Result<Option<T>, Error>,
-    {
+    // Parse expressions separated by commas (with an optional trailing comma)
+    // until the end of input. `close` is the delimiter which follows the list.
+    fn comma_separated(&mut self, close: &str) -> Result<Vec<ast::Expr>, Error> {
         let mut result = Vec::new();
-        while let Some(t) = f(self)? {
-            result.push(t);
+        while self.peek().is_some() {
+            result.push(self.parse_expr()?);
+            match self.peek() {
+                None => break,
+                Some(tok) => match tok.kind {
+                    tokens::TokenKind::Symbol(tokens::SymbolKind::Comma) => self.bump(),
+                    _ => {
+                        let tok = tok.to_string();
+                        return Err(
+                            self.make_err(format!("Expected `,` or `{}`, found `{}`", close, tok))
+                        );
+                    }
+                },
+            }
         }
         Ok(result)
     }
 
-    fn one_or_more<F, T>(&mut self, expected: &str, f: F) -> Result<Vec<T>, Error>
+    fn zero_or_more<F, T>(&mut self, mut f: F) -> Result<Vec<T>, Error>
     where
         F: FnMut(&mut Self) -> Result<Option<T>, Error>,
     {
-        let result = self.zero_or_more(f)?;
-        if result.is_empty() {
-            Err(self.make_err(format!("Expected {}, TODO found what?", expected)))
-        } else {
-            Ok(result)
+        let mut result = Vec::new();
+        while let Some(t) = f(self)? {
+            result.push(t);
         }
+        Ok(result)
     }
 
     fn exactly_one<F, T>(&mut self, expected: &str, f: F) -> Result<T, Error>
@@ -312,11 +740,33 @@ impl Parser {
         }
     }
 
+    // Make an error at the current token (or the end of input).
     fn make_err(&self, msg: String) -> parse::Error {
-        parse::Error::Parsing(msg)
+        let offset = match self.peek() {
+            Some(tok) => tok.span.start,
+            None => self.end,
+        };
+        parse::Error::Parsing(msg, offset)
     }
 }
 
+// Whether `tok` can be the first token of an expression.
+fn starts_expr(tok: &tokens::Token) -> bool {
+    matches!(
+        tok.kind,
+        tokens::TokenKind::Ident
+            | tokens::TokenKind::RawTree(_)
+            | tokens::TokenKind::Number(_)
+            | tokens::TokenKind::Str(_)
+            | tokens::TokenKind::Symbol(tokens::SymbolKind::Dollar)
+            | tokens::TokenKind::Symbol(tokens::SymbolKind::Bar)
+    )
+}
+
+fn is_eq(tok: &tokens::Token) -> bool {
+    tok.kind == tokens::TokenKind::Symbol(tokens::SymbolKind::Eq)
+}
+
 // Parse a location.
 //
 // A location consists of a filename, a line number, and column number. All parts are optional.
@@ -331,57 +781,85 @@ impl Parser {
 // Note that a trailing colon is permitted for any of the above forms.
 struct LocationParser {
     input: String,
+    // The offset of `input` in the logical input, used for errors.
+    offset: usize,
     ctx: Context,
 }
 
 impl LocationParser {
-    fn new(input: &str, ctx: Context) -> LocationParser {
+    fn new(input: &str, offset: usize, ctx: Context) -> LocationParser {
         LocationParser {
             input: input.to_owned(),
+            offset,
             ctx,
         }
     }
 
     fn location(self) -> Result<ast::Location, Error> {
-        if !self.input.starts_with(':') {
-            return Err(parse::Error::Parsing(format!(
-                "Invalid location, expected `:`, found `{}`",
-                self.input
-            )));
+        let input = self.input.trim_start();
+        let start = self.offset + self.input.len() - input.len();
+        if !input.starts_with(':') {
+            return Err(parse::Error::Parsing(
+                format!(
+                    "Invalid location, expected `:`, found `{}`",
+                    input.trim_end()
+                ),
+                start,
+            ));
         }
 
-        let mut splits = self.input[1..].split(':');
-        let first = splits.next().map(|s| s.trim());
-        let second = splits.next().map(|s| s.trim());
-        let third = splits.next().map(|s| s.trim());
+        // Each part of the location (trimmed) and its offset.
+        let mut parts = Vec::new();
+        let mut offset = start + 1;
+        for s in input[1..].split(':') {
+            let trimmed = s.trim_start();
+            parts.push((trimmed.trim_end(), offset + s.len() - trimmed.len()));
+            offset += s.len() + 1;
+        }
+        // A Windows drive (e.g., `C:\src\main.rs`) is part of the file name,
+        // not a separate part.
+        if parts.len() > 1 && is_drive(parts[0].0) && parts[1].0.starts_with(&['\\', '/'][..]) {
+            let drive_offset = parts[0].1;
+            let (rest, rest_offset) = parts.remove(1);
+            parts[0] = (
+                &input[drive_offset - start..rest_offset - start + rest.len()],
+                drive_offset,
+            );
+        }
+        let mut parts = parts.into_iter();
+        let first = parts.next();
+        let second = parts.next();
+        let third = parts.next();
 
-        if let Some(s) = splits.next() {
+        if let Some((s, offset)) = parts.next() {
             if !s.is_empty() {
-                return Err(parse::Error::Parsing(format!(
-                    "Invalid location, unexpected `{}`",
-                    s
-                )));
+                return Err(parse::Error::Parsing(
+                    format!("Invalid location, unexpected `{}`", s),
+                    offset,
+                ));
             }
         }
 
         match first {
             None => Ok(ast::Location::new(None, None, None, self.ctx)),
-            Some(s) => match s.parse::<usize>() {
+            Some((s, _)) => match s.parse::<usize>() {
                 Ok(row) => {
-                    if let Some(s) = third {
-                        return Err(parse::Error::Parsing(format!(
-                            "Invalid location, unexpected `{}`",
-                            s
-                        )));
+                    if let Some((s, offset)) = third {
+                        return Err(parse::Error::Parsing(
+                            format!("Invalid location, unexpected `{}`", s),
+                            offset,
+                        ));
                     }
-                    let second = Self::map_parse(second)?;
+                    let second = self.map_parse(second)?;
                     Ok(ast::Location::new(None, Some(row), second, self.ctx))
                 }
                 Err(_) => {
-                    let second = Self::map_parse(second)?;
-                    let third = Self::map_parse(third)?;
+                    let second = self.map_parse(second)?;
+                    let third = self.map_parse(third)?;
+                    // Either separator may be used, file names are stored
+                    // with `/` so they are the same on every platform.
                     Ok(ast::Location::new(
-                        Some(s.to_owned()),
+                        Some(s.replace('\\', "/")),
                         second,
                         third,
                         self.ctx,
@@ -391,31 +869,43 @@ impl LocationParser {
         }
     }
 
-    fn map_parse(s: Option<&str>) -> Result<Option<usize>, Error> {
+    fn map_parse(&self, s: Option<(&str, usize)>) -> Result<Option<usize>, Error> {
         match s {
-            Some(s) => match s.parse::<usize>() {
+            Some((s, offset)) => match s.parse::<usize>() {
                 Ok(n) => Ok(Some(n)),
-                Err(_) => Err(parse::Error::Parsing(format!(
-                    "Invalid location, expected number, found `{}`",
-                    s
-                ))),
+                Err(_) => Err(parse::Error::Parsing(
+                    format!("Invalid location, expected number, found `{}`", s),
+                    offset,
+                )),
             },
             None => Ok(None),
         }
     }
 }
 
+// A Windows drive letter, e.g., the `C` of `C:\src\main.rs`.
+fn is_drive(s: &str) -> bool {
+    s.len() == 1 && s.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+// The error message for `found` where a meta-command was expected.
+fn unknown_meta(found: &str) -> String {
+    let names: Vec<_> = META_COMMANDS.iter().map(|n| format!("`^{}`", n)).collect();
+    format!(
+        "Unknown meta-command `{}`, expected one of {} (see `^help`)",
+        found,
+        names.join(", ")
+    )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::parse::lexer;
 
     fn parser(tt: tokens::Token) -> Parser {
-        Parser {
-            tokens: tt.expect_tree().0.tokens,
-            position: 0,
-            ctx: Context::default(),
-        }
+        let (tt, span) = tt.expect_tree();
+        Parser::new(tt.tokens, span.text.len(), Context::default())
     }
 
     #[test]
@@ -429,29 +919,47 @@ mod test {
 
     #[test]
     fn locations() {
-        assert!(LocationParser::new("", Context::default())
+        assert!(LocationParser::new("", 0, Context::default())
             .location()
             .is_err());
 
-        let loc = LocationParser::new(":foo.rs", Context::default())
+        let loc = LocationParser::new(":foo.rs", 0, Context::default())
             .location()
             .unwrap();
         assert!(loc.file.is_some() && loc.line.is_none() && loc.column.is_none());
 
-        let loc = LocationParser::new(":0", Context::default())
+        let loc = LocationParser::new(":0", 0, Context::default())
             .location()
             .unwrap();
         assert!(loc.file.is_none() && loc.line.is_some() && loc.column.is_none());
 
-        let loc = LocationParser::new(":42:3", Context::default())
+        let loc = LocationParser::new(":42:3", 0, Context::default())
             .location()
             .unwrap();
         assert!(loc.file.is_none() && loc.line.is_some() && loc.column.is_some());
 
-        let loc = LocationParser::new(":src/bar.rs:1:2:", Context::default())
+        let loc = LocationParser::new(":src/bar.rs:1:2:", 0, Context::default())
             .location()
             .unwrap();
         assert!(loc.file.is_some() && loc.line.is_some() && loc.column.is_some());
+
+        let loc = LocationParser::new(":src\\main.rs:3", 0, Context::default())
+            .location()
+            .unwrap();
+        assert_eq!(loc.file.as_deref(), Some("src/main.rs"));
+        assert!(loc.line.is_some() && loc.column.is_none());
+
+        let loc = LocationParser::new(":C:\\src\\main.rs:3:4", 0, Context::default())
+            .location()
+            .unwrap();
+        assert_eq!(loc.file.as_deref(), Some("C:/src/main.rs"));
+        assert!(loc.line.is_some() && loc.column.is_some());
+
+        // Not a drive, just a file named `C`.
+        let loc = LocationParser::new(":C:3", 0, Context::default())
+            .location()
+            .unwrap();
+        assert_eq!(loc.file.as_deref(), Some("C"));
     }
 
     #[test]
@@ -471,6 +979,341 @@ mod test {
         }
     }
 
+    #[test]
+    fn lambda() {
+        let toks = lexer::lex("f = |x|  x->idents\n->def;", 0).unwrap();
+        let stmt = parser(toks).parse_stmt().unwrap();
+        match stmt.kind {
+            ast::StatementKind::Assign(ident, expr) => {
+                assert_eq!(ident.name, "f");
+                match expr.kind {
+                    ast::ExprKind::Lambda(l) => {
+                        assert_eq!(l.param.name, "x");
+                        assert_eq!(l.source, "|x| x->idents ->def");
+                        match l.body.kind {
+                            ast::ExprKind::Apply(a) => assert_eq!(a.ident.name, "def"),
+                            _ => panic!(),
+                        }
+                    }
+                    _ => panic!(),
+                }
+            }
+            _ => panic!(),
+        }
+
+        for input in &["|x|", "|x x", "||x| x", "f = "] {
+            let toks = lexer::lex(input, 0).unwrap();
+            assert!(parse_stmt(toks, Context::default()).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn call_args() {
+        for (input, len) in &[
+            ("$->foo()", 0),
+            ("$->foo(1, \"a\")", 2),
+            ("$->foo($->bar 1, [2, 3],)", 2),
+            ("$->foo((:foo.rs), $1->bar(2))", 2),
+            // With a space, parentheses are a single argument.
+            ("$->foo (1)", 1),
+            ("$->foo(:foo.rs)", 1),
+        ] {
+            let toks = lexer::lex(input, 0).unwrap();
+            match parser(toks).parse_expr().unwrap().kind {
+                ast::ExprKind::Apply(a) => assert_eq!(a.args.len(), *len, "{}", input),
+                _ => panic!("{}", input),
+            }
+        }
+
+        // The result of a call can be indexed or applied to.
+        let toks = lexer::lex("$->foo(1, 2)[0]->bar", 0).unwrap();
+        match parser(toks).parse_expr().unwrap().kind {
+            ast::ExprKind::Apply(a) => match a.lhs.kind {
+                ast::ExprKind::Index(i) => match i.lhs.kind {
+                    ast::ExprKind::Apply(a) => assert_eq!(a.args.len(), 2),
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+
+        let toks = lexer::lex("$->foo(1 2)", 0).unwrap();
+        match parse_stmt(toks, Context::default()) {
+            Err(Error::Parsing(msg, 9)) => assert_eq!(msg, "Expected `,` or `)`, found `2`"),
+            r => panic!("{:?}", r.is_ok()),
+        }
+        let toks = lexer::lex("$->foo(1,,)", 0).unwrap();
+        assert!(parse_stmt(toks, Context::default()).is_err());
+    }
+
+    #[test]
+    fn meta() {
+        let toks = lexer::lex("^save $-2 foo.json", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Meta(ast::MetaKind::Save(ast::MetaVarKind::Numeric(-2), path)) => {
+                assert_eq!(path, "foo.json")
+            }
+            _ => panic!(),
+        }
+
+        let toks = lexer::lex("^load foo /tmp/foo.json", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Meta(ast::MetaKind::Load(id, path)) => {
+                assert_eq!(id.name, "foo");
+                assert_eq!(path, "/tmp/foo.json");
+            }
+            _ => panic!(),
+        }
+
+        let toks = lexer::lex("^load $ foo.json", 0).unwrap();
+        assert!(parser(toks).parse_stmt().is_err());
+        let toks = lexer::lex("^save foo", 0).unwrap();
+        assert!(parser(toks).parse_stmt().is_err());
+        let toks = lexer::lex("^q", 0).unwrap();
+        parser(toks).parse_stmt().unwrap();
+
+        let toks = lexer::lex("^set display full", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Meta(ast::MetaKind::Set(Some((name, value)))) => {
+                assert_eq!(name, "display");
+                assert_eq!(value, "full");
+            }
+            _ => panic!(),
+        }
+        let toks = lexer::lex("^set display", 0).unwrap();
+        assert!(parser(toks).parse_stmt().is_err());
+        for (input, expected) in &[
+            ("^set prompt {n} >", "{n} >"),
+            ("^set prompt \"{n} > \"", "{n} > "),
+        ] {
+            let toks = lexer::lex(input, 0).unwrap();
+            match parser(toks).parse_stmt().unwrap().kind {
+                ast::StatementKind::Meta(ast::MetaKind::Set(Some((_, value)))) => {
+                    assert_eq!(&value, expected)
+                }
+                _ => panic!(),
+            }
+        }
+
+        let toks = lexer::lex("^alias refsof = idents->def -> refs", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Meta(ast::MetaKind::Alias(Some((name, body)))) => {
+                assert_eq!(name, "refsof");
+                assert_eq!(body, "idents->def -> refs");
+            }
+            _ => panic!(),
+        }
+        let toks = lexer::lex("^record --output session.cly", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Meta(ast::MetaKind::Record(path, true)) => {
+                assert_eq!(path, "session.cly")
+            }
+            _ => panic!(),
+        }
+        let toks = lexer::lex("^record a b", 0).unwrap();
+        assert!(parser(toks).parse_stmt().is_err());
+        let toks = lexer::lex("^alias save", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Meta(ast::MetaKind::SaveAliases) => {}
+            _ => panic!(),
+        }
+        let toks = lexer::lex("^alias $ = idents", 0).unwrap();
+        assert!(parser(toks).parse_stmt().is_err());
+        let toks = lexer::lex("^alias foo =", 0).unwrap();
+        assert!(parser(toks).parse_stmt().is_err());
+
+        for (input, expected) in &[("^log tail", None), ("^log tail 5", Some(5))] {
+            let toks = lexer::lex(input, 0).unwrap();
+            match parser(toks).parse_stmt().unwrap().kind {
+                ast::StatementKind::Meta(ast::MetaKind::LogTail(n)) => assert_eq!(n, *expected),
+                _ => panic!(),
+            }
+        }
+        let toks = lexer::lex("^focus src/lib.rs", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Meta(ast::MetaKind::Focus(Some(path))) => {
+                assert_eq!(path, "src/lib.rs")
+            }
+            _ => panic!(),
+        }
+        let toks = lexer::lex("^explain show (:foo.rs)->idents ", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Meta(ast::MetaKind::Explain(stmt)) => {
+                assert_eq!(stmt, "show (:foo.rs)->idents")
+            }
+            _ => panic!(),
+        }
+        for input in &[
+            "^log tail five",
+            "^log head",
+            "^log tail 5 6",
+            "^focus a b",
+            "^explain",
+            "^paste",
+        ] {
+            let toks = lexer::lex(input, 0).unwrap();
+            assert!(parser(toks).parse_stmt().is_err());
+        }
+
+        let toks = lexer::lex("^quit", 0).unwrap();
+        match parser(toks).parse_stmt() {
+            Err(Error::Parsing(msg, 1)) => assert_eq!(
+                msg,
+                "Unknown meta-command `^quit`, expected one of `^alias`, `^backend`, \
+                 `^config`, `^exit`, `^explain`, `^focus`, `^help`, `^load`, `^log`, `^paste`, \
+                 `^record`, `^reload`, `^save`, `^set`, `^stop` (see `^help`)"
+            ),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn named_var() {
+        let toks = lexer::lex("foo", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::Expr(ast::ExprKind::MetaVar(ast::MetaVarKind::Named(id))) => {
+                assert_eq!(id.name, "foo")
+            }
+            _ => panic!(),
+        }
+
+        let toks = lexer::lex("show foo", 0).unwrap();
+        match parser(toks).parse_stmt().unwrap().kind {
+            ast::StatementKind::ApplyShorthand(a) if a.ident.name == "show" => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn recovery() {
+        let toks = lexer::lex("$->foo $ ->$ ->bar $ ->(:foo.rs) $", 0).unwrap();
+        match parse_stmt(toks, Context::default()) {
+            Err(Error::Multiple(errs)) => {
+                assert_eq!(errs.len(), 2);
+                match (&errs[0], &errs[1]) {
+                    (Error::Parsing(_, 11), Error::Parsing(_, 23)) => {}
+                    _ => panic!("{:?}", errs),
+                }
+            }
+            r => panic!("{:?}", r.is_ok()),
+        }
+
+        let toks = lexer::lex("$ $->foo $", 0).unwrap();
+        match parse_stmt(toks, Context::default()) {
+            Err(Error::Parsing(_, 2)) => {}
+            r => panic!("{:?}", r.is_ok()),
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        let toks = lexer::lex("$->expand 2", 0).unwrap();
+        let expr = parser(toks).parse_expr().unwrap();
+        match &expr.kind {
+            ast::ExprKind::Apply(a) => match a.args[0].kind {
+                ast::ExprKind::Number(2) => {}
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+
+        let toks = lexer::lex("$->expand -2", 0).unwrap();
+        assert!(parse_stmt(toks, Context::default()).is_err());
+    }
+
+    #[test]
+    fn sets() {
+        let toks = lexer::lex("[(:foo.rs:3), (:bar.rs:7)]->idents", 0).unwrap();
+        let expr = parser(toks).parse_expr().unwrap();
+        match &expr.kind {
+            ast::ExprKind::Apply(a) => match &a.lhs.kind {
+                ast::ExprKind::Set(elements) => {
+                    assert_eq!(elements.len(), 2);
+                    match &elements[1].kind {
+                        ast::ExprKind::Location(l) => assert_eq!(l.line, Some(7)),
+                        _ => panic!(),
+                    }
+                }
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+
+        for (input, len) in &[("[]", 0), ("[$,]", 1), ("[$1, [$2, 3]->pick]", 2)] {
+            let toks = lexer::lex(input, 0).unwrap();
+            match parser(toks).parse_expr().unwrap().kind {
+                ast::ExprKind::Set(elements) => assert_eq!(elements.len(), *len),
+                _ => panic!("{}", input),
+            }
+        }
+
+        let toks = lexer::lex("[$ $]", 0).unwrap();
+        match parse_stmt(toks, Context::default()) {
+            Err(Error::Parsing(msg, 3)) => assert_eq!(msg, "Expected `,` or `]`, found `$`"),
+            r => panic!("{:?}", r.is_ok()),
+        }
+        let toks = lexer::lex("[$,,]", 0).unwrap();
+        assert!(parse_stmt(toks, Context::default()).is_err());
+    }
+
+    #[test]
+    fn indices() {
+        for (input, slice) in &[
+            ("$[2]", ast::Slice::Element(2)),
+            ("$3[-1]", ast::Slice::Element(-1)),
+            ("$[2..5]", ast::Slice::Range(Some(2), Some(5))),
+            ("$[..-2]", ast::Slice::Range(None, Some(-2))),
+            ("$[1..]", ast::Slice::Range(Some(1), None)),
+            ("$[..]", ast::Slice::Range(None, None)),
+        ] {
+            let toks = lexer::lex(input, 0).unwrap();
+            match parser(toks).parse_expr().unwrap().kind {
+                ast::ExprKind::Index(i) => assert_eq!(i.slice, *slice),
+                _ => panic!("{}", input),
+            }
+        }
+
+        // Indices can be chained with applications.
+        let toks = lexer::lex("$->idents[0].def", 0).unwrap();
+        match parser(toks).parse_expr().unwrap().kind {
+            ast::ExprKind::Projection(p) => match p.lhs.kind {
+                ast::ExprKind::Index(i) => match i.lhs.kind {
+                    ast::ExprKind::Apply(_) => {}
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+
+        // With a space, brackets are a set argument.
+        let toks = lexer::lex("$->foo [1]", 0).unwrap();
+        match parser(toks).parse_expr().unwrap().kind {
+            ast::ExprKind::Apply(a) => assert_eq!(a.args.len(), 1),
+            _ => panic!(),
+        }
+
+        for input in &["$[]", "$[a]", "$[1.2]", "$[1..2..3]"] {
+            let toks = lexer::lex(input, 0).unwrap();
+            assert!(parse_stmt(toks, Context::default()).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn chains() {
+        // `->` without arguments, mixed with `.`.
+        let toks = lexer::lex("$.idents->def->refs.pick", 0).unwrap();
+        let expr = parser(toks).parse_expr().unwrap();
+        match &expr.kind {
+            ast::ExprKind::Projection(p) if p.ident.name == "pick" => match &p.lhs.kind {
+                ast::ExprKind::Apply(a) if a.ident.name == "refs" && a.args.is_empty() => {}
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+    }
+
     #[test]
     fn smoke_expr() {
         let toks = lexer::lex("show (:src/back/mod.rs:10:38).idents.def", 0).unwrap();
@@ -12,7 +12,7 @@ impl Token {
     pub fn is_empty(&self) -> bool {
         match &self.kind {
             TokenKind::Tree(tt) => tt.tokens.is_empty(),
-            TokenKind::RawTree => self.span.text.trim().is_empty(),
+            TokenKind::RawTree(_) => self.span.text.trim().is_empty(),
             _ => false,
         }
     }
@@ -26,8 +26,9 @@ impl Token {
 
     pub fn expect_raw_tree(&self) -> Result<(TokenTree, Span), Error> {
         match self.kind {
-            TokenKind::RawTree => {
-                let tt = lexer::lex(self.span.inner(), self.span.start + 1)?;
+            TokenKind::RawTree(_) => {
+                // Lex the untrimmed contents so that offsets are correct.
+                let tt = lexer::lex(self.span.contents(), self.span.start + 1)?;
                 Ok(tt.expect_tree())
             }
             _ => panic!("Expected token tree, found: {:?}", self),
@@ -41,7 +42,10 @@ impl fmt::Display for Token {
             TokenKind::Symbol(s) => s.fmt(f),
             TokenKind::Ident => write!(f, "{}", self.span.text),
             TokenKind::Number(n) => n.fmt(f),
-            TokenKind::RawTree | TokenKind::Tree(_) => write!(f, "("),
+            TokenKind::Str(_) => write!(f, "{}", self.span.text),
+            TokenKind::Text => write!(f, "{}", self.span.text),
+            TokenKind::RawTree(Delimiter::Bracket) => write!(f, "["),
+            TokenKind::RawTree(Delimiter::Paren) | TokenKind::Tree(_) => write!(f, "("),
         }
     }
 }
@@ -51,12 +55,25 @@ pub enum TokenKind {
     Symbol(SymbolKind),
     Ident,
     Number(i64),
+    // A string literal, the value has escapes replaced, the span includes the
+    // quotes.
+    Str(String),
+    // Unlexed text, used for the arguments of meta-commands.
+    Text,
     // Note that the span for the token trees includes the delimiters, but no
     // padding outside the delimiters.
-    RawTree,
+    RawTree(Delimiter),
     Tree(TokenTree),
 }
 
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Delimiter {
+    // `(...)`
+    Paren,
+    // `[...]`
+    Bracket,
+}
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct TokenTree {
     pub tokens: Vec<Token>,
@@ -67,6 +84,8 @@ pub enum SymbolKind {
     Caret,
     Dollar,
     Dot,
+    Comma,
+    Colon,
 
     SemiColon,
     Hash,
@@ -75,6 +94,15 @@ pub enum SymbolKind {
     PlusEq,
     ArrowLeft,
     ArrowRight,
+
+    EqEq,
+    NotEq,
+    Lt,
+    Gt,
+    Bang,
+    AndAnd,
+    OrOr,
+    Bar,
 }
 
 impl fmt::Display for SymbolKind {
@@ -83,12 +111,22 @@ impl fmt::Display for SymbolKind {
             SymbolKind::Caret => write!(f, "^"),
             SymbolKind::Dollar => write!(f, "$"),
             SymbolKind::Dot => write!(f, "."),
+            SymbolKind::Comma => write!(f, ","),
+            SymbolKind::Colon => write!(f, ":"),
             SymbolKind::SemiColon => write!(f, ";"),
             SymbolKind::Hash => write!(f, "#"),
             SymbolKind::Eq => write!(f, "="),
             SymbolKind::PlusEq => write!(f, "+="),
             SymbolKind::ArrowLeft => write!(f, "<-"),
             SymbolKind::ArrowRight => write!(f, "->"),
+            SymbolKind::EqEq => write!(f, "=="),
+            SymbolKind::NotEq => write!(f, "!="),
+            SymbolKind::Lt => write!(f, "<"),
+            SymbolKind::Gt => write!(f, ">"),
+            SymbolKind::Bang => write!(f, "!"),
+            SymbolKind::AndAnd => write!(f, "&&"),
+            SymbolKind::OrOr => write!(f, "||"),
+            SymbolKind::Bar => write!(f, "|"),
         }
     }
 }
@@ -100,7 +138,15 @@ pub struct Span {
 }
 
 impl Span {
+    /// The text between the delimiters of a token tree, with surrounding
+    /// whitespace trimmed.
     pub fn inner(&self) -> &str {
-        self.text[1..self.text.len() - 1].trim()
+        self.contents().trim()
+    }
+
+    /// The text between the delimiters of a token tree, starting at offset
+    /// `self.start + 1` (delimiters are always one byte).
+    pub fn contents(&self) -> &str {
+        &self.text[1..self.text.len() - 1]
     }
 }